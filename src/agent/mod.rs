@@ -0,0 +1,153 @@
+//! Agent of a node with its own disk, storing the models pushed by the router under the
+//! ComfyUI root of the node, see `NodeStorage::Remote`.
+//!
+//! - `PUT /files/<path>` stores the request body at `<path>` under the root, replacing the file
+//! - `HEAD /files/<path>` responds `200 OK` with the size of the file, `404` if missing
+//!
+//! Paths are relative to the root, e.g. `models/loras/<file id>`, without `..` or absolute
+//! components. When a token is set, requests must carry it as `Authorization: Bearer <token>`.
+
+use axum::{
+    body::Body,
+    extract::{Path, Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::put,
+    Router,
+};
+use futures_util::StreamExt;
+use std::{
+    path::{Component, PathBuf},
+    sync::Arc,
+};
+use tokio::{io::AsyncWriteExt, net::TcpListener};
+
+#[derive(Clone, Debug)]
+struct AgentState {
+    root_dir: PathBuf,
+    token: Option<String>,
+}
+
+/// Serve the agent on `listener`, storing files under `root_dir`.
+pub async fn serve(
+    listener: TcpListener,
+    root_dir: PathBuf,
+    token: Option<String>,
+) -> anyhow::Result<()> {
+    let state = Arc::new(AgentState { root_dir, token });
+
+    let app = Router::new()
+        .route("/files/*path", put(put_file).head(head_file))
+        .layer(middleware::from_fn_with_state(state.clone(), authenticate))
+        .with_state(state.clone());
+
+    tracing::info!(
+        addr = %listener.local_addr()?,
+        root_dir = %state.root_dir.display(),
+        "agent listening"
+    );
+    axum::serve(listener, app).await?;
+
+    Ok(())
+}
+
+async fn authenticate(
+    State(state): State<Arc<AgentState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Some(token) = &state.token {
+        let authorized = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            == Some(token.as_str());
+        if !authorized {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+    }
+
+    next.run(request).await
+}
+
+/// `path` under the root dir, `None` if it is empty or leaves the root dir.
+fn resolve(state: &AgentState, path: &str) -> Option<PathBuf> {
+    let path = std::path::Path::new(path);
+    let mut components = path.components().peekable();
+    components.peek()?;
+    if !components.all(|v| matches!(v, Component::Normal(_))) {
+        return None;
+    }
+
+    Some(state.root_dir.join(path))
+}
+
+async fn put_file(
+    State(state): State<Arc<AgentState>>,
+    Path(path): Path<String>,
+    body: Body,
+) -> Response {
+    let Some(file_path) = resolve(&state, &path) else {
+        return (StatusCode::BAD_REQUEST, "invalid path").into_response();
+    };
+
+    match write_file(&file_path, body).await {
+        Ok(size) => {
+            tracing::info!(path, size, "file stored");
+            StatusCode::CREATED.into_response()
+        }
+        Err(e) => {
+            tracing::warn!(path, error = %e, "failed to store file");
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+        }
+    }
+}
+
+/// Write `body` into a temporary file renamed over `file_path`, so that ComfyUI never sees
+/// a partial file. Return the size written.
+async fn write_file(file_path: &std::path::Path, body: Body) -> anyhow::Result<u64> {
+    let parent = file_path
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("no parent dir"))?;
+    tokio::fs::create_dir_all(parent).await?;
+
+    let file_name = file_path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp_path = parent.join(format!(".{}.{}.tmp", file_name, uuid::Uuid::new_v4()));
+
+    let result = async {
+        let mut file = tokio::fs::File::create(&tmp_path).await?;
+        let mut size = 0;
+        let mut stream = body.into_data_stream();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            file.write_all(&chunk).await?;
+            size += chunk.len() as u64;
+        }
+        file.sync_all().await?;
+        tokio::fs::rename(&tmp_path, file_path).await?;
+        anyhow::Ok(size)
+    }
+    .await;
+
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&tmp_path).await;
+    }
+    result
+}
+
+async fn head_file(State(state): State<Arc<AgentState>>, Path(path): Path<String>) -> Response {
+    let Some(file_path) = resolve(&state, &path) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    match tokio::fs::metadata(&file_path).await {
+        Ok(metadata) if metadata.is_file() => {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::CONTENT_LENGTH, metadata.len().into());
+            (StatusCode::OK, headers).into_response()
+        }
+        _ => StatusCode::NOT_FOUND.into_response(),
+    }
+}
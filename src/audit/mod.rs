@@ -0,0 +1,191 @@
+use crate::auth::{key::now, Identity};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+};
+use tokio::{io::AsyncWriteExt, sync::RwLock};
+use utoipa::ToSchema;
+
+/// Actor of actions triggered by the router itself, e.g. cache eviction.
+pub const SYSTEM_ACTOR: &str = "system";
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct AuditEntry {
+    /// Unix timestamp in seconds.
+    pub timestamp: u64,
+    /// Id of the identity, `admin` for Basic auth and `system` for the router itself.
+    pub actor: String,
+    /// Name of the API key, if any.
+    pub actor_name: Option<String>,
+    #[schema(value_type = Option<String>)]
+    pub source_ip: Option<IpAddr>,
+    /// Dot separated action, e.g. `cluster.node.add`.
+    pub action: String,
+    /// Summary of the request.
+    pub details: Value,
+}
+
+impl AuditEntry {
+    pub fn new(action: &str, details: Value) -> Self {
+        Self {
+            timestamp: now(),
+            actor: SYSTEM_ACTOR.to_string(),
+            actor_name: None,
+            source_ip: None,
+            action: action.to_string(),
+            details,
+        }
+    }
+
+    pub fn with_identity(mut self, identity: &Identity) -> Self {
+        self.actor = identity.id().to_string();
+        self.actor_name = match identity {
+            Identity::Admin => None,
+            Identity::ApiKey { name, .. } => Some(name.clone()),
+        };
+        self
+    }
+
+    pub fn with_source(mut self, addr: SocketAddr) -> Self {
+        self.source_ip = Some(addr.ip());
+        self
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct AuditQuery {
+    /// Only entries whose action starts with this prefix, e.g. `cluster.`.
+    pub action: Option<String>,
+    pub actor: Option<String>,
+    /// Unix timestamp in seconds, inclusive.
+    pub since: Option<u64>,
+    /// Unix timestamp in seconds, inclusive.
+    pub until: Option<u64>,
+}
+
+impl AuditQuery {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        self.action
+            .as_ref()
+            .is_none_or(|v| entry.action.starts_with(v.as_str()))
+            && self.actor.as_ref().is_none_or(|v| &entry.actor == v)
+            && self.since.is_none_or(|v| entry.timestamp >= v)
+            && self.until.is_none_or(|v| entry.timestamp <= v)
+    }
+}
+
+/// Append-only audit log, stored as JSON lines.
+///
+/// When the file would exceed `max_bytes`, it is renamed to `<path>.1`, the previous
+/// `<path>.1` to `<path>.2` and so on, keeping at most `max_files` rotated files.
+#[derive(Clone, Debug)]
+pub struct AuditLog {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+}
+
+impl AuditLog {
+    pub fn new(path: impl AsRef<Path>, max_bytes: u64, max_files: usize) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            max_bytes,
+            max_files,
+        }
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        path.into()
+    }
+
+    async fn rotate(&self) -> anyhow::Result<()> {
+        let _ = tokio::fs::remove_file(self.rotated_path(self.max_files)).await;
+        for index in (1..self.max_files).rev() {
+            let from = self.rotated_path(index);
+            if from.exists() {
+                tokio::fs::rename(from, self.rotated_path(index + 1)).await?;
+            }
+        }
+        tokio::fs::rename(&self.path, self.rotated_path(1)).await?;
+
+        Ok(())
+    }
+
+    pub async fn append(&self, entry: &AuditEntry) -> anyhow::Result<()> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+
+        if let Some(parent) = self.path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        if let Ok(metadata) = tokio::fs::metadata(&self.path).await {
+            if metadata.len() > 0 && metadata.len() + line.len() as u64 > self.max_bytes {
+                self.rotate().await?;
+            }
+        }
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(line.as_bytes()).await?;
+        file.flush().await?;
+
+        Ok(())
+    }
+
+    /// Return the entries matching `query`, newest first, with the total count of matches.
+    pub async fn query(
+        &self,
+        query: &AuditQuery,
+        offset: usize,
+        limit: usize,
+    ) -> anyhow::Result<(Vec<AuditEntry>, usize)> {
+        let mut entries = vec![];
+
+        // from the oldest rotated file to the current one
+        let mut paths: Vec<PathBuf> = (1..=self.max_files)
+            .rev()
+            .map(|v| self.rotated_path(v))
+            .collect();
+        paths.push(self.path.clone());
+
+        for path in paths {
+            let content = match tokio::fs::read_to_string(&path).await {
+                Ok(content) => content,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+
+            for line in content.lines() {
+                match serde_json::from_str::<AuditEntry>(line) {
+                    Ok(entry) if query.matches(&entry) => entries.push(entry),
+                    Ok(_) => {}
+                    Err(e) => {
+                        tracing::warn!(path = %path.display(), error = %e, "invalid audit entry");
+                    }
+                }
+            }
+        }
+
+        let total = entries.len();
+        let entries = entries.into_iter().rev().skip(offset).take(limit).collect();
+
+        Ok((entries, total))
+    }
+}
+
+/// Append an entry to the audit log. Failures are logged but do not fail the action.
+pub async fn record(audit_log: &RwLock<AuditLog>, entry: AuditEntry) {
+    // writes are serialized so that rotation is safe
+    let audit_log = audit_log.write().await;
+    if let Err(e) = audit_log.append(&entry).await {
+        tracing::warn!(action = entry.action, error = %e, "failed to write audit entry");
+    }
+}
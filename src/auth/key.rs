@@ -0,0 +1,177 @@
+use super::{limit::Limits, Scope};
+use crate::fs::write_atomic;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use utoipa::ToSchema;
+
+const KEY_PREFIX: &str = "cr_";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ApiKey {
+    id: String,
+    name: String,
+    /// SHA-256 of the key, the key itself is never stored.
+    key_hash: String,
+    scopes: HashSet<Scope>,
+    #[serde(default)]
+    limits: Limits,
+    created_at: u64,
+    revoked_at: Option<u64>,
+}
+
+/// The public part of an API key.
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct ApiKeyInfo {
+    id: String,
+    name: String,
+    scopes: HashSet<Scope>,
+    limits: Limits,
+    /// Unix timestamp in seconds.
+    created_at: u64,
+    /// Unix timestamp in seconds.
+    revoked_at: Option<u64>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ApiKeyStore {
+    keys: HashMap<String, ApiKey>,
+    path: PathBuf,
+}
+
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|v| v.as_secs())
+        .unwrap_or_default()
+}
+
+fn hash_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+impl ApiKey {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn scopes(&self) -> &HashSet<Scope> {
+        &self.scopes
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    pub fn is_revoked(&self) -> bool {
+        self.revoked_at.is_some()
+    }
+
+    pub fn info(&self) -> ApiKeyInfo {
+        ApiKeyInfo {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            scopes: self.scopes.clone(),
+            limits: self.limits.clone(),
+            created_at: self.created_at,
+            revoked_at: self.revoked_at,
+        }
+    }
+}
+
+impl ApiKeyStore {
+    pub async fn new(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let keys = if path.exists() {
+            let json_str = tokio::fs::read_to_string(path).await?;
+            serde_json::from_str(&json_str)?
+        } else {
+            HashMap::new()
+        };
+
+        Ok(Self {
+            keys,
+            path: path.to_path_buf(),
+        })
+    }
+
+    async fn dump(&self) -> anyhow::Result<()> {
+        let json_str = serde_json::to_string(&self.keys)?;
+        write_atomic(&self.path, json_str).await
+    }
+
+    /// Create a new key, return the stored record and the plain key.
+    /// The plain key is only available here.
+    pub async fn create(
+        &mut self,
+        name: &str,
+        scopes: HashSet<Scope>,
+        limits: Limits,
+    ) -> anyhow::Result<(ApiKey, String)> {
+        let secret = format!(
+            "{}{}{}",
+            KEY_PREFIX,
+            uuid::Uuid::new_v4().simple(),
+            uuid::Uuid::new_v4().simple()
+        );
+        let api_key = ApiKey {
+            id: uuid::Uuid::new_v4().to_string(),
+            name: name.to_string(),
+            key_hash: hash_key(&secret),
+            scopes,
+            limits,
+            created_at: now(),
+            revoked_at: None,
+        };
+
+        self.keys.insert(api_key.id.clone(), api_key.clone());
+        self.dump().await?;
+
+        Ok((api_key, secret))
+    }
+
+    /// Revoke the key with given id. Return `None` if the key does not exist.
+    pub async fn revoke(&mut self, id: &str) -> anyhow::Result<Option<ApiKey>> {
+        let revoked = match self.keys.get_mut(id) {
+            Some(api_key) => {
+                if api_key.revoked_at.is_none() {
+                    api_key.revoked_at = Some(now());
+                }
+                Some(api_key.clone())
+            }
+            None => None,
+        };
+
+        if revoked.is_some() {
+            self.dump().await?;
+        }
+
+        Ok(revoked)
+    }
+
+    pub fn list(&self) -> Vec<&ApiKey> {
+        let mut keys: Vec<&ApiKey> = self.keys.values().collect();
+        keys.sort_by_key(|v| v.created_at);
+        keys
+    }
+
+    /// Find the active key matching the plain key.
+    pub fn verify(&self, key: &str) -> Option<&ApiKey> {
+        if !key.starts_with(KEY_PREFIX) {
+            return None;
+        }
+
+        let key_hash = hash_key(key);
+        self.keys
+            .values()
+            .find(|v| v.key_hash == key_hash && !v.is_revoked())
+    }
+}
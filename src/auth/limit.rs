@@ -0,0 +1,262 @@
+use super::key::now;
+use axum::http::{HeaderMap, HeaderValue};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    time::Instant,
+};
+use thiserror::Error;
+use utoipa::ToSchema;
+
+const SECONDS_PER_DAY: u64 = 60 * 60 * 24;
+
+/// Limits applied to an API key, `None` means unlimited.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct Limits {
+    /// Sustained `POST /workflow` requests per minute.
+    pub requests_per_minute: Option<u32>,
+    /// Maximum requests in a burst, defaults to `requests_per_minute`.
+    pub burst: Option<u32>,
+    /// Maximum pending and running jobs at the same time.
+    pub max_concurrent_jobs: Option<usize>,
+    /// Maximum images (sum of `batch_size`) per UTC day.
+    pub daily_images: Option<u64>,
+    /// Maximum sampling steps (`steps` * `batch_size`) per UTC day.
+    pub daily_steps: Option<u64>,
+}
+
+impl Limits {
+    /// Fill the unset limits with the ones from `default`.
+    pub fn or(&self, default: &Limits) -> Limits {
+        Limits {
+            requests_per_minute: self.requests_per_minute.or(default.requests_per_minute),
+            burst: self.burst.or(default.burst),
+            max_concurrent_jobs: self.max_concurrent_jobs.or(default.max_concurrent_jobs),
+            daily_images: self.daily_images.or(default.daily_images),
+            daily_steps: self.daily_steps.or(default.daily_steps),
+        }
+    }
+}
+
+/// The resources a single job consumes from the daily quotas.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JobCost {
+    pub images: u64,
+    pub steps: u64,
+}
+
+#[derive(Error, Debug)]
+pub enum LimitError {
+    #[error("rate limit exceeded, retry in {retry_after} seconds")]
+    RateLimited {
+        retry_after: u64,
+        headers: HeaderMap,
+    },
+    #[error("too many concurrent jobs (max {max})")]
+    ConcurrentJobs { max: usize },
+    #[error("daily {kind} quota exceeded ({used}/{limit})")]
+    QuotaExceeded {
+        kind: &'static str,
+        used: u64,
+        limit: u64,
+        retry_after: u64,
+    },
+}
+
+impl LimitError {
+    /// Headers that should be sent with the `429` response.
+    pub fn headers(&self) -> HeaderMap {
+        match self {
+            LimitError::RateLimited { headers, .. } => headers.clone(),
+            LimitError::QuotaExceeded { retry_after, .. } => {
+                let mut headers = HeaderMap::new();
+                headers.insert("retry-after", HeaderValue::from(*retry_after));
+                headers
+            }
+            LimitError::ConcurrentJobs { .. } => HeaderMap::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct TokenBucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+#[derive(Clone, Debug)]
+struct KeyUsage {
+    bucket: Option<TokenBucket>,
+    /// Ids of the jobs submitted with this key that may still be pending or running.
+    jobs: HashSet<String>,
+    /// Days since unix epoch of the counters below.
+    day: u64,
+    images: u64,
+    steps: u64,
+}
+
+impl Default for KeyUsage {
+    fn default() -> Self {
+        Self {
+            bucket: None,
+            jobs: HashSet::new(),
+            day: now() / SECONDS_PER_DAY,
+            images: 0,
+            steps: 0,
+        }
+    }
+}
+
+/// The outcome of a successful check, used to build the rate limit headers.
+#[derive(Clone, Debug)]
+pub struct Admission {
+    key_id: String,
+    cost: JobCost,
+    headers: HeaderMap,
+}
+
+impl Admission {
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RateLimiter {
+    usage: HashMap<String, KeyUsage>,
+}
+
+fn seconds_until_tomorrow() -> u64 {
+    SECONDS_PER_DAY - now() % SECONDS_PER_DAY
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check whether a job with `cost` can be submitted with the key.
+    /// `is_active` tells whether a previously submitted job is still pending or running.
+    ///
+    /// Nothing is consumed until `commit` is called with the returned admission,
+    /// so a job rejected for other reasons does not count.
+    pub fn check(
+        &mut self,
+        key_id: &str,
+        limits: &Limits,
+        cost: JobCost,
+        is_active: impl Fn(&str) -> bool,
+    ) -> Result<Admission, LimitError> {
+        let usage = self.usage.entry(key_id.to_string()).or_default();
+
+        // reset daily counters
+        let today = now() / SECONDS_PER_DAY;
+        if usage.day != today {
+            usage.day = today;
+            usage.images = 0;
+            usage.steps = 0;
+        }
+
+        let mut headers = HeaderMap::new();
+
+        if let Some(requests_per_minute) = limits.requests_per_minute {
+            let capacity = limits.burst.unwrap_or(requests_per_minute).max(1) as f64;
+            let refill_per_second = requests_per_minute as f64 / 60.0;
+
+            let bucket = usage.bucket.get_or_insert(TokenBucket {
+                tokens: capacity,
+                updated_at: Instant::now(),
+            });
+            let elapsed = bucket.updated_at.elapsed().as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * refill_per_second).min(capacity);
+            bucket.updated_at = Instant::now();
+
+            let seconds_until = |tokens: f64| {
+                if refill_per_second > 0.0 {
+                    (tokens.max(0.0) / refill_per_second).ceil() as u64
+                } else {
+                    SECONDS_PER_DAY
+                }
+            };
+
+            headers.insert("ratelimit-limit", HeaderValue::from(capacity as u64));
+
+            if bucket.tokens < 1.0 {
+                let retry_after = seconds_until(1.0 - bucket.tokens).max(1);
+                headers.insert("ratelimit-remaining", HeaderValue::from(0));
+                headers.insert("ratelimit-reset", HeaderValue::from(retry_after));
+                headers.insert("retry-after", HeaderValue::from(retry_after));
+
+                return Err(LimitError::RateLimited {
+                    retry_after,
+                    headers,
+                });
+            }
+
+            let remaining = bucket.tokens - 1.0;
+            headers.insert(
+                "ratelimit-remaining",
+                HeaderValue::from(remaining.floor() as u64),
+            );
+            headers.insert(
+                "ratelimit-reset",
+                HeaderValue::from(seconds_until(capacity - remaining)),
+            );
+        }
+
+        usage.jobs.retain(|id| is_active(id));
+        if let Some(max) = limits.max_concurrent_jobs {
+            if usage.jobs.len() >= max {
+                return Err(LimitError::ConcurrentJobs { max });
+            }
+        }
+
+        if let Some(limit) = limits.daily_images {
+            if usage.images + cost.images > limit {
+                return Err(LimitError::QuotaExceeded {
+                    kind: "image",
+                    used: usage.images,
+                    limit,
+                    retry_after: seconds_until_tomorrow(),
+                });
+            }
+        }
+
+        if let Some(limit) = limits.daily_steps {
+            if usage.steps + cost.steps > limit {
+                return Err(LimitError::QuotaExceeded {
+                    kind: "step",
+                    used: usage.steps,
+                    limit,
+                    retry_after: seconds_until_tomorrow(),
+                });
+            }
+        }
+
+        Ok(Admission {
+            key_id: key_id.to_string(),
+            cost,
+            headers,
+        })
+    }
+
+    /// Jobs submitted with the key that were active at the last check.
+    pub fn jobs(&self, key_id: &str) -> Vec<String> {
+        self.usage
+            .get(key_id)
+            .map(|v| v.jobs.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Consume the resources of an admitted job.
+    pub fn commit(&mut self, admission: &Admission, task_id: &str) {
+        let usage = self.usage.entry(admission.key_id.clone()).or_default();
+
+        if let Some(bucket) = usage.bucket.as_mut() {
+            bucket.tokens -= 1.0;
+        }
+        usage.jobs.insert(task_id.to_string());
+        usage.images += admission.cost.images;
+        usage.steps += admission.cost.steps;
+    }
+}
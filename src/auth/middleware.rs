@@ -0,0 +1,81 @@
+use super::Identity;
+use crate::{routes::AppError, state::AppState};
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Request, State},
+    http::{header, request::Parts},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::sync::Arc;
+
+/// Resolve the identity of the request from its `Authorization` header.
+///
+/// - `Basic` is accepted with the admin credentials from config (used by the admin page)
+/// - `Bearer` is accepted with an active API key
+///
+/// The identity is stored in request extensions, handlers read it with the `Identity` extractor.
+pub async fn authenticate(
+    State(state): State<Arc<AppState>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let authorization = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string());
+
+    let identity = match authorization {
+        Some(authorization) => resolve_identity(&state, &authorization).await,
+        None => None,
+    };
+
+    match identity {
+        Some(identity) => {
+            request.extensions_mut().insert(identity);
+            next.run(request).await
+        }
+        None => AppError::Unauthorized.into_response(),
+    }
+}
+
+async fn resolve_identity(state: &AppState, authorization: &str) -> Option<Identity> {
+    let (scheme, credentials) = authorization.split_once(' ')?;
+    let credentials = credentials.trim();
+
+    if scheme.eq_ignore_ascii_case("basic") {
+        let config = state.config();
+        let config = &config.read().await.auth;
+        let expected = STANDARD.encode(format!("{}:{}", config.username, config.password));
+        (credentials == expected).then_some(Identity::Admin)
+    } else if scheme.eq_ignore_ascii_case("bearer") {
+        let api_keys = state.api_keys();
+        let api_keys = api_keys.read().await;
+        api_keys.verify(credentials).map(|v| Identity::ApiKey {
+            id: v.id().to_string(),
+            name: v.name().to_string(),
+            scopes: v.scopes().clone(),
+            limits: v.limits().clone(),
+        })
+    } else {
+        None
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Identity
+where
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<Identity>()
+            .cloned()
+            .ok_or(AppError::Unauthorized)
+    }
+}
@@ -0,0 +1,95 @@
+pub mod key;
+pub mod limit;
+pub mod middleware;
+pub mod token;
+
+use limit::Limits;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, fmt};
+use thiserror::Error;
+use utoipa::ToSchema;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Hash, ToSchema)]
+pub enum Scope {
+    /// Submit workflows.
+    #[serde(rename = "workflow:run")]
+    WorkflowRun,
+    /// Read workflow results.
+    #[serde(rename = "workflow:read")]
+    WorkflowRead,
+    /// Manage nodes and API keys.
+    #[serde(rename = "cluster:admin")]
+    ClusterAdmin,
+}
+
+impl Scope {
+    pub fn all() -> HashSet<Scope> {
+        HashSet::from([Scope::WorkflowRun, Scope::WorkflowRead, Scope::ClusterAdmin])
+    }
+}
+
+/// The authenticated caller of a request.
+#[derive(Clone, Debug)]
+pub enum Identity {
+    /// Authenticated with the Basic auth credentials from config, has all scopes.
+    Admin,
+    ApiKey {
+        id: String,
+        name: String,
+        scopes: HashSet<Scope>,
+        limits: Limits,
+    },
+}
+
+#[derive(Error, Debug)]
+pub enum AuthError {
+    #[error("missing scope {0:?}")]
+    MissingScope(Scope),
+}
+
+impl Identity {
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        match self {
+            Identity::Admin => true,
+            Identity::ApiKey { scopes, .. } => scopes.contains(&scope),
+        }
+    }
+
+    pub fn require(&self, scope: Scope) -> Result<(), AuthError> {
+        if self.has_scope(scope) {
+            Ok(())
+        } else {
+            Err(AuthError::MissingScope(scope))
+        }
+    }
+
+    /// Stable id of the identity, used to record the owner of tasks.
+    pub fn id(&self) -> &str {
+        match self {
+            Identity::Admin => "admin",
+            Identity::ApiKey { id, .. } => id,
+        }
+    }
+
+    /// Whether the identity can access resources owned by `owner`.
+    /// Admins (`cluster:admin`) can access everything.
+    pub fn can_access(&self, owner: &str) -> bool {
+        self.has_scope(Scope::ClusterAdmin) || self.id() == owner
+    }
+
+    pub fn scopes(&self) -> HashSet<Scope> {
+        match self {
+            Identity::Admin => Scope::all(),
+            Identity::ApiKey { scopes, .. } => scopes.clone(),
+        }
+    }
+}
+
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Identity::Admin => write!(f, "admin"),
+            Identity::ApiKey { id, name, .. } => write!(f, "key {} ({})", name, id),
+        }
+    }
+}
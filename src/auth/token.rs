@@ -0,0 +1,90 @@
+use super::key::now;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use thiserror::Error;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Error, Debug)]
+pub enum TokenError {
+    #[error("missing preview token")]
+    Missing,
+    #[error("invalid preview token")]
+    Invalid,
+    #[error("preview token expired")]
+    Expired,
+}
+
+/// Signs short-lived tokens that grant access to the preview of a single task.
+///
+/// A token is `<expires_at>.<hex encoded HMAC-SHA256 of task id and expires_at>`,
+/// so it can be verified without storing anything.
+#[derive(Clone)]
+pub struct PreviewSigner {
+    secret: Vec<u8>,
+    ttl: u64,
+}
+
+impl std::fmt::Debug for PreviewSigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PreviewSigner")
+            .field("ttl", &self.ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PreviewSigner {
+    /// Create a signer with the secret, a random one is used if empty.
+    /// With a random secret, tokens will not survive a restart.
+    pub fn new(secret: &str, ttl: u64) -> Self {
+        let secret = if secret.is_empty() {
+            format!(
+                "{}{}",
+                uuid::Uuid::new_v4().simple(),
+                uuid::Uuid::new_v4().simple()
+            )
+        } else {
+            secret.to_string()
+        };
+
+        Self {
+            secret: secret.into_bytes(),
+            ttl,
+        }
+    }
+
+    fn mac(&self, task_id: &str, expires_at: u64) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.secret).expect("HMAC can take key of any size");
+        mac.update(format!("preview:{}:{}", task_id, expires_at).as_bytes());
+        mac
+    }
+
+    /// Return the token for the task and the unix timestamp (seconds) it expires at.
+    pub fn sign(&self, task_id: &str) -> (String, u64) {
+        let expires_at = now() + self.ttl;
+        let signature = self.mac(task_id, expires_at).finalize().into_bytes();
+
+        (
+            format!("{}.{}", expires_at, hex::encode(signature)),
+            expires_at,
+        )
+    }
+
+    pub fn verify(&self, task_id: &str, token: Option<&str>) -> Result<(), TokenError> {
+        let token = token.ok_or(TokenError::Missing)?;
+        let (expires_at, signature) = token.split_once('.').ok_or(TokenError::Invalid)?;
+        let expires_at: u64 = expires_at.parse().map_err(|_| TokenError::Invalid)?;
+        let signature = hex::decode(signature).map_err(|_| TokenError::Invalid)?;
+
+        self.mac(task_id, expires_at)
+            .verify_slice(&signature)
+            .map_err(|_| TokenError::Invalid)?;
+
+        if expires_at <= now() {
+            return Err(TokenError::Expired);
+        }
+
+        Ok(())
+    }
+}
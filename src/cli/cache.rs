@@ -0,0 +1,287 @@
+//! Offline commands on the download record, the router should be stopped
+//! since it keeps its own copy of the record in memory.
+
+use crate::{
+    config::AppConfig,
+    download::{
+        state::DownloadState,
+        task::{discard_partial, file_sha256, DownloadStatus, DownloadTask, Expected},
+    },
+};
+use serde::Serialize;
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Serialize)]
+struct CacheEntry {
+    file_id: String,
+    status: DownloadStatus,
+    url: String,
+    /// Size of the cached file, `None` if it is missing.
+    size: Option<u64>,
+    /// SHA-256 recorded when the file was downloaded.
+    sha256: Option<String>,
+    target_dirs: Vec<PathBuf>,
+}
+
+/// A difference between the record and the files on disk.
+#[derive(Debug)]
+enum Problem {
+    /// A completed download without its file in the cache dir.
+    MissingFile { file_id: String },
+    /// A completed download whose file is not the recorded size and hash.
+    Corrupted { file_id: String, reason: String },
+    /// A link in a target dir that is missing or does not point to the cached file.
+    BrokenLink { file_id: String, link: PathBuf },
+    /// A file in the cache dir that is not in the record, e.g. an interrupted download.
+    Orphan { path: PathBuf },
+}
+
+async fn load(config: &AppConfig) -> anyhow::Result<DownloadState> {
+    DownloadState::new(
+        &config.storage.record_path,
+        &config.download.root_dir,
+        &config.download.cache_dir,
+        config.download.max_cache_bytes,
+    )
+    .await
+}
+
+async fn file_size(path: &Path) -> Option<u64> {
+    tokio::fs::metadata(path)
+        .await
+        .ok()
+        .filter(|v| v.is_file())
+        .map(|v| v.len())
+}
+
+fn sorted_downloads(state: &DownloadState) -> Vec<DownloadTask> {
+    let mut downloads: Vec<_> = state.downloads().cloned().collect();
+    downloads.sort_by(|a, b| a.file_id().cmp(b.file_id()));
+    downloads
+}
+
+fn target_dirs(state: &DownloadState, file_id: &str) -> Vec<PathBuf> {
+    let mut target_dirs: Vec<_> = state
+        .target_dirs(file_id)
+        .map(|v| v.iter().cloned().collect())
+        .unwrap_or_default();
+    target_dirs.sort();
+    target_dirs
+}
+
+/// List the downloads in the record.
+pub async fn ls(config: &AppConfig, json: bool) -> anyhow::Result<()> {
+    let state = load(config).await?;
+
+    let mut entries = vec![];
+    for task in sorted_downloads(&state) {
+        entries.push(CacheEntry {
+            file_id: task.file_id().to_string(),
+            status: task.status().clone(),
+            url: task.url().to_string(),
+            size: file_size(&state.cache_path(&task)).await,
+            sha256: task.sha256().map(|v| v.to_string()),
+            target_dirs: target_dirs(&state, task.file_id()),
+        });
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+        return Ok(());
+    }
+
+    println!("{:<42} {:<9} {:>12}  URL", "FILE_ID", "STATUS", "SIZE");
+    for entry in entries {
+        println!(
+            "{:<42} {:<9} {:>12}  {}",
+            entry.file_id,
+            entry.status.as_str(),
+            entry
+                .size
+                .map(|v| v.to_string())
+                .unwrap_or_else(|| "-".into()),
+            entry.url
+        );
+    }
+
+    Ok(())
+}
+
+/// Delete downloads, their cached files and links, and the record entries.
+pub async fn purge(
+    config: &AppConfig,
+    file_ids: &[String],
+    failed: bool,
+    all: bool,
+) -> anyhow::Result<()> {
+    let mut state = load(config).await?;
+
+    for file_id in file_ids {
+        if state.get_by_id(file_id).is_none() {
+            anyhow::bail!("{} is not in the record", file_id);
+        }
+    }
+
+    let selected: Vec<String> = sorted_downloads(&state)
+        .into_iter()
+        .filter(|task| {
+            all || (failed && task.status() == &DownloadStatus::Failed)
+                || file_ids.iter().any(|v| v == task.file_id())
+        })
+        .map(|task| task.file_id().to_string())
+        .collect();
+
+    for file_id in selected {
+        // take target dirs before `remove`, which drops them from the record
+        let target_dirs = state.remove_target_dirs(&file_id).unwrap_or_default();
+        for target_dir in target_dirs {
+            let _ = tokio::fs::remove_file(state.root_dir().join(target_dir).join(&file_id)).await;
+        }
+        // the file is kept for the downloads of the same content from other URLs
+        if let Some(task) = state
+            .get_by_id(&file_id)
+            .filter(|_| !state.is_shared(&file_id))
+        {
+            let _ = tokio::fs::remove_file(state.cache_path(task)).await;
+        }
+        discard_partial(state.cache_dir().join(&file_id)).await;
+        state.remove(&file_id);
+
+        println!("purged {}", file_id);
+    }
+    state.save().await?;
+
+    Ok(())
+}
+
+async fn find_problems(state: &DownloadState, hashes: bool) -> anyhow::Result<Vec<Problem>> {
+    let mut problems = vec![];
+    let mut known = HashSet::new();
+
+    for task in sorted_downloads(state) {
+        let file_id = task.file_id().to_string();
+        let cache_path = state.cache_path(&task);
+        if let Some(file_name) = cache_path.file_name() {
+            known.insert(file_name.to_string_lossy().into_owned());
+        }
+
+        if task.status() != &DownloadStatus::Completed {
+            continue;
+        }
+        let Some(size) = file_size(&cache_path).await else {
+            problems.push(Problem::MissingFile { file_id });
+            continue;
+        };
+        if let (true, Some(recorded)) = (hashes, task.sha256()) {
+            let expected = Expected {
+                sha256: Some(recorded.to_string()),
+                size: task.size(),
+            };
+            if let Err(e) = expected.check(size, &file_sha256(&cache_path).await?) {
+                problems.push(Problem::Corrupted {
+                    file_id,
+                    reason: e.to_string(),
+                });
+                continue;
+            }
+        }
+
+        for target_dir in target_dirs(state, &file_id) {
+            let link = state.root_dir().join(target_dir).join(&file_id);
+            match tokio::fs::read_link(&link).await {
+                Ok(target) if target == cache_path => {}
+                _ => problems.push(Problem::BrokenLink {
+                    file_id: file_id.clone(),
+                    link,
+                }),
+            }
+        }
+    }
+
+    let mut read_dir = match tokio::fs::read_dir(state.cache_dir()).await {
+        Ok(read_dir) => read_dir,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(problems),
+        Err(e) => return Err(e.into()),
+    };
+    let mut orphans = vec![];
+    while let Some(entry) = read_dir.next_entry().await? {
+        if !known.contains(entry.file_name().to_string_lossy().as_ref()) {
+            orphans.push(entry.path());
+        }
+    }
+    orphans.sort();
+    problems.extend(orphans.into_iter().map(|path| Problem::Orphan { path }));
+
+    Ok(problems)
+}
+
+/// Compare the record with the files on disk, return whether they match.
+/// With `hashes`, files are also hashed and compared with their recorded size and hash.
+///
+/// With `fix`, records of missing or corrupted files and orphan files are deleted,
+/// and links are recreated.
+pub async fn verify(config: &AppConfig, hashes: bool, fix: bool) -> anyhow::Result<bool> {
+    let mut state = load(config).await?;
+    let problems = find_problems(&state, hashes).await?;
+
+    for problem in &problems {
+        match problem {
+            Problem::MissingFile { file_id } => {
+                println!("missing file   {}", file_id);
+                if fix {
+                    for target_dir in state.remove_target_dirs(file_id).unwrap_or_default() {
+                        let link = state.root_dir().join(target_dir).join(file_id);
+                        let _ = tokio::fs::remove_file(link).await;
+                    }
+                    state.remove(file_id);
+                }
+            }
+            Problem::Corrupted { file_id, reason } => {
+                println!("corrupted file {} ({})", file_id, reason);
+                if fix {
+                    for target_dir in state.remove_target_dirs(file_id).unwrap_or_default() {
+                        let link = state.root_dir().join(target_dir).join(file_id);
+                        let _ = tokio::fs::remove_file(link).await;
+                    }
+                    // the other downloads of the file are reported as corrupted as well
+                    let cache_path = state.get_by_id(file_id).map(|v| state.cache_path(v));
+                    if let Some(cache_path) = cache_path {
+                        let _ = tokio::fs::remove_file(cache_path).await;
+                    }
+                    state.remove(file_id);
+                }
+            }
+            Problem::BrokenLink { file_id, link } => {
+                println!("broken link    {} -> {}", link.display(), file_id);
+                if fix {
+                    if let Some(parent) = link.parent() {
+                        tokio::fs::create_dir_all(parent).await?;
+                    }
+                    let _ = tokio::fs::remove_file(link).await;
+                    let cache_path = state.get_by_id(file_id).map(|v| state.cache_path(v));
+                    if let Some(cache_path) = cache_path {
+                        tokio::fs::symlink(cache_path, link).await?;
+                    }
+                }
+            }
+            Problem::Orphan { path } => {
+                println!("orphan file    {}", path.display());
+                if fix {
+                    tokio::fs::remove_file(path).await?;
+                }
+            }
+        }
+    }
+    state.save().await?;
+
+    match (problems.len(), fix) {
+        (0, _) => println!("record and cache match"),
+        (count, true) => println!("fixed {} problems", count),
+        (count, false) => println!("{} problems, run with --fix to repair", count),
+    }
+
+    Ok(problems.is_empty() || fix)
+}
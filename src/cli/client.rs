@@ -0,0 +1,229 @@
+//! Commands against a running router, through its HTTP API.
+
+use super::ClientArgs;
+use crate::{cluster::NodeOptions, config::AppConfig};
+use reqwest::{Client, Method, RequestBuilder, Response};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use url::Url;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Deserialize)]
+struct NodesResponse {
+    nodes: Vec<NodeResponse>,
+}
+
+#[derive(Debug, Deserialize)]
+struct NodeResponse {
+    url: Url,
+    status: NodeStatus,
+}
+
+#[derive(Debug, Deserialize)]
+struct NodeStatus {
+    status: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubmitResponse {
+    id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    message: String,
+}
+
+#[derive(Debug)]
+enum Credentials {
+    Basic { username: String, password: String },
+    Bearer(String),
+}
+
+/// Client of the router API, authenticated with an API key or the admin credentials of the config.
+#[derive(Debug)]
+pub struct RouterClient {
+    client: Client,
+    url: Url,
+    credentials: Credentials,
+}
+
+impl RouterClient {
+    /// The router in `args`, or the one the config listens on.
+    pub fn new(args: &ClientArgs, config: &AppConfig) -> anyhow::Result<Self> {
+        let url = match &args.router {
+            Some(url) => url.clone(),
+            None => {
+                // listening on all interfaces, reach it on the loopback
+                let host = match config.host.as_str() {
+                    "0.0.0.0" => "127.0.0.1",
+                    "::" => "[::1]",
+                    host => host,
+                };
+                let scheme = match config.tls.cert_path {
+                    Some(_) => "https",
+                    None => "http",
+                };
+                Url::parse(&format!("{}://{}:{}", scheme, host, config.port))?
+            }
+        };
+
+        let credentials = match &args.api_key {
+            Some(key) => Credentials::Bearer(key.clone()),
+            None => Credentials::Basic {
+                username: config.auth.username.clone(),
+                password: config.auth.password.clone(),
+            },
+        };
+
+        let mut client = Client::builder();
+        if let Some(ca_cert) = &args.ca_cert {
+            let pem = std::fs::read(ca_cert)
+                .map_err(|e| anyhow::anyhow!("failed to read {}: {}", ca_cert.display(), e))?;
+            client = client.add_root_certificate(reqwest::Certificate::from_pem(&pem)?);
+        }
+
+        Ok(Self {
+            client: client.build()?,
+            url,
+            credentials,
+        })
+    }
+
+    fn request(&self, method: Method, path: &str) -> anyhow::Result<RequestBuilder> {
+        let request = self.client.request(method, self.url.join(path)?);
+
+        Ok(match &self.credentials {
+            Credentials::Basic { username, password } => {
+                request.basic_auth(username, Some(password))
+            }
+            Credentials::Bearer(key) => request.bearer_auth(key),
+        })
+    }
+
+    /// Send the request, turning error responses into errors with the router's message.
+    async fn send<T: DeserializeOwned>(&self, request: RequestBuilder) -> anyhow::Result<T> {
+        let response: Response = request.send().await?;
+        let status = response.status();
+
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            let message = serde_json::from_str::<ErrorResponse>(&body)
+                .map(|v| v.message)
+                .unwrap_or(body);
+            anyhow::bail!("{}: {}", status, message);
+        }
+
+        Ok(response.json().await?)
+    }
+
+    pub async fn add_node(&self, url: &Url, options: &NodeOptions) -> anyhow::Result<()> {
+        let request = self.request(Method::POST, "/cluster/nodes")?.json(&json!({
+            "url": url,
+            "headers": options.headers,
+            "auth": options.auth,
+            "storage": options.storage,
+        }));
+        self.send::<Value>(request).await?;
+
+        println!("added {}", url);
+        Ok(())
+    }
+
+    pub async fn remove_node(&self, url: &Url) -> anyhow::Result<()> {
+        let request = self
+            .request(Method::POST, "/cluster/nodes/delete")?
+            .json(&json!({ "url": url }));
+        self.send::<Value>(request).await?;
+
+        println!("removed {}", url);
+        Ok(())
+    }
+
+    pub async fn list_nodes(&self, json: bool) -> anyhow::Result<()> {
+        let request = self.request(Method::GET, "/cluster/nodes")?;
+
+        if json {
+            let nodes: Value = self.send(request).await?;
+            println!("{}", serde_json::to_string_pretty(&nodes["nodes"])?);
+            return Ok(());
+        }
+
+        let mut nodes = self.send::<NodesResponse>(request).await?.nodes;
+        nodes.sort_by(|a, b| a.url.cmp(&b.url));
+        println!("{:<8} URL", "STATUS");
+        for node in nodes {
+            println!("{:<8} {}", node.status.status, node.url);
+        }
+
+        Ok(())
+    }
+
+    /// Submit the workflow in `file`, wait for it and save the images into `output`.
+    /// Return the paths of the saved images.
+    pub async fn submit(
+        &self,
+        file: &Path,
+        output: &Path,
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<Vec<PathBuf>> {
+        let payload: Value = serde_json::from_str(&tokio::fs::read_to_string(file).await?)
+            .map_err(|e| anyhow::anyhow!("invalid workflow {}: {}", file.display(), e))?;
+
+        let request = self.request(Method::POST, "/workflow")?.json(&payload);
+        let id = self.send::<SubmitResponse>(request).await?.id;
+        eprintln!("submitted {}", id);
+
+        let started_at = Instant::now();
+        let mut last_status = String::new();
+        let images = loop {
+            let request = self.request(Method::GET, &format!("/workflow/{}", id))?;
+            let result: Value = self.send(request).await?;
+
+            let status = result["status"].as_str().unwrap_or_default().to_string();
+            match status.as_str() {
+                "done" => break serde_json::from_value::<Vec<Vec<u8>>>(result["data"].clone())?,
+                "error" => anyhow::bail!("workflow {} failed: {}", id, result["data"]),
+                "downloading" => {
+                    let downloads = result["data"].as_array().cloned().unwrap_or_default();
+                    let bytes = |key: &str| -> u64 {
+                        downloads.iter().filter_map(|v| v[key].as_u64()).sum()
+                    };
+                    eprintln!(
+                        "downloading {} file(s), {}/{} bytes",
+                        downloads.len(),
+                        bytes("downloaded"),
+                        bytes("total")
+                    );
+                }
+                "running" => {
+                    let progress = result["data"]["progress"].as_f64().unwrap_or_default();
+                    eprintln!("running {:.0}%", progress * 100.0);
+                }
+                _ if status != last_status => eprintln!("{}", status),
+                _ => {}
+            }
+            last_status = status;
+
+            if timeout.is_some_and(|v| started_at.elapsed() > v) {
+                anyhow::bail!("timed out waiting for workflow {}", id);
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        };
+
+        tokio::fs::create_dir_all(output).await?;
+        let mut paths = vec![];
+        for (index, image) in images.iter().enumerate() {
+            let path = output.join(format!("{}-{}.png", id, index));
+            tokio::fs::write(&path, image).await?;
+            paths.push(path);
+        }
+
+        Ok(paths)
+    }
+}
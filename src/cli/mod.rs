@@ -0,0 +1,307 @@
+pub mod cache;
+pub mod client;
+
+use crate::{
+    agent,
+    cluster::{NodeAuth, NodeOptions, NodeStorage},
+    config::{AppConfig, ConfigSource},
+    telemetry,
+};
+use clap::{Args, Parser, Subcommand};
+use client::RouterClient;
+use std::{net::SocketAddr, path::PathBuf, process::ExitCode, time::Duration};
+use tokio::net::TcpListener;
+use url::Url;
+
+/// Route ComfyUI workflows to a cluster of nodes.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Config file in TOML or YAML, overridden by `COMFY_ROUTER__*` environment variables and flags.
+    #[arg(short, long, env = "COMFY_ROUTER_CONFIG", global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Host to listen on.
+    #[arg(long, global = true)]
+    pub host: Option<String>,
+
+    /// Port to listen on.
+    #[arg(long, global = true)]
+    pub port: Option<u16>,
+
+    /// Set a config key, e.g. `--set download.max_cache_bytes=1073741824`, can be repeated.
+    #[arg(long = "set", value_name = "KEY=VALUE", value_parser = parse_override, global = true)]
+    pub overrides: Vec<(String, String)>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+#[allow(clippy::large_enum_variant)]
+pub enum Command {
+    /// Start the router, the default command.
+    Serve,
+    /// Check the config and print it with secrets redacted.
+    CheckConfig,
+    /// Manage the nodes of a running router.
+    Nodes {
+        #[command(flatten)]
+        client: ClientArgs,
+        #[command(subcommand)]
+        command: NodesCommand,
+    },
+    /// Submit a workflow JSON file to a running router, wait for it and save the images.
+    Submit {
+        #[command(flatten)]
+        client: ClientArgs,
+        /// Payload of `POST /workflow`.
+        file: PathBuf,
+        /// Directory to save the images in, as `<task id>-<index>.png`.
+        #[arg(short, long, default_value = ".")]
+        output: PathBuf,
+        /// Give up after this many seconds.
+        #[arg(long)]
+        timeout: Option<u64>,
+    },
+    /// Run the agent of a node with its own disk, storing the models pushed by the router
+    /// under the ComfyUI root of the node.
+    Agent {
+        /// ComfyUI root of the node, the folder containing `models`.
+        #[arg(long)]
+        root: PathBuf,
+        /// Address to listen on.
+        #[arg(long, default_value = "0.0.0.0:8189")]
+        listen: SocketAddr,
+        /// Bearer token requests must carry, e.g. the token of the node in the router.
+        #[arg(long, env = "COMFY_ROUTER_AGENT_TOKEN", hide_env_values = true)]
+        token: Option<String>,
+    },
+    /// Inspect and clean the download cache offline, from `storage.record_path`.
+    /// Stop the router first, it does not see changes made meanwhile.
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+}
+
+/// The router to send requests to.
+#[derive(Debug, Args)]
+pub struct ClientArgs {
+    /// URL of the router, defaults to the host and port of the config.
+    #[arg(long, env = "COMFY_ROUTER_URL", global = true)]
+    pub router: Option<Url>,
+
+    /// API key with the needed scopes, the admin credentials of the config are used if unset.
+    #[arg(
+        long,
+        env = "COMFY_ROUTER_API_KEY",
+        hide_env_values = true,
+        global = true
+    )]
+    pub api_key: Option<String>,
+
+    /// PEM CA certificate to trust for an HTTPS router, e.g. a self-signed one.
+    #[arg(long, env = "COMFY_ROUTER_CA_CERT", global = true)]
+    pub ca_cert: Option<PathBuf>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum NodesCommand {
+    /// Add a ComfyUI node, or replace the headers, credentials and storage of a node.
+    Add {
+        /// Base URL of ComfyUI, with its path prefix if behind a reverse proxy.
+        url: Url,
+        /// Header sent with every request to the node, can be repeated.
+        #[arg(long = "header", value_name = "NAME:VALUE", value_parser = parse_header)]
+        headers: Vec<(String, String)>,
+        /// Basic Authentication credentials of the node.
+        #[arg(
+            long,
+            value_name = "USERNAME:PASSWORD",
+            conflicts_with = "bearer_token"
+        )]
+        basic_auth: Option<String>,
+        /// Bearer token of the node.
+        #[arg(long)]
+        bearer_token: Option<String>,
+        /// The node has its own disk instead of the root dir of the router,
+        /// files are pushed to it before the workflows using them.
+        #[arg(long)]
+        remote: bool,
+        /// Base URL of the agent storing models on a remote node, implies `--remote`.
+        #[arg(long)]
+        agent: Option<Url>,
+        /// Token of the agent, sent instead of the credentials of the node.
+        #[arg(long, requires = "agent")]
+        agent_token: Option<String>,
+    },
+    /// List nodes and their status.
+    List {
+        #[arg(long)]
+        json: bool,
+    },
+    /// Remove a ComfyUI node.
+    Remove { url: Url },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CacheCommand {
+    /// List downloads in the record.
+    Ls {
+        #[arg(long)]
+        json: bool,
+    },
+    /// Delete downloads with their files and links.
+    #[command(group = clap::ArgGroup::new("selection").required(true).multiple(true))]
+    Purge {
+        /// Ids of the downloads, as shown by `cache ls`.
+        #[arg(group = "selection")]
+        file_ids: Vec<String>,
+        /// Delete failed downloads.
+        #[arg(long, group = "selection")]
+        failed: bool,
+        /// Delete everything.
+        #[arg(long, group = "selection")]
+        all: bool,
+    },
+    /// Compare the record with the cache dir and links, exit with 1 on mismatch.
+    Verify {
+        /// Also hash the cached files and compare them with the recorded size and hash.
+        #[arg(long)]
+        hashes: bool,
+        /// Delete records of missing or corrupted files and unknown files, recreate links.
+        #[arg(long)]
+        fix: bool,
+    },
+}
+
+fn parse_header(value: &str) -> Result<(String, String), String> {
+    value
+        .split_once(':')
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .ok_or_else(|| format!("expected NAME:VALUE, got {}", value))
+}
+
+fn parse_override(value: &str) -> Result<(String, String), String> {
+    value
+        .split_once('=')
+        .map(|(key, value)| (key.trim().to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected KEY=VALUE, got {}", value))
+}
+
+impl Cli {
+    /// The config file and the flags overriding it, see `AppConfig::load`.
+    pub fn config_source(&self) -> ConfigSource {
+        let mut overrides = self.overrides.clone();
+        if let Some(host) = &self.host {
+            overrides.push(("host".into(), host.clone()));
+        }
+        if let Some(port) = self.port {
+            overrides.push(("port".into(), port.to_string()));
+        }
+
+        ConfigSource {
+            file: self.config.clone(),
+            overrides,
+        }
+    }
+}
+
+/// Run a command other than `serve`.
+pub async fn run(command: Command, config: &AppConfig) -> anyhow::Result<ExitCode> {
+    match command {
+        Command::Serve => unreachable!("serve is run by main"),
+        Command::CheckConfig => {
+            print!("{}", config.to_redacted_toml()?);
+        }
+        Command::Nodes { client, command } => {
+            let client = RouterClient::new(&client, config)?;
+            match command {
+                NodesCommand::Add {
+                    url,
+                    headers,
+                    basic_auth,
+                    bearer_token,
+                    remote,
+                    agent,
+                    agent_token,
+                } => {
+                    let auth = match (basic_auth, bearer_token) {
+                        (Some(credentials), _) => {
+                            let (username, password) =
+                                credentials.split_once(':').ok_or_else(|| {
+                                    anyhow::anyhow!("expected USERNAME:PASSWORD for --basic-auth")
+                                })?;
+                            Some(NodeAuth::Basic {
+                                username: username.into(),
+                                password: password.into(),
+                            })
+                        }
+                        (None, Some(token)) => Some(NodeAuth::Bearer { token }),
+                        (None, None) => None,
+                    };
+                    let storage = match (remote, agent) {
+                        (_, Some(agent)) => NodeStorage::Remote {
+                            agent: Some(agent),
+                            token: agent_token,
+                        },
+                        (true, None) => NodeStorage::Remote {
+                            agent: None,
+                            token: None,
+                        },
+                        (false, None) => NodeStorage::Shared,
+                    };
+                    let options = NodeOptions {
+                        headers: headers.into_iter().collect(),
+                        auth,
+                        storage,
+                    };
+                    client.add_node(&url, &options).await?
+                }
+                NodesCommand::List { json } => client.list_nodes(json).await?,
+                NodesCommand::Remove { url } => client.remove_node(&url).await?,
+            }
+        }
+        Command::Submit {
+            client,
+            file,
+            output,
+            timeout,
+        } => {
+            let client = RouterClient::new(&client, config)?;
+            let paths = client
+                .submit(&file, &output, timeout.map(Duration::from_secs))
+                .await?;
+            for path in paths {
+                println!("{}", path.display());
+            }
+        }
+        Command::Agent {
+            root,
+            listen,
+            token,
+        } => {
+            let telemetry = telemetry::init(config)?;
+            let listener = TcpListener::bind(listen).await?;
+            let result = agent::serve(listener, root, token).await;
+            telemetry.shutdown().await;
+            result?;
+        }
+        Command::Cache { command } => match command {
+            CacheCommand::Ls { json } => cache::ls(config, json).await?,
+            CacheCommand::Purge {
+                file_ids,
+                failed,
+                all,
+            } => cache::purge(config, &file_ids, failed, all).await?,
+            CacheCommand::Verify { hashes, fix } => {
+                if !cache::verify(config, hashes, fix).await? {
+                    return Ok(ExitCode::FAILURE);
+                }
+            }
+        },
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
@@ -0,0 +1,143 @@
+use super::{Node, NodeAuth};
+use crate::{
+    config::{HttpConfig, NodeTlsConfig},
+    http::HttpClient,
+    tls,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use reqwest::{
+    header::{HeaderValue, AUTHORIZATION},
+    Method, RequestBuilder, Response,
+};
+use rustls::ClientConfig;
+use std::{sync::Arc, time::Duration};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    connect_async_tls_with_config,
+    tungstenite::{self, client::IntoClientRequest},
+    Connector, MaybeTlsStream, WebSocketStream,
+};
+use url::Url;
+
+/// Client for the HTTP and websocket connections to nodes.
+///
+/// Requests go to paths under the node URL and carry the headers and credentials of the node,
+/// `https` nodes are reached over TLS configured by `node_tls`.
+#[derive(Clone, Debug)]
+pub struct NodeClient {
+    http: HttpClient,
+    tls: Arc<ClientConfig>,
+    connect_timeout: Duration,
+}
+
+impl NodeClient {
+    pub fn new(tls_config: &NodeTlsConfig, http_config: &HttpConfig) -> anyhow::Result<Self> {
+        let tls = tls::node_client_config(tls_config)?;
+
+        // websockets are upgraded from HTTP/1.1, only plain requests may use HTTP/2
+        let mut http_tls = tls.clone();
+        http_tls.alpn_protocols = tls::ALPN_PROTOCOLS.iter().map(|v| v.to_vec()).collect();
+
+        Ok(Self {
+            http: HttpClient::node(http_config, http_tls)?,
+            tls: Arc::new(tls),
+            connect_timeout: Duration::from_secs(http_config.node_timeout),
+        })
+    }
+
+    /// Request to `path` of the node, e.g. `/prompt`.
+    pub fn request(&self, method: Method, node: &Node, path: &str) -> RequestBuilder {
+        let auth = node.options.auth.as_ref();
+        self.request_to(method, node, node.endpoint(path), auth)
+    }
+
+    /// Request to `path` of the agent of the node, with the same headers and the credentials
+    /// of `Node::agent_auth`, `None` if the node has no agent.
+    pub fn agent_request(&self, method: Method, node: &Node, path: &str) -> Option<RequestBuilder> {
+        let url = node.agent_endpoint(path)?;
+        Some(self.request_to(method, node, url, node.agent_auth().as_ref()))
+    }
+
+    fn request_to(
+        &self,
+        method: Method,
+        node: &Node,
+        url: Url,
+        auth: Option<&NodeAuth>,
+    ) -> RequestBuilder {
+        let request = self
+            .http
+            .request(method, url)
+            .headers(node.options.header_map());
+
+        match auth {
+            Some(NodeAuth::Basic { username, password }) => {
+                request.basic_auth(username, Some(password))
+            }
+            Some(NodeAuth::Bearer { token }) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    /// Send a request built by `request`, with retries, see `HttpClient::send`.
+    pub async fn send(&self, request: RequestBuilder) -> reqwest::Result<Response> {
+        self.http.send(request).await
+    }
+
+    /// Open a websocket to `path` of the node, over TLS for `https` nodes.
+    pub async fn connect_websocket(
+        &self,
+        node: &Node,
+        path: &str,
+        query: Option<&str>,
+    ) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>, tungstenite::Error> {
+        let mut url = websocket_url(&node.endpoint(path));
+        url.set_query(query);
+
+        let mut request = url.as_str().into_client_request()?;
+        let headers = request.headers_mut();
+        headers.extend(node.options.header_map());
+        let authorization = match &node.options.auth {
+            Some(NodeAuth::Basic { username, password }) => Some(format!(
+                "Basic {}",
+                STANDARD.encode(format!("{}:{}", username, password))
+            )),
+            Some(NodeAuth::Bearer { token }) => Some(format!("Bearer {}", token)),
+            None => None,
+        };
+        if let Some(authorization) = authorization {
+            let value = HeaderValue::try_from(authorization)
+                .map_err(|e| tungstenite::Error::HttpFormat(e.into()))?;
+            headers.insert(AUTHORIZATION, value);
+        }
+
+        let connect = connect_async_tls_with_config(
+            request,
+            None,
+            false,
+            Some(Connector::Rustls(self.tls.clone())),
+        );
+        let (stream, _) = tokio::time::timeout(self.connect_timeout, connect)
+            .await
+            .map_err(|_| {
+                tungstenite::Error::Io(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "websocket connection timed out",
+                ))
+            })??;
+
+        Ok(stream)
+    }
+}
+
+/// `wss` for `https` URLs and `ws` otherwise.
+fn websocket_url(url: &Url) -> Url {
+    let mut url = url.clone();
+    let scheme = match url.scheme() {
+        "https" | "wss" => "wss",
+        _ => "ws",
+    };
+    // http(s) and ws(s) are all special schemes, switching between them is allowed
+    let _ = url.set_scheme(scheme);
+    url
+}
@@ -0,0 +1,116 @@
+//! Pushing the files of workflows to nodes with their own storage.
+
+use super::{client::NodeClient, Node, NodeStorage};
+use crate::state::AppState;
+use anyhow::Context;
+use reqwest::{
+    header::CONTENT_LENGTH,
+    multipart::{Form, Part},
+    Body, Method,
+};
+use std::{path::Path, time::Duration};
+
+/// Timeout of a push, uploading a model takes much longer than `http.node_timeout`.
+const PUSH_TIMEOUT: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// Folder of the inputs, pushed with `/upload/image` of ComfyUI rather than to the agent.
+const INPUT_DIR: &str = "input";
+
+/// Push `files`, paths under the root dir, to `node` unless it reads the root dir of the router.
+/// The files pushed before are checked to still be on the node, e.g. after a wiped disk,
+/// and pushed again if missing.
+#[tracing::instrument(name = "distribute", skip_all, fields(node = %node.url))]
+pub async fn distribute(app_state: &AppState, node: &Node, files: &[String]) -> anyhow::Result<()> {
+    if node.options.storage == NodeStorage::Shared {
+        return Ok(());
+    }
+
+    let held: Vec<_> = {
+        let node_state = app_state.node_state();
+        let node_state = node_state.read().await;
+        let status = node_state.get(&node.url);
+        files
+            .iter()
+            .map(|path| status.is_some_and(|v| v.has_file(path)))
+            .collect()
+    };
+    let root_dir = app_state.download_state().read().await.root_dir().clone();
+    let client = app_state.node_client();
+
+    for (path, held) in files.iter().zip(held) {
+        if held && exists(client, node, path).await {
+            continue;
+        }
+
+        push(client, node, &root_dir, path)
+            .await
+            .with_context(|| format!("failed to push {} to node", path))?;
+        tracing::info!(path, "file pushed to node");
+
+        app_state
+            .node_state()
+            .write()
+            .await
+            .add_file(&node.url, path);
+    }
+
+    Ok(())
+}
+
+/// `(subfolder, file name)` of `path` in the input folder of ComfyUI, `None` for a model.
+fn input_name(path: &str) -> Option<(&str, &str)> {
+    let name = path
+        .strip_prefix(INPUT_DIR)
+        .and_then(|v| v.strip_prefix('/'))?;
+    Some(name.rsplit_once('/').unwrap_or(("", name)))
+}
+
+/// Whether the file at `path` is on the node, `false` if it can not be checked.
+async fn exists(client: &NodeClient, node: &Node, path: &str) -> bool {
+    let request = match input_name(path) {
+        Some((subfolder, file_name)) => client.request(Method::HEAD, node, "/view").query(&[
+            ("filename", file_name),
+            ("subfolder", subfolder),
+            ("type", "input"),
+        ]),
+        None => match client.agent_request(Method::HEAD, node, &format!("/files/{}", path)) {
+            Some(request) => request,
+            None => return false,
+        },
+    };
+
+    matches!(client.send(request).await, Ok(response) if response.status().is_success())
+}
+
+async fn push(client: &NodeClient, node: &Node, root_dir: &Path, path: &str) -> anyhow::Result<()> {
+    // the links under the root dir lead to the cached files
+    let file = tokio::fs::File::open(root_dir.join(path)).await?;
+    let size = file.metadata().await?.len();
+
+    let request = match input_name(path) {
+        Some((subfolder, file_name)) => {
+            let part =
+                Part::stream_with_length(Body::from(file), size).file_name(file_name.to_string());
+            let form = Form::new()
+                .part("image", part)
+                .text("type", "input")
+                .text("subfolder", subfolder.to_string())
+                .text("overwrite", "true");
+            client
+                .request(Method::POST, node, "/upload/image")
+                .multipart(form)
+        }
+        None => client
+            .agent_request(Method::PUT, node, &format!("/files/{}", path))
+            .context("the node has no agent to push models to")?
+            .header(CONTENT_LENGTH, size)
+            .body(Body::from(file)),
+    };
+
+    client
+        .send(request.timeout(PUSH_TIMEOUT))
+        .await?
+        .error_for_status()?;
+
+    Ok(())
+}
@@ -1,8 +1,14 @@
+pub mod client;
+pub mod distribute;
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use serde::{Deserialize, Serialize};
-use std::collections::{hash_map::Iter, HashMap};
+use std::collections::{hash_map::Iter, HashMap, HashSet};
 use url::Url;
 use utoipa::ToSchema;
 
+const REDACTED: &str = "********";
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum Status {
@@ -14,20 +20,192 @@ pub enum Status {
 #[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct NodeStatus {
     status: Status,
-    cache: HashMap<String, String>,
+    /// Files pushed to a node with its own storage, as paths under its root,
+    /// e.g. `models/loras/<file id>`.
+    files: HashSet<String>,
+}
+
+/// Credentials of a node, e.g. required by a reverse proxy in front of ComfyUI.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum NodeAuth {
+    Basic { username: String, password: String },
+    Bearer { token: String },
+}
+
+/// Where a node reads the files of workflows from.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum NodeStorage {
+    /// The root dir of the router, e.g. on the same machine or a shared volume.
+    #[default]
+    Shared,
+    /// Its own disk, files are pushed to the node before the workflows using them:
+    /// inputs with `/upload/image`, models with `PUT /files/<path>` of the agent.
+    Remote {
+        /// Base URL of the agent storing models on the node, e.g. `http://gpu-1:8189/`.
+        #[serde(default)]
+        #[schema(value_type = Option<String>)]
+        agent: Option<Url>,
+        /// Token of the agent, sent as `Authorization: Bearer <token>` instead of the
+        /// credentials of the node.
+        #[serde(default)]
+        token: Option<String>,
+    },
+}
+
+/// How to reach a node besides its URL, sent with every request to the node.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+pub struct NodeOptions {
+    /// Extra headers, e.g. `{"X-Api-Key": "..."}`.
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default)]
+    pub auth: Option<NodeAuth>,
+    #[serde(default)]
+    pub storage: NodeStorage,
+}
+
+/// A node and how to reach it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Node {
+    pub url: Url,
+    pub options: NodeOptions,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct NodeState {
     nodes: HashMap<Url, NodeStatus>,
+    options: HashMap<Url, NodeOptions>,
     task_record: HashMap<String, Url>,
 }
 
+impl Status {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Idle => "idle",
+            Status::Busy => "busy",
+            Status::Offline => "offline",
+        }
+    }
+}
+
+impl NodeStatus {
+    pub fn status(&self) -> &Status {
+        &self.status
+    }
+
+    /// Whether the file at `path` under the root was pushed to the node.
+    pub fn has_file(&self, path: &str) -> bool {
+        self.files.contains(path)
+    }
+
+    /// Whether `file_id` was pushed to the node, into any folder.
+    pub fn holds(&self, file_id: &str) -> bool {
+        self.files
+            .iter()
+            .any(|path| path.rsplit('/').next() == Some(file_id))
+    }
+}
+
+impl NodeOptions {
+    /// Check that the headers are valid HTTP headers.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (name, value) in &self.headers {
+            if HeaderName::try_from(name).is_err() {
+                anyhow::bail!("invalid header name {}", name);
+            }
+            if HeaderValue::try_from(value).is_err() {
+                anyhow::bail!("invalid value of header {}", name);
+            }
+        }
+        Ok(())
+    }
+
+    /// The headers to send, invalid ones are skipped, see `validate`.
+    pub fn header_map(&self) -> HeaderMap {
+        self.headers
+            .iter()
+            .filter_map(|(name, value)| {
+                Some((
+                    HeaderName::try_from(name).ok()?,
+                    HeaderValue::try_from(value).ok()?,
+                ))
+            })
+            .collect()
+    }
+
+    /// Copy with header values and secrets replaced, to be listed or logged.
+    pub fn redacted(&self) -> Self {
+        Self {
+            headers: self
+                .headers
+                .keys()
+                .map(|name| (name.clone(), REDACTED.to_string()))
+                .collect(),
+            auth: self.auth.as_ref().map(|auth| match auth {
+                NodeAuth::Basic { username, .. } => NodeAuth::Basic {
+                    username: username.clone(),
+                    password: REDACTED.into(),
+                },
+                NodeAuth::Bearer { .. } => NodeAuth::Bearer {
+                    token: REDACTED.into(),
+                },
+            }),
+            storage: match &self.storage {
+                NodeStorage::Remote { agent, token } => NodeStorage::Remote {
+                    agent: agent.clone(),
+                    token: token.as_ref().map(|_| REDACTED.into()),
+                },
+                storage => storage.clone(),
+            },
+        }
+    }
+}
+
+/// URL of `path` under `base`, keeping its base path.
+fn join_path(base: &Url, path: &str) -> Url {
+    let mut url = base.clone();
+    let base = url.path().trim_end_matches('/').to_string();
+    url.set_path(&format!("{}/{}", base, path.trim_start_matches('/')));
+    url
+}
+
+impl Node {
+    /// URL of `path` under the node URL, keeping its base path,
+    /// e.g. `http://proxy/comfy/prompt` for the node `http://proxy/comfy/`.
+    pub fn endpoint(&self, path: &str) -> Url {
+        join_path(&self.url, path)
+    }
+
+    /// URL of `path` under the agent URL, `None` if the node has no agent.
+    pub fn agent_endpoint(&self, path: &str) -> Option<Url> {
+        match &self.options.storage {
+            NodeStorage::Remote {
+                agent: Some(agent), ..
+            } => Some(join_path(agent, path)),
+            _ => None,
+        }
+    }
+
+    /// Credentials to send to the agent, its own token if set, those of the node otherwise.
+    pub fn agent_auth(&self) -> Option<NodeAuth> {
+        match &self.options.storage {
+            NodeStorage::Remote {
+                token: Some(token), ..
+            } => Some(NodeAuth::Bearer {
+                token: token.clone(),
+            }),
+            _ => self.options.auth.clone(),
+        }
+    }
+}
+
 impl Default for NodeStatus {
     fn default() -> Self {
         Self {
             status: Status::Idle,
-            cache: HashMap::new(),
+            files: HashSet::new(),
         }
     }
 }
@@ -36,18 +214,41 @@ impl NodeState {
     pub fn new() -> Self {
         Self {
             nodes: HashMap::new(),
+            options: HashMap::new(),
             task_record: HashMap::new(),
         }
     }
 
-    pub fn add(&mut self, url: &Url) {
+    pub fn add(&mut self, url: &Url, options: NodeOptions) {
         self.nodes.insert(url.clone(), NodeStatus::default());
+        self.options.insert(url.clone(), options);
     }
 
     pub fn get(&self, url: &Url) -> Option<&NodeStatus> {
         self.nodes.get(url)
     }
 
+    /// The node with its options, `None` if it is not in the cluster.
+    pub fn node(&self, url: &Url) -> Option<Node> {
+        self.nodes.get(url)?;
+        Some(Node {
+            url: url.clone(),
+            options: self.options.get(url).cloned().unwrap_or_default(),
+        })
+    }
+
+    /// Replace the options of a node, for the next requests to it.
+    /// The pushed files are forgotten if its storage changed.
+    pub fn set_options(&mut self, url: &Url, options: NodeOptions) {
+        if let Some(status) = self.nodes.get_mut(url) {
+            let storage = self.options.get(url).map(|v| &v.storage);
+            if storage != Some(&options.storage) {
+                status.files.clear();
+            }
+            self.options.insert(url.clone(), options);
+        }
+    }
+
     pub fn get_all<'a>(&'a self) -> Iter<'a, Url, NodeStatus> {
         self.nodes.iter()
     }
@@ -56,23 +257,34 @@ impl NodeState {
         if let Some(status) = self.nodes.get(url) {
             if status.status != Status::Busy {
                 self.nodes.remove(url);
+                self.options.remove(url);
             }
         }
     }
 
-    pub fn pick(&mut self, target: &HashMap<String, String>) -> Option<Url> {
+    /// Pick an idle node and set it busy, preferring the nodes which already have
+    /// the most of `file_ids`, so that fewer files are pushed. Nodes reading the root dir
+    /// of the router have all of them.
+    pub fn pick(&mut self, file_ids: &[String]) -> Option<Node> {
         let picked = self
             .nodes
             .iter()
-            .find(|v| v.1.status == Status::Idle)
+            .filter(|v| v.1.status == Status::Idle)
+            .max_by_key(
+                |(url, status)| match self.options.get(*url).map(|v| &v.storage) {
+                    Some(NodeStorage::Remote { .. }) => {
+                        file_ids.iter().filter(|v| status.holds(v)).count()
+                    }
+                    _ => file_ids.len(),
+                },
+            )
             .map(|v| v.0.clone());
 
         if let Some(url) = &picked {
             self.set_busy(url);
-            self.set_cache(url, target);
         }
 
-        picked
+        picked.and_then(|url| self.node(&url))
     }
 
     pub fn set_busy(&mut self, url: &Url) {
@@ -87,15 +299,19 @@ impl NodeState {
         }
     }
 
+    /// Set the node offline, forgetting its pushed files since it may come back
+    /// without them, e.g. after a restart on another disk.
     pub fn set_offline(&mut self, url: &Url) {
         if let Some(status) = self.nodes.get_mut(url) {
             status.status = Status::Offline;
+            status.files.clear();
         }
     }
 
-    pub fn set_cache(&mut self, url: &Url, cache: &HashMap<String, String>) {
+    /// Record that the file at `path` under the root was pushed to the node.
+    pub fn add_file(&mut self, url: &Url, path: &str) {
         if let Some(status) = self.nodes.get_mut(url) {
-            status.cache = cache.clone();
+            status.files.insert(path.to_string());
         }
     }
 }
@@ -1,98 +1,615 @@
-use std::{env, path::PathBuf, str::FromStr};
+use crate::auth::limit::Limits;
+use anyhow::Context;
+use config::{Config, File};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+use tracing_subscriber::EnvFilter;
+use url::Url;
+use utoipa::ToSchema;
 
-#[derive(Debug, Clone)]
+/// Prefix of environment variables, e.g. `COMFY_ROUTER__DOWNLOAD__CACHE_DIR` sets `download.cache_dir`.
+const ENV_PREFIX: &str = "COMFY_ROUTER__";
+
+/// Environment variables named before the config had sections, and the keys they set.
+const LEGACY_ENV: &[(&str, &str)] = &[
+    ("COMFY_ROUTER__USERNAME", "auth.username"),
+    ("COMFY_ROUTER__PASSWORD", "auth.password"),
+    ("COMFY_ROUTER__AUTH__API_KEYS_PATH", "storage.api_keys_path"),
+    ("COMFY_ROUTER__DOWNLOAD__RECORD_PATH", "storage.record_path"),
+    ("COMFY_ROUTER__HISTORY_LIMIT", "cluster.history_limit"),
+    ("COMFY_ROUTER__PENDING_LIMIT", "cluster.pending_limit"),
+];
+
+const REDACTED: &str = "********";
+
+/// Keys applied by `AppConfig::reload` without restart, a trailing `.` matches a whole section.
+const RELOADABLE: &[&str] = &[
+    "auth.username",
+    "auth.password",
+    "limit.",
+    "log.filter",
+    "cluster.history_limit",
+    "cluster.pending_limit",
+    "cluster.warm_up_timeout",
+    "download.max_cache_bytes",
+    "download.eviction_policy",
+    "download.quotas.",
+    "download.ttl.",
+    "download.never_evict",
+    "download.retries",
+    "download.parallel_chunks",
+    "download.chunk_size",
+];
+
+/// Where the config was loaded from, to load it again on reload.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigSource {
+    pub file: Option<PathBuf>,
+    /// `section.key` and value pairs from the command line.
+    pub overrides: Vec<(String, String)>,
+}
+
+/// Keys that changed when the config was reloaded.
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct ConfigChanges {
+    /// Applied without restart.
+    pub applied: Vec<String>,
+    /// Changed in the config, but only take effect after a restart.
+    pub restart_required: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct AppConfig {
     pub host: String,
     pub port: u16,
+    /// Application running environment, currently unused.
     pub env: String,
+    pub auth: AuthConfig,
+    /// Limits for API keys that do not set their own.
+    #[serde(rename = "limit")]
+    pub default_limits: Limits,
+    pub audit: AuditConfig,
+    pub log: LogConfig,
+    pub otel: OtelConfig,
+    pub cluster: ClusterConfig,
+    pub download: DownloadConfig,
+    pub storage: StorageConfig,
+    pub tls: TlsConfig,
+    pub node_tls: NodeTlsConfig,
+    pub http: HttpConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// Basic Authentication credentials of the admin.
     pub username: String,
     pub password: String,
-    pub workflow_history_limit: usize,
-    pub workflow_pending_limit: usize,
+    /// Secret used to sign preview tokens, a random one is generated if empty.
+    pub preview_secret: String,
+    /// Lifetime of preview tokens in seconds.
+    pub preview_token_ttl: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuditConfig {
+    /// Audit log in JSON lines, rotated when it exceeds `max_bytes`.
+    pub log_path: PathBuf,
+    pub max_bytes: u64,
+    /// Number of rotated audit log files to keep.
+    pub max_files: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    pub format: LogFormat,
+    /// Filter directives, e.g. `info,comfy_router::download=debug`.
+    pub filter: String,
+    /// Directory to also write log files to.
+    pub dir: Option<PathBuf>,
+    pub rotation: LogRotation,
+    /// Number of rotated log files to keep.
+    pub max_files: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Single line, human readable.
+    Text,
+    /// Multiple lines, human readable.
+    Pretty,
+    /// JSON lines, for log collectors.
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Minutely,
+    Hourly,
+    Daily,
+    Never,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OtelConfig {
+    /// Base URL of the OTLP/HTTP collector, spans are exported only if set.
+    pub endpoint: Option<Url>,
+    pub protocol: OtelProtocol,
+    pub service_name: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OtelProtocol {
+    #[serde(rename = "http/protobuf")]
+    HttpProtobuf,
+    #[serde(rename = "http/json")]
+    HttpJson,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClusterConfig {
+    /// Finished workflows kept for `GET /workflow/:id`, older ones are discarded,
+    /// and as many pre-fetches for `GET /downloads/prefetch/:id`.
+    pub history_limit: usize,
+    /// Workflows waiting for a node, new ones are rejected with 429 when reached.
+    pub pending_limit: usize,
+    /// Seconds a pre-fetch waits for a busy node to warm it up, before giving up on it.
+    pub warm_up_timeout: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DownloadConfig {
+    /// Download cache directory, should be readable by ComfyUI.
     pub cache_dir: PathBuf,
+    /// ComfyUI root directory, downloaded files are linked into its `models/*` folders.
     pub root_dir: PathBuf,
-    pub record_path: PathBuf,
+    /// Files are evicted by `eviction_policy` when the cache exceeds this size.
     pub max_cache_bytes: u64,
+    pub eviction_policy: EvictionPolicy,
+    /// Limits in bytes of the files linked into a target folder, e.g. `"models/loras" = 10737418240`,
+    /// files over a quota are evicted from the folder by `eviction_policy`.
+    pub quotas: BTreeMap<String, u64>,
+    /// Seconds the files only linked into a target folder are kept after their last use,
+    /// e.g. `input = 3600` for input images.
+    pub ttl: BTreeMap<String, u64>,
+    /// URLs of files never evicted, a trailing `*` matches the URLs starting with the rest.
+    pub never_evict: Vec<String>,
+    /// Times an interrupted download is resumed before it fails, after `http.retry_backoff_ms`.
+    pub retries: u32,
+    /// Range requests at once per download from servers supporting them, 1 downloads in order.
+    pub parallel_chunks: usize,
+    /// Bytes per range request of parallel downloads.
+    pub chunk_size: u64,
+    /// Query parameters identifying the file per host, e.g. `"civitai.com" = ["type", "format"]`,
+    /// `*` keeps them all and `*.example.com` matches subdomains. URLs of other hosts identify
+    /// their file without query, which often only carries signatures or tokens.
+    pub keep_query: BTreeMap<String, Vec<String>>,
+}
+
+/// Order in which cached files are evicted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EvictionPolicy {
+    /// Least recently used first.
+    Lru,
+    /// Least frequently used first, then least recently used.
+    Lfu,
+    /// Largest first, then least recently used, freeing space with the fewest evictions.
+    Size,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    /// Record of downloaded files.
+    pub record_path: PathBuf,
+    /// API key store.
+    pub api_keys_path: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TlsConfig {
+    /// PEM certificate chain and private key, HTTPS is served instead of HTTP when set.
+    pub cert_path: Option<PathBuf>,
+    pub key_path: Option<PathBuf>,
+    /// Seconds between checks for renewed certificate files, 0 to only reload them with the config.
+    pub reload_interval: u64,
+}
+
+/// TLS of the connections to `https` nodes.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NodeTlsConfig {
+    /// PEM bundle of CA certificates trusted in addition to the public roots.
+    pub ca_path: Option<PathBuf>,
+    /// PEM client certificate chain and private key, for nodes that require client authentication.
+    pub cert_path: Option<PathBuf>,
+    pub key_path: Option<PathBuf>,
+}
+
+/// Outgoing requests, to nodes and for downloads.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    /// Seconds to establish a connection.
+    pub connect_timeout: u64,
+    /// Seconds without receiving data before a request fails, e.g. a stalled download.
+    pub read_timeout: u64,
+    /// Seconds for a whole request to a node, downloads are only limited by `read_timeout`.
+    pub node_timeout: u64,
+    pub user_agent: String,
+    /// Proxy of downloads, e.g. `http://proxy:3128`, `HTTP_PROXY` and `HTTPS_PROXY` are used if unset.
+    /// Nodes are always reached directly.
+    pub proxy: Option<Url>,
+    /// Hosts reached without the proxy, comma separated like `NO_PROXY`.
+    pub no_proxy: String,
+    /// Retries of requests failing with a connection error, and for idempotent requests
+    /// with a timeout or a 429, 502, 503 or 504 status.
+    pub retries: u32,
+    /// Milliseconds before the first retry, doubled for each next one.
+    pub retry_backoff_ms: u64,
+    /// Idle connections kept open per host.
+    pub pool_max_idle_per_host: usize,
+    /// Seconds before idle connections are closed.
+    pub pool_idle_timeout: u64,
 }
 
-trait FromEnvWithDefault: Sized {
-    fn from_env_or_default(key: &str, default: Self) -> Self;
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            host: "0.0.0.0".into(),
+            port: 8080,
+            env: "dev".into(),
+            auth: Default::default(),
+            default_limits: Default::default(),
+            audit: Default::default(),
+            log: Default::default(),
+            otel: Default::default(),
+            cluster: Default::default(),
+            download: Default::default(),
+            storage: Default::default(),
+            tls: Default::default(),
+            node_tls: Default::default(),
+            http: Default::default(),
+        }
+    }
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            username: "admin".into(),
+            password: "admin".into(),
+            preview_secret: String::new(),
+            preview_token_ttl: 60 * 60,
+        }
+    }
 }
 
-impl FromEnvWithDefault for u16 {
-    fn from_env_or_default(key: &str, default: Self) -> Self {
-        env::var(key)
-            .ok()
-            .and_then(|val| val.parse().ok())
-            .unwrap_or(default)
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            log_path: "/tmp/audit.log".into(),
+            max_bytes: 1024 * 1024 * 10,
+            max_files: 5,
+        }
     }
 }
 
-impl FromEnvWithDefault for usize {
-    fn from_env_or_default(key: &str, default: Self) -> Self {
-        env::var(key)
-            .ok()
-            .and_then(|val| val.parse().ok())
-            .unwrap_or(default)
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::Text,
+            filter: "info".into(),
+            dir: None,
+            rotation: LogRotation::Daily,
+            max_files: 7,
+        }
     }
 }
 
-impl FromEnvWithDefault for u64 {
-    fn from_env_or_default(key: &str, default: Self) -> Self {
-        env::var(key)
-            .ok()
-            .and_then(|val| val.parse().ok())
-            .unwrap_or(default)
+impl Default for OtelConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: None,
+            protocol: OtelProtocol::HttpProtobuf,
+            service_name: "comfy-router".into(),
+        }
     }
 }
 
-impl FromEnvWithDefault for String {
-    fn from_env_or_default(key: &str, default: Self) -> Self {
-        env::var(key).unwrap_or(default)
+impl Default for ClusterConfig {
+    fn default() -> Self {
+        Self {
+            history_limit: 50,
+            pending_limit: 25,
+            warm_up_timeout: 1800,
+        }
     }
 }
 
-impl<T> FromEnvWithDefault for Option<T>
-where
-    T: FromStr,
-{
-    fn from_env_or_default(key: &str, default: Self) -> Self {
-        env::var(key)
-            .ok()
-            .and_then(|val| val.parse().ok())
-            .or(default)
+impl Default for DownloadConfig {
+    fn default() -> Self {
+        Self {
+            cache_dir: "/tmp/cache".into(),
+            root_dir: "/tmp/model".into(),
+            max_cache_bytes: 1024 * 1024 * 1024 * 64,
+            eviction_policy: EvictionPolicy::Lru,
+            quotas: BTreeMap::new(),
+            ttl: BTreeMap::new(),
+            never_evict: vec![],
+            retries: 5,
+            parallel_chunks: 1,
+            chunk_size: 64 * 1024 * 1024,
+            keep_query: BTreeMap::new(),
+        }
     }
 }
 
-impl AppConfig {
-    pub fn from_env() -> Self {
+impl Default for TlsConfig {
+    fn default() -> Self {
         Self {
-            host: String::from_env_or_default("COMFY_ROUTER__HOST", "0.0.0.0".into()),
-            port: u16::from_env_or_default("COMFY_ROUTER__PORT", 8080),
-            username: String::from_env_or_default("COMFY_ROUTER__USERNAME", "admin".into()),
-            password: String::from_env_or_default("COMFY_ROUTER__PASSWORD", "admin".into()),
-            workflow_history_limit: usize::from_env_or_default("COMFY_ROUTER__HISTORY_LIMIT", 50),
-            workflow_pending_limit: usize::from_env_or_default("COMFY_ROUTER__PENDING_LIMIT", 25),
-            env: String::from_env_or_default("COMFY_ROUTER__ENV", "dev".into()),
-            cache_dir: String::from_env_or_default(
-                "COMFY_ROUTER__DOWNLOAD__CACHE_DIR",
-                "/tmp/cache".into(),
-            )
-            .into(),
-            root_dir: String::from_env_or_default(
-                "COMFY_ROUTER__DOWNLOAD__ROOT_DIR",
-                "/tmp/model".into(),
-            )
-            .into(),
-            record_path: String::from_env_or_default(
-                "COMFY_ROUTER__DOWNLOAD__RECORD_PATH",
-                "/tmp/record.json".into(),
-            )
-            .into(),
-            max_cache_bytes: u64::from_env_or_default(
-                "COMFY_ROUTER__DOWNLOAD__MAX_CACHE_BYTES",
-                1024 * 1024 * 1024 * 64,
+            cert_path: None,
+            key_path: None,
+            reload_interval: 60,
+        }
+    }
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: 10,
+            read_timeout: 60,
+            node_timeout: 30,
+            user_agent: format!("comfy-router/{}", env!("CARGO_PKG_VERSION")),
+            proxy: None,
+            no_proxy: String::new(),
+            retries: 2,
+            retry_backoff_ms: 500,
+            pool_max_idle_per_host: 16,
+            pool_idle_timeout: 90,
+        }
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            record_path: "/tmp/record.json".into(),
+            api_keys_path: "/tmp/api_keys.json".into(),
+        }
+    }
+}
+
+/// Config key set by an environment variable, `None` if the variable is not for the router.
+fn env_key(name: &str) -> Option<String> {
+    if let Some((_, key)) = LEGACY_ENV.iter().find(|(legacy, _)| *legacy == name) {
+        return Some(key.to_string());
+    }
+
+    name.strip_prefix(ENV_PREFIX)
+        .map(|key| key.to_lowercase().replace("__", "."))
+}
+
+impl ConfigSource {
+    pub fn load(&self) -> anyhow::Result<AppConfig> {
+        AppConfig::load(self.file.as_deref(), &self.overrides)
+    }
+}
+
+/// Leaf values of the config by `section.key`.
+fn flatten(config: &AppConfig) -> BTreeMap<String, Value> {
+    fn walk(prefix: String, value: Value, keys: &mut BTreeMap<String, Value>) {
+        match value {
+            Value::Object(map) => {
+                for (key, value) in map {
+                    let key = if prefix.is_empty() {
+                        key
+                    } else {
+                        format!("{}.{}", prefix, key)
+                    };
+                    walk(key, value, keys);
+                }
+            }
+            value => {
+                keys.insert(prefix, value);
+            }
+        }
+    }
+
+    let mut keys = BTreeMap::new();
+    walk(
+        String::new(),
+        serde_json::to_value(config).expect("config should be serializable"),
+        &mut keys,
+    );
+    keys
+}
+
+impl AppConfig {
+    /// Load the config file if any, then `COMFY_ROUTER__*` environment variables,
+    /// then `overrides` of `section.key` from the command line. Later layers win.
+    pub fn load(file: Option<&Path>, overrides: &[(String, String)]) -> anyhow::Result<Self> {
+        Self::load_from(file, std::env::vars(), overrides)
+    }
+
+    /// Same as `load`, with the given environment variables.
+    ///
+    /// Unknown keys and values of the wrong type are errors instead of being ignored.
+    pub fn load_from(
+        file: Option<&Path>,
+        env: impl IntoIterator<Item = (String, String)>,
+        overrides: &[(String, String)],
+    ) -> anyhow::Result<Self> {
+        let mut builder = Config::builder();
+
+        if let Some(file) = file {
+            builder = builder.add_source(File::from(file));
+        }
+        for (name, value) in env {
+            if let Some(key) = env_key(&name) {
+                builder = builder
+                    .set_override(key, value)
+                    .with_context(|| format!("invalid environment variable {}", name))?;
+            }
+        }
+        for (key, value) in overrides {
+            builder = builder
+                .set_override(key.as_str(), value.as_str())
+                .with_context(|| format!("invalid override {}", key))?;
+        }
+
+        let source = builder.build().with_context(|| match file {
+            Some(file) => format!("failed to read config file {}", file.display()),
+            None => "failed to read config".into(),
+        })?;
+
+        let mut unknown = vec![];
+        let config: Self =
+            serde_ignored::deserialize(source, |path| unknown.push(path.to_string()))
+                .context("invalid config")?;
+        if !unknown.is_empty() {
+            anyhow::bail!("unknown config keys: {}", unknown.join(", "));
+        }
+
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Check values that are valid for their type but not for the router.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        EnvFilter::try_new(&self.log.filter)
+            .with_context(|| format!("invalid log.filter {}", self.log.filter))?;
+
+        for (key, value) in [
+            ("download.max_cache_bytes", self.download.max_cache_bytes),
+            (
+                "download.parallel_chunks",
+                self.download.parallel_chunks as u64,
             ),
+            ("download.chunk_size", self.download.chunk_size),
+            ("audit.max_bytes", self.audit.max_bytes),
+            ("audit.max_files", self.audit.max_files as u64),
+            ("cluster.history_limit", self.cluster.history_limit as u64),
+            ("cluster.warm_up_timeout", self.cluster.warm_up_timeout),
+            ("log.max_files", self.log.max_files as u64),
+            ("http.connect_timeout", self.http.connect_timeout),
+            ("http.read_timeout", self.http.read_timeout),
+            ("http.node_timeout", self.http.node_timeout),
+        ] {
+            if value == 0 {
+                anyhow::bail!("{} must be greater than 0", key);
+            }
         }
+
+        for (section, values) in [
+            ("download.quotas", &self.download.quotas),
+            ("download.ttl", &self.download.ttl),
+        ] {
+            if let Some((folder, _)) = values.iter().find(|(_, value)| **value == 0) {
+                anyhow::bail!("{}.{} must be greater than 0", section, folder);
+            }
+        }
+
+        for (section, cert_path, key_path) in [
+            ("tls", &self.tls.cert_path, &self.tls.key_path),
+            (
+                "node_tls",
+                &self.node_tls.cert_path,
+                &self.node_tls.key_path,
+            ),
+        ] {
+            if cert_path.is_some() != key_path.is_some() {
+                anyhow::bail!(
+                    "{0}.cert_path and {0}.key_path must be set together",
+                    section
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Take the settings of `new` that can change at runtime, see `RELOADABLE`.
+    /// The others keep their current value, so the config always shows what is in effect.
+    pub fn reload(&mut self, new: &AppConfig) -> ConfigChanges {
+        let before = flatten(self);
+        let after = flatten(new);
+
+        // keys of maps, e.g. `download.quotas`, may also be removed
+        let keys: BTreeSet<_> = before.keys().chain(after.keys()).cloned().collect();
+
+        let mut changes = ConfigChanges::default();
+        for key in keys {
+            if before.get(&key) == after.get(&key) {
+                continue;
+            }
+            let reloadable = RELOADABLE.iter().any(|v| match v.strip_suffix('.') {
+                Some(section) => key.starts_with(v) || key == section,
+                None => key == *v,
+            });
+            if reloadable {
+                changes.applied.push(key);
+            } else {
+                changes.restart_required.push(key);
+            }
+        }
+
+        self.auth.username = new.auth.username.clone();
+        self.auth.password = new.auth.password.clone();
+        self.default_limits = new.default_limits.clone();
+        self.log.filter = new.log.filter.clone();
+        self.cluster = new.cluster.clone();
+        self.download.max_cache_bytes = new.download.max_cache_bytes;
+        self.download.eviction_policy = new.download.eviction_policy;
+        self.download.quotas = new.download.quotas.clone();
+        self.download.ttl = new.download.ttl.clone();
+        self.download.never_evict = new.download.never_evict.clone();
+        self.download.retries = new.download.retries;
+        self.download.parallel_chunks = new.download.parallel_chunks;
+        self.download.chunk_size = new.download.chunk_size;
+
+        changes
+    }
+
+    /// Copy of the config with secrets replaced, to be printed or logged.
+    pub fn redacted(&self) -> Self {
+        let mut config = self.clone();
+
+        for secret in [&mut config.auth.password, &mut config.auth.preview_secret] {
+            if !secret.is_empty() {
+                *secret = REDACTED.into();
+            }
+        }
+        for url in [&mut config.otel.endpoint, &mut config.http.proxy]
+            .into_iter()
+            .flatten()
+        {
+            if url.password().is_some() {
+                let _ = url.set_password(Some(REDACTED));
+            }
+        }
+
+        config
+    }
+
+    /// The config in TOML with secrets redacted, the format of the config file.
+    pub fn to_redacted_toml(&self) -> anyhow::Result<String> {
+        Ok(toml::to_string_pretty(&self.redacted())?)
     }
 }
@@ -0,0 +1,73 @@
+//! Leases pinning downloads while workflows use them, so that their files are not evicted.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+/// Downloads pinned by leases, with the number of leases of each.
+#[derive(Clone, Debug, Default)]
+pub struct Leases(Arc<Mutex<HashMap<String, usize>>>);
+
+impl Leases {
+    /// A lease without downloads yet, see `Lease::pin`.
+    pub fn lease(&self) -> Lease {
+        Lease(Arc::new(LeaseInner {
+            leases: self.clone(),
+            file_ids: Mutex::new(vec![]),
+        }))
+    }
+
+    pub fn is_pinned(&self, file_id: &str) -> bool {
+        self.0
+            .lock()
+            .expect("lock should not be poisoned")
+            .contains_key(file_id)
+    }
+}
+
+/// Pins downloads until dropped with all its clones, e.g. by a workflow until it finishes.
+#[derive(Clone, Debug)]
+pub struct Lease(Arc<LeaseInner>);
+
+#[derive(Debug)]
+struct LeaseInner {
+    leases: Leases,
+    file_ids: Mutex<Vec<String>>,
+}
+
+impl Lease {
+    pub fn pin(&self, file_id: &str) {
+        *self
+            .0
+            .leases
+            .0
+            .lock()
+            .expect("lock should not be poisoned")
+            .entry(file_id.to_string())
+            .or_default() += 1;
+        self.0
+            .file_ids
+            .lock()
+            .expect("lock should not be poisoned")
+            .push(file_id.to_string());
+    }
+}
+
+impl Drop for LeaseInner {
+    fn drop(&mut self) {
+        let mut pinned = self.leases.0.lock().expect("lock should not be poisoned");
+        for file_id in self
+            .file_ids
+            .get_mut()
+            .expect("lock should not be poisoned")
+        {
+            if let Some(count) = pinned.get_mut(file_id.as_str()) {
+                *count -= 1;
+                if *count == 0 {
+                    pinned.remove(file_id.as_str());
+                }
+            }
+        }
+    }
+}
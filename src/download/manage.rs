@@ -1,71 +1,332 @@
-use super::state::DownloadState;
-use std::{os::unix::fs::MetadataExt, sync::Arc};
+use super::{state::DownloadState, task::now_millis};
+use crate::{
+    audit::{self, AuditEntry},
+    config::{DownloadConfig, EvictionPolicy},
+};
+use serde_json::json;
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashSet},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, UNIX_EPOCH},
+};
 use tokio::sync::RwLock;
+use url::Url;
 
-pub async fn manage_cache(download_state: Arc<RwLock<DownloadState>>) -> anyhow::Result<()> {
-    let cache_dir = download_state.read().await.cache_dir().clone();
-    let mut read_dir = tokio::fs::read_dir(cache_dir).await?;
-    let mut files = vec![];
+/// How the cache is kept within its limits besides `max_cache_bytes`, see `DownloadConfig`.
+#[derive(Clone, Debug)]
+pub struct CachePolicy {
+    pub eviction_policy: EvictionPolicy,
+    pub quotas: BTreeMap<PathBuf, u64>,
+    pub ttl: BTreeMap<PathBuf, Duration>,
+    pub never_evict: Vec<String>,
+}
 
-    while let Ok(Some(entry)) = read_dir.next_entry().await {
-        files.push(entry);
+impl CachePolicy {
+    pub fn new(config: &DownloadConfig) -> Self {
+        Self {
+            eviction_policy: config.eviction_policy,
+            quotas: config
+                .quotas
+                .iter()
+                .map(|(k, v)| (PathBuf::from(k), *v))
+                .collect(),
+            ttl: config
+                .ttl
+                .iter()
+                .map(|(k, v)| (PathBuf::from(k), Duration::from_secs(*v)))
+                .collect(),
+            never_evict: config.never_evict.clone(),
+        }
+    }
+
+    /// Whether the file of `url` is never evicted.
+    pub fn never_evicts(&self, url: &Url) -> bool {
+        self.never_evict.iter().any(|v| match v.strip_suffix('*') {
+            Some(prefix) => url.as_str().starts_with(prefix),
+            None => url.as_str() == v,
+        })
+    }
+
+    /// How often to look for expired files, half the shortest TTL between a second
+    /// and a minute, `None` without TTL.
+    pub fn check_interval(&self) -> Option<Duration> {
+        let shortest = self.ttl.values().min()?;
+        Some((*shortest / 2).clamp(Duration::from_secs(1), Duration::from_secs(60)))
     }
 
-    let mut files_with_info = vec![];
-    for file in &files {
-        let metadata = file.metadata().await?;
-        if metadata.is_file() {
-            if let Ok(modified) = metadata.modified() {
-                files_with_info.push((file, metadata, modified));
+    /// Whether `file` was not used for the longest TTL of its target dirs,
+    /// never if one of them has no TTL.
+    fn is_expired(&self, file: &CacheFile, now: u64) -> bool {
+        let mut ttl = Duration::ZERO;
+        for target_dir in &file.target_dirs {
+            match self.ttl.get(target_dir) {
+                Some(v) => ttl = ttl.max(*v),
+                None => return false,
             }
         }
+
+        !file.target_dirs.is_empty() && now.saturating_sub(file.used_at) > ttl.as_millis() as u64
     }
 
-    files_with_info.sort_by(|a, b| {
-        // NOTICE here compare right with left, which should output reversed order
-        b.2.cmp(&a.2)
-    });
+    /// Order of `a` and `b` by `eviction_policy`, the first one is evicted first.
+    fn cmp(&self, a: &CacheFile, b: &CacheFile) -> Ordering {
+        let by_use = a.used_at.cmp(&b.used_at);
+        match self.eviction_policy {
+            EvictionPolicy::Lru => by_use,
+            EvictionPolicy::Lfu => a.use_count.cmp(&b.use_count).then(by_use),
+            EvictionPolicy::Size => b.size.cmp(&a.size).then(by_use),
+        }
+    }
+}
 
-    let mut current_size = 0u64;
-    for (_, metadata, _) in &files_with_info {
-        current_size += metadata.size();
+impl Default for CachePolicy {
+    fn default() -> Self {
+        Self::new(&DownloadConfig::default())
     }
+}
+
+/// A file in the cache dir, with what decides when it is evicted.
+#[derive(Debug)]
+struct CacheFile {
+    path: PathBuf,
+    name: String,
+    size: u64,
+    /// Last use by a workflow in Unix milliseconds, or modification time if not recorded.
+    used_at: u64,
+    use_count: u64,
+    /// Target dirs of the downloads stored in the file.
+    target_dirs: HashSet<PathBuf>,
+    /// In use or never evicted.
+    kept: bool,
+}
+
+/// Why a file is evicted, recorded in the audit log.
+enum Reason {
+    Expired,
+    Quota(PathBuf, u64),
+    MaxCacheBytes(u64),
+}
 
-    // Remove oldest files if cache size exceeds the limit
-    let max_cache_bytes = download_state.read().await.max_cache_bytes();
-    while current_size > max_cache_bytes && !files_with_info.is_empty() {
-        if let Some((oldest_file, metadata, _)) = files_with_info.pop() {
+/// The files in the cache dir, in eviction order.
+async fn cache_files(state: &DownloadState) -> anyhow::Result<Vec<CacheFile>> {
+    let policy = state.cache_policy();
+    let mut read_dir = tokio::fs::read_dir(state.cache_dir()).await?;
+    let mut files = vec![];
+
+    while let Ok(Some(entry)) = read_dir.next_entry().await {
+        let metadata = entry.metadata().await?;
+        if !metadata.is_file() || is_partial(&entry.path()) {
+            continue;
+        }
+        let Ok(modified) = metadata.modified() else {
+            continue;
+        };
+
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let downloads: Vec<_> = state
+            .stored_in(&name)
+            .into_iter()
+            .filter_map(|v| state.get_by_id(&v))
+            .collect();
+        let modified = modified
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |v| v.as_millis() as u64);
+
+        files.push(CacheFile {
+            path: entry.path(),
+            size: metadata.size(),
+            used_at: state.last_used_at(&name).unwrap_or(modified),
+            use_count: downloads.iter().map(|v| v.use_count()).sum(),
+            target_dirs: downloads
+                .iter()
+                .flat_map(|v| state.target_dirs(v.file_id()).into_iter().flatten())
+                .cloned()
+                .collect(),
+            kept: state.in_use(&name) || downloads.iter().any(|v| policy.never_evicts(v.url())),
+            name,
+        });
+    }
+
+    files.sort_by(|a, b| policy.cmp(a, b));
+    Ok(files)
+}
+
+/// Delete `file` with the downloads stored in it and their links.
+async fn evict(state: &mut DownloadState, file: &CacheFile, reason: &Reason) {
+    let file_name = file.name.as_str();
+    let (reason_name, limit) = match reason {
+        Reason::Expired => {
+            tracing::info!(file_name, "cache file expired, evicting file");
+            ("expired", None)
+        }
+        Reason::Quota(target_dir, quota) => {
+            tracing::info!(file_name, target_dir = %target_dir.display(), quota, "folder exceeds quota, evicting file");
+            ("quota", Some(*quota))
+        }
+        Reason::MaxCacheBytes(max_cache_bytes) => {
             tracing::info!(
-                "cache exceed limit ({} Bytes), deleting {}",
+                file_name,
                 max_cache_bytes,
-                oldest_file.path().to_string_lossy()
+                "cache exceeds limit, evicting file"
             );
+            ("max_cache_bytes", Some(*max_cache_bytes))
+        }
+    };
+
+    // If delete failed, just continue with warning
+    if let Err(e) = tokio::fs::remove_file(&file.path).await {
+        tracing::warn!(file_name, error = %e, "failed to delete cache file");
+    }
+
+    if let Some(metrics) = state.metrics() {
+        metrics.cache_evictions_total.inc();
+        metrics.cache_evicted_bytes_total.inc_by(file.size);
+    }
 
-            let file_size = metadata.size();
-            // If delete failed, just continue with warning
-            if let Err(e) = tokio::fs::remove_file(oldest_file.path()).await {
-                tracing::warn!(
-                    "failed to delete cache file {}: {}",
-                    oldest_file.path().display(),
-                    e
-                );
+    // Remove the download entries of the file, all URLs with its content
+    let mut file_ids = state.stored_in(file_name);
+    if file_ids.is_empty() {
+        file_ids.push(file_name.to_string());
+    }
+    for file_id in file_ids {
+        // take target dirs before `remove`, which drops them from the record
+        let target_dirs = state.remove_target_dirs(&file_id).unwrap_or_default();
+        let removed = state.remove(&file_id);
+
+        // Remove symlinks
+        for target_dir in &target_dirs {
+            let target_path = state.root_dir().join(target_dir).join(&file_id);
+            let _ = tokio::fs::remove_dir_all(target_path).await;
+        }
+
+        if let Some(audit_log) = state.audit_log() {
+            let mut details = json!({
+                "file_id": file_id,
+                "url": removed.as_ref().map(|v| v.url().to_string()),
+                "sha256": removed.as_ref().and_then(|v| v.sha256()),
+                "size": file.size,
+                "target_dirs": target_dirs,
+                "reason": reason_name,
+            });
+            if let Reason::Quota(target_dir, _) = reason {
+                details["target_dir"] = json!(target_dir);
             }
-            current_size -= file_size;
-
-            // Remove the corresponding download entry
-            let file_id = oldest_file.file_name().to_string_lossy().into_owned();
-            let mut state = download_state.write().await;
-            let _ = state.remove(&file_id).await;
-
-            // Remove symlinks
-            if let Some(target_dirs) = state.remove_target_dirs(&file_id) {
-                for target_dir in target_dirs {
-                    let target_path = state.root_dir().join(target_dir).join(&file_id);
-                    let _ = tokio::fs::remove_dir_all(target_path).await;
-                }
+            if let Some(limit) = limit {
+                details["limit"] = json!(limit);
             }
+            audit::record(&audit_log, AuditEntry::new("download.cache.evict", details)).await;
+        }
+    }
+}
+
+/// Whether `path` is the partial file of a download, not cached yet.
+fn is_partial(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|v| v.to_str()),
+        Some("download" | "partial")
+    )
+}
+
+/// Total size of the cached files in the cache dir, without partial files.
+pub async fn cache_size(cache_dir: impl AsRef<Path>) -> anyhow::Result<u64> {
+    let mut read_dir = match tokio::fs::read_dir(cache_dir).await {
+        Ok(read_dir) => read_dir,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut size = 0;
+    while let Ok(Some(entry)) = read_dir.next_entry().await {
+        let metadata = entry.metadata().await?;
+        if metadata.is_file() && !is_partial(&entry.path()) {
+            size += metadata.size();
+        }
+    }
+
+    Ok(size)
+}
+
+/// Evict the files unused for their TTL, then evict files by the eviction policy while a target
+/// folder exceeds its quota or the cache exceeds its limit, see `CachePolicy`.
+///
+/// The files in use by workflows and running downloads are kept, see `DownloadState::in_use`,
+/// and so are the files of URLs never evicted.
+pub async fn manage_cache(download_state: Arc<RwLock<DownloadState>>) -> anyhow::Result<()> {
+    // locked until done, so that no workflow starts using a file being evicted
+    let mut state = download_state.write().await;
+    let policy = state.cache_policy().clone();
+    let mut files = cache_files(&state).await?;
+
+    let now = now_millis();
+    let (expired, mut files): (Vec<_>, Vec<_>) = files
+        .drain(..)
+        .partition(|v| !v.kept && policy.is_expired(v, now));
+    for file in &expired {
+        evict(&mut state, file, &Reason::Expired).await;
+    }
+
+    for (target_dir, quota) in &policy.quotas {
+        let mut usage: u64 = files
+            .iter()
+            .filter(|v| v.target_dirs.contains(target_dir))
+            .map(|v| v.size)
+            .sum();
+
+        while usage > *quota {
+            let Some(index) = files
+                .iter()
+                .position(|v| !v.kept && v.target_dirs.contains(target_dir))
+            else {
+                tracing::warn!(target_dir = %target_dir.display(), usage, quota, "folder exceeds quota, remaining files are kept");
+                break;
+            };
+            let file = files.remove(index);
+            evict(
+                &mut state,
+                &file,
+                &Reason::Quota(target_dir.clone(), *quota),
+            )
+            .await;
+            usage -= file.size;
         }
     }
 
+    let mut current_size: u64 = files.iter().map(|v| v.size).sum();
+    let max_cache_bytes = state.max_cache_bytes();
+    while current_size > max_cache_bytes {
+        let Some(index) = files.iter().position(|v| !v.kept) else {
+            tracing::warn!(
+                current_size,
+                max_cache_bytes,
+                "cache exceeds limit, remaining files are kept"
+            );
+            break;
+        };
+        let file = files.remove(index);
+        evict(&mut state, &file, &Reason::MaxCacheBytes(max_cache_bytes)).await;
+        current_size -= file.size;
+    }
+
+    if let Some(metrics) = state.metrics() {
+        metrics.cache_size_bytes.set(current_size as i64);
+    }
+
     Ok(())
 }
+
+/// Run `manage_cache` periodically to evict expired files, see `CachePolicy::check_interval`.
+pub async fn expire_cache(download_state: Arc<RwLock<DownloadState>>) {
+    loop {
+        let interval = download_state.read().await.cache_policy().check_interval();
+        tokio::time::sleep(interval.unwrap_or(Duration::from_secs(60))).await;
+
+        if interval.is_some() {
+            if let Err(e) = manage_cache(download_state.clone()).await {
+                tracing::warn!(error = %e, "failed to manage cache");
+            }
+        }
+    }
+}
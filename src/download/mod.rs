@@ -1,4 +1,6 @@
+pub mod lease;
 pub mod manage;
+pub mod progress;
 pub mod state;
 pub mod task;
 mod utils;
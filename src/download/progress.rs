@@ -0,0 +1,108 @@
+use super::task::DownloadStatus;
+use serde::{Deserialize, Serialize};
+use std::{
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Instant,
+};
+use url::Url;
+use utoipa::ToSchema;
+
+/// Bytes of a running download, updated as they are written.
+#[derive(Debug)]
+pub struct DownloadProgress {
+    /// Bytes in the partial file, including those of previous attempts.
+    downloaded: AtomicU64,
+    /// Size of the file, 0 until known.
+    total: AtomicU64,
+    /// Bytes received since `started_at`.
+    received: AtomicU64,
+    started_at: Instant,
+}
+
+impl DownloadProgress {
+    pub fn new() -> Self {
+        Self {
+            downloaded: AtomicU64::new(0),
+            total: AtomicU64::new(0),
+            received: AtomicU64::new(0),
+            started_at: Instant::now(),
+        }
+    }
+
+    /// Set the bytes already in the partial file, e.g. when resuming it.
+    pub fn reset(&self, downloaded: u64, total: Option<u64>) {
+        self.downloaded.store(downloaded, Ordering::Relaxed);
+        self.total.store(total.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    pub fn add(&self, bytes: u64) {
+        self.downloaded.fetch_add(bytes, Ordering::Relaxed);
+        self.received.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Bytes received since the download started, resumed bytes excepted.
+    pub fn received(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+
+    pub fn downloaded(&self) -> u64 {
+        self.downloaded.load(Ordering::Relaxed)
+    }
+
+    pub fn total(&self) -> Option<u64> {
+        Some(self.total.load(Ordering::Relaxed)).filter(|v| *v > 0)
+    }
+
+    /// Average bytes per second since the download started.
+    pub fn speed(&self) -> u64 {
+        let elapsed = self.started_at.elapsed().as_secs_f64();
+        if elapsed > 0.0 {
+            (self.received() as f64 / elapsed) as u64
+        } else {
+            0
+        }
+    }
+
+    /// Seconds left at the current speed, if the size is known.
+    pub fn eta(&self) -> Option<u64> {
+        let speed = self.speed();
+        let left = self.total()?.saturating_sub(self.downloaded());
+        (speed > 0).then(|| left.div_ceil(speed))
+    }
+}
+
+impl Default for DownloadProgress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A download of the record, with its progress while pending.
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct DownloadInfo {
+    pub file_id: String,
+    /// URL without its query, which identifies the file.
+    #[schema(value_type = String)]
+    pub url: Url,
+    pub status: DownloadStatus,
+    /// Folders under the ComfyUI root the file is linked into.
+    #[schema(value_type = Vec<String>)]
+    pub target_dirs: Vec<PathBuf>,
+    /// SHA-256 in hex of the completed file.
+    pub sha256: Option<String>,
+    /// Bytes downloaded so far, the file size once completed.
+    pub downloaded: u64,
+    /// File size, if known.
+    pub total: Option<u64>,
+    /// Bytes per second of a pending download.
+    pub speed: Option<u64>,
+    /// Estimated seconds left of a pending download.
+    pub eta: Option<u64>,
+    /// When the file was last used by a workflow, in Unix milliseconds.
+    pub last_used_at: Option<u64>,
+    /// How many times the file was used by workflows.
+    pub use_count: u64,
+    /// Whether the file is used by a running workflow, so it cannot be deleted.
+    pub pinned: bool,
+}
@@ -1,12 +1,28 @@
-use super::task::{DownloadStatus, DownloadTask};
+use super::{
+    lease::{Lease, Leases},
+    manage::CachePolicy,
+    progress::{DownloadInfo, DownloadProgress},
+    task::{DownloadOptions, DownloadStatus, DownloadTask, Expected},
+};
+use crate::{audit::AuditLog, fs::write_atomic, http::HttpClient, metrics::Metrics};
+use anyhow::Context;
 use serde::{Deserialize, Serialize};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
 use std::{
     collections::{HashMap, HashSet},
+    ffi::OsString,
     path::{Path, PathBuf},
+    time::Duration,
 };
-use tokio::sync::watch;
+use tokio::sync::{watch, Mutex, Notify, RwLock};
 use url::Url;
 
+/// Delay before writing the record again after a failed write.
+const SAVE_RETRY_DELAY: Duration = Duration::from_secs(1);
+
 #[derive(Clone, Debug)]
 pub struct DownloadState {
     inner: InnerState,
@@ -14,10 +30,29 @@ pub struct DownloadState {
     cache_dir: PathBuf,
     root_dir: PathBuf,
     max_cache_bytes: u64,
+    cache_policy: CachePolicy,
     notification: HashMap<String, watch::Receiver<DownloadStatus>>,
+    progress: HashMap<String, Arc<DownloadProgress>>,
+    leases: Leases,
+    audit_log: Option<Arc<RwLock<AuditLog>>>,
+    metrics: Option<Metrics>,
+    client: HttpClient,
+    options: DownloadOptions,
+    writer: Arc<RecordWriter>,
+}
+
+/// Writes of the record, see `save` and `save_record`.
+#[derive(Debug, Default)]
+struct RecordWriter {
+    /// Notified when the record changed.
+    changed: Notify,
+    /// Whether the record changed since last written.
+    dirty: AtomicBool,
+    /// Held while writing, so that writes are not interleaved.
+    lock: Mutex<()>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 struct InnerState {
     downloads: HashMap<String, DownloadTask>,
     // Url -> file_id (key of downloads)
@@ -26,57 +61,390 @@ struct InnerState {
     symlinks: HashMap<String, HashSet<PathBuf>>,
 }
 
-impl Default for InnerState {
-    fn default() -> Self {
-        Self {
-            downloads: HashMap::new(),
-            url_mapping: HashMap::new(),
-            symlinks: HashMap::new(),
-        }
-    }
-}
-
 impl DownloadState {
     pub async fn new(
         record_path: impl AsRef<Path>,
         root_dir: impl AsRef<Path>,
         cache_dir: impl AsRef<Path>,
         max_cache_bytes: u64,
-    ) -> Self {
+    ) -> anyhow::Result<Self> {
         let inner_state = {
             let record_path = record_path.as_ref();
             if record_path.exists() {
                 let json_str = tokio::fs::read_to_string(record_path)
                     .await
-                    .unwrap_or_default();
-                serde_json::from_str(&json_str).unwrap_or_default()
+                    .with_context(|| {
+                        format!("failed to read download record {}", record_path.display())
+                    })?;
+                match serde_json::from_str(&json_str) {
+                    Ok(inner_state) => inner_state,
+                    Err(e) => {
+                        // kept for inspection, the record is rebuilt by `reconcile`
+                        let mut corrupted_path = OsString::from(record_path);
+                        corrupted_path.push(".corrupted");
+                        tracing::error!(
+                            path = %record_path.display(),
+                            moved_to = %Path::new(&corrupted_path).display(),
+                            error = %e,
+                            "download record is corrupted"
+                        );
+                        if let Err(e) = tokio::fs::rename(record_path, &corrupted_path).await {
+                            tracing::warn!(error = %e, "failed to move corrupted download record");
+                        }
+                        InnerState::default()
+                    }
+                }
             } else {
                 InnerState::default()
             }
         };
 
-        Self {
+        let mut state = Self {
             inner: inner_state,
             record_path: record_path.as_ref().to_path_buf(),
             cache_dir: cache_dir.as_ref().to_path_buf(),
             root_dir: root_dir.as_ref().to_path_buf(),
             max_cache_bytes,
+            cache_policy: CachePolicy::default(),
             notification: HashMap::new(),
+            progress: HashMap::new(),
+            leases: Leases::default(),
+            audit_log: None,
+            metrics: None,
+            client: HttpClient::default(),
+            options: DownloadOptions::default(),
+            writer: Arc::new(RecordWriter::default()),
+        };
+        state.store_legacy_files().await;
+
+        Ok(state)
+    }
+
+    /// Move the hashed files still named by their file id to the blobs of their hash.
+    async fn store_legacy_files(&mut self) {
+        let legacy: Vec<_> = self
+            .downloads()
+            .filter(|v| v.status() == &DownloadStatus::Completed)
+            .filter_map(|v| Some((v.file_id().to_string(), v.size()?, v.sha256()?.to_string())))
+            .filter(|(file_id, _, _)| self.cache_dir.join(file_id).is_file())
+            .collect();
+
+        for (file_id, size, sha256) in legacy {
+            if let Err(e) = self.store_by_hash(&file_id, size, sha256).await {
+                tracing::warn!(file_id, error = %e, "failed to move cached file to its blob");
+            }
         }
     }
 
-    async fn dump(&self) -> anyhow::Result<()> {
+    /// Mark the record as changed, it is written by `save_record` or `save`.
+    fn dump(&self) {
+        self.writer.dirty.store(true, Ordering::SeqCst);
+        self.writer.changed.notify_one();
+    }
+
+    /// Write the record now if it changed, e.g. before exiting.
+    pub async fn save(&self) -> anyhow::Result<()> {
+        let _guard = self.writer.lock.lock().await;
+        if !self.writer.dirty.swap(false, Ordering::SeqCst) {
+            return Ok(());
+        }
+
         let json_str = serde_json::to_string(&self.inner)?;
-        tokio::fs::write(&self.record_path, json_str).await?;
+        if let Err(e) = write_atomic(&self.record_path, json_str).await {
+            self.writer.dirty.store(true, Ordering::SeqCst);
+            return Err(e);
+        }
         Ok(())
     }
 
+    /// Bring the record and the files back in line, e.g. after a crash or a lost record.
+    ///
+    /// Interrupted downloads are marked as failed, so that the next request resumes them.
+    /// Partial files of unknown downloads are deleted, completed downloads without their file
+    /// are dropped, and links are repaired. Links into the cache dir of unknown downloads are
+    /// deleted if dangling, and their downloads restored otherwise, under the `file` URL of
+    /// the link since the original URL is unknown.
+    ///
+    /// Unreadable folders and files are skipped with a warning rather than stopping the router.
+    pub async fn reconcile(&mut self) {
+        // the downloads recorded as pending were interrupted, e.g. by a crash
+        let interrupted: Vec<_> = self
+            .downloads()
+            .filter(|v| {
+                v.status() == &DownloadStatus::Pending
+                    && self.get_notification(v.file_id()).is_none()
+            })
+            .map(|v| v.file_id().to_string())
+            .collect();
+        for file_id in &interrupted {
+            tracing::info!(file_id, "interrupted download marked as failed");
+            self.update_status(file_id, DownloadStatus::Failed);
+        }
+
+        // the partial files of unfinished downloads are resumed by the next request
+        let resumable: HashSet<PathBuf> = self
+            .downloads()
+            .filter(|v| v.status() != &DownloadStatus::Completed)
+            .flat_map(|v| {
+                let download_path = self.cache_dir.join(v.file_id());
+                [
+                    download_path.with_extension("download"),
+                    download_path.with_extension("partial"),
+                ]
+            })
+            .collect();
+        let mut stale_files = 0;
+        if let Ok(mut read_dir) = tokio::fs::read_dir(&self.cache_dir).await {
+            loop {
+                let entry = match read_dir.next_entry().await {
+                    Ok(Some(entry)) => entry,
+                    Ok(None) => break,
+                    Err(e) => {
+                        tracing::warn!(path = %self.cache_dir.display(), error = %e, "failed to read cache dir");
+                        break;
+                    }
+                };
+                let path = entry.path();
+                let partial = matches!(
+                    path.extension().and_then(|v| v.to_str()),
+                    Some("download" | "partial")
+                );
+                if partial && !resumable.contains(&path) {
+                    match tokio::fs::remove_file(&path).await {
+                        Ok(()) => stale_files += 1,
+                        Err(e) => {
+                            tracing::warn!(path = %path.display(), error = %e, "failed to delete stale partial file")
+                        }
+                    }
+                }
+            }
+        }
+
+        let missing: Vec<_> = self
+            .downloads()
+            .filter(|v| v.status() == &DownloadStatus::Completed && !self.cache_path(v).is_file())
+            .map(|v| v.file_id().to_string())
+            .collect();
+        for file_id in &missing {
+            for target_dir in self.remove_target_dirs(file_id).unwrap_or_default() {
+                let _ = tokio::fs::remove_file(self.root_dir.join(target_dir).join(file_id)).await;
+            }
+            self.remove(file_id);
+        }
+
+        let (mut restored, mut removed_links) = (0, 0);
+        for (link, target) in links_into(&self.root_dir, &self.cache_dir).await {
+            let (Some(file_id), Some(target_dir)) = (
+                link.file_name().map(|v| v.to_string_lossy().to_string()),
+                link.parent()
+                    .and_then(|v| v.strip_prefix(&self.root_dir).ok())
+                    .map(|v| v.to_path_buf()),
+            ) else {
+                continue;
+            };
+
+            match self.get_by_id(&file_id) {
+                Some(task) if task.status() == &DownloadStatus::Completed => {
+                    self.add_target_dir(&file_id, target_dir);
+                }
+                Some(_) => {}
+                None if target.is_file() => {
+                    let file_name = target.file_name().unwrap_or_default().to_string_lossy();
+                    let sha256 = (file_name.len() == 64
+                        && file_name.chars().all(|c| c.is_ascii_hexdigit()))
+                    .then(|| file_name.to_string());
+                    // the file is not one of the cache dir otherwise
+                    if sha256.is_none() && file_name != file_id {
+                        continue;
+                    }
+
+                    let url = std::path::absolute(&link)
+                        .ok()
+                        .and_then(|v| Url::from_file_path(v).ok());
+                    let Some(url) = url else {
+                        continue;
+                    };
+                    let mut task = DownloadTask::restored(&file_id, url);
+                    if let Some(sha256) = sha256 {
+                        let size = match tokio::fs::metadata(&target).await {
+                            Ok(metadata) => metadata.len(),
+                            Err(e) => {
+                                tracing::warn!(path = %target.display(), error = %e, "failed to read cached file");
+                                continue;
+                            }
+                        };
+                        task.with_content(size, sha256);
+                    }
+                    tracing::info!(file_id, link = %link.display(), "download restored from link");
+                    self.add(task);
+                    self.add_target_dir(&file_id, target_dir);
+                    restored += 1;
+                }
+                None => match tokio::fs::remove_file(&link).await {
+                    Ok(()) => removed_links += 1,
+                    Err(e) => {
+                        tracing::warn!(link = %link.display(), error = %e, "failed to delete dangling link")
+                    }
+                },
+            }
+        }
+
+        let mut relinked = 0;
+        let completed: Vec<_> = self
+            .downloads()
+            .filter(|v| v.status() == &DownloadStatus::Completed)
+            .map(|v| v.file_id().to_string())
+            .collect();
+        for file_id in completed {
+            let (Some(task), Some(target_dirs)) =
+                (self.get_by_id(&file_id), self.target_dirs(&file_id))
+            else {
+                continue;
+            };
+            let cache_path = self.cache_path(task);
+            let mut broken = false;
+            for target_dir in target_dirs {
+                let link = self.root_dir.join(target_dir).join(&file_id);
+                broken |= tokio::fs::read_link(&link).await.ok().as_ref() != Some(&cache_path);
+            }
+            if broken {
+                self.link(&file_id).await;
+                relinked += 1;
+            }
+        }
+
+        tracing::info!(
+            interrupted = interrupted.len(),
+            stale_files,
+            missing_files = missing.len(),
+            restored,
+            removed_links,
+            relinked,
+            "download record reconciled"
+        );
+    }
+
+    pub fn downloads(&self) -> impl Iterator<Item = &DownloadTask> {
+        self.inner.downloads.values()
+    }
+
     pub fn get_by_id(&self, id: &str) -> Option<&DownloadTask> {
         self.inner.downloads.get(id)
     }
 
+    /// The cached file of a completed download: the blob named by its hash, shared by the
+    /// downloads of the same content, or the file named by its file id if not hashed yet.
+    pub fn cache_path(&self, task: &DownloadTask) -> PathBuf {
+        self.cache_dir.join(task.sha256().unwrap_or(task.file_id()))
+    }
+
+    /// File ids of the downloads stored in `file_name` of the cache dir.
+    pub fn stored_in(&self, file_name: &str) -> Vec<String> {
+        self.downloads()
+            .filter(|v| v.sha256().unwrap_or(v.file_id()) == file_name)
+            .map(|v| v.file_id().to_string())
+            .collect()
+    }
+
+    /// Whether the cached file of `file_id` is shared by other downloads.
+    pub fn is_shared(&self, file_id: &str) -> bool {
+        let Some(task) = self.get_by_id(file_id) else {
+            return false;
+        };
+        let file_name = task.sha256().unwrap_or(task.file_id());
+        self.stored_in(file_name).len() > 1
+    }
+
+    /// Whether the download is pinned by a lease, e.g. of a running workflow.
+    pub fn is_pinned(&self, file_id: &str) -> bool {
+        self.leases.is_pinned(file_id)
+    }
+
+    /// Whether `file_name` of the cache dir is used: the file of a download pinned by a lease,
+    /// or the partial file of a running download.
+    pub fn in_use(&self, file_name: &str) -> bool {
+        let stored = self.stored_in(file_name);
+        if stored.iter().any(|v| self.leases.is_pinned(v)) {
+            return true;
+        }
+
+        self.downloads()
+            .filter(|v| v.status() == &DownloadStatus::Pending)
+            .any(|v| {
+                let download_path = self.cache_dir.join(v.file_id());
+                ["download", "partial"].into_iter().any(|extension| {
+                    download_path.with_extension(extension).file_name() == Some(file_name.as_ref())
+                })
+            })
+    }
+
+    /// When `file_name` of the cache dir was last used by a workflow, in Unix milliseconds,
+    /// `None` if not recorded.
+    pub fn last_used_at(&self, file_name: &str) -> Option<u64> {
+        self.stored_in(file_name)
+            .iter()
+            .filter_map(|v| self.get_by_id(v)?.last_used_at())
+            .max()
+    }
+
+    /// A completed download of the content `expected` by its hash, e.g. from another mirror.
+    pub fn find_by_content(&self, expected: &Expected) -> Option<&DownloadTask> {
+        let sha256 = expected.sha256.as_deref()?;
+        self.downloads().find(|v| {
+            v.status() == &DownloadStatus::Completed
+                && v.sha256() == Some(sha256)
+                && expected.matches(v)
+                && self.cache_path(v).is_file()
+        })
+    }
+
+    /// The download with its progress while pending.
+    pub async fn info(&self, task: &DownloadTask) -> DownloadInfo {
+        let file_size =
+            |path: PathBuf| async move { tokio::fs::metadata(path).await.ok().map(|v| v.len()) };
+        let (downloaded, total, speed, eta) = match (task.status(), self.progress(task.file_id())) {
+            (DownloadStatus::Pending, Some(progress)) => (
+                progress.downloaded(),
+                progress.total(),
+                Some(progress.speed()),
+                progress.eta(),
+            ),
+            (DownloadStatus::Completed, _) => {
+                let size = file_size(self.cache_path(task)).await;
+                (size.unwrap_or(0), size, None, None)
+            }
+            // what is left of an interrupted download, resumed by the next request
+            _ => {
+                let download_path = self.cache_dir.join(task.file_id());
+                let size = file_size(download_path.with_extension("download")).await;
+                (size.unwrap_or(0), None, None, None)
+            }
+        };
+
+        let mut target_dirs: Vec<_> = self
+            .target_dirs(task.file_id())
+            .map(|v| v.iter().cloned().collect())
+            .unwrap_or_default();
+        target_dirs.sort();
+
+        DownloadInfo {
+            file_id: task.file_id().to_string(),
+            url: task.url().clone(),
+            status: task.status().clone(),
+            target_dirs,
+            sha256: task.sha256().map(|v| v.to_string()),
+            downloaded,
+            total,
+            speed,
+            eta,
+            last_used_at: task.last_used_at(),
+            use_count: task.use_count(),
+            pinned: self.is_pinned(task.file_id()),
+        }
+    }
+
     pub fn get_by_url(&self, url: &Url) -> Option<&DownloadTask> {
-        let file_id = self.inner.url_mapping.get(url).map(|v| v.clone());
+        let file_id = self.inner.url_mapping.get(url).cloned();
 
         match file_id {
             Some(file_id) => self.get_by_id(&file_id),
@@ -84,22 +452,16 @@ impl DownloadState {
         }
     }
 
-    pub async fn add(&mut self, task: DownloadTask) -> anyhow::Result<()> {
+    pub fn add(&mut self, task: DownloadTask) {
         let file_id = task.file_id().to_string();
         let url = task.url().clone();
         self.inner.downloads.insert(file_id.clone(), task);
         self.inner.url_mapping.insert(url, file_id);
 
-        self.dump().await?;
-
-        Ok(())
+        self.dump();
     }
 
-    pub async fn add_target_dir(
-        &mut self,
-        file_id: &str,
-        target_dir: impl AsRef<Path>,
-    ) -> anyhow::Result<()> {
+    pub fn add_target_dir(&mut self, file_id: &str, target_dir: impl AsRef<Path>) {
         let target_dir = target_dir.as_ref().to_path_buf();
 
         match self.inner.symlinks.get_mut(file_id) {
@@ -113,27 +475,76 @@ impl DownloadState {
             }
         }
 
-        self.dump().await?;
+        self.dump();
+    }
 
-        Ok(())
+    /// Record that a workflow uses the file of `file_id` now, saved with the next change.
+    pub fn touch(&mut self, file_id: &str) {
+        if let Some(task) = self.inner.downloads.get_mut(file_id) {
+            task.touch();
+        }
     }
 
-    pub async fn update_status(
+    pub fn update_status(&mut self, file_id: &str, status: DownloadStatus) {
+        if let Some(task) = self.inner.downloads.get_mut(file_id) {
+            task.with_status(status);
+        }
+
+        self.dump();
+    }
+
+    /// Record the size and hash of a downloaded file.
+    pub fn set_content(&mut self, file_id: &str, size: u64, sha256: String) {
+        if let Some(task) = self.inner.downloads.get_mut(file_id) {
+            task.with_content(size, sha256);
+        }
+
+        self.dump();
+    }
+
+    /// Record the size and hash of a file cached under its file id before files were stored
+    /// by hash, and move it to the blob of its hash.
+    pub async fn store_by_hash(
         &mut self,
         file_id: &str,
-        status: DownloadStatus,
+        size: u64,
+        sha256: String,
     ) -> anyhow::Result<()> {
-        match self.inner.downloads.get_mut(file_id) {
-            Some(task) => task.with_status(status),
-            _ => (),
+        let file_path = self.cache_dir.join(file_id);
+        let blob_path = self.cache_dir.join(&sha256);
+        if tokio::fs::try_exists(&blob_path).await? {
+            tokio::fs::remove_file(&file_path).await?;
+        } else {
+            tokio::fs::rename(&file_path, &blob_path).await?;
         }
 
-        self.dump().await?;
+        self.set_content(file_id, size, sha256);
+        self.link(file_id).await;
 
         Ok(())
     }
 
-    pub async fn remove(&mut self, file_id: &str) -> anyhow::Result<Option<DownloadTask>> {
+    /// Link the cached file of `file_id` into its target dirs, replacing existing links.
+    pub async fn link(&self, file_id: &str) {
+        let (Some(task), Some(target_dirs)) = (self.get_by_id(file_id), self.target_dirs(file_id))
+        else {
+            return;
+        };
+        let cache_path = self.cache_path(task);
+
+        for target_dir in target_dirs {
+            let dst = self.root_dir.join(target_dir);
+            let _ = tokio::fs::create_dir_all(&dst).await;
+
+            let dst = dst.join(file_id);
+            let _ = tokio::fs::remove_file(&dst).await;
+            if let Err(e) = tokio::fs::symlink(&cache_path, &dst).await {
+                tracing::warn!(file_id, error = %e, "failed to create symlink");
+            }
+        }
+    }
+
+    pub fn remove(&mut self, file_id: &str) -> Option<DownloadTask> {
         let removed = self.inner.downloads.remove(file_id);
 
         if let Some(task) = &removed {
@@ -142,9 +553,9 @@ impl DownloadState {
 
         self.inner.symlinks.remove(file_id);
 
-        self.dump().await?;
+        self.dump();
 
-        Ok(removed)
+        removed
     }
 
     pub fn target_dirs(&self, file_id: &str) -> Option<&HashSet<PathBuf>> {
@@ -167,8 +578,75 @@ impl DownloadState {
         self.max_cache_bytes
     }
 
+    /// Change the cache limit, call `manage_cache` to apply a lower limit to the cached files.
+    pub fn set_max_cache_bytes(&mut self, max_cache_bytes: u64) {
+        self.max_cache_bytes = max_cache_bytes;
+    }
+
+    pub fn cache_policy(&self) -> &CachePolicy {
+        &self.cache_policy
+    }
+
+    /// Change how files are evicted, applied by the next `manage_cache`.
+    pub fn set_cache_policy(&mut self, cache_policy: CachePolicy) {
+        self.cache_policy = cache_policy;
+    }
+
+    /// Audit log that cache evictions are recorded to.
+    pub fn audit_log(&self) -> Option<Arc<RwLock<AuditLog>>> {
+        self.audit_log.clone()
+    }
+
+    pub fn set_audit_log(&mut self, audit_log: Arc<RwLock<AuditLog>>) {
+        self.audit_log = Some(audit_log);
+    }
+
+    /// Metrics that downloads and cache usage are recorded to.
+    pub fn metrics(&self) -> Option<Metrics> {
+        self.metrics.clone()
+    }
+
+    pub fn set_metrics(&mut self, metrics: Metrics) {
+        self.metrics = Some(metrics);
+    }
+
+    /// Client the files are downloaded with.
+    pub fn client(&self) -> &HttpClient {
+        &self.client
+    }
+
+    pub fn set_client(&mut self, client: HttpClient) {
+        self.client = client;
+    }
+
+    pub fn options(&self) -> &DownloadOptions {
+        &self.options
+    }
+
+    pub fn set_options(&mut self, options: DownloadOptions) {
+        self.options = options;
+    }
+
+    /// A lease to pin the files used by a workflow, released when dropped.
+    pub fn lease(&self) -> Lease {
+        self.leases.lease()
+    }
+
+    /// Progress of a running download.
+    pub fn progress(&self, file_id: &str) -> Option<Arc<DownloadProgress>> {
+        self.progress.get(file_id).cloned()
+    }
+
+    pub fn set_progress(&mut self, file_id: &str, progress: Arc<DownloadProgress>) {
+        self.progress.insert(file_id.to_string(), progress);
+    }
+
+    pub fn remove_progress(&mut self, file_id: &str) -> Option<Arc<DownloadProgress>> {
+        self.progress.remove(file_id)
+    }
+
     pub fn get_notification(&self, file_id: &str) -> Option<watch::Receiver<DownloadStatus>> {
-        self.notification.get(file_id).map(|v| v.clone())
+        self.notification.get(file_id).cloned()
     }
 
     pub fn set_notification(
@@ -179,7 +657,76 @@ impl DownloadState {
         self.notification.insert(file_id.to_string(), notification);
     }
 
-    pub fn remove_notification(&mut self, file_id: &str) -> Option<watch::Receiver<DownloadStatus>> {
+    pub fn remove_notification(
+        &mut self,
+        file_id: &str,
+    ) -> Option<watch::Receiver<DownloadStatus>> {
         self.notification.remove(file_id)
     }
 }
+
+/// Links under `root_dir` to files of `cache_dir`, with their targets.
+/// Linked directories are not followed, unreadable ones are skipped.
+async fn links_into(root_dir: &Path, cache_dir: &Path) -> Vec<(PathBuf, PathBuf)> {
+    let mut links = vec![];
+    let mut dirs = vec![root_dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let result = async {
+            let mut read_dir = match tokio::fs::read_dir(&dir).await {
+                Ok(read_dir) => read_dir,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            while let Some(entry) = read_dir.next_entry().await? {
+                let file_type = entry.file_type().await?;
+                if file_type.is_symlink() {
+                    let target = tokio::fs::read_link(entry.path()).await?;
+                    if target.parent() == Some(cache_dir) {
+                        links.push((entry.path(), target));
+                    }
+                } else if file_type.is_dir() {
+                    dirs.push(entry.path());
+                }
+            }
+            Ok(())
+        }
+        .await;
+        if let Err(e) = result {
+            tracing::warn!(dir = %dir.display(), error = %e, "failed to read folder, its links are skipped");
+        }
+    }
+
+    links
+}
+
+/// Save the record after each change until the router stops. The changes made while
+/// the record is written are saved together by the next write.
+pub async fn save_record(download_state: Arc<RwLock<DownloadState>>) {
+    let writer = download_state.read().await.writer.clone();
+    loop {
+        writer.changed.notified().await;
+
+        let (record_path, json_str) = {
+            let state = download_state.read().await;
+            let guard = writer.lock.lock().await;
+            if !writer.dirty.swap(false, Ordering::SeqCst) {
+                continue;
+            }
+            match serde_json::to_string(&state.inner) {
+                Ok(json_str) => (state.record_path.clone(), (json_str, guard)),
+                Err(e) => {
+                    tracing::warn!(error = %e, "failed to serialize download record");
+                    continue;
+                }
+            }
+        };
+
+        let (json_str, _guard) = json_str;
+        if let Err(e) = write_atomic(&record_path, json_str).await {
+            tracing::warn!(path = %record_path.display(), error = %e, "failed to save download record");
+            writer.dirty.store(true, Ordering::SeqCst);
+            tokio::time::sleep(SAVE_RETRY_DELAY).await;
+            writer.changed.notify_one();
+        }
+    }
+}
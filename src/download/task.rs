@@ -1,11 +1,30 @@
-use futures_util::StreamExt;
-use reqwest::Client;
+use super::progress::DownloadProgress;
+use crate::{
+    config::{DownloadConfig, HttpConfig},
+    http::HttpClient,
+};
+use futures_util::{StreamExt, TryStreamExt};
+use reqwest::{
+    header::{CONTENT_RANGE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE},
+    Response, StatusCode,
+};
 use serde::{Deserialize, Serialize};
-use std::path::{Path, PathBuf};
-use tokio::io::AsyncWriteExt;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    sync::Mutex,
+};
 use url::Url;
+use utoipa::ToSchema;
 
-#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum DownloadStatus {
     Pending,
@@ -13,22 +32,52 @@ pub enum DownloadStatus {
     Failed,
 }
 
+impl DownloadStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DownloadStatus::Pending => "pending",
+            DownloadStatus::Completed => "completed",
+            DownloadStatus::Failed => "failed",
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct DownloadTask {
-    /// The URL used to identify the file, without any search parameters.
+    /// The URL used to identify the file, see `DownloadOptions::identity`.
     url: Url,
     downloadable_url: Url,
     status: DownloadStatus,
     file_id: String,
+    /// Size and SHA-256 in hex of the completed file, stored in the cache dir under its hash.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sha256: Option<String>,
+    /// Unix timestamp in milliseconds of the last workflow using the file,
+    /// not recorded by older versions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_used_at: Option<u64>,
+    /// Number of workflows that used the file.
+    #[serde(default)]
+    use_count: u64,
+}
+
+pub(crate) fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|v| v.as_millis() as u64)
+        .unwrap_or_default()
 }
 
 impl DownloadTask {
-    pub fn new(url: &Url) -> Self {
+    /// A download of `url`, identified by `identity`.
+    pub fn new(url: &Url, identity: Url) -> Self {
         let mut file_id = uuid::Uuid::new_v4().to_string();
 
         // preserve the file extension if any
-        if let Some(segments) = url.path_segments() {
-            if let Some(file_name) = segments.last() {
+        if let Some(mut segments) = url.path_segments() {
+            if let Some(file_name) = segments.next_back() {
                 let file_path = PathBuf::from(file_name);
                 if let Some(extension) = file_path.extension() {
                     file_id = format!("{}.{}", file_id, extension.to_string_lossy());
@@ -36,50 +85,312 @@ impl DownloadTask {
             }
         }
 
-        let downloadable_url = url.clone();
-        let mut url = url.clone();
-        url.set_query(None);
-
         Self {
-            url,
-            downloadable_url,
+            url: identity,
+            downloadable_url: url.clone(),
             status: DownloadStatus::Pending,
             file_id,
+            size: None,
+            sha256: None,
+            last_used_at: None,
+            use_count: 0,
         }
     }
 
-    #[tracing::instrument(skip_all, fields(file_id = self.file_id))]
-    pub async fn run(&self, cache_dir: impl AsRef<Path>) -> anyhow::Result<()> {
-        tracing::info!("task started {}", &self.file_id);
+    /// The completed download of `file_id` found in the cache dir without its record,
+    /// known by `url` since the original URL is lost.
+    pub fn restored(file_id: &str, url: Url) -> Self {
+        Self {
+            url: url.clone(),
+            downloadable_url: url,
+            status: DownloadStatus::Completed,
+            file_id: file_id.to_string(),
+            size: None,
+            sha256: None,
+            last_used_at: None,
+            use_count: 0,
+        }
+    }
 
-        let cache_path = cache_dir.as_ref().to_path_buf().join(self.file_id());
-        let download_path = cache_path.with_extension("download");
+    /// Download the file into the cache dir, and check it against `expected`.
+    ///
+    /// The file is stored under its SHA-256, so the same content downloaded from another URL
+    /// is only kept once.
+    ///
+    /// The partial file left by a failed or interrupted download is resumed with range requests,
+    /// and so is the download after a transient error, up to `options.retries` times.
+    /// A file not matching `expected` is deleted.
+    #[tracing::instrument(name = "download", skip_all, fields(file_id = self.file_id, url = %self.url))]
+    pub async fn run(
+        &self,
+        client: &HttpClient,
+        cache_dir: impl AsRef<Path>,
+        options: &DownloadOptions,
+        expected: &Expected,
+        progress: &DownloadProgress,
+    ) -> anyhow::Result<DownloadedFile> {
+        tracing::info!("download started");
 
+        let cache_path = cache_dir.as_ref().to_path_buf().join(self.file_id());
         if let Some(parent) = cache_path.parent() {
             tokio::fs::create_dir_all(parent).await?;
         }
 
-        let client = Client::new();
-        let res = client.get(self.downloadable_url().clone()).send().await?;
-        let total_size = res.content_length().unwrap_or(0);
+        let mut backoff = options.retry_backoff;
+        for attempt in 1.. {
+            let e = match self.attempt(client, &cache_path, options, progress).await {
+                Ok(()) => break,
+                Err(e) => e,
+            };
 
-        let mut file = tokio::fs::File::create(&download_path).await?;
-        let mut downloaded: u64 = 0;
-        let mut stream = res.bytes_stream();
+            if e.is::<RemoteChanged>() {
+                discard_partial(&cache_path).await;
+            }
+            if attempt > options.retries || !is_transient(&e) {
+                return Err(e);
+            }
+
+            tracing::warn!(
+                attempt,
+                error = format!("{:#}", e),
+                "download interrupted, resuming"
+            );
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+
+        let download_path = cache_path.with_extension("download");
+        let size = file_size(&download_path).await;
+        let sha256 = file_sha256(&download_path).await?;
+        if let Err(e) = expected.check(size, &sha256) {
+            // nothing to resume, the next request downloads it again
+            discard_partial(&cache_path).await;
+            return Err(e);
+        }
+
+        // replacing the same content if already downloaded from another URL, so that a blob
+        // evicted meanwhile is restored
+        tokio::fs::rename(&download_path, cache_dir.as_ref().join(&sha256)).await?;
+        let _ = tokio::fs::remove_file(cache_path.with_extension("partial")).await;
+
+        let received = progress.received();
+        tracing::debug!(bytes = received, size, sha256, "download finished");
+
+        Ok(DownloadedFile {
+            received,
+            size,
+            sha256,
+        })
+    }
+
+    /// Download what is missing from the partial file, in order or in chunks.
+    async fn attempt(
+        &self,
+        client: &HttpClient,
+        cache_path: &Path,
+        options: &DownloadOptions,
+        progress: &DownloadProgress,
+    ) -> anyhow::Result<()> {
+        let download_path = cache_path.with_extension("download");
+        let (offset, validator) = match Partial::load(cache_path).await {
+            Some(partial) if partial.ranges.is_some() => {
+                let ranges = partial.ranges.as_deref().unwrap_or_default();
+                let total = ranges.last().map_or(0, |(_, end)| *end);
+                let left: u64 = ranges.iter().map(|(start, end)| end - start).sum();
+                progress.reset(total - left, Some(total));
+
+                return self
+                    .download_ranges(client, cache_path, partial, None, options, progress)
+                    .await;
+            }
+            Some(partial) => (file_size(&download_path).await, partial.validator),
+            None => (0, None),
+        };
+
+        let mut request = client.get(self.downloadable_url().clone());
+        if offset > 0 {
+            request = request.header(RANGE, format!("bytes={}-", offset));
+            if let Some(validator) = &validator {
+                request = request.header(IF_RANGE, validator);
+            }
+        } else if options.parallel_chunks > 1 {
+            // the first chunk tells whether the server supports ranges, and the file size
+            request = request.header(RANGE, format!("bytes=0-{}", options.chunk_size - 1));
+        }
+
+        let res = client.send(request).await?;
+        if res.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            return Err(RemoteChanged.into());
+        }
+        let res = res.error_for_status()?;
+        let validator = response_validator(&res);
+
+        match (res.status(), content_range(&res)) {
+            (StatusCode::PARTIAL_CONTENT, Some((start, _, total))) if offset > 0 => {
+                if start != offset {
+                    return Err(RemoteChanged.into());
+                }
+                progress.reset(offset, Some(total));
+
+                let mut file = OpenOptions::new().append(true).open(&download_path).await?;
+                write_body(res, &mut file, progress).await?;
+                check_size(&download_path, total).await
+            }
+            (StatusCode::PARTIAL_CONTENT, Some((0, end, total))) if end + 1 < total => {
+                let ranges = (0..total)
+                    .step_by(options.chunk_size as usize)
+                    .map(|start| (start, (start + options.chunk_size).min(total)))
+                    .collect();
+                let partial = Partial {
+                    validator,
+                    ranges: Some(ranges),
+                };
 
+                let file = File::create(&download_path).await?;
+                file.set_len(total).await?;
+                progress.reset(0, Some(total));
+                partial.save(cache_path).await?;
+
+                self.download_ranges(client, cache_path, partial, Some(res), options, progress)
+                    .await
+            }
+            (StatusCode::PARTIAL_CONTENT, Some((0, _, _))) | (StatusCode::OK, _) => {
+                // the server ignored the range, the file changed since the partial download,
+                // or it fits in the first chunk
+                let total = content_range(&res)
+                    .map(|(_, _, total)| total)
+                    .or(res.content_length());
+                Partial {
+                    validator,
+                    ranges: None,
+                }
+                .save(cache_path)
+                .await?;
+                progress.reset(0, total);
+
+                let mut file = File::create(&download_path).await?;
+                write_body(res, &mut file, progress).await?;
+                match total {
+                    Some(total) => check_size(&download_path, total).await,
+                    None => Ok(()),
+                }
+            }
+            _ => anyhow::bail!(
+                "unexpected {} response, content range {:?}",
+                res.status(),
+                res.headers().get(CONTENT_RANGE)
+            ),
+        }
+    }
+
+    /// Download the ranges left in `partial` with up to `options.parallel_chunks` requests at once,
+    /// `first` being the response of the first range if already requested.
+    async fn download_ranges(
+        &self,
+        client: &HttpClient,
+        cache_path: &Path,
+        partial: Partial,
+        first: Option<Response>,
+        options: &DownloadOptions,
+        progress: &DownloadProgress,
+    ) -> anyhow::Result<()> {
+        let count = partial.ranges.as_ref().map_or(0, |v| v.len());
+        let partial = Mutex::new(partial);
+
+        let mut first = first;
+        let result = futures_util::stream::iter(0..count)
+            .map(|index| {
+                let res = if index == 0 { first.take() } else { None };
+                self.download_range(client, cache_path, &partial, index, res, progress)
+            })
+            .buffer_unordered(options.parallel_chunks)
+            .try_collect::<Vec<_>>()
+            .await;
+
+        if result.is_err() {
+            // keep the progress of the ranges interrupted with the others
+            if let Err(e) = partial.lock().await.save(cache_path).await {
+                tracing::warn!(error = %e, "failed to save download progress");
+            }
+        }
+
+        result.map(|_| ())
+    }
+
+    async fn download_range(
+        &self,
+        client: &HttpClient,
+        cache_path: &Path,
+        partial: &Mutex<Partial>,
+        index: usize,
+        res: Option<Response>,
+        progress: &DownloadProgress,
+    ) -> anyhow::Result<()> {
+        let (validator, (start, end)) = {
+            let partial = partial.lock().await;
+            let ranges = partial.ranges.as_ref().expect("ranges should be planned");
+            (partial.validator.clone(), ranges[index])
+        };
+        if start >= end {
+            return Ok(());
+        }
+
+        let res = match res {
+            Some(res) => res,
+            None => {
+                let mut request = client
+                    .get(self.downloadable_url().clone())
+                    .header(RANGE, format!("bytes={}-{}", start, end - 1));
+                if let Some(validator) = &validator {
+                    request = request.header(IF_RANGE, validator);
+                }
+
+                let res = client.send(request).await?;
+                if res.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+                    return Err(RemoteChanged.into());
+                }
+                let res = res.error_for_status()?;
+                match content_range(&res) {
+                    Some((range_start, _, _)) if range_start == start => res,
+                    _ => return Err(RemoteChanged.into()),
+                }
+            }
+        };
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(cache_path.with_extension("download"))
+            .await?;
+        file.seek(SeekFrom::Start(start)).await?;
+
+        let mut position = start;
+        let mut stream = res.bytes_stream();
         while let Some(item) = stream.next().await {
             let chunk = item?;
-            file.write_all(&chunk).await?;
-            let new = std::cmp::min(downloaded + (chunk.len() as u64), total_size);
-            downloaded = new;
-        }
+            // servers may send more than the range asked for
+            let len = chunk.len().min((end - position) as usize);
+            file.write_all(&chunk[..len]).await?;
+            position += len as u64;
+            progress.add(len as u64);
 
+            if let Some(ranges) = partial.lock().await.ranges.as_mut() {
+                ranges[index].0 = position;
+            }
+            if position >= end {
+                break;
+            }
+        }
         file.flush().await?;
-        tokio::fs::rename(&download_path, &cache_path).await?;
 
-        tracing::debug!("task completed {}", self.file_id());
+        if position < end {
+            anyhow::bail!(
+                "incomplete chunk: {}/{} bytes",
+                position - start,
+                end - start
+            );
+        }
 
-        Ok(())
+        partial.lock().await.save(cache_path).await
     }
 
     pub fn url(&self) -> &Url {
@@ -101,4 +412,262 @@ impl DownloadTask {
     pub fn with_status(&mut self, status: DownloadStatus) {
         self.status = status;
     }
+
+    /// Size of the completed file, not recorded by older versions.
+    pub fn size(&self) -> Option<u64> {
+        self.size
+    }
+
+    /// SHA-256 in hex of the completed file, not recorded by older versions.
+    pub fn sha256(&self) -> Option<&str> {
+        self.sha256.as_deref()
+    }
+
+    pub fn last_used_at(&self) -> Option<u64> {
+        self.last_used_at
+    }
+
+    pub fn use_count(&self) -> u64 {
+        self.use_count
+    }
+
+    /// Record that a workflow uses the file now.
+    pub fn touch(&mut self) {
+        self.last_used_at = Some(now_millis());
+        self.use_count += 1;
+    }
+
+    pub fn with_content(&mut self, size: u64, sha256: String) {
+        self.size = Some(size);
+        self.sha256 = Some(sha256);
+    }
+
+    /// The task to download the file again from `url` after a failure,
+    /// resuming its partial file if any.
+    pub fn retry(&self, url: &Url) -> Self {
+        Self {
+            downloadable_url: url.clone(),
+            status: DownloadStatus::Pending,
+            ..self.clone()
+        }
+    }
+}
+
+/// A downloaded file, see `DownloadTask::run`.
+#[derive(Clone, Debug)]
+pub struct DownloadedFile {
+    /// Bytes received, less than `size` if a partial file was resumed.
+    pub received: u64,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// Size and SHA-256 a file is expected to have, e.g. as published with a model.
+#[derive(Clone, Debug, Default)]
+pub struct Expected {
+    /// SHA-256 in lowercase hex.
+    pub sha256: Option<String>,
+    pub size: Option<u64>,
+}
+
+impl Expected {
+    pub fn check(&self, size: u64, sha256: &str) -> anyhow::Result<()> {
+        if let Some(expected) = self.size.filter(|v| *v != size) {
+            anyhow::bail!("size mismatch: expected {} bytes, got {}", expected, size);
+        }
+        if let Some(expected) = self.sha256.as_deref().filter(|v| *v != sha256) {
+            anyhow::bail!("sha256 mismatch: expected {}, got {}", expected, sha256);
+        }
+        Ok(())
+    }
+
+    /// Whether the recorded size and hash of `task` are expected, unknown ones are.
+    pub fn matches(&self, task: &DownloadTask) -> bool {
+        let size_matches = match (self.size, task.size()) {
+            (Some(expected), Some(size)) => expected == size,
+            _ => true,
+        };
+        let sha256_matches = match (self.sha256.as_deref(), task.sha256()) {
+            (Some(expected), Some(sha256)) => expected == sha256,
+            _ => true,
+        };
+        size_matches && sha256_matches
+    }
+}
+
+/// How files are downloaded, see `DownloadConfig`.
+#[derive(Clone, Debug)]
+pub struct DownloadOptions {
+    pub retries: u32,
+    pub retry_backoff: Duration,
+    pub parallel_chunks: usize,
+    pub chunk_size: u64,
+    pub keep_query: BTreeMap<String, Vec<String>>,
+}
+
+impl DownloadOptions {
+    pub fn new(download: &DownloadConfig, http: &HttpConfig) -> Self {
+        Self {
+            retries: download.retries,
+            retry_backoff: Duration::from_millis(http.retry_backoff_ms),
+            parallel_chunks: download.parallel_chunks,
+            chunk_size: download.chunk_size,
+            keep_query: download.keep_query.clone(),
+        }
+    }
+
+    /// The URL identifying the file of `url`, without the query parameters not kept
+    /// for its host by `keep_query`. Kept ones are sorted, so their order does not matter.
+    pub fn identity(&self, url: &Url) -> Url {
+        let mut identity = url.clone();
+        identity.set_query(None);
+
+        let host = url.host_str().unwrap_or_default();
+        let kept = self.keep_query.get(host).or_else(|| {
+            self.keep_query
+                .iter()
+                .find(|(pattern, _)| {
+                    pattern
+                        .strip_prefix("*.")
+                        .is_some_and(|domain| host.ends_with(&format!(".{}", domain)))
+                })
+                .map(|(_, params)| params)
+        });
+
+        if let Some(kept) = kept {
+            let mut pairs: Vec<_> = url
+                .query_pairs()
+                .filter(|(key, _)| kept.iter().any(|v| v == "*" || v == key))
+                .collect();
+            pairs.sort();
+            if !pairs.is_empty() {
+                identity.query_pairs_mut().extend_pairs(pairs);
+            }
+        }
+
+        identity
+    }
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        Self::new(&DownloadConfig::default(), &HttpConfig::default())
+    }
+}
+
+/// Progress of an unfinished download, saved next to its `.download` file as `.partial`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Partial {
+    /// Strong ETag or Last-Modified of the remote file, sent in `If-Range` so that
+    /// partial content is only resumed if the file did not change.
+    validator: Option<String>,
+    /// Byte ranges `[start, end)` left to download in chunks,
+    /// `None` when downloaded in order and resumed from the length of the file.
+    ranges: Option<Vec<(u64, u64)>>,
+}
+
+impl Partial {
+    /// Progress of the download into `cache_path`, `None` if there is no partial file to resume.
+    async fn load(cache_path: &Path) -> Option<Self> {
+        if !tokio::fs::try_exists(cache_path.with_extension("download"))
+            .await
+            .unwrap_or(false)
+        {
+            return None;
+        }
+
+        let json_str = tokio::fs::read_to_string(cache_path.with_extension("partial"))
+            .await
+            .ok()?;
+        serde_json::from_str(&json_str).ok()
+    }
+
+    async fn save(&self, cache_path: &Path) -> anyhow::Result<()> {
+        let json_str = serde_json::to_string(self)?;
+        tokio::fs::write(cache_path.with_extension("partial"), json_str).await?;
+        Ok(())
+    }
+}
+
+/// Delete the partial file of the download into `cache_path` and its progress.
+pub async fn discard_partial(cache_path: impl AsRef<Path>) {
+    let cache_path = cache_path.as_ref();
+    let _ = tokio::fs::remove_file(cache_path.with_extension("download")).await;
+    let _ = tokio::fs::remove_file(cache_path.with_extension("partial")).await;
+}
+
+/// The remote file changed since the partial file was downloaded, which is downloaded again.
+#[derive(Debug, thiserror::Error)]
+#[error("remote file changed since the download started")]
+struct RemoteChanged;
+
+/// Whether the transfer was interrupted and may succeed if resumed. Failed requests are not,
+/// the client already retried them, and neither are local errors such as a full disk.
+fn is_transient(e: &anyhow::Error) -> bool {
+    if let Some(e) = e.downcast_ref::<reqwest::Error>() {
+        return e.status().is_none() && !e.is_connect() && !e.is_builder();
+    }
+    e.downcast_ref::<std::io::Error>().is_none()
+}
+
+fn response_validator(res: &Response) -> Option<String> {
+    let header = |name| res.headers().get(name).and_then(|v| v.to_str().ok());
+
+    // weak ETags cannot be used in `If-Range`
+    match header(ETAG) {
+        Some(etag) if !etag.starts_with("W/") => Some(etag.to_string()),
+        _ => header(LAST_MODIFIED).map(|v| v.to_string()),
+    }
+}
+
+/// First byte, last byte and total size of `Content-Range: bytes 0-99/1000`.
+fn content_range(res: &Response) -> Option<(u64, u64, u64)> {
+    let value = res.headers().get(CONTENT_RANGE)?.to_str().ok()?;
+    let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let (start, end) = range.split_once('-')?;
+    Some((start.parse().ok()?, end.parse().ok()?, total.parse().ok()?))
+}
+
+async fn file_size(path: &Path) -> u64 {
+    tokio::fs::metadata(path).await.map_or(0, |v| v.len())
+}
+
+/// SHA-256 in lowercase hex of the file at `path`.
+pub async fn file_sha256(path: impl AsRef<Path>) -> std::io::Result<String> {
+    let mut file = File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 1024 * 1024];
+    loop {
+        let len = file.read(&mut buf).await?;
+        if len == 0 {
+            break;
+        }
+        hasher.update(&buf[..len]);
+    }
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+async fn check_size(path: &Path, total: u64) -> anyhow::Result<()> {
+    let size = file_size(path).await;
+    if size != total {
+        anyhow::bail!("incomplete download: {}/{} bytes", size, total);
+    }
+    Ok(())
+}
+
+async fn write_body(
+    res: Response,
+    file: &mut File,
+    progress: &DownloadProgress,
+) -> anyhow::Result<()> {
+    let mut stream = res.bytes_stream();
+    while let Some(item) = stream.next().await {
+        let chunk = item?;
+        file.write_all(&chunk).await?;
+        progress.add(chunk.len() as u64);
+    }
+    file.flush().await?;
+
+    Ok(())
 }
@@ -1,8 +1,11 @@
+use super::lease::Lease;
 use super::manage::manage_cache;
+use super::progress::DownloadProgress;
 use super::state::DownloadState;
-use super::task::{DownloadStatus, DownloadTask};
-use std::{path::Path, sync::Arc};
+use super::task::{discard_partial, file_sha256, DownloadStatus, DownloadTask, Expected};
+use std::{path::Path, sync::Arc, time::Instant};
 use tokio::sync::{watch, RwLock};
+use tracing::Instrument;
 use url::Url;
 
 pub enum CreateDownloadTaskResult {
@@ -10,24 +13,151 @@ pub enum CreateDownloadTaskResult {
     Created(watch::Receiver<DownloadStatus>),
 }
 
+/// Hash the completed file of `url` again and drop it from the cache if it is not `expected`
+/// or changed since downloaded, so that it is downloaded again. A file in use is kept and
+/// marked as failed instead, the next request downloads it again over the same file.
+///
+/// Only done if `verify`, or to check a file downloaded before hashes were recorded.
+async fn verify_cached(
+    identity: &Url,
+    expected: &Expected,
+    verify: bool,
+    download_state: &Arc<RwLock<DownloadState>>,
+) {
+    let (file_id, cache_path, recorded) = {
+        let state = download_state.read().await;
+        match state.get_by_url(identity) {
+            Some(task)
+                if task.status() == &DownloadStatus::Completed
+                    && (verify || (expected.sha256.is_some() && task.sha256().is_none())) =>
+            {
+                (
+                    task.file_id().to_string(),
+                    state.cache_path(task),
+                    task.sha256().map(|v| v.to_string()),
+                )
+            }
+            _ => return,
+        }
+    };
+
+    // hashing may take a while, without holding the lock
+    let (size, sha256) = match file_sha256(&cache_path).await {
+        Ok(sha256) => (
+            tokio::fs::metadata(&cache_path)
+                .await
+                .map_or(0, |v| v.len()),
+            sha256,
+        ),
+        // a missing file is downloaded again anyway
+        Err(_) => return,
+    };
+
+    let mut state = download_state.write().await;
+    if state.get_by_id(&file_id).is_none() {
+        return;
+    }
+
+    let result = match &recorded {
+        Some(recorded) if *recorded != sha256 => Err(anyhow::anyhow!(
+            "sha256 changed since downloaded: recorded {}, got {}",
+            recorded,
+            sha256
+        )),
+        _ => expected.check(size, &sha256),
+    };
+    match result {
+        Ok(()) if recorded.is_none() => {
+            if let Err(e) = state.store_by_hash(&file_id, size, sha256).await {
+                tracing::warn!(file_id, error = %e, "failed to store file by hash");
+            }
+        }
+        Ok(()) => {}
+        Err(e) => {
+            tracing::warn!(file_id, url = %identity, error = %e, "cached file is corrupted, downloading again");
+            // the other downloads of the file are corrupted as well
+            let cache_path = state.cache_path(state.get_by_id(&file_id).expect("checked above"));
+            let file_name = cache_path.file_name().unwrap_or_default().to_string_lossy();
+            let in_use = state.in_use(&file_name);
+            for file_id in state.stored_in(&file_name) {
+                if in_use {
+                    // the links of running workflows are kept, the download replaces the file
+                    state.update_status(&file_id, DownloadStatus::Failed);
+                } else {
+                    remove_download(&mut state, &file_id).await;
+                }
+            }
+        }
+    }
+}
+
+/// Delete a download with its partial file and links, and its cached file unless shared
+/// with the downloads of the same content from other URLs.
+pub async fn remove_download(state: &mut DownloadState, file_id: &str) {
+    if let Some(target_dirs) = state.remove_target_dirs(file_id) {
+        for target_dir in target_dirs {
+            let dst = state.root_dir().join(target_dir).join(file_id);
+            let _ = tokio::fs::remove_file(dst).await;
+        }
+    }
+
+    if let Some(task) = state.get_by_id(file_id) {
+        if !state.is_shared(file_id) {
+            let _ = tokio::fs::remove_file(state.cache_path(task)).await;
+        }
+    }
+    discard_partial(state.cache_dir().join(file_id)).await;
+
+    state.remove(file_id);
+}
+
+/// Link the file of `url` into `target_dir`, downloading it unless already cached,
+/// from `url` or from another URL with the SHA-256 in `expected`.
+///
+/// The downloaded file is checked against `expected`, and so is the recorded size and hash
+/// of a cached one. With `verify`, a cached file is also hashed again before reuse.
+///
+/// The download is pinned by `lease`, so that its file is not evicted while in use.
+/// Fails if the cached file is not the expected one but in use, e.g. by a running workflow.
 pub async fn create_download_task(
     url: &Url,
     target_dir: impl AsRef<Path>,
+    expected: &Expected,
+    verify: bool,
+    lease: &Lease,
     download_state: Arc<RwLock<DownloadState>>,
-) -> (String, CreateDownloadTaskResult) {
+) -> anyhow::Result<(String, CreateDownloadTaskResult)> {
+    let identity = download_state.read().await.options().identity(url);
+    verify_cached(&identity, expected, verify, &download_state).await;
+
     // when create download task, state should be locked until result is returned
     let state_clone = download_state.clone();
     let mut state = state_clone.write().await;
-    let existed_task = state.get_by_url(&url).map(|v| v.clone());
+    let existed_task = state.get_by_url(&identity).cloned();
+    let mut retried_task = None;
 
     if let Some(task) = existed_task {
         match task.status() {
             DownloadStatus::Completed => {
                 // make sure task exists (check if the file exists)
-                let cache_path = state.cache_dir().join(task.file_id());
-                if cache_path.exists() && tokio::fs::metadata(&cache_path).await.is_ok() {
+                let cache_path = state.cache_path(&task);
+                let cached = cache_path.exists() && tokio::fs::metadata(&cache_path).await.is_ok();
+                let file_name = cache_path.file_name().unwrap_or_default().to_string_lossy();
+                if cached && !expected.matches(&task) && state.in_use(&file_name) {
+                    // the request is likely wrong, the file of the workflows is left alone
+                    anyhow::bail!(
+                        "the cached file of {} does not have the expected size or sha256, \
+                        and is in use",
+                        url
+                    );
+                }
+
+                if cached && expected.matches(&task) {
+                    lease.pin(task.file_id());
+                    state.touch(task.file_id());
+
                     // create symlink
-                    let _ = state.add_target_dir(task.file_id(), &target_dir).await;
+                    state.add_target_dir(task.file_id(), &target_dir);
 
                     let dst = state.root_dir().join(target_dir);
                     let _ = tokio::fs::create_dir_all(&dst).await;
@@ -49,115 +179,148 @@ pub async fn create_download_task(
 
                     if !dst.exists() {
                         if let Err(e) = tokio::fs::symlink(&cache_path, &dst).await {
-                            tracing::warn!("failed to create symlink: {}", e);
+                            tracing::warn!(file_id = task.file_id(), error = %e, "failed to create symlink");
                         }
                     }
 
-                    return (
+                    if let Some(metrics) = state.metrics() {
+                        metrics.cache_hits_total.inc();
+                    }
+
+                    return Ok((
                         task.file_id().to_string(),
                         CreateDownloadTaskResult::Existed,
-                    );
+                    ));
                 } else {
-                    // else the state need to be cleaned and symlinks should be removed
-                    if let Some(symlinks) = state.target_dirs(task.file_id()) {
-                        for symlink in symlinks {
-                            let dst = state.root_dir().join(symlink).join(task.file_id());
-                            let _ = tokio::fs::remove_file(dst).await;
-                        }
-                    }
-
-                    if let Err(e) = state.remove(task.file_id()).await {
-                        tracing::warn!("failed to remove download task: {}", e);
-                    }
+                    // the file is missing or not the expected one, download it again
+                    remove_download(&mut state, task.file_id()).await;
                 }
             }
-            DownloadStatus::Pending => {
-                let _ = state.add_target_dir(task.file_id(), &target_dir).await;
-                let rx = state
-                    .get_notification(task.file_id())
-                    .expect("notification must exists");
+            DownloadStatus::Pending => match state.get_notification(task.file_id()) {
+                Some(rx) => {
+                    lease.pin(task.file_id());
+                    state.touch(task.file_id());
+                    state.add_target_dir(task.file_id(), &target_dir);
 
-                return (
-                    task.file_id().to_string(),
-                    CreateDownloadTaskResult::Created(rx),
-                );
-            }
+                    // joining an ongoing download is a hit as well, nothing is downloaded twice
+                    if let Some(metrics) = state.metrics() {
+                        metrics.cache_hits_total.inc();
+                    }
+
+                    return Ok((
+                        task.file_id().to_string(),
+                        CreateDownloadTaskResult::Created(rx),
+                    ));
+                }
+                None => {
+                    // no download is running, e.g. it was interrupted by a restart
+                    tracing::warn!(file_id = task.file_id(), "pending download is not running");
+                    retried_task = Some(task.retry(url));
+                }
+            },
             DownloadStatus::Failed => {
-                // remove failed task
-                let _ = state.remove(task.file_id()).await;
+                // download again under the same file id, resuming the partial file if any
+                retried_task = Some(task.retry(url));
             }
         }
     }
 
-    let task = DownloadTask::new(&url);
-
+    let mut task = retried_task.unwrap_or_else(|| DownloadTask::new(url, identity));
     let file_id = task.file_id().to_string();
+    task.touch();
+    lease.pin(&file_id);
+
+    // the same content is already cached from another URL, e.g. a mirror
+    if let Some(cached) = state.find_by_content(expected) {
+        tracing::info!(file_id, url = %task.url(), from = %cached.url(), "file already cached");
+        task.with_content(
+            cached.size().unwrap_or_default(),
+            cached.sha256().unwrap_or_default().to_string(),
+        );
+        task.with_status(DownloadStatus::Completed);
 
-    if let Err(e) = state.add(task.clone()).await {
-        tracing::warn!("failed to add download task: {}", e);
+        state.add(task);
+        state.add_target_dir(file_id.as_str(), target_dir);
+        state.link(&file_id).await;
+
+        if let Some(metrics) = state.metrics() {
+            metrics.cache_hits_total.inc();
+        }
+
+        return Ok((file_id, CreateDownloadTaskResult::Existed));
     }
 
-    if let Err(e) = state.add_target_dir(file_id.as_str(), target_dir).await {
-        tracing::warn!("failed to add target dir: {}", e);
+    state.add(task.clone());
+    state.add_target_dir(file_id.as_str(), target_dir);
+
+    let metrics = state.metrics();
+    if let Some(metrics) = &metrics {
+        metrics.cache_misses_total.inc();
     }
 
     let cache_dir = state.cache_dir().clone();
+    let client = state.client().clone();
+    let options = state.options().clone();
+    let expected = expected.clone();
     let (tx, rx) = watch::channel(task.status().clone());
 
     state.set_notification(file_id.as_str(), rx.clone());
+    let progress = Arc::new(DownloadProgress::new());
+    state.set_progress(file_id.as_str(), progress.clone());
 
     tokio::spawn(async move {
-        let result = task.run(&cache_dir).await;
+        let started_at = Instant::now();
+        let result = task
+            .run(&client, &cache_dir, &options, &expected, &progress)
+            .await;
         let download_success = result.is_ok();
 
+        if let Some(metrics) = &metrics {
+            metrics
+                .download_duration_seconds
+                .observe(started_at.elapsed().as_secs_f64());
+            match &result {
+                Ok(file) => {
+                    metrics.download_bytes_total.inc_by(file.received);
+                    metrics.downloads_total.with_label_values(&["completed"]).inc();
+                }
+                Err(_) => {
+                    metrics.downloads_total.with_label_values(&["failed"]).inc();
+                }
+            }
+        }
+
         {
             let mut state = download_state.write().await;
-            if let Err(e) = state
-                .update_status(
-                    task.file_id(),
-                    match result {
-                        Err(e) => {
-                            tracing::warn!(
-                                "failed to download {}({}): {}",
-                                task.file_id(),
-                                task.url(),
-                                e
-                            );
-                            DownloadStatus::Failed
-                        }
-                        _ => {
-                            tracing::info!(
-                                "download completed: {}({})",
-                                task.file_id(),
-                                task.url()
-                            );
-                            DownloadStatus::Completed
-                        }
-                    },
-                )
-                .await
-            {
-                tracing::warn!("failed to update downloaded status: {}", e);
+            if let Ok(file) = &result {
+                state.set_content(task.file_id(), file.size, file.sha256.clone());
             }
+            state.update_status(
+                task.file_id(),
+                match result {
+                    Err(e) => {
+                        tracing::warn!(
+                            file_id = task.file_id(),
+                            url = %task.url(),
+                            error = %e,
+                            "download failed"
+                        );
+                        DownloadStatus::Failed
+                    }
+                    _ => {
+                        tracing::info!(
+                            file_id = task.file_id(),
+                            url = %task.url(),
+                            "download completed"
+                        );
+                        DownloadStatus::Completed
+                    }
+                },
+            );
         }
 
         if download_success {
-            // create symlinks
-            let state = download_state.read().await;
-            let symlinks = state.target_dirs(task.file_id());
-
-            if let Some(symlinks) = symlinks {
-                for symlink in symlinks {
-                    let dst = state.root_dir().join(symlink);
-                    let _ = tokio::fs::create_dir_all(&dst).await;
-
-                    let dst = dst.join(task.file_id());
-                    if let Err(e) = tokio::fs::symlink(&cache_dir.join(task.file_id()), &dst).await
-                    {
-                        tracing::warn!("failed to create symlink: {}", e);
-                    }
-                }
-            }
+            download_state.read().await.link(task.file_id()).await;
 
             let _ = tx.send(DownloadStatus::Completed);
         } else {
@@ -168,14 +331,17 @@ pub async fn create_download_task(
         {
             let mut state = download_state.write().await;
             state.remove_notification(task.file_id());
+            state.remove_progress(task.file_id());
         }
 
         // manage cache
         // delete oldest file if cache folder size exceed max_cache_bytes
         if let Err(e) = manage_cache(download_state.clone()).await {
-            tracing::warn!("failed to manage cache: {}", e);
+            tracing::warn!(error = %e, "failed to manage cache");
         }
-    });
+    }
+    // the download belongs to the trace of the task that started it
+    .in_current_span());
 
-    (file_id, CreateDownloadTaskResult::Created(rx))
+    Ok((file_id, CreateDownloadTaskResult::Created(rx)))
 }
@@ -0,0 +1,21 @@
+//! File system helpers shared by the record and key stores.
+
+use std::{ffi::OsString, path::Path};
+use tokio::io::AsyncWriteExt;
+
+/// Replace `path` with `contents` through a temporary file, so that a crash while writing
+/// leaves the previous file.
+pub async fn write_atomic(path: &Path, contents: String) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    let mut temp_path = OsString::from(path);
+    temp_path.push(".tmp");
+    let mut file = tokio::fs::File::create(&temp_path).await?;
+    file.write_all(contents.as_bytes()).await?;
+    file.sync_all().await?;
+    tokio::fs::rename(&temp_path, path).await?;
+
+    Ok(())
+}
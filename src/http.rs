@@ -0,0 +1,107 @@
+//! Shared clients of outgoing requests, pooling connections across requests.
+
+use crate::config::HttpConfig;
+use reqwest::{ClientBuilder, IntoUrl, Method, NoProxy, Proxy, RequestBuilder, Response};
+use std::time::Duration;
+
+/// A pooled client retrying transient failures, see `HttpConfig`.
+#[derive(Clone, Debug)]
+pub struct HttpClient {
+    client: reqwest::Client,
+    retries: u32,
+    retry_backoff: Duration,
+}
+
+fn builder(config: &HttpConfig) -> ClientBuilder {
+    reqwest::Client::builder()
+        .user_agent(&config.user_agent)
+        .connect_timeout(Duration::from_secs(config.connect_timeout))
+        .read_timeout(Duration::from_secs(config.read_timeout))
+        .pool_max_idle_per_host(config.pool_max_idle_per_host)
+        .pool_idle_timeout(Duration::from_secs(config.pool_idle_timeout))
+}
+
+impl HttpClient {
+    fn new(config: &HttpConfig, client: reqwest::Client) -> Self {
+        Self {
+            client,
+            retries: config.retries,
+            retry_backoff: Duration::from_millis(config.retry_backoff_ms),
+        }
+    }
+
+    /// Client of downloads, through `proxy` if set.
+    pub fn download(config: &HttpConfig) -> anyhow::Result<Self> {
+        let mut builder = builder(config);
+        if let Some(proxy) = &config.proxy {
+            builder = builder.proxy(
+                Proxy::all(proxy.as_str())?.no_proxy(NoProxy::from_string(&config.no_proxy)),
+            );
+        }
+
+        Ok(Self::new(config, builder.build()?))
+    }
+
+    /// Client of nodes with the given TLS, never through a proxy.
+    pub fn node(config: &HttpConfig, tls: rustls::ClientConfig) -> anyhow::Result<Self> {
+        let client = builder(config)
+            .no_proxy()
+            .timeout(Duration::from_secs(config.node_timeout))
+            .use_preconfigured_tls(tls)
+            .build()?;
+
+        Ok(Self::new(config, client))
+    }
+
+    pub fn request(&self, method: Method, url: impl IntoUrl) -> RequestBuilder {
+        self.client.request(method, url)
+    }
+
+    pub fn get(&self, url: impl IntoUrl) -> RequestBuilder {
+        self.client.get(url)
+    }
+
+    /// Send the request, retrying after a backoff when it failed to connect, and for idempotent
+    /// methods when it timed out or the server is overloaded or unavailable.
+    pub async fn send(&self, request: RequestBuilder) -> reqwest::Result<Response> {
+        let request = request.build()?;
+        let idempotent = request.method().is_idempotent();
+
+        let mut backoff = self.retry_backoff;
+        for attempt in 1..=self.retries {
+            // requests with a streaming body cannot be sent again
+            let Some(retry) = request.try_clone() else {
+                break;
+            };
+
+            let reason = match self.client.execute(retry).await {
+                Err(e) if e.is_connect() || (idempotent && e.is_timeout()) => e.to_string(),
+                Ok(response) if idempotent && is_transient(response.status()) => {
+                    response.status().to_string()
+                }
+                result => return result,
+            };
+
+            tracing::warn!(
+                url = %request.url(),
+                attempt,
+                reason,
+                "request failed, retrying"
+            );
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+
+        self.client.execute(request).await
+    }
+}
+
+fn is_transient(status: reqwest::StatusCode) -> bool {
+    matches!(status.as_u16(), 429 | 502 | 503 | 504)
+}
+
+impl Default for HttpClient {
+    fn default() -> Self {
+        Self::download(&HttpConfig::default()).expect("default http config should be valid")
+    }
+}
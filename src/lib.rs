@@ -1,23 +1,37 @@
+pub mod agent;
+mod audit;
+mod auth;
+pub mod cli;
 mod cluster;
 pub mod config;
 mod download;
+mod fs;
+mod http;
+mod metrics;
 mod routes;
 pub mod state;
+pub mod telemetry;
+mod tls;
 mod workflow;
 
-use axum::{extract::Request, routing::get, Router, ServiceExt};
+use axum::{extract::Request, middleware, routing::get, Router, ServiceExt};
 use routes::{
+    audit::audit_routes,
+    auth::auth_routes,
     cluster::cluster_routes,
+    config::config_routes,
+    download::download_routes,
+    logging::logging_routes,
+    metrics::metrics_routes,
     workflow::{preview_workflow, workflow_routes},
 };
 use state::AppState;
-use std::{net::SocketAddr, str::FromStr, sync::Arc};
+use std::{net::SocketAddr, str::FromStr, sync::Arc, time::Duration};
+use tls::ServerTls;
+use tokio::net::TcpListener;
 use tower::{Layer, ServiceBuilder};
 use tower_http::cors::{Any, CorsLayer};
-use tower_http::{
-    normalize_path::NormalizePathLayer, trace::TraceLayer,
-    validate_request::ValidateRequestHeaderLayer,
-};
+use tower_http::{normalize_path::NormalizePathLayer, trace::TraceLayer};
 use utoipa::{
     openapi::security::{Http, HttpAuthScheme, SecurityScheme},
     Modify, OpenApi,
@@ -49,28 +63,65 @@ impl Modify for SecurityAddon {
             components.add_security_scheme(
                 "basic_auth",
                 SecurityScheme::Http(Http::new(HttpAuthScheme::Basic)),
-            )
+            );
+            components.add_security_scheme(
+                "bearer_auth",
+                SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+            );
         }
     }
 }
 
 pub async fn run(app_state: AppState) -> anyhow::Result<()> {
+    let config = app_state.config().read().await.clone();
+    let addr = SocketAddr::from_str(format!("{}:{}", &config.host, &config.port).as_str())?;
+
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+
+    serve(listener, app_state).await
+}
+
+/// Serve the router on an already bound listener.
+/// This is useful when the address is picked by the OS, e.g. in integration tests.
+pub async fn serve(listener: TcpListener, app_state: AppState) -> anyhow::Result<()> {
     #[cfg(not(debug_assertions))]
     let serve_admin_web = ServeEmbed::<AdminWebDist>::new();
 
-    let config = app_state.config().clone();
+    let tls_config = app_state.config().read().await.tls.clone();
+    let server_tls = ServerTls::load(&tls_config)?;
+    let app_state = match &server_tls {
+        Some(server_tls) => app_state.with_server_tls(server_tls.clone()),
+        None => app_state,
+    };
+    let app_state = Arc::new(app_state);
+
+    #[cfg(unix)]
+    tokio::spawn(signal_reload(app_state.clone()));
+    tokio::spawn(download::manage::expire_cache(app_state.download_state()));
+    tokio::spawn(download::state::save_record(app_state.download_state()));
+    // saved once more on shutdown, for the changes the writer has not caught up with
+    let download_state = app_state.download_state();
 
     let auth_routes = Router::new()
-        .nest("/cluster", cluster_routes(app_state.node_state()))
+        .nest("/audit", audit_routes())
+        .nest("/auth", auth_routes())
+        .nest(
+            "/cluster",
+            cluster_routes(app_state.node_state(), app_state.node_client().clone()),
+        )
+        .nest("/config", config_routes())
+        .nest("/downloads", download_routes())
         .nest("/workflow", workflow_routes())
+        .nest("/metrics", metrics_routes())
+        .nest("/logging", logging_routes())
         .merge(RapiDoc::with_openapi("/api-docs/openapi.json", ApiDoc::openapi()).path("/doc"));
 
     #[cfg(not(debug_assertions))]
     let auth_routes = auth_routes.nest_service("/admin", serve_admin_web);
 
-    let auth_routes = auth_routes.layer(ValidateRequestHeaderLayer::basic(
-        &config.username,
-        &config.password,
+    let auth_routes = auth_routes.layer(middleware::from_fn_with_state(
+        app_state.clone(),
+        auth::middleware::authenticate,
     ));
 
     let preview_route = Router::new()
@@ -86,12 +137,16 @@ pub async fn run(app_state: AppState) -> anyhow::Result<()> {
         .merge(auth_routes)
         .merge(preview_route)
         .route("/health_check", get(routes::health_check))
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            metrics::middleware::track_http,
+        ))
         .layer(
             ServiceBuilder::new()
-                .layer(TraceLayer::new_for_http())
+                .layer(TraceLayer::new_for_http().make_span_with(telemetry::request_span))
                 .into_inner(),
         )
-        .with_state(Arc::new(app_state));
+        .with_state(app_state);
 
     #[cfg(debug_assertions)]
     let app = app.layer(
@@ -103,17 +158,60 @@ pub async fn run(app_state: AppState) -> anyhow::Result<()> {
 
     let app = NormalizePathLayer::trim_trailing_slash().layer(app);
 
-    let addr = SocketAddr::from_str(format!("{}:{}", &config.host, &config.port).as_str())?;
+    tracing::info!(addr = %listener.local_addr()?, tls = server_tls.is_some(), "listening");
+
+    // peer addresses are recorded in the audit log
+    let make_service =
+        ServiceExt::<Request>::into_make_service_with_connect_info::<SocketAddr>(app);
+
+    match server_tls {
+        Some(server_tls) => {
+            if tls_config.reload_interval > 0 {
+                tokio::spawn(
+                    server_tls
+                        .clone()
+                        .watch(Duration::from_secs(tls_config.reload_interval)),
+                );
+            }
+
+            let handle = axum_server::Handle::new();
+            tokio::spawn({
+                let handle = handle.clone();
+                async move {
+                    signal_shutdown().await;
+                    handle.graceful_shutdown(None);
+                }
+            });
+
+            axum_server::from_tcp_rustls(listener.into_std()?, server_tls.rustls_config())
+                .handle(handle)
+                .serve(make_service)
+                .await?;
+        }
+        None => {
+            axum::serve(listener, make_service)
+                .with_graceful_shutdown(signal_shutdown())
+                .await?;
+        }
+    }
 
-    let listener = tokio::net::TcpListener::bind(addr).await?;
+    download_state.read().await.save().await?;
 
-    tracing::info!("Listening on http://{}", listener.local_addr()?);
+    Ok(())
+}
 
-    axum::serve(listener, ServiceExt::<Request>::into_make_service(app))
-        .with_graceful_shutdown(signal_shutdown())
-        .await?;
+/// Reload the config on SIGHUP, see `AppState::reload_config`.
+#[cfg(unix)]
+async fn signal_reload(app_state: Arc<AppState>) {
+    use tokio::signal::unix::{signal, SignalKind};
 
-    Ok(())
+    let mut hangup = signal(SignalKind::hangup()).expect("expect tokio signal hangup");
+    while hangup.recv().await.is_some() {
+        tracing::info!("signal reload");
+        if let Err(e) = app_state.reload_config().await {
+            tracing::error!(error = format!("{:#}", e), "failed to reload config");
+        }
+    }
 }
 
 async fn signal_shutdown() {
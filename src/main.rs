@@ -1,23 +1,75 @@
-use comfy_router::config::AppConfig;
+use clap::Parser;
+use comfy_router::cli::{self, Cli, Command};
+use comfy_router::config::{AppConfig, ConfigSource};
 use comfy_router::run;
 use comfy_router::state::AppState;
-use tracing::{debug, error};
+use comfy_router::telemetry::{self, LogFilter};
+use std::process::ExitCode;
+use tracing::{error, info};
 
 #[cfg(debug_assertions)]
 use dotenv::dotenv;
 
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
     #[cfg(debug_assertions)]
     dotenv().ok();
 
-    tracing_subscriber::fmt::init();
+    let cli = Cli::parse();
+    let config_source = cli.config_source();
+    // fail before anything starts, the subscriber is not installed yet
+    let config = match config_source.load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("error: {:#}", e);
+            return ExitCode::FAILURE;
+        }
+    };
 
-    let config = AppConfig::from_env();
-    debug!("config: {:?}", config);
-    let state = AppState::new(config).await;
-
-    if let Err(e) = run(state).await {
-        error!("failed to start app: {}", e);
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(config, config_source).await,
+        command => match cli::run(command, &config).await {
+            Ok(code) => code,
+            Err(e) => {
+                eprintln!("error: {:#}", e);
+                ExitCode::FAILURE
+            }
+        },
     }
 }
+
+async fn serve(config: AppConfig, config_source: ConfigSource) -> ExitCode {
+    let telemetry = match telemetry::init(&config) {
+        Ok(telemetry) => telemetry,
+        Err(e) => {
+            // the subscriber is not installed
+            eprintln!("error: failed to initialize tracing: {:#}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let code = match start(config, config_source, telemetry.log_filter()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("failed to start app: {:#}", e);
+            ExitCode::FAILURE
+        }
+    };
+
+    telemetry.shutdown().await;
+    code
+}
+
+async fn start(
+    config: AppConfig,
+    config_source: ConfigSource,
+    log_filter: LogFilter,
+) -> anyhow::Result<()> {
+    info!("effective config:\n{}", config.to_redacted_toml()?);
+    let state = AppState::new(config)
+        .await?
+        .with_log_filter(log_filter)
+        .with_config_source(config_source);
+
+    run(state).await
+}
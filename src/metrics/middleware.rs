@@ -0,0 +1,39 @@
+use crate::state::AppState;
+use axum::{
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use std::{sync::Arc, time::Instant};
+
+/// Count HTTP requests and their duration.
+///
+/// Requests are labelled by route pattern (e.g. `/workflow/:id`) rather than the
+/// actual path, to keep the number of series bounded.
+pub async fn track_http(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().to_string();
+    let path = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|v| v.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+
+    let started_at = Instant::now();
+    let response = next.run(request).await;
+
+    let metrics = state.metrics();
+    metrics
+        .http_requests_total
+        .with_label_values(&[&method, &path, response.status().as_str()])
+        .inc();
+    metrics
+        .http_request_duration_seconds
+        .with_label_values(&[&method, &path])
+        .observe(started_at.elapsed().as_secs_f64());
+
+    response
+}
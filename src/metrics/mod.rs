@@ -0,0 +1,205 @@
+pub mod middleware;
+
+use prometheus::{
+    exponential_buckets, Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter,
+    IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+
+const NAMESPACE: &str = "comfy_router";
+
+/// Prometheus metrics of the router.
+///
+/// Counters and histograms are updated where things happen, gauges describing
+/// the current state (queue, nodes, cache size) are refreshed when scraped.
+#[derive(Clone, Debug)]
+pub struct Metrics {
+    registry: Registry,
+
+    pub queue_depth: IntGauge,
+    pub queue_wait_seconds: Histogram,
+    pub workflow_duration_seconds: HistogramVec,
+    pub workflows_total: IntCounterVec,
+    pub workflow_errors_total: IntCounterVec,
+
+    pub node_status: IntGaugeVec,
+
+    pub download_bytes_total: IntCounter,
+    pub download_duration_seconds: Histogram,
+    pub downloads_total: IntCounterVec,
+
+    pub cache_size_bytes: IntGauge,
+    pub cache_max_bytes: IntGauge,
+    pub cache_hits_total: IntCounter,
+    pub cache_misses_total: IntCounter,
+    pub cache_evictions_total: IntCounter,
+    pub cache_evicted_bytes_total: IntCounter,
+
+    pub http_requests_total: IntCounterVec,
+    pub http_request_duration_seconds: HistogramVec,
+}
+
+fn opts(name: &str, help: &str) -> Opts {
+    Opts::new(name, help).namespace(NAMESPACE)
+}
+
+fn histogram_opts(name: &str, help: &str, buckets: Vec<f64>) -> HistogramOpts {
+    HistogramOpts::new(name, help)
+        .namespace(NAMESPACE)
+        .buckets(buckets)
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        // 0.1s to ~27min, workflows and downloads can be slow
+        let long_buckets = exponential_buckets(0.1, 2.0, 15).expect("valid buckets");
+        // 1ms to ~16s
+        let http_buckets = exponential_buckets(0.001, 2.0, 15).expect("valid buckets");
+
+        let metrics = Self {
+            registry: Registry::new(),
+
+            queue_depth: IntGauge::with_opts(opts(
+                "queue_depth",
+                "Number of workflows waiting in the pending queue.",
+            ))
+            .expect("valid metric"),
+            queue_wait_seconds: Histogram::with_opts(histogram_opts(
+                "queue_wait_seconds",
+                "Time workflows spend in the pending queue before a node picks them.",
+                long_buckets.clone(),
+            ))
+            .expect("valid metric"),
+            workflow_duration_seconds: HistogramVec::new(
+                histogram_opts(
+                    "workflow_duration_seconds",
+                    "Time from picking a node to the end of the workflow, including downloads.",
+                    long_buckets.clone(),
+                ),
+                &["type", "node"],
+            )
+            .expect("valid metric"),
+            workflows_total: IntCounterVec::new(
+                opts("workflows_total", "Finished workflows by result."),
+                &["type", "status"],
+            )
+            .expect("valid metric"),
+            workflow_errors_total: IntCounterVec::new(
+                opts("workflow_errors_total", "Failed workflows by error kind."),
+                &["type", "kind"],
+            )
+            .expect("valid metric"),
+
+            node_status: IntGaugeVec::new(
+                opts(
+                    "node_status",
+                    "Status of each node, 1 for the current status and 0 for the others.",
+                ),
+                &["node", "status"],
+            )
+            .expect("valid metric"),
+
+            download_bytes_total: IntCounter::with_opts(opts(
+                "download_bytes_total",
+                "Bytes of completed downloads.",
+            ))
+            .expect("valid metric"),
+            download_duration_seconds: Histogram::with_opts(histogram_opts(
+                "download_duration_seconds",
+                "Duration of downloads, both completed and failed.",
+                long_buckets,
+            ))
+            .expect("valid metric"),
+            downloads_total: IntCounterVec::new(
+                opts("downloads_total", "Finished downloads by status."),
+                &["status"],
+            )
+            .expect("valid metric"),
+
+            cache_size_bytes: IntGauge::with_opts(opts(
+                "cache_size_bytes",
+                "Total size of files in the download cache.",
+            ))
+            .expect("valid metric"),
+            cache_max_bytes: IntGauge::with_opts(opts(
+                "cache_max_bytes",
+                "Configured limit of the download cache.",
+            ))
+            .expect("valid metric"),
+            cache_hits_total: IntCounter::with_opts(opts(
+                "cache_hits_total",
+                "Files requested by workflows that were cached or being downloaded.",
+            ))
+            .expect("valid metric"),
+            cache_misses_total: IntCounter::with_opts(opts(
+                "cache_misses_total",
+                "Files requested by workflows that had to be downloaded.",
+            ))
+            .expect("valid metric"),
+            cache_evictions_total: IntCounter::with_opts(opts(
+                "cache_evictions_total",
+                "Files evicted from the download cache.",
+            ))
+            .expect("valid metric"),
+            cache_evicted_bytes_total: IntCounter::with_opts(opts(
+                "cache_evicted_bytes_total",
+                "Bytes evicted from the download cache.",
+            ))
+            .expect("valid metric"),
+
+            http_requests_total: IntCounterVec::new(
+                opts("http_requests_total", "HTTP requests by route and status."),
+                &["method", "path", "status"],
+            )
+            .expect("valid metric"),
+            http_request_duration_seconds: HistogramVec::new(
+                histogram_opts(
+                    "http_request_duration_seconds",
+                    "Duration of HTTP requests by route.",
+                    http_buckets,
+                ),
+                &["method", "path"],
+            )
+            .expect("valid metric"),
+        };
+
+        metrics
+            .register()
+            .expect("metrics should be registered once");
+        metrics
+    }
+
+    fn register(&self) -> prometheus::Result<()> {
+        let registry = &self.registry;
+        registry.register(Box::new(self.queue_depth.clone()))?;
+        registry.register(Box::new(self.queue_wait_seconds.clone()))?;
+        registry.register(Box::new(self.workflow_duration_seconds.clone()))?;
+        registry.register(Box::new(self.workflows_total.clone()))?;
+        registry.register(Box::new(self.workflow_errors_total.clone()))?;
+        registry.register(Box::new(self.node_status.clone()))?;
+        registry.register(Box::new(self.download_bytes_total.clone()))?;
+        registry.register(Box::new(self.download_duration_seconds.clone()))?;
+        registry.register(Box::new(self.downloads_total.clone()))?;
+        registry.register(Box::new(self.cache_size_bytes.clone()))?;
+        registry.register(Box::new(self.cache_max_bytes.clone()))?;
+        registry.register(Box::new(self.cache_hits_total.clone()))?;
+        registry.register(Box::new(self.cache_misses_total.clone()))?;
+        registry.register(Box::new(self.cache_evictions_total.clone()))?;
+        registry.register(Box::new(self.cache_evicted_bytes_total.clone()))?;
+        registry.register(Box::new(self.http_requests_total.clone()))?;
+        registry.register(Box::new(self.http_request_duration_seconds.clone()))?;
+        Ok(())
+    }
+
+    /// Encode all metrics in Prometheus text format.
+    pub fn encode(&self) -> anyhow::Result<String> {
+        let mut buffer = vec![];
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,93 @@
+use super::{AppError, AppJson};
+use crate::{
+    audit::{AuditEntry, AuditQuery},
+    auth::{Identity, Scope},
+    state::AppState,
+};
+use axum::{
+    extract::{Query, State},
+    routing::get,
+    Router,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use utoipa::{IntoParams, ToSchema};
+
+const OPENAPI_TAG: &str = "Audit";
+
+const MAX_PER_PAGE: usize = 500;
+
+#[derive(Clone, Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AuditListQuery {
+    /// Page number, starting from 1.
+    page: Option<usize>,
+    /// Entries per page, default 50, max 500.
+    per_page: Option<usize>,
+    /// Action prefix, e.g. `cluster.` or `workflow.submit`.
+    action: Option<String>,
+    /// Actor id, `admin`, `system` or an API key id.
+    actor: Option<String>,
+    /// Unix timestamp in seconds, inclusive.
+    since: Option<u64>,
+    /// Unix timestamp in seconds, inclusive.
+    until: Option<u64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct AuditResponse {
+    /// Matching entries, newest first.
+    entries: Vec<AuditEntry>,
+    page: usize,
+    per_page: usize,
+    /// Total number of matching entries.
+    total: usize,
+}
+
+/// List audit log
+///
+/// List audit log entries, newest first, filtered by action, actor and time range.
+#[utoipa::path(
+    get,
+    path = "/audit",
+    params(AuditListQuery),
+    responses((
+        status = OK, body = AuditResponse,
+    )),
+    security(("basic_auth" = []), ("bearer_auth" = ["cluster:admin"])),
+    tag = OPENAPI_TAG
+)]
+pub async fn list_audit(
+    State(state): State<Arc<AppState>>,
+    identity: Identity,
+    Query(query): Query<AuditListQuery>,
+) -> Result<AppJson<AuditResponse>, AppError> {
+    identity.require(Scope::ClusterAdmin)?;
+
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query.per_page.unwrap_or(50).clamp(1, MAX_PER_PAGE);
+    let filter = AuditQuery {
+        action: query.action,
+        actor: query.actor,
+        since: query.since,
+        until: query.until,
+    };
+
+    let audit_log = state.audit_log();
+    let audit_log = audit_log.read().await;
+    let (entries, total) = audit_log
+        .query(&filter, (page - 1).saturating_mul(per_page), per_page)
+        .await
+        .map_err(AppError::InternalServerError)?;
+
+    Ok(AppJson(AuditResponse {
+        entries,
+        page,
+        per_page,
+        total,
+    }))
+}
+
+pub fn audit_routes() -> Router<Arc<AppState>> {
+    Router::new().route("/", get(list_audit))
+}
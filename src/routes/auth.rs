@@ -0,0 +1,191 @@
+use super::{AppError, AppJson};
+use crate::{
+    audit::{self, AuditEntry},
+    auth::{key::ApiKeyInfo, limit::Limits, Identity, Scope},
+    state::AppState,
+};
+use axum::{
+    extract::{ConnectInfo, State},
+    routing::{get, post},
+    Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{collections::HashSet, net::SocketAddr, sync::Arc};
+use utoipa::ToSchema;
+
+const OPENAPI_TAG: &str = "Auth";
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct CreateKeyRequest {
+    name: String,
+    scopes: HashSet<Scope>,
+    /// Limits of the key, unset limits fall back to the defaults from config.
+    #[serde(default)]
+    limits: Limits,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct CreateKeyResponse {
+    /// The API key, use it as Bearer token. It will not be shown again.
+    key: String,
+    info: ApiKeyInfo,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct RequestKeyId {
+    id: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct KeysResponse {
+    keys: Vec<ApiKeyInfo>,
+}
+
+/// Create API key
+///
+/// Create an API key with given scopes.
+/// The scopes can not exceed the scopes of the caller.
+#[utoipa::path(
+    post,
+    path = "/auth/keys",
+    request_body = CreateKeyRequest,
+    responses((
+        status = OK, body = CreateKeyResponse,
+    )),
+    security(("basic_auth" = []), ("bearer_auth" = ["cluster:admin"])),
+    tag = OPENAPI_TAG
+)]
+pub async fn create_key(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    identity: Identity,
+    AppJson(data): AppJson<CreateKeyRequest>,
+) -> Result<AppJson<CreateKeyResponse>, AppError> {
+    identity.require(Scope::ClusterAdmin)?;
+
+    if data.name.trim().is_empty() {
+        return Err(AppError::BadRequest(anyhow::anyhow!("name is required")));
+    }
+
+    if !data.scopes.is_subset(&identity.scopes()) {
+        return Err(AppError::Forbidden(anyhow::anyhow!(
+            "cannot grant scopes exceeding your own"
+        )));
+    }
+
+    let (api_key, key) = {
+        let api_keys = state.api_keys();
+        let mut api_keys = api_keys.write().await;
+        api_keys
+            .create(data.name.trim(), data.scopes, data.limits)
+            .await
+            .map_err(AppError::InternalServerError)?
+    };
+
+    audit::record(
+        &state.audit_log(),
+        AuditEntry::new(
+            "auth.key.create",
+            json!({
+                "id": api_key.id(),
+                "name": api_key.name(),
+                "scopes": api_key.scopes(),
+            }),
+        )
+        .with_identity(&identity)
+        .with_source(addr),
+    )
+    .await;
+
+    Ok(AppJson(CreateKeyResponse {
+        key,
+        info: api_key.info(),
+    }))
+}
+
+/// List API keys
+///
+/// List all API keys, including revoked ones.
+#[utoipa::path(
+    get,
+    path = "/auth/keys",
+    responses((
+        status = OK, body = KeysResponse,
+    )),
+    security(("basic_auth" = []), ("bearer_auth" = ["cluster:admin"])),
+    tag = OPENAPI_TAG
+)]
+pub async fn list_keys(
+    State(state): State<Arc<AppState>>,
+    identity: Identity,
+) -> Result<AppJson<KeysResponse>, AppError> {
+    identity.require(Scope::ClusterAdmin)?;
+
+    let api_keys = state.api_keys();
+    let api_keys = api_keys.read().await;
+
+    Ok(AppJson(KeysResponse {
+        keys: api_keys.list().into_iter().map(|v| v.info()).collect(),
+    }))
+}
+
+/// Revoke API key
+///
+/// Revoke an API key using its id, the key can no longer be used.
+#[utoipa::path(
+    post,
+    path = "/auth/keys/revoke",
+    request_body = RequestKeyId,
+    responses((
+        status = OK, body = ApiKeyInfo,
+    ), (
+        status = NOT_FOUND,
+        description = "Key not found.",
+        body = String
+    )),
+    security(("basic_auth" = []), ("bearer_auth" = ["cluster:admin"])),
+    tag = OPENAPI_TAG
+)]
+pub async fn revoke_key(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    identity: Identity,
+    AppJson(data): AppJson<RequestKeyId>,
+) -> Result<AppJson<ApiKeyInfo>, AppError> {
+    identity.require(Scope::ClusterAdmin)?;
+
+    let revoked = {
+        let api_keys = state.api_keys();
+        let mut api_keys = api_keys.write().await;
+        api_keys
+            .revoke(&data.id)
+            .await
+            .map_err(AppError::InternalServerError)?
+    };
+
+    match revoked {
+        Some(revoked) => {
+            audit::record(
+                &state.audit_log(),
+                AuditEntry::new(
+                    "auth.key.revoke",
+                    json!({ "id": revoked.id(), "name": revoked.name() }),
+                )
+                .with_identity(&identity)
+                .with_source(addr),
+            )
+            .await;
+
+            Ok(AppJson(revoked.info()))
+        }
+        None => Err(AppError::NotFoundError(anyhow::anyhow!("key not found"))),
+    }
+}
+
+pub fn auth_routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/keys", post(create_key))
+        .route("/keys", get(list_keys))
+        .route("/keys/revoke", post(revoke_key))
+}
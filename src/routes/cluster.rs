@@ -1,16 +1,19 @@
 use super::{AppError, AppJson};
 use crate::{
-    cluster::{NodeState, NodeStatus},
+    audit::{self, AuditEntry},
+    auth::{Identity, Scope},
+    cluster::{client::NodeClient, NodeOptions, NodeState, NodeStatus},
     state::AppState,
     workflow::record::run_task,
 };
 use axum::{
-    extract::State,
+    extract::{ConnectInfo, State},
     routing::{get, post},
     Router,
 };
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use serde_json::json;
+use std::{net::SocketAddr, sync::Arc};
 use tokio::sync::RwLock;
 use url::Url;
 use utoipa::ToSchema;
@@ -18,7 +21,7 @@ use utoipa::ToSchema;
 const OPENAPI_TAG: &str = "Cluster";
 
 #[cfg(not(debug_assertions))]
-use reqwest::{Client, StatusCode};
+use reqwest::{Method, StatusCode};
 
 #[cfg(not(debug_assertions))]
 use std::{collections::HashMap, time::Duration};
@@ -26,34 +29,41 @@ use std::{collections::HashMap, time::Duration};
 #[cfg(not(debug_assertions))]
 async fn health_check(
     node_state: Arc<RwLock<NodeState>>,
+    node_client: &NodeClient,
     unhealthy_count: &mut HashMap<Url, usize>,
 ) {
-    let node_urls: Vec<Url> = {
+    let nodes: Vec<_> = {
         let node_state = node_state.read().await;
-        node_state.get_all().map(|(k, _)| k.clone()).collect()
+        node_state
+            .get_all()
+            .filter_map(|(k, _)| node_state.node(k))
+            .collect()
     };
 
-    if node_urls.is_empty() {
+    if nodes.is_empty() {
         return;
     }
 
-    let client = Client::new();
-    for node_url in node_urls {
-        let resp = client.get(node_url.join("/prompt").expect("")).send().await;
+    for node in nodes {
+        let node_url = node.url.clone();
+        // A single attempt bounded by the node timeout, retrying a dead node would delay
+        // the probes of the others.
+        let resp = node_client
+            .request(Method::GET, &node, "/prompt")
+            .send()
+            .await;
         match resp {
             Ok(resp) if resp.status() == StatusCode::OK => {
-                match unhealthy_count.remove(&node_url) {
-                    Some(count) => {
-                        if count >= 3 {
-                            let mut node_state = node_state.write().await;
-                            node_state.set_idle(&node_url);
-                        }
-                    }
-                    _ => {}
+                if unhealthy_count
+                    .remove(&node_url)
+                    .is_some_and(|count| count >= 3)
+                {
+                    let mut node_state = node_state.write().await;
+                    node_state.set_idle(&node_url);
                 }
             }
             _ => {
-                tracing::warn!("node {} is unhealthy", node_url);
+                tracing::warn!(node = %node_url, "node is unhealthy");
 
                 match unhealthy_count.get(&node_url) {
                     Some(count) => {
@@ -80,11 +90,22 @@ pub struct RequestUrl {
     url: Url,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct AddNodeRequest {
+    /// Base URL of ComfyUI, with its path prefix if behind a reverse proxy.
+    #[schema(value_type = String)]
+    url: Url,
+    #[serde(flatten)]
+    options: NodeOptions,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct NodeResponse {
     #[schema(value_type = String)]
     pub url: Url,
     pub status: NodeStatus,
+    /// Header values and secrets are redacted.
+    pub options: NodeOptions,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
@@ -93,28 +114,49 @@ pub struct NodesResponse {
 }
 
 /// Add node
-/// 
-/// Add a single ComfyUI node to cluster using URL.
+///
+/// Add a single ComfyUI node to cluster using URL, with the headers and credentials
+/// to send to it, and its storage. Adding a node again replaces its headers, credentials
+/// and storage.
 #[utoipa::path(
     post,
     path = "/cluster/nodes",
-    request_body = RequestUrl,
+    request_body = AddNodeRequest,
     responses((
         status = OK, description = "Add node successfully.", body = (), 
     )),
-    security(("basic_auth" = [])),
+    security(("basic_auth" = []), ("bearer_auth" = ["cluster:admin"])),
     tag = OPENAPI_TAG
 )]
 pub async fn join(
     State(state): State<Arc<AppState>>,
-    AppJson(data): AppJson<RequestUrl>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    identity: Identity,
+    AppJson(data): AppJson<AddNodeRequest>,
 ) -> Result<AppJson<()>, AppError> {
-    let node_state = state.node_state();
-    if node_state.read().await.get(&data.url).is_none() {
+    identity.require(Scope::ClusterAdmin)?;
+    data.options.validate().map_err(AppError::BadRequest)?;
+
+    {
+        let node_state = state.node_state();
         let mut node_state = node_state.write().await;
-        node_state.add(&data.url);
+        match node_state.get(&data.url) {
+            Some(_) => node_state.set_options(&data.url, data.options.clone()),
+            None => node_state.add(&data.url, data.options.clone()),
+        }
     }
 
+    audit::record(
+        &state.audit_log(),
+        AuditEntry::new(
+            "cluster.node.add",
+            json!({ "url": data.url, "options": data.options.redacted() }),
+        )
+        .with_identity(&identity)
+        .with_source(addr),
+    )
+    .await;
+
     // after new node join, safely trigger new task to run
     tokio::spawn(async move {
         run_task(state).await;
@@ -124,7 +166,7 @@ pub async fn join(
 }
 
 /// Remove node
-/// 
+///
 /// Remove a node from cluster using URL.
 #[utoipa::path(
     post,
@@ -133,22 +175,36 @@ pub async fn join(
     responses((
         status = OK, description = "Remove node successfully.", body = (), 
     )),
-    security(("basic_auth" = [])),
+    security(("basic_auth" = []), ("bearer_auth" = ["cluster:admin"])),
     tag = OPENAPI_TAG
 )]
 pub async fn remove(
     State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    identity: Identity,
     AppJson(data): AppJson<RequestUrl>,
 ) -> Result<AppJson<()>, AppError> {
-    let node_state = state.node_state();
-    let mut node_state = node_state.write().await;
-    node_state.remove(&data.url);
+    identity.require(Scope::ClusterAdmin)?;
+
+    {
+        let node_state = state.node_state();
+        let mut node_state = node_state.write().await;
+        node_state.remove(&data.url);
+    }
+
+    audit::record(
+        &state.audit_log(),
+        AuditEntry::new("cluster.node.remove", json!({ "url": data.url }))
+            .with_identity(&identity)
+            .with_source(addr),
+    )
+    .await;
 
     Ok(AppJson(()))
 }
 
 /// List nodes
-/// 
+///
 /// List all nodes in cluster.
 #[utoipa::path(
     get,
@@ -156,10 +212,15 @@ pub async fn remove(
     responses((
         status = OK, body = NodesResponse,
     )),
-    security(("basic_auth" = [])),
+    security(("basic_auth" = []), ("bearer_auth" = ["cluster:admin"])),
     tag = OPENAPI_TAG
 )]
-pub async fn nodes(State(state): State<Arc<AppState>>) -> Result<AppJson<NodesResponse>, AppError> {
+pub async fn nodes(
+    State(state): State<Arc<AppState>>,
+    identity: Identity,
+) -> Result<AppJson<NodesResponse>, AppError> {
+    identity.require(Scope::ClusterAdmin)?;
+
     let node_state = state.node_state();
     let node_state = node_state.read().await;
 
@@ -169,18 +230,25 @@ pub async fn nodes(State(state): State<Arc<AppState>>) -> Result<AppJson<NodesRe
             .map(|(url, status)| NodeResponse {
                 url: url.clone(),
                 status: status.clone(),
+                options: node_state
+                    .node(url)
+                    .map(|v| v.options.redacted())
+                    .unwrap_or_default(),
             })
             .collect(),
     }))
 }
 
 #[allow(unused_variables)]
-pub fn cluster_routes(node_state: Arc<RwLock<NodeState>>) -> Router<Arc<AppState>> {
+pub fn cluster_routes(
+    node_state: Arc<RwLock<NodeState>>,
+    node_client: NodeClient,
+) -> Router<Arc<AppState>> {
     #[cfg(not(debug_assertions))]
     tokio::spawn(async move {
         let mut node_unhealthy_count = HashMap::new();
         loop {
-            health_check(node_state.clone(), &mut node_unhealthy_count).await;
+            health_check(node_state.clone(), &node_client, &mut node_unhealthy_count).await;
             tokio::time::sleep(Duration::from_secs(1)).await;
         }
     });
@@ -0,0 +1,95 @@
+use super::{AppError, AppJson};
+use crate::{
+    audit::{self, AuditEntry},
+    auth::{Identity, Scope},
+    config::ConfigChanges,
+    state::AppState,
+};
+use axum::{
+    extract::{ConnectInfo, State},
+    routing::{get, post},
+    Router,
+};
+use serde_json::{json, Value};
+use std::{net::SocketAddr, sync::Arc};
+
+const OPENAPI_TAG: &str = "Config";
+
+/// Get config
+///
+/// The config in effect, with secrets redacted.
+#[utoipa::path(
+    get,
+    path = "/config",
+    responses((
+        status = OK, body = Object,
+    )),
+    security(("basic_auth" = []), ("bearer_auth" = ["cluster:admin"])),
+    tag = OPENAPI_TAG
+)]
+pub async fn get_config(
+    State(state): State<Arc<AppState>>,
+    identity: Identity,
+) -> Result<AppJson<Value>, AppError> {
+    identity.require(Scope::ClusterAdmin)?;
+
+    let config = state.config().read().await.redacted();
+    let config =
+        serde_json::to_value(config).map_err(|e| AppError::InternalServerError(e.into()))?;
+
+    Ok(AppJson(config))
+}
+
+/// Reload config
+///
+/// Load the config file, environment variables and flags again, same as sending SIGHUP.
+/// Credentials, default limits, the log filter, the queue and history limits and the cache limit
+/// are applied at once, other changed keys are reported in `restart_required`.
+#[utoipa::path(
+    post,
+    path = "/config/reload",
+    responses((
+        status = OK, body = ConfigChanges,
+    ), (
+        status = BAD_REQUEST,
+        description = "The config is invalid, nothing is changed.",
+        body = String
+    )),
+    security(("basic_auth" = []), ("bearer_auth" = ["cluster:admin"])),
+    tag = OPENAPI_TAG
+)]
+pub async fn reload_config(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    identity: Identity,
+) -> Result<AppJson<ConfigChanges>, AppError> {
+    identity.require(Scope::ClusterAdmin)?;
+
+    // the whole chain, the key at fault is in the innermost error
+    let changes = state
+        .reload_config()
+        .await
+        .map_err(|e| AppError::BadRequest(anyhow::anyhow!("{:#}", e)))?;
+
+    audit::record(
+        &state.audit_log(),
+        AuditEntry::new(
+            "config.reload",
+            json!({
+                "applied": changes.applied,
+                "restart_required": changes.restart_required,
+            }),
+        )
+        .with_identity(&identity)
+        .with_source(addr),
+    )
+    .await;
+
+    Ok(AppJson(changes))
+}
+
+pub fn config_routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/", get(get_config))
+        .route("/reload", post(reload_config))
+}
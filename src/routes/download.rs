@@ -0,0 +1,523 @@
+use super::{AppError, AppJson};
+use crate::{
+    audit::{self, AuditEntry},
+    auth::{Identity, Scope},
+    download::{
+        create_download_task,
+        progress::DownloadInfo,
+        remove_download,
+        task::{now_millis, DownloadStatus, Expected},
+    },
+    state::AppState,
+    workflow::{
+        payload::deserialize_sha256,
+        prefetch::{prefetch, ModelKind, PrefetchInfo, PrefetchRequest},
+    },
+};
+use axum::{
+    extract::{ConnectInfo, Path, Query, State},
+    routing::{get, post},
+    Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    net::SocketAddr,
+    path::{Component, PathBuf},
+    sync::Arc,
+};
+use url::Url;
+use utoipa::{IntoParams, ToSchema};
+
+const OPENAPI_TAG: &str = "Download";
+
+#[derive(Clone, Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DownloadListQuery {
+    /// Only downloads with this status.
+    status: Option<DownloadStatus>,
+}
+
+/// List downloads
+///
+/// List the downloads of the record, pending ones first with their progress.
+#[utoipa::path(
+    get,
+    path = "/downloads",
+    params(DownloadListQuery),
+    responses((
+        status = OK, body = Vec<DownloadInfo>,
+    )),
+    security(("basic_auth" = []), ("bearer_auth" = ["cluster:admin"])),
+    tag = OPENAPI_TAG
+)]
+pub async fn list_downloads(
+    State(state): State<Arc<AppState>>,
+    identity: Identity,
+    Query(query): Query<DownloadListQuery>,
+) -> Result<AppJson<Vec<DownloadInfo>>, AppError> {
+    identity.require(Scope::ClusterAdmin)?;
+
+    let download_state = state.download_state();
+    let download_state = download_state.read().await;
+
+    let mut downloads = vec![];
+    for task in download_state.downloads() {
+        if query.status.as_ref().is_none_or(|v| v == task.status()) {
+            downloads.push(download_state.info(task).await);
+        }
+    }
+    downloads.sort_by(|a, b| {
+        let pending = |v: &DownloadInfo| v.status != DownloadStatus::Pending;
+        (pending(a), &a.url).cmp(&(pending(b), &b.url))
+    });
+
+    Ok(AppJson(downloads))
+}
+
+/// Get download
+///
+/// Get a download of the record with its progress.
+#[utoipa::path(
+    get,
+    path = "/downloads/{file_id}",
+    responses((
+        status = OK, body = DownloadInfo,
+    ), (
+        status = NOT_FOUND,
+        description = "Download not found.",
+        body = String
+    )),
+    security(("basic_auth" = []), ("bearer_auth" = ["cluster:admin"])),
+    tag = OPENAPI_TAG
+)]
+pub async fn get_download(
+    State(state): State<Arc<AppState>>,
+    identity: Identity,
+    Path(file_id): Path<String>,
+) -> Result<AppJson<DownloadInfo>, AppError> {
+    identity.require(Scope::ClusterAdmin)?;
+
+    let download_state = state.download_state();
+    let download_state = download_state.read().await;
+    match download_state.get_by_id(&file_id) {
+        Some(task) => Ok(AppJson(download_state.info(task).await)),
+        None => Err(AppError::NotFoundError(anyhow::anyhow!(
+            "download not found"
+        ))),
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, ToSchema)]
+pub struct PrewarmRequest {
+    /// URL of the file.
+    #[schema(value_type = String)]
+    url: Url,
+    /// Folders under the ComfyUI root to link the file into, e.g. `checkpoints`.
+    target_dirs: Vec<String>,
+    /// Expected SHA-256 in hex, the download fails if the file does not match.
+    #[serde(default, deserialize_with = "deserialize_sha256")]
+    sha256: Option<String>,
+    /// Expected size in bytes.
+    #[serde(default)]
+    size: Option<u64>,
+}
+
+/// A folder under the ComfyUI root, which cannot be absolute or go up.
+fn target_dir(dir: &str) -> anyhow::Result<PathBuf> {
+    let path = PathBuf::from(dir);
+    if dir.is_empty() || !path.components().all(|v| matches!(v, Component::Normal(_))) {
+        anyhow::bail!("invalid target dir: {:?}", dir);
+    }
+    Ok(path)
+}
+
+/// Pre-warm download
+///
+/// Download a file into the cache and link it into the target folders, so that the first
+/// workflow using it does not wait. Returns once the download started.
+#[utoipa::path(
+    post,
+    path = "/downloads",
+    request_body = PrewarmRequest,
+    responses((
+        status = OK, body = DownloadInfo,
+    ), (
+        status = BAD_REQUEST,
+        description = "No or invalid target dirs.",
+        body = String
+    ), (
+        status = CONFLICT,
+        description = "The cached file is not the expected one but in use.",
+        body = String
+    )),
+    security(("basic_auth" = []), ("bearer_auth" = ["cluster:admin"])),
+    tag = OPENAPI_TAG
+)]
+pub async fn prewarm_download(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    identity: Identity,
+    AppJson(data): AppJson<PrewarmRequest>,
+) -> Result<AppJson<DownloadInfo>, AppError> {
+    identity.require(Scope::ClusterAdmin)?;
+
+    let target_dirs = data
+        .target_dirs
+        .iter()
+        .map(|v| target_dir(v))
+        .collect::<anyhow::Result<Vec<_>>>()
+        .map_err(AppError::BadRequest)?;
+    let Some((first, others)) = target_dirs.split_first() else {
+        return Err(AppError::BadRequest(anyhow::anyhow!(
+            "at least one target dir is required"
+        )));
+    };
+
+    let download_state = state.download_state();
+    let expected = Expected {
+        sha256: data.sha256.clone(),
+        size: data.size,
+    };
+    // only pinned while linking, the file is not used by a workflow yet
+    let lease = download_state.read().await.lease();
+    let (file_id, _) = create_download_task(
+        &data.url,
+        first,
+        &expected,
+        false,
+        &lease,
+        download_state.clone(),
+    )
+    .await
+    .map_err(AppError::Conflict)?;
+
+    let info = {
+        let mut download_state = download_state.write().await;
+        for target_dir in others {
+            download_state.add_target_dir(&file_id, target_dir);
+        }
+        let Some(task) = download_state.get_by_id(&file_id) else {
+            return Err(AppError::InternalServerError(anyhow::anyhow!(
+                "download {} removed while pre-warming",
+                file_id
+            )));
+        };
+        // a pending download links all its target dirs once completed
+        if task.status() == &DownloadStatus::Completed {
+            download_state.link(&file_id).await;
+        }
+        download_state.info(task).await
+    };
+
+    audit::record(
+        &state.audit_log(),
+        AuditEntry::new(
+            "download.prewarm",
+            json!({ "file_id": file_id, "url": info.url, "target_dirs": target_dirs }),
+        )
+        .with_identity(&identity)
+        .with_source(addr),
+    )
+    .await;
+
+    Ok(AppJson(info))
+}
+
+/// Delete download
+///
+/// Delete a download with its links and cached file, unless the file is shared with downloads
+/// of the same content from other URLs.
+#[utoipa::path(
+    post,
+    path = "/downloads/{file_id}/delete",
+    responses((
+        status = OK, description = "The deleted download.", body = DownloadInfo,
+    ), (
+        status = NOT_FOUND,
+        description = "Download not found.",
+        body = String
+    ), (
+        status = CONFLICT,
+        description = "The download is pending or used by a running workflow.",
+        body = String
+    )),
+    security(("basic_auth" = []), ("bearer_auth" = ["cluster:admin"])),
+    tag = OPENAPI_TAG
+)]
+pub async fn delete_download(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    identity: Identity,
+    Path(file_id): Path<String>,
+) -> Result<AppJson<DownloadInfo>, AppError> {
+    identity.require(Scope::ClusterAdmin)?;
+
+    let info = {
+        let download_state = state.download_state();
+        let mut download_state = download_state.write().await;
+        let Some(task) = download_state.get_by_id(&file_id) else {
+            return Err(AppError::NotFoundError(anyhow::anyhow!(
+                "download not found"
+            )));
+        };
+        if task.status() == &DownloadStatus::Pending {
+            return Err(AppError::Conflict(anyhow::anyhow!("download is pending")));
+        }
+        if download_state.is_pinned(&file_id) {
+            return Err(AppError::Conflict(anyhow::anyhow!(
+                "download is used by a running workflow"
+            )));
+        }
+
+        let info = download_state.info(task).await;
+        remove_download(&mut download_state, &file_id).await;
+        info
+    };
+
+    audit::record(
+        &state.audit_log(),
+        AuditEntry::new(
+            "download.delete",
+            json!({ "file_id": file_id, "url": info.url, "sha256": info.sha256 }),
+        )
+        .with_identity(&identity)
+        .with_source(addr),
+    )
+    .await;
+
+    Ok(AppJson(info))
+}
+
+#[derive(Clone, Debug, Deserialize, ToSchema)]
+pub struct PurgeRequest {
+    /// Only downloads whose URL matches, `*` matching any characters,
+    /// e.g. `https://civitai.com/*`.
+    pattern: Option<String>,
+    /// Only downloads not used by workflows for this many seconds.
+    older_than: Option<u64>,
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct PurgeResult {
+    /// File ids of the deleted downloads.
+    purged: Vec<String>,
+    /// File ids of the matching downloads kept because pending or used by a running workflow.
+    skipped: Vec<String>,
+}
+
+/// Whether `text` matches `pattern`, where `*` matches any characters.
+fn matches(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let Some(mut rest) = text.strip_prefix(parts.next().unwrap_or_default()) else {
+        return false;
+    };
+    let parts: Vec<_> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// Purge downloads
+///
+/// Delete the downloads matching a URL pattern and/or not used for a while, with their links
+/// and cached files. Pending downloads and those used by running workflows are kept.
+#[utoipa::path(
+    post,
+    path = "/downloads/purge",
+    request_body = PurgeRequest,
+    responses((
+        status = OK, body = PurgeResult,
+    ), (
+        status = BAD_REQUEST,
+        description = "Neither a pattern nor an age.",
+        body = String
+    )),
+    security(("basic_auth" = []), ("bearer_auth" = ["cluster:admin"])),
+    tag = OPENAPI_TAG
+)]
+pub async fn purge_downloads(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    identity: Identity,
+    AppJson(data): AppJson<PurgeRequest>,
+) -> Result<AppJson<PurgeResult>, AppError> {
+    identity.require(Scope::ClusterAdmin)?;
+
+    if data.pattern.is_none() && data.older_than.is_none() {
+        return Err(AppError::BadRequest(anyhow::anyhow!(
+            "pattern or older_than is required"
+        )));
+    }
+    let used_before = data
+        .older_than
+        .map(|v| now_millis().saturating_sub(v.saturating_mul(1000)));
+
+    let mut result = PurgeResult {
+        purged: vec![],
+        skipped: vec![],
+    };
+    {
+        let download_state = state.download_state();
+        let mut download_state = download_state.write().await;
+        let mut matched: Vec<_> = download_state
+            .downloads()
+            .filter(|v| {
+                data.pattern
+                    .as_deref()
+                    .is_none_or(|pattern| matches(pattern, v.url().as_str()))
+            })
+            // files downloaded before their use was recorded are as old as can be
+            .filter(|v| used_before.is_none_or(|t| v.last_used_at().is_none_or(|u| u < t)))
+            .map(|v| {
+                let in_use =
+                    v.status() == &DownloadStatus::Pending || download_state.is_pinned(v.file_id());
+                (v.file_id().to_string(), in_use)
+            })
+            .collect();
+        matched.sort();
+
+        for (file_id, in_use) in matched {
+            if in_use {
+                result.skipped.push(file_id);
+            } else {
+                remove_download(&mut download_state, &file_id).await;
+                result.purged.push(file_id);
+            }
+        }
+    }
+
+    audit::record(
+        &state.audit_log(),
+        AuditEntry::new(
+            "download.purge",
+            json!({
+                "pattern": data.pattern,
+                "older_than": data.older_than,
+                "purged": result.purged,
+            }),
+        )
+        .with_identity(&identity)
+        .with_source(addr),
+    )
+    .await;
+
+    Ok(AppJson(result))
+}
+
+/// Pre-fetch models
+///
+/// Download models into the cache and link them into the `models/*` folder of their kind,
+/// so that the first workflows using them do not wait. With `warm_up`, every node then
+/// generates a single step at 64x64 with each checkpoint and the LoRAs once idle, to load them.
+/// Returns once the downloads started, see `GET /downloads/prefetch/{id}` for the progress.
+#[utoipa::path(
+    post,
+    path = "/downloads/prefetch",
+    request_body = PrefetchRequest,
+    responses((
+        status = OK, body = PrefetchInfo,
+    ), (
+        status = BAD_REQUEST,
+        description = "No models, or a warm-up without checkpoint.",
+        body = String
+    ), (
+        status = CONFLICT,
+        description = "The cached file of a model is not the expected one but in use.",
+        body = String
+    )),
+    security(("basic_auth" = []), ("bearer_auth" = ["cluster:admin"])),
+    tag = OPENAPI_TAG
+)]
+pub async fn prefetch_models(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    identity: Identity,
+    AppJson(data): AppJson<PrefetchRequest>,
+) -> Result<AppJson<PrefetchInfo>, AppError> {
+    identity.require(Scope::ClusterAdmin)?;
+
+    if data.models.is_empty() {
+        return Err(AppError::BadRequest(anyhow::anyhow!(
+            "at least one model is required"
+        )));
+    }
+    if data.warm_up && !data.models.iter().any(|v| v.kind == ModelKind::Checkpoint) {
+        return Err(AppError::BadRequest(anyhow::anyhow!(
+            "warm-up requires a checkpoint"
+        )));
+    }
+
+    let urls: Vec<_> = data.models.iter().map(|v| v.url.clone()).collect();
+    let warm_up = data.warm_up;
+    let prefetch = prefetch(state.clone(), data)
+        .await
+        .map_err(AppError::Conflict)?;
+    let info = prefetch.info(&*state.download_state().read().await).await;
+    state.prefetch_record().write().await.add(prefetch);
+
+    audit::record(
+        &state.audit_log(),
+        AuditEntry::new(
+            "download.prefetch",
+            json!({ "id": info.id, "urls": urls, "warm_up": warm_up }),
+        )
+        .with_identity(&identity)
+        .with_source(addr),
+    )
+    .await;
+
+    Ok(AppJson(info))
+}
+
+/// Get pre-fetch
+///
+/// Get the progress of each model of a pre-fetch, and the warm-up of each node.
+#[utoipa::path(
+    get,
+    path = "/downloads/prefetch/{id}",
+    responses((
+        status = OK, body = PrefetchInfo,
+    ), (
+        status = NOT_FOUND,
+        description = "Pre-fetch not found.",
+        body = String
+    )),
+    security(("basic_auth" = []), ("bearer_auth" = ["cluster:admin"])),
+    tag = OPENAPI_TAG
+)]
+pub async fn get_prefetch(
+    State(state): State<Arc<AppState>>,
+    identity: Identity,
+    Path(id): Path<String>,
+) -> Result<AppJson<PrefetchInfo>, AppError> {
+    identity.require(Scope::ClusterAdmin)?;
+
+    let prefetch = state.prefetch_record().read().await.get(&id).cloned();
+    match prefetch {
+        Some(prefetch) => Ok(AppJson(
+            prefetch.info(&*state.download_state().read().await).await,
+        )),
+        None => Err(AppError::NotFoundError(anyhow::anyhow!(
+            "pre-fetch not found"
+        ))),
+    }
+}
+
+pub fn download_routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/", get(list_downloads))
+        .route("/", post(prewarm_download))
+        .route("/purge", post(purge_downloads))
+        .route("/prefetch", post(prefetch_models))
+        .route("/prefetch/:id", get(get_prefetch))
+        .route("/:file_id", get(get_download))
+        .route("/:file_id/delete", post(delete_download))
+}
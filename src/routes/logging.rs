@@ -0,0 +1,111 @@
+use super::{AppError, AppJson};
+use crate::{
+    audit::{self, AuditEntry},
+    auth::{Identity, Scope},
+    state::AppState,
+};
+use axum::{
+    extract::{ConnectInfo, State},
+    routing::{get, post},
+    Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{net::SocketAddr, sync::Arc};
+use utoipa::ToSchema;
+
+const OPENAPI_TAG: &str = "Logging";
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct LogFilterPayload {
+    /// Filter directives, e.g. `info,comfy_router::download=debug`.
+    filter: String,
+}
+
+/// Get log filter
+///
+/// Get the log filter currently in use.
+#[utoipa::path(
+    get,
+    path = "/logging/filter",
+    responses((
+        status = OK, body = LogFilterPayload,
+    )),
+    security(("basic_auth" = []), ("bearer_auth" = ["cluster:admin"])),
+    tag = OPENAPI_TAG
+)]
+pub async fn get_filter(
+    State(state): State<Arc<AppState>>,
+    identity: Identity,
+) -> Result<AppJson<LogFilterPayload>, AppError> {
+    identity.require(Scope::ClusterAdmin)?;
+
+    let log_filter = state
+        .log_filter()
+        .ok_or_else(|| AppError::NotFoundError(anyhow::anyhow!("logging is not configured")))?;
+
+    Ok(AppJson(LogFilterPayload {
+        filter: log_filter
+            .current()
+            .map_err(AppError::InternalServerError)?,
+    }))
+}
+
+/// Set log filter
+///
+/// Change the log filter without restart, e.g. to enable debug logs of a module.
+/// The change is lost on restart.
+#[utoipa::path(
+    post,
+    path = "/logging/filter",
+    request_body = LogFilterPayload,
+    responses((
+        status = OK, body = LogFilterPayload,
+    ), (
+        status = BAD_REQUEST,
+        description = "Invalid filter.",
+        body = String
+    )),
+    security(("basic_auth" = []), ("bearer_auth" = ["cluster:admin"])),
+    tag = OPENAPI_TAG
+)]
+pub async fn set_filter(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    identity: Identity,
+    AppJson(data): AppJson<LogFilterPayload>,
+) -> Result<AppJson<LogFilterPayload>, AppError> {
+    identity.require(Scope::ClusterAdmin)?;
+
+    let log_filter = state
+        .log_filter()
+        .ok_or_else(|| AppError::NotFoundError(anyhow::anyhow!("logging is not configured")))?;
+
+    let previous = log_filter
+        .current()
+        .map_err(AppError::InternalServerError)?;
+    log_filter.set(&data.filter).map_err(AppError::BadRequest)?;
+    let current = log_filter
+        .current()
+        .map_err(AppError::InternalServerError)?;
+
+    tracing::info!(previous, current, "log filter changed");
+    audit::record(
+        &state.audit_log(),
+        AuditEntry::new(
+            "logging.filter.set",
+            json!({ "previous": previous, "filter": current }),
+        )
+        .with_identity(&identity)
+        .with_source(addr),
+    )
+    .await;
+
+    Ok(AppJson(LogFilterPayload { filter: current }))
+}
+
+pub fn logging_routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/filter", get(get_filter))
+        .route("/filter", post(set_filter))
+}
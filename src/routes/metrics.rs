@@ -0,0 +1,87 @@
+use super::AppError;
+use crate::{
+    auth::{Identity, Scope},
+    cluster::Status,
+    download::manage::cache_size,
+    state::AppState,
+};
+use axum::{extract::State, http::header, response::IntoResponse, routing::get, Router};
+use std::sync::Arc;
+
+const OPENAPI_TAG: &str = "Metrics";
+
+/// Metrics
+///
+/// Metrics in Prometheus text format.
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    responses((
+        status = OK, body = String, content_type = "text/plain"
+    )),
+    security(("basic_auth" = []), ("bearer_auth" = ["cluster:admin"])),
+    tag = OPENAPI_TAG
+)]
+pub async fn metrics(
+    State(state): State<Arc<AppState>>,
+    identity: Identity,
+) -> Result<impl IntoResponse, AppError> {
+    identity.require(Scope::ClusterAdmin)?;
+
+    let metrics = state.metrics();
+
+    // refresh gauges of the current state
+    {
+        let workflow_record = state.workflow_record();
+        let workflow_record = workflow_record.read().await;
+        metrics
+            .queue_depth
+            .set(workflow_record.pending_len() as i64);
+    }
+
+    {
+        let node_state = state.node_state();
+        let node_state = node_state.read().await;
+        // reset so that removed nodes disappear
+        metrics.node_status.reset();
+        for (url, node_status) in node_state.get_all() {
+            for status in [Status::Idle, Status::Busy, Status::Offline] {
+                let value = (node_status.status() == &status) as i64;
+                metrics
+                    .node_status
+                    .with_label_values(&[url.as_str(), status.as_str()])
+                    .set(value);
+            }
+        }
+    }
+
+    {
+        let (cache_dir, max_cache_bytes) = {
+            let download_state = state.download_state();
+            let download_state = download_state.read().await;
+            (
+                download_state.cache_dir().clone(),
+                download_state.max_cache_bytes(),
+            )
+        };
+        let size = cache_size(&cache_dir)
+            .await
+            .map_err(AppError::InternalServerError)?;
+        metrics.cache_size_bytes.set(size as i64);
+        metrics.cache_max_bytes.set(max_cache_bytes as i64);
+    }
+
+    let body = metrics.encode().map_err(AppError::InternalServerError)?;
+
+    Ok((
+        [(
+            header::CONTENT_TYPE,
+            "text/plain; version=0.0.4; charset=utf-8",
+        )],
+        body,
+    ))
+}
+
+pub fn metrics_routes() -> Router<Arc<AppState>> {
+    Router::new().route("/", get(metrics))
+}
@@ -1,10 +1,19 @@
+pub mod audit;
+pub mod auth;
 pub mod cluster;
+pub mod config;
+pub mod download;
+pub mod logging;
+pub mod metrics;
 pub mod workflow;
 
-use crate::workflow::record::WorkflowRecordError;
+use crate::{
+    auth::{limit::LimitError, token::TokenError, AuthError},
+    workflow::record::WorkflowRecordError,
+};
 use axum::{
     extract::{rejection::JsonRejection, FromRequest},
-    http::StatusCode,
+    http::{header, HeaderMap, StatusCode},
     response::{IntoResponse, Response},
 };
 use serde::Serialize;
@@ -29,8 +38,12 @@ where
 
 pub enum AppError {
     JsonRejection(JsonRejection),
+    BadRequest(anyhow::Error),
+    Unauthorized,
+    Forbidden(anyhow::Error),
     NotFoundError(anyhow::Error),
-    TooManyRequests,
+    Conflict(anyhow::Error),
+    TooManyRequests(anyhow::Error, HeaderMap),
     InternalServerError(anyhow::Error),
 }
 
@@ -50,20 +63,40 @@ impl IntoResponse for AppError {
                 // This error is caused by bad user input so don't log it
                 (rejection.status(), rejection.body_text())
             }
+            AppError::BadRequest(error) => (StatusCode::BAD_REQUEST, error.to_string()),
+            AppError::Unauthorized => {
+                // ask for Basic credentials so that browsers can log into the admin page
+                return (
+                    StatusCode::UNAUTHORIZED,
+                    [(header::WWW_AUTHENTICATE, "Basic")],
+                    AppJson(ErrorResponse {
+                        message: "Unauthorized".to_string(),
+                    }),
+                )
+                    .into_response();
+            }
+            AppError::Forbidden(error) => (StatusCode::FORBIDDEN, format!("Forbidden: {}", error)),
             AppError::NotFoundError(error) => {
                 (StatusCode::NOT_FOUND, format!("Not found: {}", error))
             }
+            AppError::Conflict(error) => (StatusCode::CONFLICT, format!("Conflict: {}", error)),
             AppError::InternalServerError(error) => {
-                eprintln!("Internal Server Error: {}", error);
+                tracing::error!(error = format!("{:#}", error), "internal server error");
                 (
                     StatusCode::INTERNAL_SERVER_ERROR,
                     "Internal Server Error".to_string(),
                 )
             }
-            AppError::TooManyRequests => (
-                StatusCode::TOO_MANY_REQUESTS,
-                "No node available".to_string(),
-            ),
+            AppError::TooManyRequests(error, headers) => {
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    headers,
+                    AppJson(ErrorResponse {
+                        message: format!("Too many requests: {}", error),
+                    }),
+                )
+                    .into_response();
+            }
         };
 
         (status, AppJson(ErrorResponse { message })).into_response()
@@ -79,11 +112,32 @@ impl From<JsonRejection> for AppError {
 impl From<WorkflowRecordError> for AppError {
     fn from(error: WorkflowRecordError) -> Self {
         match error {
-            WorkflowRecordError::PendingQueueFull => Self::TooManyRequests,
+            WorkflowRecordError::PendingQueueFull => {
+                Self::TooManyRequests(error.into(), HeaderMap::new())
+            }
         }
     }
 }
 
+impl From<TokenError> for AppError {
+    fn from(error: TokenError) -> Self {
+        Self::Forbidden(error.into())
+    }
+}
+
+impl From<LimitError> for AppError {
+    fn from(error: LimitError) -> Self {
+        let headers = error.headers();
+        Self::TooManyRequests(error.into(), headers)
+    }
+}
+
+impl From<AuthError> for AppError {
+    fn from(error: AuthError) -> Self {
+        Self::Forbidden(error.into())
+    }
+}
+
 /// Health check
 #[utoipa::path(
     get,
@@ -1,15 +1,19 @@
 use super::{AppError, AppJson};
 use crate::{
+    audit::{self, AuditEntry},
+    auth::{Identity, Scope},
     state::AppState,
     workflow::{payload::WorkflowPayload, record::run_task, task::WorkflowResult},
 };
 use axum::{
-    extract::{Path, State},
+    extract::{ConnectInfo, Path, Query, State},
+    http::HeaderMap,
     routing::{get, post},
     Router,
 };
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use serde_json::json;
+use std::{collections::HashSet, net::SocketAddr, sync::Arc};
 use utoipa::ToSchema;
 
 const OPENAPI_TAG: &str = "Workflow";
@@ -17,11 +21,30 @@ const OPENAPI_TAG: &str = "Workflow";
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct WorkflowResponse {
     id: String,
+    /// Token for `GET /preview/{id}?token=`.
+    preview_token: String,
+    /// Unix timestamp in seconds when `preview_token` expires.
+    preview_expires_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PreviewTokenResponse {
+    preview_token: String,
+    /// Unix timestamp in seconds when `preview_token` expires.
+    preview_expires_at: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PreviewQuery {
+    token: Option<String>,
 }
 
 /// Run workflow
-/// 
+///
 /// Run SD15, SDXL or Flux workflow using predefined params.
+///
+/// Requests with API keys are subject to the rate limit, concurrent job limit
+/// and daily quotas of the key. The `RateLimit-*` headers describe the rate limit.
 #[utoipa::path(
     post, 
     path = "/workflow",
@@ -29,29 +52,105 @@ pub struct WorkflowResponse {
     responses((
         status = OK, 
         body = WorkflowResponse
+    ), (
+        status = TOO_MANY_REQUESTS,
+        description = "Rate limit, quota or pending queue exceeded.",
+        body = String
     )),
-    security(("basic_auth" = [])),
+    security(("basic_auth" = []), ("bearer_auth" = ["workflow:run"])),
     tag = OPENAPI_TAG
 )]
 pub async fn run_workflow(
     State(app_state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    identity: Identity,
     AppJson(data): AppJson<WorkflowPayload>,
-) -> Result<AppJson<WorkflowResponse>, AppError> {
+) -> Result<(HeaderMap, AppJson<WorkflowResponse>), AppError> {
+    identity.require(Scope::WorkflowRun)?;
+
+    let cost = data.cost();
+    let workflow_type = data.workflow_type();
+    // read before taking the locks, the config can be reloaded meanwhile
+    let default_limits = app_state.config().read().await.default_limits.clone();
+    let rate_limiter = app_state.rate_limiter();
+    let mut rate_limiter = rate_limiter.write().await;
     let workflow_record = app_state.workflow_record();
     let mut workflow_record = workflow_record.write().await;
-    let workflow_task = workflow_record.add(data)?;
+
+    let admission = match &identity {
+        Identity::ApiKey { id, limits, .. } => {
+            let limits = limits.or(&default_limits);
+
+            let mut active_jobs = HashSet::new();
+            for job_id in rate_limiter.jobs(id) {
+                if let Some(task) = workflow_record.get(&job_id) {
+                    if matches!(
+                        task.result().await,
+                        WorkflowResult::Pending(_)
+                            | WorkflowResult::Downloading(_)
+                            | WorkflowResult::Running(_)
+                    ) {
+                        active_jobs.insert(job_id);
+                    }
+                }
+            }
+
+            Some(rate_limiter.check(id, &limits, cost, |v| active_jobs.contains(v))?)
+        }
+        Identity::Admin => None,
+    };
+
+    let workflow_task = workflow_record.add(data, identity.id())?;
     let task_id = workflow_task.id().to_string();
+    tracing::info!(task_id, identity = %identity, "workflow submitted");
+
+    let headers = match &admission {
+        Some(admission) => {
+            rate_limiter.commit(admission, &task_id);
+            admission.headers().clone()
+        }
+        None => HeaderMap::new(),
+    };
+
+    drop(workflow_record);
+    drop(rate_limiter);
+
+    audit::record(
+        &app_state.audit_log(),
+        AuditEntry::new(
+            "workflow.submit",
+            json!({
+                "task_id": task_id,
+                "type": workflow_type,
+                "images": cost.images,
+                "steps": cost.steps,
+            }),
+        )
+        .with_identity(&identity)
+        .with_source(addr),
+    )
+    .await;
+
+    let (preview_token, preview_expires_at) = app_state.preview_signer().sign(&task_id);
 
     tokio::spawn(async move {
         run_task(app_state).await;
     });
 
-    Ok(AppJson(WorkflowResponse { id: task_id }))
+    Ok((
+        headers,
+        AppJson(WorkflowResponse {
+            id: task_id,
+            preview_token,
+            preview_expires_at,
+        }),
+    ))
 }
 
 /// Check workflow
-/// 
+///
 /// Get the full results of a workflow with given id.
+/// Only the identity that submitted the workflow and admins can see it.
 #[utoipa::path(
     get, 
     path = "/workflow/{id}", 
@@ -63,33 +162,112 @@ pub async fn run_workflow(
         description = "Workflow not found.",
         body = String
     )),
-    security(("basic_auth" = [])),
+    security(("basic_auth" = []), ("bearer_auth" = ["workflow:read"])),
     tag = OPENAPI_TAG
 )]
 pub async fn check_workflow(
     State(app_state): State<Arc<AppState>>,
+    identity: Identity,
     Path(id): Path<String>,
 ) -> Result<AppJson<WorkflowResult>, AppError> {
+    identity.require(Scope::WorkflowRead)?;
+
+    let result = {
+        let workflow_record = app_state.workflow_record();
+        let workflow_record = workflow_record.read().await;
+        // tasks of others are reported as not found, to not reveal their existence
+        let task = workflow_record
+            .get(&id)
+            .filter(|task| identity.can_access(task.owner()));
+        match task {
+            Some(task) => task.result().await,
+            None => return Err(AppError::NotFoundError(anyhow::anyhow!("task not found"))),
+        }
+    };
+
+    Ok(AppJson(with_download_progress(&app_state, result).await))
+}
+
+/// The result with the current progress of the downloads the task waits for.
+async fn with_download_progress(app_state: &AppState, result: WorkflowResult) -> WorkflowResult {
+    let WorkflowResult::Downloading(downloads) = result else {
+        return result;
+    };
+
+    let download_state = app_state.download_state();
+    let download_state = download_state.read().await;
+    let mut refreshed = vec![];
+    for download in downloads {
+        match download_state.get_by_id(&download.file_id) {
+            Some(task) => refreshed.push(download_state.info(task).await),
+            None => refreshed.push(download),
+        }
+    }
+
+    WorkflowResult::Downloading(refreshed)
+}
+
+/// Renew preview token
+///
+/// Get a new preview token of a workflow, e.g. when the one returned
+/// on submission has expired.
+#[utoipa::path(
+    post,
+    path = "/workflow/{id}/preview_token",
+    responses((
+        status = OK,
+        body = PreviewTokenResponse
+    ), (
+        status = NOT_FOUND,
+        description = "Workflow not found.",
+        body = String
+    )),
+    security(("basic_auth" = []), ("bearer_auth" = ["workflow:read"])),
+    tag = OPENAPI_TAG
+)]
+pub async fn renew_preview_token(
+    State(app_state): State<Arc<AppState>>,
+    identity: Identity,
+    Path(id): Path<String>,
+) -> Result<AppJson<PreviewTokenResponse>, AppError> {
+    identity.require(Scope::WorkflowRead)?;
+
     let workflow_record = app_state.workflow_record();
     let workflow_record = workflow_record.read().await;
-    let task = workflow_record.get(&id);
+    let task = workflow_record
+        .get(&id)
+        .filter(|task| identity.can_access(task.owner()));
     if let Some(task) = task {
-        Ok(AppJson(task.result().await))
+        let (preview_token, preview_expires_at) = app_state.preview_signer().sign(task.id());
+        Ok(AppJson(PreviewTokenResponse {
+            preview_token,
+            preview_expires_at,
+        }))
     } else {
         Err(AppError::NotFoundError(anyhow::anyhow!("task not found")))
     }
 }
 
 /// Get preview
-/// 
+///
 /// Get the preview result of a workflow with given id.
 /// If the workflow has finished, the preview will no longer be available.
+///
+/// No authentication is needed, but `token` must be a valid preview token of the
+/// workflow, as returned by `POST /workflow` or `POST /workflow/{id}/preview_token`.
 #[utoipa::path(
     get, 
     path = "/preview/{id}", 
+    params(
+        ("token" = String, Query, description = "Preview token of the workflow")
+    ),
     responses((
         status = OK, 
         body = WorkflowResult
+    ), (
+        status = FORBIDDEN,
+        description = "Missing, invalid or expired token.",
+        body = String
     ), (
         status = NOT_FOUND,
         description = "Workflow not found.",
@@ -100,27 +278,33 @@ pub async fn check_workflow(
 pub async fn preview_workflow(
     State(app_state): State<Arc<AppState>>,
     Path(id): Path<String>,
+    Query(query): Query<PreviewQuery>,
 ) -> Result<AppJson<WorkflowResult>, AppError> {
-    let workflow_record = app_state.workflow_record();
-    let workflow_record = workflow_record.read().await;
-    let task = workflow_record.get(&id);
-    if let Some(task) = task {
-        let result = task.result().await;
+    app_state
+        .preview_signer()
+        .verify(&id, query.token.as_deref())?;
 
-        let result = match result {
-            // ignore result
-            WorkflowResult::Done(_) => WorkflowResult::Done(vec![]),
-            _ => result,
-        };
+    let result = {
+        let workflow_record = app_state.workflow_record();
+        let workflow_record = workflow_record.read().await;
+        match workflow_record.get(&id) {
+            Some(task) => task.result().await,
+            None => return Err(AppError::NotFoundError(anyhow::anyhow!("task not found"))),
+        }
+    };
 
-        Ok(AppJson(result))
-    } else {
-        Err(AppError::NotFoundError(anyhow::anyhow!("task not found")))
-    }
+    let result = match result {
+        // ignore result
+        WorkflowResult::Done(_) => WorkflowResult::Done(vec![]),
+        _ => with_download_progress(&app_state, result).await,
+    };
+
+    Ok(AppJson(result))
 }
 
 pub fn workflow_routes() -> Router<Arc<AppState>> {
     Router::new()
         .route("/", post(run_workflow))
         .route("/:id", get(check_workflow))
+        .route("/:id/preview_token", post(renew_preview_token))
 }
@@ -1,44 +1,217 @@
 use crate::{
-    cluster::NodeState, config::AppConfig, download::state::DownloadState,
-    workflow::record::WorkflowRecord,
+    audit::AuditLog,
+    auth::{key::ApiKeyStore, limit::RateLimiter, token::PreviewSigner},
+    cluster::{client::NodeClient, NodeState},
+    config::{AppConfig, ConfigChanges, ConfigSource},
+    download::{
+        manage::{manage_cache, CachePolicy},
+        state::DownloadState,
+        task::DownloadOptions,
+    },
+    http::HttpClient,
+    metrics::Metrics,
+    telemetry::LogFilter,
+    tls::ServerTls,
+    workflow::{prefetch::PrefetchRecord, record::WorkflowRecord},
 };
+use anyhow::Context;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
 #[derive(Clone, Debug)]
 pub struct AppState {
-    config: AppConfig,
+    config: Arc<RwLock<AppConfig>>,
+    config_source: Option<ConfigSource>,
     download_state: Arc<RwLock<DownloadState>>,
     node_state: Arc<RwLock<NodeState>>,
     workflow_record: Arc<RwLock<WorkflowRecord>>,
+    prefetch_record: Arc<RwLock<PrefetchRecord>>,
+    api_keys: Arc<RwLock<ApiKeyStore>>,
+    rate_limiter: Arc<RwLock<RateLimiter>>,
+    preview_signer: PreviewSigner,
+    audit_log: Arc<RwLock<AuditLog>>,
+    metrics: Metrics,
+    log_filter: Option<LogFilter>,
+    node_client: NodeClient,
+    server_tls: Option<ServerTls>,
 }
 
 impl AppState {
-    pub async fn new(config: AppConfig) -> Self {
-        let download_state = DownloadState::new(
-            &config.record_path,
-            &config.root_dir,
-            &config.cache_dir,
-            config.max_cache_bytes,
+    pub async fn new(config: AppConfig) -> anyhow::Result<Self> {
+        let audit_log = Arc::new(RwLock::new(AuditLog::new(
+            &config.audit.log_path,
+            config.audit.max_bytes,
+            config.audit.max_files,
+        )));
+
+        let mut download_state = DownloadState::new(
+            &config.storage.record_path,
+            &config.download.root_dir,
+            &config.download.cache_dir,
+            config.download.max_cache_bytes,
         )
-        .await;
+        .await?;
+        download_state.set_audit_log(audit_log.clone());
+
+        let metrics = Metrics::new();
+        download_state.set_metrics(metrics.clone());
+        download_state.set_client(
+            HttpClient::download(&config.http).context("failed to create download client")?,
+        );
+        download_state.set_options(DownloadOptions::new(&config.download, &config.http));
+        download_state.set_cache_policy(CachePolicy::new(&config.download));
+        download_state.reconcile().await;
         let node_state = NodeState::new();
+        let api_keys = ApiKeyStore::new(&config.storage.api_keys_path)
+            .await
+            .context("failed to load api keys")?;
+
+        let node_client = NodeClient::new(&config.node_tls, &config.http)
+            .context("failed to load node tls config")?;
+
+        let preview_signer =
+            PreviewSigner::new(&config.auth.preview_secret, config.auth.preview_token_ttl);
 
-        // TODO make record resizable according to node list size
-        // for now, 50 is suitable for most of the cases
         let workflow_record =
-            WorkflowRecord::new(config.workflow_history_limit, config.workflow_pending_limit);
+            WorkflowRecord::new(config.cluster.history_limit, config.cluster.pending_limit);
+        let prefetch_record = PrefetchRecord::new(config.cluster.history_limit);
 
-        Self {
-            config,
+        Ok(Self {
+            config: Arc::new(RwLock::new(config)),
+            config_source: None,
             download_state: Arc::new(RwLock::new(download_state)),
             node_state: Arc::new(RwLock::new(node_state)),
             workflow_record: Arc::new(RwLock::new(workflow_record)),
-        }
+            prefetch_record: Arc::new(RwLock::new(prefetch_record)),
+            api_keys: Arc::new(RwLock::new(api_keys)),
+            rate_limiter: Arc::new(RwLock::new(RateLimiter::new())),
+            preview_signer,
+            audit_log,
+            metrics,
+            log_filter: None,
+            node_client,
+            server_tls: None,
+        })
+    }
+
+    /// Allow changing the log filter at runtime, see `telemetry::init`.
+    pub fn with_log_filter(mut self, log_filter: LogFilter) -> Self {
+        self.log_filter = Some(log_filter);
+        self
+    }
+
+    /// Allow reloading the config, see `reload_config`.
+    pub fn with_config_source(mut self, config_source: ConfigSource) -> Self {
+        self.config_source = Some(config_source);
+        self
     }
 
-    pub fn config(&self) -> &AppConfig {
-        &self.config
+    /// Reload the HTTPS certificate with the config, see `reload_config`.
+    pub fn with_server_tls(mut self, server_tls: ServerTls) -> Self {
+        self.server_tls = Some(server_tls);
+        self
+    }
+
+    pub fn config(&self) -> Arc<RwLock<AppConfig>> {
+        self.config.clone()
+    }
+
+    /// Load the config again and apply the settings that can change at runtime,
+    /// see `AppConfig::reload`. Nothing changes if the new config is invalid.
+    pub async fn reload_config(&self) -> anyhow::Result<ConfigChanges> {
+        let config_source = self
+            .config_source
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("the router was not started from a config"))?;
+        let new_config = config_source.load()?;
+
+        let (changes, config) = {
+            let mut config = self.config.write().await;
+            let changes = config.reload(&new_config);
+            (changes, config.clone())
+        };
+        let applied = |key: &str| changes.applied.iter().any(|v| v == key);
+
+        self.workflow_record
+            .write()
+            .await
+            .set_capacity(config.cluster.history_limit, config.cluster.pending_limit);
+        self.prefetch_record
+            .write()
+            .await
+            .set_capacity(config.cluster.history_limit);
+
+        if applied("log.filter") {
+            if let Some(log_filter) = &self.log_filter {
+                log_filter.set(&config.log.filter)?;
+            }
+        }
+
+        if [
+            "download.retries",
+            "download.parallel_chunks",
+            "download.chunk_size",
+        ]
+        .into_iter()
+        .any(applied)
+        {
+            self.download_state
+                .write()
+                .await
+                .set_options(DownloadOptions::new(&config.download, &config.http));
+        }
+
+        let policy_changed = changes.applied.iter().any(|v| {
+            [
+                "download.eviction_policy",
+                "download.quotas",
+                "download.ttl",
+                "download.never_evict",
+            ]
+            .iter()
+            .any(|key| v == key || v.starts_with(&format!("{}.", key)))
+        });
+        if policy_changed {
+            self.download_state
+                .write()
+                .await
+                .set_cache_policy(CachePolicy::new(&config.download));
+        }
+
+        if applied("download.max_cache_bytes") {
+            self.download_state
+                .write()
+                .await
+                .set_max_cache_bytes(config.download.max_cache_bytes);
+        }
+
+        if applied("download.max_cache_bytes") || policy_changed {
+            // evict files now if a limit is lower, not on the next download
+            let download_state = self.download_state.clone();
+            tokio::spawn(async move {
+                if let Err(e) = manage_cache(download_state).await {
+                    tracing::warn!(error = %e, "failed to manage cache");
+                }
+            });
+        }
+
+        // renewed certificates are picked up even if the paths did not change
+        if let Some(server_tls) = &self.server_tls {
+            if let Err(e) = server_tls.reload() {
+                tracing::warn!(
+                    error = format!("{:#}", e),
+                    "failed to reload tls certificate"
+                );
+            }
+        }
+
+        tracing::info!(
+            applied = ?changes.applied,
+            restart_required = ?changes.restart_required,
+            "config reloaded"
+        );
+
+        Ok(changes)
     }
 
     pub fn download_state(&self) -> Arc<RwLock<DownloadState>> {
@@ -52,4 +225,36 @@ impl AppState {
     pub fn workflow_record(&self) -> Arc<RwLock<WorkflowRecord>> {
         self.workflow_record.clone()
     }
+
+    pub fn prefetch_record(&self) -> Arc<RwLock<PrefetchRecord>> {
+        self.prefetch_record.clone()
+    }
+
+    pub fn api_keys(&self) -> Arc<RwLock<ApiKeyStore>> {
+        self.api_keys.clone()
+    }
+
+    pub fn rate_limiter(&self) -> Arc<RwLock<RateLimiter>> {
+        self.rate_limiter.clone()
+    }
+
+    pub fn preview_signer(&self) -> &PreviewSigner {
+        &self.preview_signer
+    }
+
+    pub fn audit_log(&self) -> Arc<RwLock<AuditLog>> {
+        self.audit_log.clone()
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub fn log_filter(&self) -> Option<&LogFilter> {
+        self.log_filter.as_ref()
+    }
+
+    pub fn node_client(&self) -> &NodeClient {
+        &self.node_client
+    }
 }
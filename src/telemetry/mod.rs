@@ -0,0 +1,216 @@
+use crate::config::{AppConfig, LogFormat, LogRotation, OtelProtocol};
+use axum::{extract::MatchedPath, http::HeaderMap, http::Request};
+use opentelemetry::{
+    global,
+    propagation::Extractor,
+    trace::{Span as _, Tracer, TracerProvider as _},
+    Context, KeyValue,
+};
+use opentelemetry_otlp::{Protocol, WithExportConfig};
+use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::SdkTracerProvider, Resource};
+use std::time::SystemTime;
+use tracing::{Span, Subscriber};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{
+    fmt::MakeWriter, layer::SubscriberExt, registry::LookupSpan, reload, util::SubscriberInitExt,
+    EnvFilter, Layer, Registry,
+};
+
+const TRACER_NAME: &str = "comfy-router";
+
+const LOG_FILE_PREFIX: &str = "comfy-router";
+
+/// Handle of the installed tracing pipeline, call `shutdown` before exit to flush spans.
+#[derive(Debug)]
+pub struct Telemetry {
+    provider: Option<SdkTracerProvider>,
+    log_filter: LogFilter,
+}
+
+/// Handle to change the log filter at runtime.
+#[derive(Clone, Debug)]
+pub struct LogFilter {
+    handle: reload::Handle<EnvFilter, Registry>,
+}
+
+impl LogFilter {
+    /// The current filter directives, e.g. `info,comfy_router::download=debug`.
+    pub fn current(&self) -> anyhow::Result<String> {
+        Ok(self.handle.with_current(|v| v.to_string())?)
+    }
+
+    pub fn set(&self, directives: &str) -> anyhow::Result<()> {
+        let filter = EnvFilter::try_new(directives)?;
+        self.handle.reload(filter)?;
+        Ok(())
+    }
+}
+
+fn fmt_layer<S, W>(format: LogFormat, writer: W, ansi: bool) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_ansi(ansi);
+
+    match format {
+        LogFormat::Text => layer.boxed(),
+        LogFormat::Pretty => layer.pretty().boxed(),
+        LogFormat::Json => layer.json().boxed(),
+    }
+}
+
+/// Install the global tracing subscriber, logging to stdout (and files in `log.dir` if set)
+/// and, when `otel.endpoint` is set, exporting spans with OTLP over HTTP.
+pub fn init(config: &AppConfig) -> anyhow::Result<Telemetry> {
+    let log = &config.log;
+    let format = log.format;
+    let filter = EnvFilter::try_new(&log.filter)
+        .map_err(|e| anyhow::anyhow!("invalid log filter {}: {}", log.filter, e))?;
+    let (filter, filter_handle) = reload::Layer::new(filter);
+
+    let mut fmt_layers = vec![fmt_layer(format, std::io::stdout, true)];
+    if let Some(log_dir) = &log.dir {
+        let rotation = match log.rotation {
+            LogRotation::Minutely => Rotation::MINUTELY,
+            LogRotation::Hourly => Rotation::HOURLY,
+            LogRotation::Daily => Rotation::DAILY,
+            LogRotation::Never => Rotation::NEVER,
+        };
+        let appender = RollingFileAppender::builder()
+            .rotation(rotation)
+            .filename_prefix(LOG_FILE_PREFIX)
+            .filename_suffix("log")
+            .max_log_files(log.max_files)
+            .build(log_dir)?;
+        fmt_layers.push(fmt_layer(format, appender, false));
+    }
+
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let otel = &config.otel;
+    let provider = match &otel.endpoint {
+        Some(endpoint) => {
+            let protocol = match otel.protocol {
+                OtelProtocol::HttpProtobuf => Protocol::HttpBinary,
+                OtelProtocol::HttpJson => Protocol::HttpJson,
+            };
+
+            let exporter = opentelemetry_otlp::SpanExporter::builder()
+                .with_http()
+                .with_protocol(protocol)
+                .with_endpoint(format!(
+                    "{}/v1/traces",
+                    endpoint.as_str().trim_end_matches('/')
+                ))
+                .build()?;
+
+            let provider = SdkTracerProvider::builder()
+                .with_batch_exporter(exporter)
+                .with_resource(
+                    Resource::builder()
+                        .with_service_name(otel.service_name.clone())
+                        .build(),
+                )
+                .build();
+            global::set_tracer_provider(provider.clone());
+
+            Some(provider)
+        }
+        None => None,
+    };
+
+    let otel_layer = provider
+        .as_ref()
+        .map(|v| tracing_opentelemetry::layer().with_tracer(v.tracer(TRACER_NAME)));
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt_layers)
+        .with(otel_layer)
+        .try_init()?;
+
+    Ok(Telemetry {
+        provider,
+        log_filter: LogFilter {
+            handle: filter_handle,
+        },
+    })
+}
+
+impl Telemetry {
+    pub fn log_filter(&self) -> LogFilter {
+        self.log_filter.clone()
+    }
+    /// Flush pending spans and stop the exporter.
+    pub async fn shutdown(self) {
+        if let Some(provider) = self.provider {
+            // the batch exporter blocks until spans are exported
+            let result = tokio::task::spawn_blocking(move || provider.shutdown()).await;
+            if let Ok(Err(e)) = result {
+                tracing::warn!("failed to shutdown tracer provider: {}", e);
+            }
+        }
+    }
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|v| v.as_str()).collect()
+    }
+}
+
+/// Span of an HTTP request, continuing the trace of the W3C `traceparent` header if any.
+pub fn request_span<B>(request: &Request<B>) -> Span {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|v| v.as_str())
+        .unwrap_or(request.uri().path());
+
+    let span = tracing::info_span!(
+        "request",
+        otel.name = format!("{} {}", request.method(), route),
+        otel.kind = "server",
+        method = %request.method(),
+        uri = %request.uri(),
+        version = ?request.version(),
+    );
+
+    let parent =
+        global::get_text_map_propagator(|v| v.extract(&HeaderExtractor(request.headers())));
+    span.set_parent(parent);
+
+    span
+}
+
+/// Trace context of the current span, to continue the trace later, e.g. in another task.
+pub fn current_context() -> Context {
+    Span::current().context()
+}
+
+/// Record a span that started in the past and ends now,
+/// e.g. the time a workflow waited in the queue.
+pub fn record_span(
+    name: &'static str,
+    parent: &Context,
+    start_time: SystemTime,
+    attributes: Vec<KeyValue>,
+) {
+    let tracer = global::tracer(TRACER_NAME);
+    tracer
+        .span_builder(name)
+        .with_start_time(start_time)
+        .with_attributes(attributes)
+        .start_with_context(&tracer, parent)
+        .end();
+}
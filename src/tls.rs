@@ -0,0 +1,161 @@
+//! TLS of the HTTPS listener and of the connections to nodes.
+
+use crate::config::{NodeTlsConfig, TlsConfig};
+use anyhow::Context;
+use axum_server::tls_rustls::RustlsConfig;
+use rustls::{
+    crypto::CryptoProvider,
+    pki_types::{CertificateDer, PrivateKeyDer},
+    ClientConfig, RootCertStore, ServerConfig,
+};
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+/// HTTP/2 and HTTP/1.1, in order of preference.
+pub const ALPN_PROTOCOLS: &[&[u8]] = &[b"h2", b"http/1.1"];
+
+fn crypto_provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+fn load_certs(path: &Path) -> anyhow::Result<Vec<CertificateDer<'static>>> {
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("invalid certificate in {}", path.display()))?;
+
+    if certs.is_empty() {
+        anyhow::bail!("no certificate in {}", path.display());
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &Path) -> anyhow::Result<PrivateKeyDer<'static>> {
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .with_context(|| format!("invalid private key in {}", path.display()))?
+        .ok_or_else(|| anyhow::anyhow!("no private key in {}", path.display()))
+}
+
+fn server_config(cert_path: &Path, key_path: &Path) -> anyhow::Result<Arc<ServerConfig>> {
+    let mut config = ServerConfig::builder_with_provider(crypto_provider())
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(load_certs(cert_path)?, load_key(key_path)?)
+        .context("invalid certificate or private key")?;
+    config.alpn_protocols = ALPN_PROTOCOLS.iter().map(|v| v.to_vec()).collect();
+
+    Ok(Arc::new(config))
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|v| v.modified()).ok()
+}
+
+/// Certificate of the HTTPS listener, swapped without restart when its files are renewed.
+/// Connections already established keep the previous certificate.
+#[derive(Clone, Debug)]
+pub struct ServerTls {
+    rustls_config: RustlsConfig,
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    /// Modification times of the loaded files.
+    loaded: Arc<Mutex<(Option<SystemTime>, Option<SystemTime>)>>,
+}
+
+impl ServerTls {
+    /// Load the certificate of `config`, `None` if HTTPS is not configured.
+    pub fn load(config: &TlsConfig) -> anyhow::Result<Option<Self>> {
+        let (Some(cert_path), Some(key_path)) = (&config.cert_path, &config.key_path) else {
+            return Ok(None);
+        };
+
+        let modified_at = (modified(cert_path), modified(key_path));
+        let server_tls = Self {
+            rustls_config: RustlsConfig::from_config(server_config(cert_path, key_path)?),
+            cert_path: cert_path.clone(),
+            key_path: key_path.clone(),
+            loaded: Arc::new(Mutex::new(modified_at)),
+        };
+
+        Ok(Some(server_tls))
+    }
+
+    pub fn rustls_config(&self) -> RustlsConfig {
+        self.rustls_config.clone()
+    }
+
+    /// Load the certificate files again, the current certificate is kept if they are invalid.
+    pub fn reload(&self) -> anyhow::Result<()> {
+        let modified_at = (modified(&self.cert_path), modified(&self.key_path));
+
+        let config = server_config(&self.cert_path, &self.key_path)?;
+        self.rustls_config.reload_from_config(config);
+        *self.loaded.lock().expect("lock should not be poisoned") = modified_at;
+
+        Ok(())
+    }
+
+    /// Reload if the files changed since they were loaded, return whether they were.
+    pub fn reload_if_changed(&self) -> anyhow::Result<bool> {
+        let modified_at = (modified(&self.cert_path), modified(&self.key_path));
+        if *self.loaded.lock().expect("lock should not be poisoned") == modified_at {
+            return Ok(false);
+        }
+
+        self.reload()?;
+        Ok(true)
+    }
+
+    /// Check the files for renewal every `interval`, e.g. by certbot or cert-manager.
+    pub async fn watch(self, interval: Duration) {
+        loop {
+            tokio::time::sleep(interval).await;
+
+            match self.reload_if_changed() {
+                Ok(true) => {
+                    tracing::info!(cert_path = %self.cert_path.display(), "tls certificate reloaded")
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    tracing::warn!(
+                        error = format!("{:#}", e),
+                        "failed to reload tls certificate"
+                    )
+                }
+            }
+        }
+    }
+}
+
+/// TLS of the connections to nodes, trusting the public roots and the CA bundle of `config`,
+/// and presenting its client certificate if any.
+pub fn node_client_config(config: &NodeTlsConfig) -> anyhow::Result<ClientConfig> {
+    let mut roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    if let Some(ca_path) = &config.ca_path {
+        for cert in load_certs(ca_path)? {
+            roots
+                .add(cert)
+                .with_context(|| format!("invalid CA certificate in {}", ca_path.display()))?;
+        }
+    }
+
+    let builder = ClientConfig::builder_with_provider(crypto_provider())
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots);
+    let config = match (&config.cert_path, &config.key_path) {
+        (Some(cert_path), Some(key_path)) => builder
+            .with_client_auth_cert(load_certs(cert_path)?, load_key(key_path)?)
+            .context("invalid client certificate or private key")?,
+        _ => builder.with_no_client_auth(),
+    };
+
+    Ok(config)
+}
@@ -1,36 +1,83 @@
-use crate::{download::task::DownloadStatus, state::AppState};
+use super::task::WorkflowResult;
+use crate::{
+    download::{lease::Lease, task::DownloadStatus},
+    state::AppState,
+};
 use std::sync::Arc;
-use tokio::{sync::watch, task::JoinSet};
+use tokio::{
+    sync::{watch, RwLock},
+    task::JoinSet,
+};
 
 pub trait Fetch {
     fn fetch(
         &self,
         app_state: Arc<AppState>,
         target_folder: &str,
-    ) -> impl std::future::Future<Output = (String, Option<watch::Receiver<DownloadStatus>>)> + Send;
+        lease: &Lease,
+    ) -> impl std::future::Future<
+        Output = anyhow::Result<(String, Option<watch::Receiver<DownloadStatus>>)>,
+    > + Send;
 }
 
 pub struct FetchHelper {
     join_set: JoinSet<DownloadStatus>,
+    /// Files of the triggered download tasks.
+    file_ids: Vec<String>,
+    /// Fetched files, as paths under the root dir, e.g. `models/loras/<file id>`.
+    files: Vec<String>,
+    /// Files which could not be fetched, reported by `wait_all`.
+    errors: Vec<anyhow::Error>,
     app_state: Arc<AppState>,
+    /// Result of the workflow, showing the downloads while waiting for them.
+    result: Arc<RwLock<WorkflowResult>>,
+    /// Pins the fetched files until the workflow finishes.
+    lease: Lease,
 }
 
 impl FetchHelper {
-    pub fn new(app_state: Arc<AppState>) -> Self {
+    pub fn new(
+        app_state: Arc<AppState>,
+        result: Arc<RwLock<WorkflowResult>>,
+        lease: Lease,
+    ) -> Self {
         Self {
             join_set: JoinSet::new(),
+            file_ids: vec![],
+            files: vec![],
+            errors: vec![],
             app_state,
+            result,
+            lease,
         }
     }
 
     /// Get the filename of the artifact.
     /// If the file doesn't exist, a download task will be triggered
     /// in the background, which will not block this function.
-    /// Use `wait_all` to wait for all triggered download task.
+    /// Use `wait_all` to wait for all triggered download task, and for the errors of
+    /// the files which could not be fetched.
     pub async fn add(&mut self, artifact: impl Fetch, target_folder: &str) -> String {
-        let (name, rx) = artifact.fetch(self.app_state.clone(), target_folder).await;
+        let (name, rx) = match artifact
+            .fetch(self.app_state.clone(), target_folder, &self.lease)
+            .await
+        {
+            Ok(fetched) => fetched,
+            Err(e) => {
+                self.errors.push(e);
+                return String::new();
+            }
+        };
+
+        // build-in models are not downloads, nodes are expected to have them
+        let download_state = self.app_state.download_state();
+        if download_state.read().await.get_by_id(&name).is_some() {
+            self.files
+                .push(format!("{}/{}", target_folder.trim_end_matches('/'), name));
+        }
 
         if let Some(mut rx) = rx {
+            self.file_ids.push(name.clone());
             self.join_set.spawn(async move {
                 loop {
                     if rx.changed().await.is_err() {
@@ -52,15 +99,35 @@ impl FetchHelper {
         name
     }
 
-    /// Wait for all download task added by `add` to finish.
+    /// Wait for all download task added by `add` to finish, and return the paths
+    /// of the fetched files under the root dir.
     /// If any task failed, this function will return an error.
-    pub async fn wait_all(self) -> anyhow::Result<()> {
+    pub async fn wait_all(mut self) -> anyhow::Result<Vec<String>> {
+        if !self.errors.is_empty() {
+            return Err(self.errors.remove(0));
+        }
+
+        if !self.file_ids.is_empty() {
+            let downloads = {
+                let download_state = self.app_state.download_state();
+                let download_state = download_state.read().await;
+                let mut downloads = vec![];
+                for file_id in &self.file_ids {
+                    if let Some(task) = download_state.get_by_id(file_id) {
+                        downloads.push(download_state.info(task).await);
+                    }
+                }
+                downloads
+            };
+            *self.result.write().await = WorkflowResult::Downloading(downloads);
+        }
+
         let results = self.join_set.join_all().await;
 
         if results.iter().any(|v| *v != DownloadStatus::Completed) {
             anyhow::bail!("download failed");
         } else {
-            Ok(())
+            Ok(self.files)
         }
     }
 }
@@ -52,6 +52,14 @@ pub struct ExecutionErrorMessage {
     // there are other params that can be ignored for now
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExecutionInterruptedMessage {
+    pub prompt_id: String,
+    pub node_id: String,
+    pub node_type: String,
+    // there are other params that can be ignored for now
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case", tag = "type", content = "data")]
 #[non_exhaustive]
@@ -63,4 +71,5 @@ pub enum WorkflowMessage {
     Progress(ProgressMessage),
     ExecutionSuccess(ExecutionSuccessMessage),
     ExecutionError(ExecutionErrorMessage),
+    ExecutionInterrupted(ExecutionInterruptedMessage),
 }
@@ -1,5 +1,6 @@
 pub mod task;
 pub mod payload;
 pub mod message;
+pub mod prefetch;
 pub mod record;
 mod fetch;
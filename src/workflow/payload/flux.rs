@@ -1,8 +1,12 @@
 use super::{ComfyUIPrompt, ControlNetPayload, Image, LoRAPayload, Model};
-use crate::workflow::{fetch::FetchHelper, payload::CurrentNodeId};
+use crate::{
+    auth::limit::JobCost,
+    workflow::{fetch::FetchHelper, payload::CurrentNodeId},
+};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use std::collections::HashMap;
+use url::Url;
 use utoipa::ToSchema;
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
@@ -38,6 +42,29 @@ pub struct FluxWorkflowPayload {
 }
 
 impl FluxWorkflowPayload {
+    pub fn cost(&self) -> JobCost {
+        JobCost {
+            images: self.batch_size as u64,
+            steps: self.steps as u64 * self.batch_size as u64,
+        }
+    }
+
+    pub fn urls(&self) -> Vec<&Url> {
+        let models = [&self.unet, &self.vae, &self.t5xxl, &self.clip]
+            .into_iter()
+            .chain(self.loras.iter().map(|v| &v.model))
+            .filter_map(|v| v.url());
+        let images = [&self.input_image, &self.input_mask]
+            .into_iter()
+            .flatten()
+            .filter_map(|v| v.url());
+
+        models
+            .chain(images)
+            .chain(self.controlnets.iter().flat_map(|v| v.urls()))
+            .collect()
+    }
+
     #[tracing::instrument(skip_all)]
     pub async fn into_comfy_prompt(
         &self,
@@ -411,12 +438,13 @@ impl FluxWorkflowPayload {
 
         tracing::debug!("comfyui prompt: {:?}", json!(prompt).to_string());
 
-        fetch_helper.wait_all().await?;
+        let files = fetch_helper.wait_all().await?;
 
         Ok(ComfyUIPrompt {
             prompt: json!(prompt),
             k_sampler_node_id: k_sampler_node_id.clone(),
             output_node_id: output_node_id.clone(),
+            files,
         })
     }
 }
@@ -2,9 +2,18 @@ pub mod flux;
 pub mod sd15;
 pub mod sdxl;
 
-use super::fetch::{Fetch, FetchHelper};
+use super::{
+    fetch::{Fetch, FetchHelper},
+    task::WorkflowResult,
+};
 use crate::{
-    download::{create_download_task, task::DownloadStatus, CreateDownloadTaskResult},
+    auth::limit::JobCost,
+    download::{
+        create_download_task,
+        lease::Lease,
+        task::{DownloadStatus, Expected},
+        CreateDownloadTaskResult,
+    },
     state::AppState,
 };
 use flux::FluxWorkflowPayload;
@@ -12,17 +21,58 @@ use sd15::SD15WorkflowPayload;
 use sdxl::SDXLWorkflowPayload;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::{collections::HashMap, sync::Arc};
-use tokio::sync::watch;
+use std::sync::Arc;
+use tokio::sync::{watch, RwLock};
 use url::Url;
 use utoipa::ToSchema;
 
 #[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
-#[serde(rename_all = "snake_case", tag = "type", content = "name")]
+#[serde(rename_all = "snake_case", tag = "type")]
 pub enum Model {
-    BuildIn(String),
-    #[schema(value_type = String, default = String::default)]
-    Custom(Url),
+    BuildIn {
+        name: String,
+    },
+    Custom {
+        /// URL of the file.
+        #[schema(value_type = String)]
+        name: Url,
+        /// Expected SHA-256 in hex, the download fails if the file does not match.
+        #[serde(
+            default,
+            deserialize_with = "deserialize_sha256",
+            skip_serializing_if = "Option::is_none"
+        )]
+        sha256: Option<String>,
+        /// Expected size in bytes.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        size: Option<u64>,
+        /// Hash the cached file again before using it, instead of trusting the recorded hash.
+        #[serde(default)]
+        verify: bool,
+    },
+}
+
+impl Model {
+    /// URL of a custom model, `None` for build-in ones.
+    pub fn url(&self) -> Option<&Url> {
+        match self {
+            Model::BuildIn { .. } => None,
+            Model::Custom { name, .. } => Some(name),
+        }
+    }
+}
+
+pub(crate) fn deserialize_sha256<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let value = Option::<String>::deserialize(deserializer)?;
+    match value {
+        Some(v) if v.len() != 64 || !v.chars().all(|c| c.is_ascii_hexdigit()) => Err(
+            serde::de::Error::custom("sha256 must be 64 hexadecimal characters"),
+        ),
+        v => Ok(v.map(|v| v.to_ascii_lowercase())),
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
@@ -34,6 +84,15 @@ pub enum Image {
     Url(Url),
 }
 
+impl Image {
+    pub fn url(&self) -> Option<&Url> {
+        match self {
+            Image::Base64(_) => None,
+            Image::Url(url) => Some(url),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct ControlNetPayload {
     model: Model,
@@ -52,11 +111,25 @@ pub struct LoRAPayload {
     weight: f32,
 }
 
+impl ControlNetPayload {
+    pub fn urls(&self) -> impl Iterator<Item = &Url> {
+        self.model.url().into_iter().chain(self.image.url())
+    }
+}
+
+impl LoRAPayload {
+    pub fn new(model: Model, weight: f32) -> Self {
+        Self { model, weight }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct ComfyUIPrompt {
     pub prompt: Value,
     pub k_sampler_node_id: String,
     pub output_node_id: String,
+    /// Files of the workflow, as paths under the root dir, e.g. `models/loras/<file id>`.
+    pub files: Vec<String>,
 }
 
 pub struct CurrentNodeId {
@@ -76,6 +149,7 @@ impl CurrentNodeId {
 
 #[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 #[serde(tag = "type", content = "params")]
+#[allow(clippy::upper_case_acronyms, clippy::large_enum_variant)]
 pub enum WorkflowPayload {
     SD15(SD15WorkflowPayload),
     SDXL(SDXLWorkflowPayload),
@@ -83,8 +157,34 @@ pub enum WorkflowPayload {
 }
 
 impl WorkflowPayload {
-    pub fn cache_map(&self) -> HashMap<String, String> {
-        HashMap::new()
+    /// URLs of the files of the workflow, to prefer the nodes which already have them.
+    pub fn urls(&self) -> Vec<&Url> {
+        match self {
+            WorkflowPayload::SD15(payload) => payload.urls(),
+            WorkflowPayload::SDXL(_) => vec![],
+            WorkflowPayload::Flux(payload) => payload.urls(),
+        }
+    }
+
+    /// Name of the workflow type, same as the `type` tag of the payload.
+    pub fn workflow_type(&self) -> &'static str {
+        match self {
+            WorkflowPayload::SD15(_) => "SD15",
+            WorkflowPayload::SDXL(_) => "SDXL",
+            WorkflowPayload::Flux(_) => "Flux",
+        }
+    }
+
+    /// The images and sampling steps the workflow will produce, used for quotas.
+    pub fn cost(&self) -> JobCost {
+        match self {
+            WorkflowPayload::SD15(payload) => payload.cost(),
+            WorkflowPayload::SDXL(_) => JobCost {
+                images: 1,
+                steps: 0,
+            },
+            WorkflowPayload::Flux(payload) => payload.cost(),
+        }
     }
 }
 
@@ -93,16 +193,33 @@ impl Fetch for &Model {
         &self,
         app_state: Arc<AppState>,
         target_folder: &str,
-    ) -> (String, Option<watch::Receiver<DownloadStatus>>) {
+        lease: &Lease,
+    ) -> anyhow::Result<(String, Option<watch::Receiver<DownloadStatus>>)> {
         match self {
-            Model::BuildIn(name) => (name.to_string(), None),
-            Model::Custom(url) => {
-                let (file_name, result) =
-                    create_download_task(&url, target_folder, app_state.download_state()).await;
+            Model::BuildIn { name } => Ok((name.to_string(), None)),
+            Model::Custom {
+                name: url,
+                sha256,
+                size,
+                verify,
+            } => {
+                let expected = Expected {
+                    sha256: sha256.clone(),
+                    size: *size,
+                };
+                let (file_name, result) = create_download_task(
+                    url,
+                    target_folder,
+                    &expected,
+                    *verify,
+                    lease,
+                    app_state.download_state(),
+                )
+                .await?;
 
                 match result {
-                    CreateDownloadTaskResult::Existed => (file_name, None),
-                    CreateDownloadTaskResult::Created(rx) => (file_name, Some(rx)),
+                    CreateDownloadTaskResult::Existed => Ok((file_name, None)),
+                    CreateDownloadTaskResult::Created(rx) => Ok((file_name, Some(rx))),
                 }
             }
         }
@@ -114,15 +231,23 @@ impl Fetch for &Image {
         &self,
         app_state: Arc<AppState>,
         target_folder: &str,
-    ) -> (String, Option<watch::Receiver<DownloadStatus>>) {
+        lease: &Lease,
+    ) -> anyhow::Result<(String, Option<watch::Receiver<DownloadStatus>>)> {
         match self {
             Image::Url(url) => {
-                let (file_name, result) =
-                    create_download_task(&url, target_folder, app_state.download_state()).await;
+                let (file_name, result) = create_download_task(
+                    url,
+                    target_folder,
+                    &Expected::default(),
+                    false,
+                    lease,
+                    app_state.download_state(),
+                )
+                .await?;
 
                 match result {
-                    CreateDownloadTaskResult::Existed => (file_name, None),
-                    CreateDownloadTaskResult::Created(rx) => (file_name, Some(rx)),
+                    CreateDownloadTaskResult::Existed => Ok((file_name, None)),
+                    CreateDownloadTaskResult::Created(rx) => Ok((file_name, Some(rx))),
                 }
             }
             _ => {
@@ -132,11 +257,15 @@ impl Fetch for &Image {
     }
 }
 
+/// The prompt of `payload`, with its files downloaded and pinned by `lease`.
+#[tracing::instrument(name = "generate_prompt", skip_all, fields(workflow_type = payload.workflow_type()))]
 pub async fn generate_comfy_prompt(
     payload: &WorkflowPayload,
     app_state: Arc<AppState>,
+    result: Arc<RwLock<WorkflowResult>>,
+    lease: Lease,
 ) -> anyhow::Result<ComfyUIPrompt> {
-    let fetch_helper = FetchHelper::new(app_state.clone());
+    let fetch_helper = FetchHelper::new(app_state.clone(), result, lease);
 
     match payload {
         WorkflowPayload::SD15(payload) => payload.into_comfy_prompt(fetch_helper).await,
@@ -1,9 +1,10 @@
 use super::{ComfyUIPrompt, ControlNetPayload, CurrentNodeId, Image, LoRAPayload, Model};
-use crate::workflow::fetch::FetchHelper;
+use crate::{auth::limit::JobCost, workflow::fetch::FetchHelper};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use utoipa::ToSchema;
 use std::collections::HashMap;
+use url::Url;
 
 #[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 pub struct SD15WorkflowPayload {
@@ -27,6 +28,53 @@ pub struct SD15WorkflowPayload {
 }
 
 impl SD15WorkflowPayload {
+    /// A single step at 64x64 with `checkpoint` and `loras`, to have a node load them.
+    pub fn warm_up(checkpoint: Model, loras: Vec<LoRAPayload>) -> Self {
+        Self {
+            checkpoint,
+            vae: None,
+            loras,
+            controlnets: vec![],
+            prompt: String::new(),
+            negative_prompt: String::new(),
+            input_image: None,
+            input_mask: None,
+            denoise: None,
+            width: 64,
+            height: 64,
+            batch_size: 1,
+            sampler: "euler".into(),
+            scheduler: "normal".into(),
+            steps: 1,
+            cfg_scale: 1.0,
+            seed: None,
+        }
+    }
+
+    pub fn cost(&self) -> JobCost {
+        JobCost {
+            images: self.batch_size as u64,
+            steps: self.steps as u64 * self.batch_size as u64,
+        }
+    }
+
+    pub fn urls(&self) -> Vec<&Url> {
+        let models = [Some(&self.checkpoint), self.vae.as_ref()]
+            .into_iter()
+            .flatten()
+            .chain(self.loras.iter().map(|v| &v.model))
+            .filter_map(|v| v.url());
+        let images = [&self.input_image, &self.input_mask]
+            .into_iter()
+            .flatten()
+            .filter_map(|v| v.url());
+
+        models
+            .chain(images)
+            .chain(self.controlnets.iter().flat_map(|v| v.urls()))
+            .collect()
+    }
+
     #[tracing::instrument(skip_all)]
     pub async fn into_comfy_prompt(
         &self,
@@ -373,12 +421,13 @@ impl SD15WorkflowPayload {
 
         tracing::debug!("comfyui prompt: {:?}", json!(prompt).to_string());
 
-        fetch_helper.wait_all().await?;
+        let files = fetch_helper.wait_all().await?;
 
         Ok(ComfyUIPrompt {
             prompt: json!(prompt),
             k_sampler_node_id: k_sampler_node_id.clone(),
             output_node_id: output_node_id.clone(),
+            files,
         })
     }
 }
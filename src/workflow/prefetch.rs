@@ -0,0 +1,428 @@
+//! Pre-fetching models before the workflows using them, and warming nodes up with them.
+
+use super::{
+    payload::{
+        deserialize_sha256, generate_comfy_prompt, sd15::SD15WorkflowPayload, ComfyUIPrompt,
+        LoRAPayload, Model, WorkflowPayload,
+    },
+    record::run_task,
+    task::{executor::TaskExecutor, WorkflowResult},
+};
+use crate::{
+    cluster::{distribute::distribute, Status},
+    download::{
+        create_download_task, lease::Lease, progress::DownloadInfo, state::DownloadState,
+        task::Expected,
+    },
+    state::AppState,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{sync::RwLock, task::JoinSet};
+use url::Url;
+use utoipa::ToSchema;
+
+/// How often a node is checked while waiting for it to be idle.
+const WARM_UP_POLL: Duration = Duration::from_millis(200);
+
+/// Kind of a model, deciding the folder under the ComfyUI root it is linked into.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ModelKind {
+    Checkpoint,
+    Lora,
+    Vae,
+    Controlnet,
+    Unet,
+    Clip,
+}
+
+impl ModelKind {
+    pub fn folder(&self) -> &'static str {
+        match self {
+            ModelKind::Checkpoint => "models/checkpoints",
+            ModelKind::Lora => "models/loras",
+            ModelKind::Vae => "models/vae",
+            ModelKind::Controlnet => "models/controlnet",
+            ModelKind::Unet => "models/unet",
+            ModelKind::Clip => "models/clip",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, ToSchema)]
+pub struct PrefetchModel {
+    /// URL of the file.
+    #[schema(value_type = String)]
+    pub url: Url,
+    pub kind: ModelKind,
+    /// Expected SHA-256 in hex, the download fails if the file does not match.
+    #[serde(default, deserialize_with = "deserialize_sha256")]
+    pub sha256: Option<String>,
+    /// Expected size in bytes.
+    #[serde(default)]
+    pub size: Option<u64>,
+}
+
+#[derive(Clone, Debug, Deserialize, ToSchema)]
+pub struct PrefetchRequest {
+    pub models: Vec<PrefetchModel>,
+    /// Once downloaded, have every node generate a single step with each checkpoint and
+    /// the LoRAs, so that the next workflows do not wait for them to load.
+    #[serde(default)]
+    pub warm_up: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum WarmUpStatus {
+    /// Waiting for the downloads, or for the node to be idle.
+    Pending,
+    Running,
+    Done,
+    Error,
+}
+
+/// Warm-up of a node.
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct WarmUpInfo {
+    #[schema(value_type = String)]
+    pub node: Url,
+    pub status: WarmUpStatus,
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct PrefetchModelInfo {
+    /// URL of the file as requested.
+    #[schema(value_type = String)]
+    pub url: Url,
+    pub kind: ModelKind,
+    /// The download with its progress, `None` if deleted since.
+    pub download: Option<DownloadInfo>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct PrefetchInfo {
+    pub id: String,
+    pub models: Vec<PrefetchModelInfo>,
+    /// Warm-up of each node, empty unless requested.
+    pub warm_up: Vec<WarmUpInfo>,
+}
+
+/// Models fetched together, with their warm-up on nodes.
+#[derive(Clone, Debug)]
+pub struct Prefetch {
+    id: String,
+    /// Models as requested, with the file id of their download.
+    models: Vec<(PrefetchModel, String)>,
+    warm_up: Arc<RwLock<Vec<WarmUpInfo>>>,
+}
+
+impl Prefetch {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub async fn info(&self, download_state: &DownloadState) -> PrefetchInfo {
+        let mut models = vec![];
+        for (model, file_id) in &self.models {
+            let download = match download_state.get_by_id(file_id) {
+                Some(task) => Some(download_state.info(task).await),
+                None => None,
+            };
+            models.push(PrefetchModelInfo {
+                url: model.url.clone(),
+                kind: model.kind,
+                download,
+            });
+        }
+
+        PrefetchInfo {
+            id: self.id.clone(),
+            models,
+            warm_up: self.warm_up.read().await.clone(),
+        }
+    }
+
+    async fn set_warm_up(&self, node: &Url, status: WarmUpStatus, error: Option<String>) {
+        let mut warm_up = self.warm_up.write().await;
+        if let Some(info) = warm_up.iter_mut().find(|v| v.node == *node) {
+            info.status = status;
+            info.error = error;
+        }
+    }
+}
+
+/// The latest pre-fetches, the oldest are dropped beyond `capacity`.
+#[derive(Clone, Debug)]
+pub struct PrefetchRecord {
+    inner: HashMap<String, Prefetch>,
+    order: VecDeque<String>,
+    capacity: usize,
+}
+
+impl PrefetchRecord {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: HashMap::new(),
+            order: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub fn add(&mut self, prefetch: Prefetch) {
+        while self.inner.len() >= self.capacity {
+            match self.order.pop_front() {
+                Some(oldest_key) => self.inner.remove(&oldest_key),
+                None => break,
+            };
+        }
+
+        self.order.push_back(prefetch.id.clone());
+        self.inner.insert(prefetch.id.clone(), prefetch);
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Prefetch> {
+        self.inner.get(id)
+    }
+
+    /// Change the capacity at runtime, the oldest records are dropped to fit.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+
+        while self.inner.len() > self.capacity {
+            match self.order.pop_front() {
+                Some(oldest_key) => self.inner.remove(&oldest_key),
+                None => break,
+            };
+        }
+    }
+}
+
+/// Start downloading the models of `request` into their folders, and warming the nodes
+/// up with them once downloaded if requested.
+/// Fails if the cached file of a model is not the expected one but in use.
+pub async fn prefetch(
+    app_state: Arc<AppState>,
+    request: PrefetchRequest,
+) -> anyhow::Result<Prefetch> {
+    let download_state = app_state.download_state();
+    // the files are pinned until the nodes are warmed up
+    let lease = download_state.read().await.lease();
+
+    let mut models = vec![];
+    for model in request.models {
+        let expected = Expected {
+            sha256: model.sha256.clone(),
+            size: model.size,
+        };
+        let (file_id, _) = create_download_task(
+            &model.url,
+            model.kind.folder(),
+            &expected,
+            false,
+            &lease,
+            download_state.clone(),
+        )
+        .await?;
+        models.push((model, file_id));
+    }
+
+    let warm_up = match request.warm_up {
+        true => {
+            let node_state = app_state.node_state();
+            let node_state = node_state.read().await;
+            let mut nodes: Vec<_> = node_state
+                .get_all()
+                .filter(|(_, status)| status.status() != &Status::Offline)
+                .map(|(url, _)| WarmUpInfo {
+                    node: url.clone(),
+                    status: WarmUpStatus::Pending,
+                    error: None,
+                })
+                .collect();
+            nodes.sort_by(|a, b| a.node.cmp(&b.node));
+            nodes
+        }
+        false => vec![],
+    };
+
+    let prefetch = Prefetch {
+        id: uuid::Uuid::new_v4().to_string(),
+        models,
+        warm_up: Arc::new(RwLock::new(warm_up)),
+    };
+
+    if request.warm_up {
+        tokio::spawn(warm_up_nodes(app_state, prefetch.clone(), lease));
+    }
+
+    Ok(prefetch)
+}
+
+/// The warm-up payload of each checkpoint of `prefetch`, with all its LoRAs.
+fn warm_up_payloads(prefetch: &Prefetch) -> Vec<WorkflowPayload> {
+    let custom = |model: &PrefetchModel| Model::Custom {
+        name: model.url.clone(),
+        sha256: model.sha256.clone(),
+        size: model.size,
+        verify: false,
+    };
+    let of_kind = |kind: ModelKind| {
+        prefetch
+            .models
+            .iter()
+            .map(|(model, _)| model)
+            .filter(move |model| model.kind == kind)
+    };
+
+    let loras: Vec<_> = of_kind(ModelKind::Lora)
+        .map(|model| LoRAPayload::new(custom(model), 1.0))
+        .collect();
+    of_kind(ModelKind::Checkpoint)
+        .map(|model| {
+            WorkflowPayload::SD15(SD15WorkflowPayload::warm_up(custom(model), loras.clone()))
+        })
+        .collect()
+}
+
+async fn warm_up_nodes(app_state: Arc<AppState>, prefetch: Prefetch, lease: Lease) {
+    let nodes: Vec<_> = prefetch
+        .warm_up
+        .read()
+        .await
+        .iter()
+        .map(|v| v.node.clone())
+        .collect();
+
+    // the prompts wait for the downloads, they are the same for all nodes
+    let mut prompts = vec![];
+    for payload in warm_up_payloads(&prefetch) {
+        let result = Arc::new(RwLock::new(WorkflowResult::Pending(0)));
+        match generate_comfy_prompt(&payload, app_state.clone(), result, lease.clone()).await {
+            Ok(prompt) => prompts.push(prompt),
+            Err(e) => {
+                tracing::warn!(prefetch_id = prefetch.id(), error = %e, "failed to fetch models");
+                for node in &nodes {
+                    prefetch
+                        .set_warm_up(node, WarmUpStatus::Error, Some(e.to_string()))
+                        .await;
+                }
+                return;
+            }
+        }
+    }
+
+    let mut join_set = JoinSet::new();
+    for node in nodes {
+        join_set.spawn(warm_up_node(
+            app_state.clone(),
+            prefetch.clone(),
+            node,
+            prompts.clone(),
+        ));
+    }
+    join_set.join_all().await;
+
+    drop(lease);
+}
+
+/// Run `prompts` on the node once idle, keeping workflows off it meanwhile.
+/// Give up if the node stays busy for `cluster.warm_up_timeout`.
+async fn warm_up_node(
+    app_state: Arc<AppState>,
+    prefetch: Prefetch,
+    url: Url,
+    prompts: Vec<ComfyUIPrompt>,
+) {
+    let timeout = Duration::from_secs(app_state.config().read().await.cluster.warm_up_timeout);
+    let deadline = Instant::now() + timeout;
+    let node = loop {
+        {
+            let node_state = app_state.node_state();
+            let mut node_state = node_state.write().await;
+            match node_state.get(&url).map(|v| v.status().clone()) {
+                Some(Status::Idle) => {
+                    node_state.set_busy(&url);
+                    break node_state.node(&url).ok_or("node is removed");
+                }
+                Some(Status::Busy) if Instant::now() >= deadline => {
+                    break Err("node stayed busy until the warm-up timeout");
+                }
+                Some(Status::Busy) => {}
+                Some(Status::Offline) | None => break Err("node is offline or removed"),
+            }
+        }
+        tokio::time::sleep(WARM_UP_POLL).await;
+    };
+    let node = match node {
+        Ok(node) => node,
+        Err(e) => {
+            tracing::warn!(prefetch_id = prefetch.id(), node = %url, error = e, "warm-up failed");
+            prefetch
+                .set_warm_up(&url, WarmUpStatus::Error, Some(e.into()))
+                .await;
+            return;
+        }
+    };
+
+    prefetch
+        .set_warm_up(&url, WarmUpStatus::Running, None)
+        .await;
+    let mut error = None;
+    for prompt in prompts {
+        if let Err(e) = distribute(&app_state, &node, &prompt.files).await {
+            error = Some(format!("{:#}", e));
+            break;
+        }
+
+        let result = Arc::new(RwLock::new(WorkflowResult::Pending(0)));
+        let mut executor = TaskExecutor::new(
+            prompt,
+            result.clone(),
+            &uuid::Uuid::new_v4().to_string(),
+            app_state.node_client().clone(),
+        );
+        error = match executor.run(&node).await {
+            Ok(()) => match &*result.read().await {
+                WorkflowResult::Error(e) => Some(e.clone()),
+                _ => None,
+            },
+            Err(e) => Some(e.to_string()),
+        };
+        if error.is_some() {
+            break;
+        }
+    }
+
+    match &error {
+        Some(e) => {
+            tracing::warn!(prefetch_id = prefetch.id(), node = %url, error = e, "warm-up failed")
+        }
+        None => tracing::info!(prefetch_id = prefetch.id(), node = %url, "node warmed up"),
+    }
+    let status = match error {
+        Some(_) => WarmUpStatus::Error,
+        None => WarmUpStatus::Done,
+    };
+    prefetch.set_warm_up(&url, status, error).await;
+
+    {
+        // the node may have gone offline or been removed meanwhile
+        let node_state = app_state.node_state();
+        let mut node_state = node_state.write().await;
+        if node_state
+            .get(&url)
+            .is_some_and(|v| v.status() == &Status::Busy)
+        {
+            node_state.set_idle(&url);
+        }
+    }
+    // workflows may have queued while the node was warming up
+    tokio::spawn(run_task(app_state));
+}
@@ -1,8 +1,10 @@
 use super::{payload::WorkflowPayload, task::WorkflowTask};
-use crate::state::AppState;
+use crate::{state::AppState, telemetry};
+use opentelemetry::KeyValue;
 use std::{
     collections::{HashMap, VecDeque},
     sync::Arc,
+    time::SystemTime,
 };
 use thiserror::Error;
 
@@ -17,7 +19,7 @@ pub struct WorkflowRecord {
 
 #[derive(Error, Debug)]
 pub enum WorkflowRecordError {
-    #[error("pending queue is full")]
+    #[error("pending queue is full, try again later")]
     PendingQueueFull,
 }
 
@@ -32,11 +34,15 @@ impl WorkflowRecord {
         }
     }
 
-    pub fn add(&mut self, payload: WorkflowPayload) -> Result<&WorkflowTask, WorkflowRecordError> {
-        let task = WorkflowTask::new(payload);
+    pub fn add(
+        &mut self,
+        payload: WorkflowPayload,
+        owner: &str,
+    ) -> Result<&WorkflowTask, WorkflowRecordError> {
+        let task = WorkflowTask::new(payload, owner);
         let task_id = task.id().to_string();
 
-        if self.pending.len() == self.pending_capacity {
+        if self.pending.len() >= self.pending_capacity {
             return Err(WorkflowRecordError::PendingQueueFull);
         }
 
@@ -46,10 +52,11 @@ impl WorkflowRecord {
             self.order.retain(|k| k != task_id.as_str());
         }
 
-        if self.inner.len() == self.capacity {
-            if let Some(oldest_key) = self.order.pop_front() {
-                self.inner.remove(&oldest_key);
-            }
+        while self.inner.len() >= self.capacity {
+            match self.order.pop_front() {
+                Some(oldest_key) => self.inner.remove(&oldest_key),
+                None => break,
+            };
         }
 
         self.inner.insert(task_id.clone(), task);
@@ -58,6 +65,21 @@ impl WorkflowRecord {
         Ok(self.get(&task_id).expect("task_id should exist"))
     }
 
+    /// Change the capacities at runtime, the oldest records are dropped to fit `capacity`.
+    /// Pending tasks are kept even if there are more than `pending_capacity`,
+    /// new ones are rejected until the queue drains.
+    pub fn set_capacity(&mut self, capacity: usize, pending_capacity: usize) {
+        self.capacity = capacity;
+        self.pending_capacity = pending_capacity;
+
+        while self.inner.len() > self.capacity {
+            match self.order.pop_front() {
+                Some(oldest_key) => self.inner.remove(&oldest_key),
+                None => break,
+            };
+        }
+    }
+
     pub fn get(&self, id: &str) -> Option<&WorkflowTask> {
         self.inner.get(id)
     }
@@ -69,6 +91,10 @@ impl WorkflowRecord {
         }
     }
 
+    pub fn pending_len(&self) -> usize {
+        self.pending.len()
+    }
+
     pub fn top_pending(&self) -> Option<&WorkflowTask> {
         self.pending.front().and_then(|k| self.get(k))
     }
@@ -84,27 +110,52 @@ pub async fn run_task(app_state: Arc<AppState>) {
 
         if let Some(payload) = task_payload {
             let picked = {
-                let cache_map = payload.cache_map();
+                // the files already downloaded, the nodes may have them
+                let file_ids: Vec<_> = {
+                    let download_state = app_state.download_state();
+                    let download_state = download_state.read().await;
+                    payload
+                        .urls()
+                        .into_iter()
+                        .filter_map(|url| {
+                            let identity = download_state.options().identity(url);
+                            download_state.get_by_url(&identity)
+                        })
+                        .map(|v| v.file_id().to_string())
+                        .collect()
+                };
                 let node_state = app_state.node_state();
                 let mut node_state = node_state.write().await;
-                node_state.pick(&cache_map)
+                node_state.pick(&file_ids)
             };
 
             if let Some(node) = picked {
                 let task = {
                     let workflow_record = app_state.workflow_record();
                     let mut workflow_record = workflow_record.write().await;
-                    workflow_record.pop_pending().map(|v| v.clone())
+                    workflow_record.pop_pending().cloned()
                 }
                 .expect("task should exist");
 
+                let waited = task.created_at().elapsed();
+                app_state
+                    .metrics()
+                    .queue_wait_seconds
+                    .observe(waited.as_secs_f64());
+                telemetry::record_span(
+                    "queue_wait",
+                    task.trace_context(),
+                    SystemTime::now() - waited,
+                    vec![KeyValue::new("task_id", task.id().to_string())],
+                );
+
                 task.run(&node, app_state.clone()).await;
 
                 // after task done, set node to idle
                 {
                     let node_state = app_state.node_state();
                     let mut node_state = node_state.write().await;
-                    node_state.set_idle(&node);
+                    node_state.set_idle(&node.url);
                 }
             } else {
                 break;
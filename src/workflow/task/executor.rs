@@ -1,26 +1,40 @@
 use super::WorkflowResult;
-use crate::workflow::{
-    message::WorkflowMessage, payload::ComfyUIPrompt, task::WorkflowRunningResult,
+use crate::{
+    cluster::{client::NodeClient, Node},
+    workflow::{message::WorkflowMessage, payload::ComfyUIPrompt, task::WorkflowRunningResult},
 };
 use futures_util::StreamExt;
-use reqwest::Client;
+use reqwest::Method;
 use serde_json::{json, Value};
 use std::sync::Arc;
 use thiserror::Error;
 use tokio::sync::RwLock;
-use tokio_tungstenite::{connect_async, tungstenite::Message};
-use url::Url;
+use tokio_tungstenite::tungstenite::Message;
 
 #[derive(Error, Debug)]
 pub enum WorkflowExecutionError {
     #[error("failed to connect to node using websocket")]
     WebSocketConnectionError,
+    #[error("websocket disconnected before workflow finished")]
+    WebSocketDisconnected,
     #[error("ComfyUI error: {0}")]
     ComfyUIError(String),
     #[error("Invalid response: {0}")]
     InvalidResponse(String),
 }
 
+impl WorkflowExecutionError {
+    /// Short name of the error, used as metric label.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            WorkflowExecutionError::WebSocketConnectionError => "connection",
+            WorkflowExecutionError::WebSocketDisconnected => "disconnected",
+            WorkflowExecutionError::ComfyUIError(_) => "comfyui",
+            WorkflowExecutionError::InvalidResponse(_) => "invalid_response",
+        }
+    }
+}
+
 pub struct TaskExecutor {
     prompt: ComfyUIPrompt,
     result: Arc<RwLock<WorkflowResult>>,
@@ -28,10 +42,16 @@ pub struct TaskExecutor {
     prompt_id: String,
     current_node_id: Option<String>,
     results: Vec<Vec<u8>>,
+    client: NodeClient,
 }
 
 impl TaskExecutor {
-    pub fn new(prompt: ComfyUIPrompt, result: Arc<RwLock<WorkflowResult>>, task_id: &str) -> Self {
+    pub fn new(
+        prompt: ComfyUIPrompt,
+        result: Arc<RwLock<WorkflowResult>>,
+        task_id: &str,
+        client: NodeClient,
+    ) -> Self {
         Self {
             prompt,
             result,
@@ -39,18 +59,21 @@ impl TaskExecutor {
             prompt_id: String::new(),
             current_node_id: None,
             results: vec![],
+            client,
         }
     }
 
-    async fn trigger_workflow(&self, node: &Url) -> Result<String, WorkflowExecutionError> {
-        let client = Client::new();
-        let response = client
-            .post(node.join("/prompt").expect(""))
+    async fn trigger_workflow(&self, node: &Node) -> Result<String, WorkflowExecutionError> {
+        let request = self
+            .client
+            .request(Method::POST, node, "/prompt")
             .json(&json!({
                 "prompt": &self.prompt.prompt,
                 "client_id": &self.task_id
-            }))
-            .send()
+            }));
+        let response = self
+            .client
+            .send(request)
             .await
             .map_err(|e| WorkflowExecutionError::ComfyUIError(e.to_string()))?;
 
@@ -130,7 +153,7 @@ impl TaskExecutor {
             }
             WorkflowMessage::ExecutionSuccess(data) => {
                 if data.prompt_id == self.prompt_id {
-                    tracing::info!("execution success: {:?}", data);
+                    tracing::info!(prompt_id = data.prompt_id, "execution success");
                     let mut result = self.result.write().await;
                     // Move out the items and reset the field
                     *result = WorkflowResult::Done(std::mem::take(&mut self.results));
@@ -144,6 +167,18 @@ impl TaskExecutor {
                     let mut result = self.result.write().await;
                     *result = WorkflowResult::Error(error.exception_message.unwrap_or_default());
 
+                    return true;
+                }
+            }
+            WorkflowMessage::ExecutionInterrupted(data) => {
+                if data.prompt_id == self.prompt_id {
+                    tracing::info!("execution interrupted: {:?}", data);
+                    let mut result = self.result.write().await;
+                    *result = WorkflowResult::Error(format!(
+                        "execution interrupted at node {} ({})",
+                        data.node_id, data.node_type
+                    ));
+
                     return true;
                 }
             }
@@ -168,12 +203,12 @@ impl TaskExecutor {
 
     /// Establish websocket connection with ComfyUI,
     /// and update result when new message come in.
-    pub async fn run(&mut self, node: &Url) -> Result<(), WorkflowExecutionError> {
-        let mut connection_url = node.join("/ws").expect("");
-        let _ = connection_url.set_scheme("ws");
-        connection_url.set_query(Some(&format!("clientId={}", &self.task_id)));
-
-        let (mut ws_stream, _) = connect_async(&connection_url.to_string())
+    #[tracing::instrument(name = "execute", skip_all, fields(node = %node.url))]
+    pub async fn run(&mut self, node: &Node) -> Result<(), WorkflowExecutionError> {
+        let query = format!("clientId={}", &self.task_id);
+        let mut ws_stream = self
+            .client
+            .connect_websocket(node, "/ws", Some(&query))
             .await
             .map_err(|_| WorkflowExecutionError::WebSocketConnectionError)?;
 
@@ -182,7 +217,7 @@ impl TaskExecutor {
         let prompt_id = self.trigger_workflow(node).await?;
         self.prompt_id = prompt_id;
 
-        tracing::info!("prompt_id: {}, node: {}", &self.prompt_id, node);
+        tracing::info!(prompt_id = self.prompt_id, "workflow queued on node");
 
         while let Some(msg) = ws_stream.next().await {
             match msg {
@@ -192,11 +227,11 @@ impl TaskExecutor {
                             match serde_json::from_str::<WorkflowMessage>(&text) {
                                 Ok(data) => {
                                     if self.on_message(data).await {
-                                        break;
+                                        return Ok(());
                                     }
                                 }
                                 _ => {
-                                    tracing::warn!("unknown message: {}", text);
+                                    tracing::warn!(text = %text, "unknown message");
                                 }
                             }
                         }
@@ -209,11 +244,13 @@ impl TaskExecutor {
                     }
                 }
                 Err(e) => {
-                    tracing::warn!("websocket error: {}", e);
+                    tracing::warn!(error = %e, "websocket error");
                 }
             }
         }
 
-        Ok(())
+        // the stream ends without success or error message from ComfyUI,
+        // e.g. the node restarted during execution
+        Err(WorkflowExecutionError::WebSocketDisconnected)
     }
 }
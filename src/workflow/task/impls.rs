@@ -1,23 +1,28 @@
 use super::{WorkflowResult, WorkflowTask};
 use crate::{
+    cluster::{distribute::distribute, Node},
     state::AppState,
+    telemetry,
     workflow::{
         payload::{generate_comfy_prompt, WorkflowPayload},
         task::executor::TaskExecutor,
     },
 };
-use std::sync::Arc;
+use std::{sync::Arc, time::Instant};
 use tokio::sync::RwLock;
-use url::Url;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 
 impl WorkflowTask {
-    pub fn new(payload: WorkflowPayload) -> Self {
+    pub fn new(payload: WorkflowPayload, owner: &str) -> Self {
         let result = Arc::new(RwLock::new(WorkflowResult::Pending(0)));
 
         Self {
             id: uuid::Uuid::new_v4().to_string(),
+            owner: owner.to_string(),
             payload,
             result,
+            created_at: Instant::now(),
+            trace_context: telemetry::current_context(),
         }
     }
 
@@ -25,6 +30,10 @@ impl WorkflowTask {
         &self.id
     }
 
+    pub fn owner(&self) -> &str {
+        &self.owner
+    }
+
     pub fn payload(&self) -> &WorkflowPayload {
         &self.payload
     }
@@ -33,24 +42,97 @@ impl WorkflowTask {
         self.result.read().await.clone()
     }
 
-    #[tracing::instrument(skip_all, fields(task_id = self.id))]
-    pub async fn run(&self, node: &Url, app_state: Arc<AppState>) {
-        match generate_comfy_prompt(&self.payload, app_state).await {
+    pub fn created_at(&self) -> Instant {
+        self.created_at
+    }
+
+    pub fn trace_context(&self) -> &opentelemetry::Context {
+        &self.trace_context
+    }
+
+    #[tracing::instrument(name = "workflow_task", skip_all, fields(task_id = self.id, node = %node.url))]
+    pub async fn run(&self, node: &Node, app_state: Arc<AppState>) {
+        // continue the trace of the submission, before any child span is created
+        tracing::Span::current().set_parent(self.trace_context.clone());
+
+        let started_at = Instant::now();
+        // the files of the workflow are not evicted until it finishes
+        let lease = app_state.download_state().read().await.lease();
+
+        let error_kind = match generate_comfy_prompt(
+            &self.payload,
+            app_state.clone(),
+            self.result.clone(),
+            lease.clone(),
+        )
+        .await
+        {
             Ok(prompt) => {
                 tracing::info!("got prompt");
 
-                let mut executor = TaskExecutor::new(prompt, self.result.clone(), self.id());
-                match executor.run(node).await {
+                match distribute(&app_state, node, &prompt.files).await {
+                    Ok(()) => {
+                        let mut executor = TaskExecutor::new(
+                            prompt,
+                            self.result.clone(),
+                            self.id(),
+                            app_state.node_client().clone(),
+                        );
+                        match executor.run(node).await {
+                            // errors reported by ComfyUI are stored in the result by the executor
+                            Ok(()) => match &*self.result.read().await {
+                                WorkflowResult::Error(_) => Some("execution"),
+                                _ => None,
+                            },
+                            Err(e) => {
+                                let mut result = self.result.write().await;
+                                *result = WorkflowResult::Error(e.to_string());
+                                Some(e.kind())
+                            }
+                        }
+                    }
                     Err(e) => {
                         let mut result = self.result.write().await;
-                        *result = WorkflowResult::Error(e.to_string());
+                        *result = WorkflowResult::Error(format!("{:#}", e));
+                        Some("distribute")
                     }
-                    _ => {}
                 }
             }
             Err(e) => {
                 let mut result = self.result.write().await;
                 *result = WorkflowResult::Error(e.to_string());
+                Some("prompt")
+            }
+        };
+
+        match error_kind {
+            Some(kind) => tracing::warn!(kind, "workflow failed"),
+            None => tracing::info!("workflow finished"),
+        }
+        drop(lease);
+
+        let metrics = app_state.metrics();
+        let workflow_type = self.payload.workflow_type();
+        metrics
+            .workflow_duration_seconds
+            .with_label_values(&[workflow_type, node.url.as_str()])
+            .observe(started_at.elapsed().as_secs_f64());
+        match error_kind {
+            Some(kind) => {
+                metrics
+                    .workflows_total
+                    .with_label_values(&[workflow_type, "error"])
+                    .inc();
+                metrics
+                    .workflow_errors_total
+                    .with_label_values(&[workflow_type, kind])
+                    .inc();
+            }
+            None => {
+                metrics
+                    .workflows_total
+                    .with_label_values(&[workflow_type, "success"])
+                    .inc();
             }
         }
     }
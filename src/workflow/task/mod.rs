@@ -2,8 +2,9 @@ pub mod executor;
 pub mod impls;
 
 use super::payload::WorkflowPayload;
+use crate::download::progress::DownloadInfo;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::{sync::Arc, time::Instant};
 use tokio::sync::RwLock;
 use utoipa::ToSchema;
 
@@ -18,6 +19,8 @@ pub struct WorkflowRunningResult {
 pub enum WorkflowResult {
     #[schema(value_type = u32, default = u32::default)]
     Pending(usize),
+    /// Waiting for the files of the workflow to download.
+    Downloading(Vec<DownloadInfo>),
     Running(WorkflowRunningResult),
     Done(Vec<Vec<u8>>),
     Error(String),
@@ -26,6 +29,11 @@ pub enum WorkflowResult {
 #[derive(Clone, Debug)]
 pub struct WorkflowTask {
     id: String,
+    /// Id of the identity that submitted the task.
+    owner: String,
     payload: WorkflowPayload,
     result: Arc<RwLock<WorkflowResult>>,
+    created_at: Instant,
+    /// Trace context of the submission, spans of the task continue this trace.
+    trace_context: opentelemetry::Context,
 }
@@ -0,0 +1,71 @@
+mod common;
+
+use common::agent::TestAgent;
+use reqwest::{header::CONTENT_LENGTH, Client, StatusCode};
+
+#[tokio::test]
+async fn stores_files_under_the_root() {
+    let agent = TestAgent::start().await;
+    let client = Client::new();
+    let url = agent.url().join("files/models/loras/abc").unwrap();
+
+    let resp = client.head(url.clone()).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    let resp = client.put(url.clone()).body("first").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let resp = client.put(url.clone()).body("second").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    assert_eq!(agent.read("models/loras/abc"), b"second");
+    // no temporary file is left behind
+    assert_eq!(agent.files(), vec!["models/loras/abc"]);
+
+    let resp = client.head(url).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()[CONTENT_LENGTH], "6");
+}
+
+#[tokio::test]
+async fn rejects_paths_outside_the_root() {
+    let agent = TestAgent::start().await;
+    let client = Client::new();
+
+    for path in ["files/models%2F..%2F..%2Fescaped", "files/%2Fescaped"] {
+        let url = agent.url().join(path).unwrap();
+        let resp = client.put(url.clone()).body("x").send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", path);
+        let resp = client.head(url).send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", path);
+    }
+    assert!(agent.files().is_empty());
+    assert!(!agent.path("../escaped").exists());
+}
+
+#[tokio::test]
+async fn requires_the_token() {
+    let agent = TestAgent::start_with_token(Some("secret")).await;
+    let client = Client::new();
+    let url = agent.url().join("files/models/loras/abc").unwrap();
+
+    let resp = client.put(url.clone()).body("x").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let resp = client
+        .put(url.clone())
+        .bearer_auth("wrong")
+        .body("x")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    assert!(agent.files().is_empty());
+
+    let resp = client
+        .put(url)
+        .bearer_auth("secret")
+        .body("x")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    assert_eq!(agent.read("models/loras/abc"), b"x");
+}
@@ -0,0 +1,197 @@
+mod common;
+
+use common::{
+    file_server::FileServer, mock_comfy::MockComfyUI, sd15_payload, wait_until, TestRouter,
+};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+
+async fn audit(router: &TestRouter, query: &str) -> Value {
+    let resp = router
+        .get(&format!("/audit{}", query))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    resp.json().await.unwrap()
+}
+
+fn actions(body: &Value) -> Vec<&str> {
+    body["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| v["action"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn records_actions_with_identity_and_source() {
+    let router = TestRouter::start().await;
+    let node = MockComfyUI::start().await;
+    router.add_node(node.url()).await;
+    let (key, key_id) = router.create_key(&["workflow:run"]).await;
+
+    let resp = router
+        .post_as(&key, "/workflow")
+        .json(&sd15_payload())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let task_id = resp.json::<Value>().await.unwrap()["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    let body = audit(&router, "").await;
+    assert_eq!(body["total"], 3);
+    // newest first
+    assert_eq!(
+        actions(&body),
+        ["workflow.submit", "auth.key.create", "cluster.node.add"]
+    );
+
+    let submit = &body["entries"][0];
+    assert_eq!(submit["actor"], key_id);
+    assert_eq!(submit["actor_name"], "test");
+    assert_eq!(submit["source_ip"], "127.0.0.1");
+    assert_eq!(submit["details"]["task_id"], task_id);
+    assert_eq!(submit["details"]["type"], "SD15");
+    assert!(submit["timestamp"].as_u64().unwrap() > 0);
+
+    let add_node = &body["entries"][2];
+    assert_eq!(add_node["actor"], "admin");
+    assert_eq!(add_node["details"]["url"], node.url().as_str());
+
+    // the log is kept on disk as JSON lines
+    let content = std::fs::read_to_string(router.path("audit.log")).unwrap();
+    assert_eq!(content.lines().count(), 3);
+}
+
+#[tokio::test]
+async fn filters_and_paginates_entries() {
+    let router = TestRouter::start().await;
+    for port in 0..5 {
+        router
+            .add_node(&format!("http://127.0.0.1:{}", 9000 + port).parse().unwrap())
+            .await;
+    }
+    router
+        .post("/cluster/nodes/delete")
+        .json(&json!({ "url": "http://127.0.0.1:9000" }))
+        .send()
+        .await
+        .unwrap();
+    router.create_key(&["workflow:run"]).await;
+
+    let body = audit(&router, "?action=cluster.&per_page=2&page=2").await;
+    assert_eq!(body["total"], 6);
+    assert_eq!(body["page"], 2);
+    assert_eq!(body["entries"].as_array().unwrap().len(), 2);
+    assert_eq!(actions(&body), ["cluster.node.add", "cluster.node.add"]);
+
+    // pages past the end are empty, however large
+    let query = format!("?action=cluster.&per_page=500&page={}", usize::MAX);
+    let body = audit(&router, &query).await;
+    assert_eq!(body["total"], 6);
+    assert!(body["entries"].as_array().unwrap().is_empty());
+
+    let body = audit(&router, "?action=cluster.node.remove").await;
+    assert_eq!(body["total"], 1);
+    assert_eq!(
+        body["entries"][0]["details"]["url"],
+        "http://127.0.0.1:9000/"
+    );
+
+    let body = audit(&router, "?actor=admin&action=auth.").await;
+    assert_eq!(actions(&body), ["auth.key.create"]);
+
+    let body = audit(&router, "?actor=someone-else").await;
+    assert_eq!(body["total"], 0);
+
+    let body = audit(&router, "?since=4102444800").await;
+    assert_eq!(body["total"], 0);
+}
+
+#[tokio::test]
+async fn requires_cluster_admin() {
+    let router = TestRouter::start().await;
+    let (key, _) = router.create_key(&["workflow:run", "workflow:read"]).await;
+
+    let resp = router.get_as(&key, "/audit").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    let resp = router
+        .anonymous()
+        .get(router.url("/audit"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn rotates_log_files() {
+    let router = TestRouter::start_with(|config| {
+        config.audit.max_bytes = 400;
+        config.audit.max_files = 2;
+    })
+    .await;
+
+    for port in 0..20 {
+        router
+            .add_node(&format!("http://127.0.0.1:{}", 9000 + port).parse().unwrap())
+            .await;
+    }
+
+    assert!(router.path("audit.log.1").exists());
+    assert!(router.path("audit.log.2").exists());
+    assert!(!router.path("audit.log.3").exists());
+    for name in ["audit.log", "audit.log.1", "audit.log.2"] {
+        assert!(std::fs::metadata(router.path(name)).unwrap().len() <= 400);
+    }
+
+    // entries are read across rotated files, the oldest ones are dropped
+    let body = audit(&router, "?per_page=500").await;
+    let total = body["total"].as_u64().unwrap();
+    assert!(total > 2 && total < 20, "{}", total);
+    assert_eq!(
+        body["entries"][0]["details"]["url"],
+        "http://127.0.0.1:9019/"
+    );
+}
+
+#[tokio::test]
+async fn records_cache_evictions() {
+    let router = TestRouter::start_with(|config| {
+        config.download.max_cache_bytes = 12;
+    })
+    .await;
+    let files = FileServer::start().await;
+    let node = MockComfyUI::start().await;
+    router.add_node(node.url()).await;
+
+    let first = files.add("/first.safetensors", b"checkpoint");
+    let second = files.add("/second.safetensors", b"CHECKPOINT");
+    for url in [&first, &second] {
+        let result = router.run_with_checkpoint(url).await;
+        assert_eq!(result["status"], "done", "{}", result);
+    }
+
+    let body = wait_until(|| async {
+        let body = audit(&router, "?action=download.cache.evict").await;
+        (body["total"] == 1).then_some(body)
+    })
+    .await;
+
+    let entry = &body["entries"][0];
+    assert_eq!(entry["actor"], "system");
+    assert_eq!(entry["source_ip"], Value::Null);
+    assert_eq!(entry["details"]["url"], first.as_str());
+    assert_eq!(entry["details"]["size"], 10);
+    assert_eq!(
+        entry["details"]["target_dirs"],
+        json!(["models/checkpoints"])
+    );
+}
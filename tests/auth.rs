@@ -0,0 +1,173 @@
+mod common;
+
+use common::{mock_comfy::MockComfyUI, sd15_payload, TestRouter};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+
+#[tokio::test]
+async fn runs_workflow_with_api_key() {
+    let router = TestRouter::start().await;
+    let node = MockComfyUI::start().await;
+    router.add_node(node.url()).await;
+
+    let (key, _) = router.create_key(&["workflow:run", "workflow:read"]).await;
+    assert!(key.starts_with("cr_"));
+
+    let resp = router
+        .post_as(&key, "/workflow")
+        .json(&sd15_payload())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: Value = resp.json().await.unwrap();
+    let id = body["id"].as_str().unwrap();
+
+    let resp = router
+        .get_as(&key, &format!("/workflow/{}", id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn enforces_scopes() {
+    let router = TestRouter::start().await;
+    let (key, _) = router.create_key(&["workflow:read"]).await;
+
+    let resp = router
+        .post_as(&key, "/workflow")
+        .json(&sd15_payload())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    let resp = router.get_as(&key, "/cluster/nodes").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    let resp = router
+        .post_as(&key, "/cluster/nodes/delete")
+        .json(&json!({ "url": "http://127.0.0.1:8188" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    let resp = router.get_as(&key, "/auth/keys").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn cannot_grant_more_scopes_than_owned() {
+    let router = TestRouter::start().await;
+    let (admin_key, _) = router.create_key(&["cluster:admin"]).await;
+
+    let resp = router
+        .post_as(&admin_key, "/auth/keys")
+        .json(&json!({ "name": "escalated", "scopes": ["cluster:admin", "workflow:run"] }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    let resp = router
+        .post_as(&admin_key, "/auth/keys")
+        .json(&json!({ "name": "ops", "scopes": ["cluster:admin"] }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn rejects_unknown_scope() {
+    let router = TestRouter::start().await;
+
+    let resp = router
+        .post("/auth/keys")
+        .json(&json!({ "name": "test", "scopes": ["everything"] }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn revokes_key() {
+    let router = TestRouter::start().await;
+    let (key, id) = router.create_key(&["workflow:read"]).await;
+
+    let resp = router
+        .get_as(&key, "/workflow/unknown")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    let resp = router
+        .post("/auth/keys/revoke")
+        .json(&json!({ "id": id }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: Value = resp.json().await.unwrap();
+    assert!(body["revoked_at"].is_u64());
+
+    let resp = router
+        .get_as(&key, "/workflow/unknown")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+    let resp = router
+        .post("/auth/keys/revoke")
+        .json(&json!({ "id": "unknown" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn lists_keys_without_secrets() {
+    let router = TestRouter::start().await;
+    let (key, id) = router.create_key(&["workflow:run"]).await;
+
+    let body: Value = router
+        .get("/auth/keys")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let keys = body["keys"].as_array().unwrap();
+    assert_eq!(keys.len(), 1);
+    assert_eq!(keys[0]["id"], id.as_str());
+    assert_eq!(keys[0]["scopes"], json!(["workflow:run"]));
+    assert!(!body.to_string().contains(&key));
+
+    // only the hash is stored on disk
+    let stored = std::fs::read_to_string(router.path("api_keys.json")).unwrap();
+    assert!(stored.contains(&id));
+    assert!(!stored.contains(&key));
+    // written through a temporary file renamed over the store
+    assert!(!router.path("api_keys.json.tmp").exists());
+}
+
+#[tokio::test]
+async fn rejects_invalid_bearer_token() {
+    let router = TestRouter::start().await;
+
+    let resp = router
+        .get_as("cr_invalid", "/workflow/unknown")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(resp.headers()["www-authenticate"], "Basic");
+}
@@ -0,0 +1,283 @@
+mod common;
+
+use common::{
+    file_server::FileServer,
+    mock_comfy::{MockBehavior, MockComfyUI},
+    sd15_payload, TestRouter, PASSWORD, USERNAME,
+};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::process::Output;
+use tokio::process::Command;
+
+/// Run the binary with `args`, without config files or env vars of the environment.
+async fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_comfy-router"))
+        .args(args)
+        .env_remove("COMFY_ROUTER_CONFIG")
+        .env_remove("COMFY_ROUTER_URL")
+        .env_remove("COMFY_ROUTER_API_KEY")
+        .output()
+        .await
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+/// Flags to reach the router as admin.
+fn client_args(router: &TestRouter) -> Vec<String> {
+    vec![
+        "--router".into(),
+        router.url("/").to_string(),
+        "--set".into(),
+        format!("auth.username={}", USERNAME),
+        "--set".into(),
+        format!("auth.password={}", PASSWORD),
+    ]
+}
+
+/// Flags to use the download record and folders of the router.
+fn storage_args(router: &TestRouter) -> Vec<String> {
+    vec![
+        "--set".into(),
+        format!(
+            "storage.record_path={}",
+            router.path("record.json").display()
+        ),
+        "--set".into(),
+        format!("download.cache_dir={}", router.path("cache").display()),
+        "--set".into(),
+        format!("download.root_dir={}", router.root_dir().display()),
+    ]
+}
+
+async fn run_with(command: &[&str], extra: &[String]) -> Output {
+    let mut args: Vec<&str> = command.to_vec();
+    args.extend(extra.iter().map(|v| v.as_str()));
+    run(&args).await
+}
+
+#[tokio::test]
+async fn checks_config() {
+    let output = run(&["check-config", "--set", "auth.password=hunter2"]).await;
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("[download]"));
+    assert!(!stdout(&output).contains("hunter2"));
+
+    let output = run(&["check-config", "--set", "cluster.history_limit=0"]).await;
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("history_limit"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[tokio::test]
+async fn manages_nodes() {
+    let router = TestRouter::start().await;
+    let node = MockComfyUI::start().await;
+    let args = client_args(&router);
+    let url = node.url().to_string();
+
+    let output = run_with(
+        &[
+            "nodes",
+            "add",
+            &url,
+            "--header",
+            "X-Proxy-Key: key",
+            "--bearer-token",
+            "token",
+        ],
+        &args,
+    )
+    .await;
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let output = run_with(&["nodes", "list", "--json"], &args).await;
+    assert!(output.status.success(), "{}", stderr(&output));
+    let nodes: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(nodes.as_array().unwrap().len(), 1);
+    assert_eq!(nodes[0]["url"], url);
+    assert_eq!(nodes[0]["options"]["headers"]["X-Proxy-Key"], "********");
+    assert_eq!(nodes[0]["options"]["auth"]["type"], "bearer");
+
+    let output = run_with(&["nodes", "remove", &url], &args).await;
+    assert!(output.status.success(), "{}", stderr(&output));
+    let output = run_with(&["nodes", "list"], &args).await;
+    assert!(!stdout(&output).contains(&url));
+
+    // wrong credentials are reported with the router's message
+    let mut args = args;
+    args.extend(["--set".into(), "auth.password=wrong".into()]);
+    let output = run_with(&["nodes", "list"], &args).await;
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("401"), "{}", stderr(&output));
+}
+
+#[tokio::test]
+async fn submits_workflow_and_saves_images() {
+    let router = TestRouter::start().await;
+    let node = MockComfyUI::start_with(MockBehavior {
+        outputs: vec![b"first".to_vec(), b"second".to_vec()],
+        ..Default::default()
+    })
+    .await;
+    router.add_node(node.url()).await;
+
+    let workflow = router.path("workflow.json");
+    std::fs::write(&workflow, sd15_payload().to_string()).unwrap();
+    let output_dir = router.path("images");
+
+    let output = run_with(
+        &[
+            "submit",
+            workflow.to_str().unwrap(),
+            "--output",
+            output_dir.to_str().unwrap(),
+            "--timeout",
+            "10",
+        ],
+        &client_args(&router),
+    )
+    .await;
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let paths: Vec<String> = stdout(&output).lines().map(String::from).collect();
+    assert_eq!(paths.len(), 2);
+    assert_eq!(std::fs::read(&paths[0]).unwrap(), b"first");
+    assert_eq!(std::fs::read(&paths[1]).unwrap(), b"second");
+}
+
+#[tokio::test]
+async fn lists_verifies_and_purges_cache() {
+    let router = TestRouter::start().await;
+    let files = FileServer::start().await;
+    let node = MockComfyUI::start().await;
+    router.add_node(node.url()).await;
+
+    let checkpoint = files.add("/models/model.safetensors", b"checkpoint");
+    let mut payload = sd15_payload();
+    payload["params"]["checkpoint"] = json!({ "type": "custom", "name": checkpoint });
+    let id = router.submit(&payload).await;
+    assert_eq!(router.wait_for(&id).await["status"], "done");
+
+    let args = storage_args(&router);
+    let output = run_with(&["cache", "ls", "--json"], &args).await;
+    assert!(output.status.success(), "{}", stderr(&output));
+    let entries: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(entries.as_array().unwrap().len(), 1);
+    assert_eq!(entries[0]["status"], "completed");
+    assert_eq!(entries[0]["url"], checkpoint.as_str());
+    assert_eq!(entries[0]["size"], b"checkpoint".len());
+    assert_eq!(
+        entries[0]["sha256"],
+        hex::encode(Sha256::digest(b"checkpoint"))
+    );
+    let file_id = entries[0]["file_id"].as_str().unwrap().to_string();
+    let blob = router
+        .path("cache")
+        .join(entries[0]["sha256"].as_str().unwrap());
+
+    let output = run_with(&["cache", "verify"], &args).await;
+    assert!(output.status.success(), "{}", stdout(&output));
+
+    // a broken link and an orphan file are reported, then repaired
+    let link = router.root_dir().join("models/checkpoints").join(&file_id);
+    std::fs::remove_file(&link).unwrap();
+    std::fs::write(router.path("cache").join("leftover.download"), b"").unwrap();
+    let output = run_with(&["cache", "verify"], &args).await;
+    assert!(!output.status.success());
+    assert!(
+        stdout(&output).contains("broken link"),
+        "{}",
+        stdout(&output)
+    );
+    assert!(
+        stdout(&output).contains("orphan file"),
+        "{}",
+        stdout(&output)
+    );
+
+    let output = run_with(&["cache", "verify", "--fix"], &args).await;
+    assert!(output.status.success(), "{}", stdout(&output));
+    assert_eq!(std::fs::read(&link).unwrap(), b"checkpoint");
+    assert!(!router.path("cache").join("leftover.download").exists());
+
+    // a corrupted file of the same size is only found by hashing
+    std::fs::write(&blob, b"CHECKPOINT").unwrap();
+    let output = run_with(&["cache", "verify"], &args).await;
+    assert!(output.status.success(), "{}", stdout(&output));
+    let output = run_with(&["cache", "verify", "--hashes"], &args).await;
+    assert!(!output.status.success());
+    assert!(
+        stdout(&output).contains("corrupted file"),
+        "{}",
+        stdout(&output)
+    );
+
+    let output = run_with(&["cache", "purge", "unknown"], &args).await;
+    assert!(!output.status.success());
+
+    let output = run_with(&["cache", "purge", &file_id], &args).await;
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(!link.exists());
+    assert!(!blob.exists());
+    let output = run_with(&["cache", "ls", "--json"], &args).await;
+    assert_eq!(
+        serde_json::from_slice::<Value>(&output.stdout).unwrap(),
+        json!([])
+    );
+}
+
+#[tokio::test]
+async fn reports_unreadable_record_on_startup() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = |name: &str| format!("{}", dir.path().join(name).display());
+    // a directory in place of the record
+    std::fs::create_dir(dir.path().join("record.json")).unwrap();
+
+    let output = run(&[
+        "serve",
+        "--set",
+        "port=0",
+        "--set",
+        &format!("storage.record_path={}", path("record.json")),
+        "--set",
+        &format!("storage.api_keys_path={}", path("api_keys.json")),
+        "--set",
+        &format!("audit.log_path={}", path("audit.log")),
+        "--set",
+        &format!("download.cache_dir={}", path("cache")),
+        "--set",
+        &format!("download.root_dir={}", path("root")),
+    ])
+    .await;
+    assert!(!output.status.success());
+    let logs = format!("{}{}", stdout(&output), stderr(&output));
+    assert!(logs.contains("failed to read download record"), "{}", logs);
+}
+
+#[tokio::test]
+async fn reports_invalid_log_dir_on_startup() {
+    let dir = tempfile::tempdir().unwrap();
+    // a file in place of the log folder
+    let log_dir = dir.path().join("logs");
+    std::fs::write(&log_dir, b"").unwrap();
+
+    let output = run(&["serve", "--set", &format!("log.dir={}", log_dir.display())]).await;
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("failed to initialize tracing"),
+        "{}",
+        stderr(&output)
+    );
+    assert!(!stderr(&output).contains("panicked"), "{}", stderr(&output));
+}
@@ -0,0 +1,158 @@
+mod common;
+
+use common::{
+    mock_comfy::{MockComfyUI, MockServer},
+    sd15_payload, TestRouter,
+};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+
+#[tokio::test]
+async fn requires_basic_auth() {
+    let router = TestRouter::start().await;
+
+    let resp = router
+        .anonymous()
+        .get(router.url("/cluster/nodes"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+    let resp = router
+        .anonymous()
+        .get(router.url("/cluster/nodes"))
+        .basic_auth(common::USERNAME, Some("wrong"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+    let resp = router
+        .anonymous()
+        .get(router.url("/health_check"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn adds_lists_and_removes_nodes() {
+    let router = TestRouter::start().await;
+    let first = MockComfyUI::start().await;
+    let second = MockComfyUI::start().await;
+
+    router.add_node(first.url()).await;
+    router.add_node(second.url()).await;
+    // adding the same node twice is a no-op
+    router.add_node(first.url()).await;
+
+    let nodes: Value = router
+        .get("/cluster/nodes")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let nodes = nodes["nodes"].as_array().unwrap();
+    assert_eq!(nodes.len(), 2);
+    assert!(nodes.iter().all(|node| node["status"]["status"] == "idle"));
+
+    let resp = router
+        .post("/cluster/nodes/delete")
+        .json(&json!({ "url": first.url() }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let nodes: Value = router
+        .get("/cluster/nodes")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let nodes = nodes["nodes"].as_array().unwrap();
+    assert_eq!(nodes.len(), 1);
+    assert_eq!(nodes[0]["url"], second.url().as_str());
+}
+
+#[tokio::test]
+async fn rejects_invalid_node_url() {
+    let router = TestRouter::start().await;
+
+    let resp = router
+        .post("/cluster/nodes")
+        .json(&json!({ "url": "not a url" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn reaches_nodes_behind_proxy_with_credentials() {
+    let router = TestRouter::start().await;
+    let node = MockComfyUI::start_with_server(
+        Default::default(),
+        MockServer {
+            prefix: Some("/comfy".into()),
+            required_headers: vec![
+                ("authorization".into(), "Basic dXNlcjpwYXNz".into()),
+                ("x-proxy-key".into(), "key".into()),
+            ],
+            ..Default::default()
+        },
+    )
+    .await;
+    assert_eq!(node.url().path(), "/comfy/");
+
+    // without credentials the proxy rejects the router
+    router.add_node(node.url()).await;
+    let id = router.submit(&sd15_payload()).await;
+    assert_eq!(router.wait_for(&id).await["status"], "error");
+
+    // adding the node again sets its credentials
+    let resp = router
+        .post("/cluster/nodes")
+        .json(&json!({
+            "url": node.url(),
+            "headers": { "X-Proxy-Key": "key" },
+            "auth": { "type": "basic", "username": "user", "password": "pass" }
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let id = router.submit(&sd15_payload()).await;
+    let result = router.wait_for(&id).await;
+    assert_eq!(result["status"], "done", "{}", result);
+    assert_eq!(node.prompts().len(), 1);
+
+    let body: Value = router
+        .get("/cluster/nodes")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["nodes"].as_array().unwrap().len(), 1);
+    let options = &body["nodes"][0]["options"];
+    assert_eq!(options["headers"]["X-Proxy-Key"], "********");
+    assert_eq!(options["auth"]["username"], "user");
+    assert_eq!(options["auth"]["password"], "********");
+
+    let resp = router
+        .post("/cluster/nodes")
+        .json(&json!({ "url": node.url(), "headers": { "bad header": "value" } }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
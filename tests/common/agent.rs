@@ -0,0 +1,73 @@
+//! The node agent served on a random local port, with its root in a temporary directory.
+
+use comfy_router::agent;
+use std::{path::PathBuf, time::SystemTime};
+use tempfile::TempDir;
+use tokio::net::TcpListener;
+use url::Url;
+
+pub struct TestAgent {
+    url: Url,
+    dir: TempDir,
+}
+
+impl TestAgent {
+    pub async fn start() -> Self {
+        Self::start_with_token(None).await
+    }
+
+    pub async fn start_with_token(token: Option<&str>) -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let root_dir = dir.path().to_path_buf();
+        let token = token.map(str::to_string);
+        tokio::spawn(async move {
+            agent::serve(listener, root_dir, token).await.unwrap();
+        });
+
+        Self {
+            url: Url::parse(&format!("http://{}/", addr)).unwrap(),
+            dir,
+        }
+    }
+
+    pub fn url(&self) -> Url {
+        self.url.clone()
+    }
+
+    pub fn path(&self, path: &str) -> PathBuf {
+        self.dir.path().join(path)
+    }
+
+    /// Paths of the files stored under the root, sorted.
+    pub fn files(&self) -> Vec<String> {
+        fn walk(dir: &std::path::Path, root: &std::path::Path, files: &mut Vec<String>) {
+            for entry in std::fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    walk(&path, root, files);
+                } else {
+                    let path = path.strip_prefix(root).unwrap();
+                    files.push(path.to_string_lossy().into_owned());
+                }
+            }
+        }
+
+        let mut files = Vec::new();
+        walk(self.dir.path(), self.dir.path(), &mut files);
+        files.sort();
+        files
+    }
+
+    pub fn read(&self, path: &str) -> Vec<u8> {
+        std::fs::read(self.path(path)).unwrap()
+    }
+
+    pub fn modified(&self, path: &str) -> SystemTime {
+        std::fs::metadata(self.path(path))
+            .unwrap()
+            .modified()
+            .unwrap()
+    }
+}
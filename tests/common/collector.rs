@@ -0,0 +1,83 @@
+//! An OTLP/HTTP collector standing in for the tracing backend,
+//! it only accepts the `http/json` protocol.
+
+use axum::{extract::State, http::StatusCode, routing::post, Json, Router};
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+use url::Url;
+
+/// A span as exported by the router.
+#[derive(Clone, Debug)]
+pub struct ExportedSpan {
+    pub trace_id: String,
+    pub span_id: String,
+    /// Empty for root spans.
+    pub parent_span_id: String,
+    pub name: String,
+    pub attributes: Value,
+}
+
+pub struct Collector {
+    url: Url,
+    spans: Arc<Mutex<Vec<ExportedSpan>>>,
+}
+
+impl Collector {
+    pub async fn start() -> Self {
+        let spans = Arc::new(Mutex::new(vec![]));
+
+        let app = Router::new()
+            .route("/v1/traces", post(export))
+            .with_state(spans.clone());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+
+        Self {
+            url: Url::parse(&format!("http://{}", addr)).unwrap(),
+            spans,
+        }
+    }
+
+    /// Base URL, as configured in `otel_endpoint`.
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    pub fn spans(&self) -> Vec<ExportedSpan> {
+        self.spans.lock().unwrap().clone()
+    }
+}
+
+async fn export(
+    State(spans): State<Arc<Mutex<Vec<ExportedSpan>>>>,
+    Json(body): Json<Value>,
+) -> StatusCode {
+    let mut spans = spans.lock().unwrap();
+    for resource_spans in body["resourceSpans"].as_array().into_iter().flatten() {
+        for scope_spans in resource_spans["scopeSpans"]
+            .as_array()
+            .into_iter()
+            .flatten()
+        {
+            for span in scope_spans["spans"].as_array().into_iter().flatten() {
+                spans.push(ExportedSpan {
+                    trace_id: span["traceId"].as_str().unwrap_or_default().to_string(),
+                    span_id: span["spanId"].as_str().unwrap_or_default().to_string(),
+                    parent_span_id: span["parentSpanId"]
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_string(),
+                    name: span["name"].as_str().unwrap_or_default().to_string(),
+                    attributes: span["attributes"].clone(),
+                });
+            }
+        }
+    }
+
+    StatusCode::OK
+}
@@ -0,0 +1,270 @@
+//! A static file server standing in for model hosts.
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{Path, State},
+    http::{
+        header::{ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, ETAG, IF_RANGE, RANGE, USER_AGENT},
+        HeaderMap, StatusCode,
+    },
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use futures_util::StreamExt;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::net::TcpListener;
+use url::Url;
+
+#[derive(Default)]
+struct FileServerState {
+    files: Mutex<HashMap<String, Vec<u8>>>,
+    hits: Mutex<HashMap<String, usize>>,
+    /// Remaining requests answered with `503 Service Unavailable`, by path.
+    failures: Mutex<HashMap<String, usize>>,
+    user_agents: Mutex<HashMap<String, String>>,
+    /// Bytes sent before the connection is cut, and for how many responses, by path.
+    interruptions: Mutex<HashMap<String, (usize, usize)>>,
+    /// Bytes sent before pausing, and for how long, by path.
+    delays: Mutex<HashMap<String, (usize, Duration)>>,
+    ranges: Mutex<HashMap<String, Vec<Option<String>>>>,
+    /// Paths answered in full whatever the `Range` header, as by servers without range support.
+    ignore_ranges: Mutex<HashSet<String>>,
+}
+
+pub struct FileServer {
+    url: Url,
+    state: Arc<FileServerState>,
+}
+
+impl FileServer {
+    pub async fn start() -> Self {
+        let state = Arc::new(FileServerState::default());
+
+        let app = Router::new()
+            .route("/*path", get(serve_file))
+            .with_state(state.clone());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+
+        Self {
+            url: Url::parse(&format!("http://{}", addr)).unwrap(),
+            state,
+        }
+    }
+
+    /// Serve `content` at `path` and return its full URL.
+    pub fn add(&self, path: &str, content: &[u8]) -> Url {
+        let path = path.trim_start_matches('/');
+        self.state
+            .files
+            .lock()
+            .unwrap()
+            .insert(path.to_string(), content.to_vec());
+        self.url.join(path).unwrap()
+    }
+
+    /// Answer the next `count` requests for `path` with `503 Service Unavailable`.
+    pub fn fail(&self, path: &str, count: usize) {
+        let path = path.trim_start_matches('/');
+        self.state
+            .failures
+            .lock()
+            .unwrap()
+            .insert(path.to_string(), count);
+    }
+
+    /// Cut the connection of the next `count` responses for `path` after `after` bytes of body.
+    pub fn interrupt(&self, path: &str, after: usize, count: usize) {
+        let path = path.trim_start_matches('/');
+        self.state
+            .interruptions
+            .lock()
+            .unwrap()
+            .insert(path.to_string(), (after, count));
+    }
+
+    /// Answer requests for `path` in full, ignoring their `Range` header.
+    pub fn ignore_ranges(&self, path: &str) {
+        let path = path.trim_start_matches('/');
+        self.state
+            .ignore_ranges
+            .lock()
+            .unwrap()
+            .insert(path.to_string());
+    }
+
+    /// Pause the responses for `path` for `duration` after `after` bytes of body.
+    pub fn delay(&self, path: &str, after: usize, duration: Duration) {
+        let path = path.trim_start_matches('/');
+        self.state
+            .delays
+            .lock()
+            .unwrap()
+            .insert(path.to_string(), (after, duration));
+    }
+
+    /// `Range` header of each request for `path`, in order.
+    pub fn ranges(&self, path: &str) -> Vec<Option<String>> {
+        let path = path.trim_start_matches('/');
+        self.state
+            .ranges
+            .lock()
+            .unwrap()
+            .get(path)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// User agent of the last request for `path`.
+    pub fn user_agent(&self, path: &str) -> Option<String> {
+        let path = path.trim_start_matches('/');
+        self.state.user_agents.lock().unwrap().get(path).cloned()
+    }
+
+    /// Number of requests received for `path`.
+    pub fn hits(&self, path: &str) -> usize {
+        let path = path.trim_start_matches('/');
+        self.state
+            .hits
+            .lock()
+            .unwrap()
+            .get(path)
+            .copied()
+            .unwrap_or(0)
+    }
+}
+
+async fn serve_file(
+    State(state): State<Arc<FileServerState>>,
+    Path(path): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    *state.hits.lock().unwrap().entry(path.clone()).or_default() += 1;
+    if let Some(user_agent) = headers.get(USER_AGENT).and_then(|v| v.to_str().ok()) {
+        state
+            .user_agents
+            .lock()
+            .unwrap()
+            .insert(path.clone(), user_agent.to_string());
+    }
+
+    if let Some(remaining) = state.failures.lock().unwrap().get_mut(&path) {
+        if *remaining > 0 {
+            *remaining -= 1;
+            return StatusCode::SERVICE_UNAVAILABLE.into_response();
+        }
+    }
+
+    let range = headers.get(RANGE).and_then(|v| v.to_str().ok());
+    state
+        .ranges
+        .lock()
+        .unwrap()
+        .entry(path.clone())
+        .or_default()
+        .push(range.map(|v| v.to_string()));
+
+    let Some(content) = state.files.lock().unwrap().get(&path).cloned() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let etag = etag(&content);
+    let size = content.len();
+
+    // ranges are ignored, as in RFC 9110, when the file changed since `If-Range`
+    let unchanged = headers
+        .get(IF_RANGE)
+        .is_none_or(|v| v.as_bytes() == etag.as_bytes())
+        && !state.ignore_ranges.lock().unwrap().contains(&path);
+    let (status, body, content_range) = match range.and_then(parse_range).filter(|_| unchanged) {
+        Some((start, _)) if start >= size => {
+            return (
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(CONTENT_RANGE, format!("bytes */{}", size))],
+            )
+                .into_response();
+        }
+        Some((start, end)) => {
+            let end = end.unwrap_or(size - 1).min(size - 1);
+            (
+                StatusCode::PARTIAL_CONTENT,
+                content[start..=end].to_vec(),
+                Some(format!("bytes {}-{}/{}", start, end, size)),
+            )
+        }
+        None => (StatusCode::OK, content, None),
+    };
+
+    let mut response = Response::builder()
+        .status(status)
+        .header(ETAG, etag)
+        .header(ACCEPT_RANGES, "bytes")
+        .header(CONTENT_LENGTH, body.len());
+    if let Some(content_range) = content_range {
+        response = response.header(CONTENT_RANGE, content_range);
+    }
+
+    let interrupt_after = match state.interruptions.lock().unwrap().get_mut(&path) {
+        Some((after, remaining)) if *remaining > 0 => {
+            *remaining -= 1;
+            Some(*after)
+        }
+        _ => None,
+    };
+    let delay = state.delays.lock().unwrap().get(&path).copied();
+    let body = match (interrupt_after, delay) {
+        (Some(after), _) if after < body.len() => {
+            let sent = Bytes::from(body[..after].to_vec());
+            Body::from_stream(interrupted_stream(sent))
+        }
+        (_, Some((after, duration))) if after < body.len() => {
+            let rest = Bytes::from(body[after..].to_vec());
+            let first = futures_util::stream::once(async move {
+                Ok::<_, std::io::Error>(Bytes::from(body[..after].to_vec()))
+            });
+            let rest = futures_util::stream::once(async move {
+                tokio::time::sleep(duration).await;
+                Ok(rest)
+            });
+            Body::from_stream(first.chain(rest))
+        }
+        _ => Body::from(body),
+    };
+
+    response.body(body).unwrap()
+}
+
+/// Send `sent`, then fail once the client had time to read it.
+fn interrupted_stream(
+    sent: Bytes,
+) -> impl futures_util::Stream<Item = Result<Bytes, std::io::Error>> + Send {
+    futures_util::stream::once(async move { Ok(sent) }).chain(futures_util::stream::once(async {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        Err(std::io::Error::other("interrupted"))
+    }))
+}
+
+fn etag(content: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    format!("\"{:x}\"", hasher.finish())
+}
+
+/// First and last byte of `bytes=0-99` or `bytes=100-`.
+fn parse_range(value: &str) -> Option<(usize, Option<usize>)> {
+    let (start, end) = value.strip_prefix("bytes=")?.split_once('-')?;
+    let end = match end {
+        "" => None,
+        end => Some(end.parse().ok()?),
+    };
+    Some((start.parse().ok()?, end))
+}
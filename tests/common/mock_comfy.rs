@@ -0,0 +1,556 @@
+//! A scriptable stand-in for a ComfyUI node.
+//!
+//! It implements the subset of the ComfyUI API the router talks to
+//! (`/prompt`, `/ws`, `/interrupt`, `/upload/image`, `/view`, `/object_info`,
+//! `/system_stats`) and executes every submitted
+//! prompt by replaying the websocket messages a real node would send, according
+//! to the current [`MockBehavior`].
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Multipart, Query, Request, State,
+    },
+    http::StatusCode,
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use axum_server::tls_rustls::RustlsConfig;
+use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{net::TcpListener, sync::mpsc};
+use url::Url;
+
+/// Binary event type used by ComfyUI for sampler previews.
+const PREVIEW_IMAGE: u32 = 1;
+/// Binary event type used by `SaveImageWebsocket` outputs.
+const UNENCODED_PREVIEW_IMAGE: u32 = 2;
+
+/// How the mock node reacts to the next prompts.
+#[derive(Clone, Debug)]
+pub struct MockBehavior {
+    /// Number of sampler steps, each step sends a `progress` message and a preview.
+    pub steps: usize,
+    /// Delay before each sampler step.
+    pub step_delay: Duration,
+    /// Delay before `POST /prompt` responds.
+    pub prompt_delay: Duration,
+    /// Reject `POST /prompt` with `400 Bad Request` and this message.
+    pub prompt_error: Option<String>,
+    /// Send `execution_error` with this message after sampling.
+    pub execution_error: Option<String>,
+    /// Close the websocket after this many sampler steps.
+    pub disconnect_after: Option<usize>,
+    /// Images sent by the `SaveImageWebsocket` node.
+    pub outputs: Vec<Vec<u8>>,
+}
+
+impl Default for MockBehavior {
+    fn default() -> Self {
+        Self {
+            steps: 3,
+            step_delay: Duration::from_millis(10),
+            prompt_delay: Duration::ZERO,
+            prompt_error: None,
+            execution_error: None,
+            disconnect_after: None,
+            outputs: vec![b"image-0".to_vec()],
+        }
+    }
+}
+
+/// How the mock node is served.
+#[derive(Clone, Default)]
+pub struct MockServer {
+    /// Serve HTTPS, e.g. to require client certificates.
+    pub tls: Option<Arc<rustls::ServerConfig>>,
+    /// Path prefix of a reverse proxy in front of the node, e.g. `/comfy`.
+    pub prefix: Option<String>,
+    /// Headers every request must carry, like a reverse proxy checking credentials.
+    pub required_headers: Vec<(String, String)>,
+}
+
+enum ClientCommand {
+    Send(Message),
+    Disconnect,
+}
+
+#[derive(Default)]
+struct MockState {
+    behavior: Mutex<MockBehavior>,
+    clients: Mutex<HashMap<String, mpsc::UnboundedSender<ClientCommand>>>,
+    prompts: Mutex<Vec<Value>>,
+    interrupted: AtomicBool,
+    interrupt_count: AtomicUsize,
+    queue_remaining: AtomicUsize,
+    /// Files pushed by `/upload/image`, as paths under the root, in order.
+    pushed: Mutex<Vec<(String, Vec<u8>)>>,
+}
+
+pub struct MockComfyUI {
+    url: Url,
+    state: Arc<MockState>,
+}
+
+impl MockComfyUI {
+    pub async fn start() -> Self {
+        Self::start_with(MockBehavior::default()).await
+    }
+
+    pub async fn start_with(behavior: MockBehavior) -> Self {
+        Self::start_with_server(behavior, MockServer::default()).await
+    }
+
+    pub async fn start_with_server(behavior: MockBehavior, server: MockServer) -> Self {
+        let state = Arc::new(MockState {
+            behavior: Mutex::new(behavior),
+            ..Default::default()
+        });
+
+        let app = Router::new()
+            .route("/prompt", get(queue_info).post(prompt))
+            .route("/ws", get(ws))
+            .route("/interrupt", post(interrupt))
+            .route("/upload/image", post(upload_image))
+            .route("/view", get(view))
+            .route("/object_info", get(object_info))
+            .route("/system_stats", get(system_stats))
+            .with_state(state.clone());
+        let app = match &server.prefix {
+            Some(prefix) => Router::new().nest(prefix, app),
+            None => app,
+        };
+        let required_headers = Arc::new(server.required_headers);
+        let app = app.layer(middleware::from_fn(move |request: Request, next: Next| {
+            let required_headers = required_headers.clone();
+            async move {
+                let authorized = required_headers.iter().all(|(name, value)| {
+                    request.headers().get(name).and_then(|v| v.to_str().ok()) == Some(value)
+                });
+                match authorized {
+                    true => next.run(request).await,
+                    false => StatusCode::UNAUTHORIZED.into_response(),
+                }
+            }
+        }));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let scheme = match server.tls {
+            Some(tls) => {
+                let listener = listener.into_std().unwrap();
+                tokio::spawn(async move {
+                    axum_server::from_tcp_rustls(listener, RustlsConfig::from_config(tls))
+                        .serve(app.into_make_service())
+                        .await
+                        .unwrap();
+                });
+                "https"
+            }
+            None => {
+                tokio::spawn(async move {
+                    axum::serve(listener, app).await.unwrap();
+                });
+                "http"
+            }
+        };
+
+        let path = server.prefix.unwrap_or_default();
+        Self {
+            url: Url::parse(&format!("{}://{}{}/", scheme, addr, path)).unwrap(),
+            state,
+        }
+    }
+
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Replace the behavior for all following prompts.
+    pub fn set_behavior(&self, behavior: MockBehavior) {
+        *self.state.behavior.lock().unwrap() = behavior;
+    }
+
+    /// All prompts received by `POST /prompt`, in order.
+    pub fn prompts(&self) -> Vec<Value> {
+        self.state.prompts.lock().unwrap().clone()
+    }
+
+    pub fn interrupt_count(&self) -> usize {
+        self.state.interrupt_count.load(Ordering::SeqCst)
+    }
+
+    /// Inputs pushed to the node, as paths under its root with their content, in order.
+    pub fn pushed(&self) -> Vec<(String, Vec<u8>)> {
+        self.state.pushed.lock().unwrap().clone()
+    }
+}
+
+fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+fn binary_frame(event_type: u32, image: &[u8]) -> Message {
+    // 4 bytes event type + 4 bytes image format (2 = PNG) + image data
+    let mut data = event_type.to_be_bytes().to_vec();
+    data.extend_from_slice(&2u32.to_be_bytes());
+    data.extend_from_slice(image);
+    Message::Binary(data)
+}
+
+fn text_frame(message_type: &str, data: Value) -> Message {
+    Message::Text(json!({ "type": message_type, "data": data }).to_string())
+}
+
+async fn queue_info(State(state): State<Arc<MockState>>) -> Json<Value> {
+    Json(json!({
+        "exec_info": { "queue_remaining": state.queue_remaining.load(Ordering::SeqCst) }
+    }))
+}
+
+#[derive(Deserialize)]
+struct PromptRequest {
+    prompt: Value,
+    client_id: String,
+}
+
+async fn prompt(State(state): State<Arc<MockState>>, Json(data): Json<PromptRequest>) -> Response {
+    let behavior = state.behavior.lock().unwrap().clone();
+    tokio::time::sleep(behavior.prompt_delay).await;
+
+    state.prompts.lock().unwrap().push(data.prompt.clone());
+
+    if let Some(message) = &behavior.prompt_error {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": { "type": "prompt_outputs_failed_validation", "message": message },
+                "node_errors": {}
+            })),
+        )
+            .into_response();
+    }
+
+    let prompt_id = uuid::Uuid::new_v4().to_string();
+    state.queue_remaining.fetch_add(1, Ordering::SeqCst);
+    tokio::spawn(execute(
+        state.clone(),
+        behavior,
+        data.client_id,
+        prompt_id.clone(),
+        data.prompt,
+    ));
+
+    Json(json!({ "prompt_id": prompt_id, "number": 0, "node_errors": {} })).into_response()
+}
+
+/// Replay the messages of a real execution to the websocket client.
+async fn execute(
+    state: Arc<MockState>,
+    behavior: MockBehavior,
+    client_id: String,
+    prompt_id: String,
+    prompt: Value,
+) {
+    // the router connects the websocket before submitting the prompt,
+    // but the upgrade may not be registered yet
+    let mut client = None;
+    for _ in 0..100 {
+        client = state.clients.lock().unwrap().get(&client_id).cloned();
+        if client.is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let Some(client) = client else {
+        state.queue_remaining.fetch_sub(1, Ordering::SeqCst);
+        return;
+    };
+
+    let send = |message: Message| {
+        let _ = client.send(ClientCommand::Send(message));
+    };
+
+    state.interrupted.store(false, Ordering::SeqCst);
+
+    let mut nodes: Vec<(String, String)> = prompt
+        .as_object()
+        .map(|nodes| {
+            nodes
+                .iter()
+                .map(|(id, node)| {
+                    let class_type = node["class_type"].as_str().unwrap_or_default();
+                    (id.clone(), class_type.to_string())
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    nodes.sort_by_key(|(id, _)| id.parse::<u64>().unwrap_or(u64::MAX));
+
+    send(text_frame(
+        "execution_start",
+        json!({ "prompt_id": prompt_id, "timestamp": timestamp() }),
+    ));
+    send(text_frame(
+        "execution_cached",
+        json!({ "nodes": [], "prompt_id": prompt_id, "timestamp": timestamp() }),
+    ));
+
+    for (node_id, class_type) in &nodes {
+        send(text_frame(
+            "executing",
+            json!({ "node": node_id, "display_node": node_id, "prompt_id": prompt_id }),
+        ));
+
+        match class_type.as_str() {
+            "KSampler" => {
+                for step in 0..behavior.steps {
+                    if behavior.disconnect_after == Some(step) {
+                        let _ = client.send(ClientCommand::Disconnect);
+                        state.queue_remaining.fetch_sub(1, Ordering::SeqCst);
+                        return;
+                    }
+
+                    if state.interrupted.swap(false, Ordering::SeqCst) {
+                        send(text_frame(
+                            "execution_interrupted",
+                            json!({
+                                "prompt_id": prompt_id,
+                                "node_id": node_id,
+                                "node_type": class_type,
+                                "executed": []
+                            }),
+                        ));
+                        state.queue_remaining.fetch_sub(1, Ordering::SeqCst);
+                        return;
+                    }
+
+                    tokio::time::sleep(behavior.step_delay).await;
+
+                    send(text_frame(
+                        "progress",
+                        json!({
+                            "value": step + 1,
+                            "max": behavior.steps,
+                            "prompt_id": prompt_id,
+                            "node": node_id
+                        }),
+                    ));
+                    send(binary_frame(
+                        PREVIEW_IMAGE,
+                        format!("preview-{}", step).as_bytes(),
+                    ));
+                }
+
+                if let Some(message) = &behavior.execution_error {
+                    send(text_frame(
+                        "execution_error",
+                        json!({
+                            "prompt_id": prompt_id,
+                            "timestamp": timestamp(),
+                            "node_id": node_id,
+                            "node_type": class_type,
+                            "executed": [],
+                            "exception_type": "RuntimeError",
+                            "exception_message": message,
+                            "traceback": [],
+                            "current_inputs": {},
+                            "current_outputs": {}
+                        }),
+                    ));
+                    state.queue_remaining.fetch_sub(1, Ordering::SeqCst);
+                    return;
+                }
+            }
+            "SaveImageWebsocket" => {
+                for output in &behavior.outputs {
+                    send(binary_frame(UNENCODED_PREVIEW_IMAGE, output));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    send(text_frame(
+        "executing",
+        json!({ "node": null, "display_node": null, "prompt_id": prompt_id }),
+    ));
+    send(text_frame(
+        "execution_success",
+        json!({ "prompt_id": prompt_id, "timestamp": timestamp() }),
+    ));
+
+    let remaining = state.queue_remaining.fetch_sub(1, Ordering::SeqCst) - 1;
+    send(text_frame(
+        "status",
+        json!({ "status": { "exec_info": { "queue_remaining": remaining } } }),
+    ));
+}
+
+#[derive(Deserialize)]
+struct WsQuery {
+    #[serde(rename = "clientId")]
+    client_id: Option<String>,
+}
+
+async fn ws(
+    State(state): State<Arc<MockState>>,
+    Query(query): Query<WsQuery>,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let client_id = query
+        .client_id
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+
+    upgrade.on_upgrade(move |socket| handle_socket(state, client_id, socket))
+}
+
+async fn handle_socket(state: Arc<MockState>, client_id: String, socket: WebSocket) {
+    let (mut sink, mut stream) = socket.split();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+
+    let _ = sink
+        .send(text_frame(
+            "status",
+            json!({
+                "status": {
+                    "exec_info": {
+                        "queue_remaining": state.queue_remaining.load(Ordering::SeqCst)
+                    }
+                },
+                "sid": client_id
+            }),
+        ))
+        .await;
+
+    state.clients.lock().unwrap().insert(client_id.clone(), tx);
+
+    loop {
+        tokio::select! {
+            command = rx.recv() => match command {
+                Some(ClientCommand::Send(message)) => {
+                    if sink.send(message).await.is_err() {
+                        break;
+                    }
+                }
+                // drop the connection without a close frame, like a crashed node
+                Some(ClientCommand::Disconnect) | None => break,
+            },
+            message = stream.next() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                _ => {}
+            },
+        }
+    }
+
+    state.clients.lock().unwrap().remove(&client_id);
+}
+
+async fn interrupt(State(state): State<Arc<MockState>>) -> StatusCode {
+    state.interrupted.store(true, Ordering::SeqCst);
+    state.interrupt_count.fetch_add(1, Ordering::SeqCst);
+    StatusCode::OK
+}
+
+async fn upload_image(State(state): State<Arc<MockState>>, mut multipart: Multipart) -> Response {
+    let mut image = None;
+    let mut subfolder = String::new();
+    while let Ok(Some(field)) = multipart.next_field().await {
+        match field.name() {
+            Some("image") => {
+                let name = field.file_name().unwrap_or_default().to_string();
+                image = Some((name, field.bytes().await.unwrap().to_vec()));
+            }
+            Some("subfolder") => subfolder = field.text().await.unwrap(),
+            _ => {}
+        }
+    }
+    let Some((name, content)) = image else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let path = match subfolder.as_str() {
+        "" => format!("input/{}", name),
+        subfolder => format!("input/{}/{}", subfolder, name),
+    };
+    state.pushed.lock().unwrap().push((path, content));
+
+    Json(json!({ "name": name, "subfolder": subfolder, "type": "input" })).into_response()
+}
+
+#[derive(Deserialize)]
+struct ViewQuery {
+    filename: String,
+    #[serde(default)]
+    subfolder: String,
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+async fn view(State(state): State<Arc<MockState>>, Query(query): Query<ViewQuery>) -> Response {
+    let path = match query.subfolder.as_str() {
+        "" => format!("{}/{}", query.kind, query.filename),
+        subfolder => format!("{}/{}/{}", query.kind, subfolder, query.filename),
+    };
+    let pushed = state.pushed.lock().unwrap();
+    match pushed.iter().rev().find(|(v, _)| *v == path) {
+        Some((_, content)) => content.clone().into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+async fn object_info() -> Json<Value> {
+    let node = |inputs: Value, outputs: Value| {
+        json!({
+            "input": { "required": inputs },
+            "output": outputs,
+            "category": "mock"
+        })
+    };
+
+    Json(json!({
+        "CheckpointLoaderSimple": node(json!({ "ckpt_name": [[]] }), json!(["MODEL", "CLIP", "VAE"])),
+        "UNETLoader": node(json!({ "unet_name": [[]], "weight_dtype": [["default"]] }), json!(["MODEL"])),
+        "VAELoader": node(json!({ "vae_name": [[]] }), json!(["VAE"])),
+        "LoraLoader": node(json!({ "lora_name": [[]] }), json!(["MODEL", "CLIP"])),
+        "CLIPTextEncode": node(json!({ "text": ["STRING"], "clip": ["CLIP"] }), json!(["CONDITIONING"])),
+        "EmptyLatentImage": node(json!({ "width": ["INT"], "height": ["INT"], "batch_size": ["INT"] }), json!(["LATENT"])),
+        "KSampler": node(json!({ "seed": ["INT"], "steps": ["INT"] }), json!(["LATENT"])),
+        "VAEDecode": node(json!({ "samples": ["LATENT"], "vae": ["VAE"] }), json!(["IMAGE"])),
+        "SaveImageWebsocket": node(json!({ "images": ["IMAGE"] }), json!([])),
+    }))
+}
+
+async fn system_stats() -> Json<Value> {
+    Json(json!({
+        "system": {
+            "os": "posix",
+            "python_version": "3.11.0",
+            "embedded_python": false,
+            "comfyui_version": "mock"
+        },
+        "devices": [{
+            "name": "mock:0",
+            "type": "cuda",
+            "index": 0,
+            "vram_total": 25769803776u64,
+            "vram_free": 25769803776u64,
+            "torch_vram_total": 0,
+            "torch_vram_free": 0
+        }]
+    }))
+}
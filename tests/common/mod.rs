@@ -0,0 +1,346 @@
+//! Shared helpers for the integration tests.
+//!
+//! Every test binary only uses part of the helpers.
+#![allow(dead_code)]
+
+pub mod agent;
+pub mod collector;
+pub mod file_server;
+pub mod mock_comfy;
+pub mod tls;
+
+use comfy_router::{
+    config::{
+        AppConfig, AuditConfig, AuthConfig, ClusterConfig, DownloadConfig, LogConfig, LogRotation,
+        StorageConfig,
+    },
+    serve,
+    state::AppState,
+};
+use reqwest::{Client, RequestBuilder, StatusCode};
+use serde_json::{json, Value};
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::net::TcpListener;
+use url::Url;
+
+pub const USERNAME: &str = "admin";
+pub const PASSWORD: &str = "secret";
+
+/// A router served on a random local port, with its download folders
+/// in a temporary directory.
+pub struct TestRouter {
+    url: Url,
+    client: Client,
+    dir: TempDir,
+}
+
+impl TestRouter {
+    pub async fn start() -> Self {
+        Self::start_with(|_| {}).await
+    }
+
+    pub async fn start_with(configure: impl FnOnce(&mut AppConfig)) -> Self {
+        Self::start_with_state(configure, |_, state| state).await
+    }
+
+    /// Start with a custom config and adjust the state before serving,
+    /// e.g. to install the global subscriber.
+    pub async fn start_with_state(
+        configure: impl FnOnce(&mut AppConfig),
+        prepare: impl FnOnce(&AppConfig, AppState) -> AppState,
+    ) -> Self {
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut config = AppConfig {
+            host: "127.0.0.1".into(),
+            port: 0,
+            env: "test".into(),
+            auth: AuthConfig {
+                username: USERNAME.into(),
+                password: PASSWORD.into(),
+                preview_secret: "preview-secret".into(),
+                preview_token_ttl: 60,
+            },
+            default_limits: Default::default(),
+            audit: AuditConfig {
+                log_path: dir.path().join("audit.log"),
+                max_bytes: 1024 * 1024,
+                max_files: 2,
+            },
+            log: LogConfig {
+                rotation: LogRotation::Never,
+                max_files: 1,
+                ..Default::default()
+            },
+            otel: Default::default(),
+            cluster: ClusterConfig {
+                history_limit: 50,
+                pending_limit: 25,
+                ..Default::default()
+            },
+            download: DownloadConfig {
+                cache_dir: dir.path().join("cache"),
+                root_dir: dir.path().join("root"),
+                max_cache_bytes: 1024 * 1024 * 1024,
+                ..Default::default()
+            },
+            storage: StorageConfig {
+                record_path: dir.path().join("record.json"),
+                api_keys_path: dir.path().join("api_keys.json"),
+            },
+            tls: Default::default(),
+            node_tls: Default::default(),
+            http: Default::default(),
+        };
+        configure(&mut config);
+
+        let scheme = match config.tls.cert_path {
+            Some(_) => "https",
+            None => "http",
+        };
+        let state = prepare(&config.clone(), AppState::new(config).await.unwrap());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            serve(listener, state).await.unwrap();
+        });
+
+        Self {
+            url: Url::parse(&format!("{}://{}", scheme, addr)).unwrap(),
+            // tests checking the certificate use their own client
+            client: Client::builder()
+                .danger_accept_invalid_certs(true)
+                .build()
+                .unwrap(),
+            dir,
+        }
+    }
+
+    pub fn url(&self, path: &str) -> Url {
+        self.url.join(path).unwrap()
+    }
+
+    /// The ComfyUI root folder the router links downloaded files into.
+    pub fn root_dir(&self) -> std::path::PathBuf {
+        self.dir.path().join("root")
+    }
+
+    pub fn get(&self, path: &str) -> RequestBuilder {
+        self.client
+            .get(self.url(path))
+            .basic_auth(USERNAME, Some(PASSWORD))
+    }
+
+    pub fn post(&self, path: &str) -> RequestBuilder {
+        self.client
+            .post(self.url(path))
+            .basic_auth(USERNAME, Some(PASSWORD))
+    }
+
+    pub fn anonymous(&self) -> &Client {
+        &self.client
+    }
+
+    pub fn get_as(&self, key: &str, path: &str) -> RequestBuilder {
+        self.client.get(self.url(path)).bearer_auth(key)
+    }
+
+    pub fn post_as(&self, key: &str, path: &str) -> RequestBuilder {
+        self.client.post(self.url(path)).bearer_auth(key)
+    }
+
+    /// Create an API key with given scopes as admin, return the key and its id.
+    pub async fn create_key(&self, scopes: &[&str]) -> (String, String) {
+        self.create_key_with_limits(scopes, json!({})).await
+    }
+
+    pub async fn create_key_with_limits(&self, scopes: &[&str], limits: Value) -> (String, String) {
+        let resp = self
+            .post("/auth/keys")
+            .json(&json!({ "name": "test", "scopes": scopes, "limits": limits }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: Value = resp.json().await.unwrap();
+        (
+            body["key"].as_str().unwrap().to_string(),
+            body["info"]["id"].as_str().unwrap().to_string(),
+        )
+    }
+
+    /// Path of a file in the router's temporary directory.
+    pub fn path(&self, name: &str) -> std::path::PathBuf {
+        self.dir.path().join(name)
+    }
+
+    pub async fn add_node(&self, node: &Url) {
+        let resp = self
+            .post("/cluster/nodes")
+            .json(&json!({ "url": node }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    pub async fn submit(&self, payload: &Value) -> String {
+        let body = self.submit_full(payload).await;
+        body["id"].as_str().unwrap().to_string()
+    }
+
+    /// Submit as admin and return the whole response, including the preview token.
+    pub async fn submit_full(&self, payload: &Value) -> Value {
+        let resp = self.post("/workflow").json(payload).send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        resp.json().await.unwrap()
+    }
+
+    /// Submit with the API key `key`, whatever the response.
+    pub async fn submit_as(&self, key: &str, payload: &Value) -> reqwest::Response {
+        self.post_as(key, "/workflow")
+            .json(payload)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    /// Submit a workflow with `url` as its custom checkpoint.
+    pub async fn submit_with_checkpoint(&self, url: &Url) -> String {
+        let mut payload = sd15_payload();
+        payload["params"]["checkpoint"] = json!({ "type": "custom", "name": url });
+        self.submit(&payload).await
+    }
+
+    /// Submit a workflow with `url` as its custom checkpoint and wait for it.
+    pub async fn run_with_checkpoint(&self, url: &Url) -> Value {
+        let id = self.submit_with_checkpoint(url).await;
+        self.wait_for(&id).await
+    }
+
+    /// Pre-warm `url` into `target_dirs` and return its file id, without waiting.
+    pub async fn prewarm(&self, url: &Url, target_dirs: &[&str]) -> String {
+        let resp = self
+            .post("/downloads")
+            .json(&json!({ "url": url, "target_dirs": target_dirs }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let info: Value = resp.json().await.unwrap();
+        info["file_id"].as_str().unwrap().to_string()
+    }
+
+    /// Pre-warm `url` into `target_dirs` and wait until the download is completed.
+    pub async fn prewarmed(&self, url: &Url, target_dirs: &[&str]) -> String {
+        let file_id = self.prewarm(url, target_dirs).await;
+        wait_until(|| async {
+            let info = self.get_json(&format!("/downloads/{}", file_id)).await;
+            (info["status"] == "completed").then_some(())
+        })
+        .await;
+        file_id
+    }
+
+    pub async fn preview(&self, id: &str, token: &str) -> reqwest::Response {
+        self.client
+            .get(self.url(&format!("/preview/{}", id)))
+            .query(&[("token", token)])
+            .send()
+            .await
+            .unwrap()
+    }
+
+    /// GET `path` as admin, expecting `200 OK`.
+    pub async fn get_json(&self, path: &str) -> Value {
+        let resp = self.get(path).send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        resp.json().await.unwrap()
+    }
+
+    pub async fn status(&self, id: &str) -> Value {
+        let resp = self.get(&format!("/workflow/{}", id)).send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        resp.json().await.unwrap()
+    }
+
+    /// Poll the task until it is done or failed.
+    pub async fn wait_for(&self, id: &str) -> Value {
+        wait_until(|| async {
+            let status = self.status(id).await;
+            match status["status"].as_str() {
+                Some("done") | Some("error") => Some(status),
+                _ => None,
+            }
+        })
+        .await
+    }
+}
+
+/// Poll `check` until it returns `Some`, panicking after 10 seconds.
+pub async fn wait_until<T, F, Fut>(mut check: F) -> T
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Option<T>>,
+{
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+    loop {
+        if let Some(value) = check().await {
+            return value;
+        }
+        assert!(
+            tokio::time::Instant::now() < deadline,
+            "timed out waiting for condition"
+        );
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+/// A minimal SD15 payload that only uses build-in models.
+/// `len` bytes derived from `seed`, distinct for distinct seeds.
+pub fn content(len: usize, seed: u8) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8 ^ seed).collect()
+}
+
+pub fn sd15_payload() -> Value {
+    json!({
+        "type": "SD15",
+        "params": {
+            "checkpoint": { "type": "build_in", "name": "v1-5-pruned-emaonly.safetensors" },
+            "vae": null,
+            "loras": [],
+            "controlnets": [],
+            "prompt": "a cat",
+            "negative_prompt": "",
+            "input_image": null,
+            "input_mask": null,
+            "denoise": null,
+            "width": 512,
+            "height": 512,
+            "batch_size": 1,
+            "sampler": "euler",
+            "scheduler": "normal",
+            "steps": 3,
+            "cfg_scale": 7.0,
+            "seed": 42
+        }
+    })
+}
+
+/// Decode the images of a `done` result.
+pub fn images(result: &Value) -> Vec<Vec<u8>> {
+    result["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|image| {
+            image
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|v| v.as_u64().unwrap() as u8)
+                .collect()
+        })
+        .collect()
+}
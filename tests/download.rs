@@ -0,0 +1,1077 @@
+mod common;
+
+use comfy_router::config::{AppConfig, EvictionPolicy};
+use common::{
+    content,
+    file_server::FileServer,
+    mock_comfy::{MockBehavior, MockComfyUI},
+    sd15_payload, wait_until, TestRouter,
+};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+
+const PATH: &str = "/models/model.safetensors";
+
+async fn start(configure: impl FnOnce(&mut AppConfig)) -> (TestRouter, MockComfyUI) {
+    let router = TestRouter::start_with(|config| {
+        config.http.retry_backoff_ms = 10;
+        configure(config);
+    })
+    .await;
+    let node = MockComfyUI::start().await;
+    router.add_node(node.url()).await;
+    (router, node)
+}
+
+/// Content of the only checkpoint linked into the root folder.
+fn checkpoint(router: &TestRouter) -> Vec<u8> {
+    let mut links: Vec<_> = std::fs::read_dir(router.root_dir().join("models/checkpoints"))
+        .unwrap()
+        .map(|v| v.unwrap().path())
+        .collect();
+    assert_eq!(links.len(), 1);
+    std::fs::read(links.pop().unwrap()).unwrap()
+}
+
+/// Names of the files in the cache dir, sorted.
+fn cached_files(router: &TestRouter) -> Vec<String> {
+    let mut files: Vec<_> = std::fs::read_dir(router.path("cache"))
+        .unwrap()
+        .map(|v| v.unwrap().file_name().into_string().unwrap())
+        .collect();
+    files.sort();
+    files
+}
+
+/// Content of the checkpoints linked into the root folder, sorted.
+fn checkpoints(router: &TestRouter) -> Vec<Vec<u8>> {
+    let mut checkpoints: Vec<_> = std::fs::read_dir(router.root_dir().join("models/checkpoints"))
+        .unwrap()
+        .map(|v| std::fs::read(v.unwrap().path()).unwrap())
+        .collect();
+    checkpoints.sort();
+    checkpoints
+}
+
+fn range(value: &str) -> Option<String> {
+    Some(value.to_string())
+}
+
+#[tokio::test]
+async fn resumes_interrupted_download() {
+    let (router, _node) = start(|_| {}).await;
+    let files = FileServer::start().await;
+    let url = files.add(PATH, &content(200_000, 0));
+    files.interrupt(PATH, 80_000, 1);
+
+    let result = router.run_with_checkpoint(&url).await;
+    assert_eq!(result["status"], "done", "{}", result);
+
+    assert_eq!(checkpoint(&router), content(200_000, 0));
+    assert_eq!(files.ranges(PATH), vec![None, range("bytes=80000-")]);
+}
+
+#[tokio::test]
+async fn resumes_failed_download_on_next_workflow() {
+    let (router, _node) = start(|config| config.download.retries = 0).await;
+    let files = FileServer::start().await;
+    let url = files.add(PATH, &content(200_000, 0));
+    files.interrupt(PATH, 80_000, 1);
+
+    let result = router.run_with_checkpoint(&url).await;
+    assert_eq!(result["status"], "error", "{}", result);
+
+    let result = router.run_with_checkpoint(&url).await;
+    assert_eq!(result["status"], "done", "{}", result);
+
+    assert_eq!(checkpoint(&router), content(200_000, 0));
+    assert_eq!(files.ranges(PATH), vec![None, range("bytes=80000-")]);
+}
+
+#[tokio::test]
+async fn downloads_again_when_remote_file_changed() {
+    let (router, _node) = start(|config| config.download.retries = 0).await;
+    let files = FileServer::start().await;
+    let url = files.add(PATH, &content(200_000, 0));
+    files.interrupt(PATH, 80_000, 1);
+
+    let result = router.run_with_checkpoint(&url).await;
+    assert_eq!(result["status"], "error", "{}", result);
+
+    // the partial content is stale, `If-Range` makes the server send the whole file
+    files.add(PATH, &content(150_000, 1));
+    let result = router.run_with_checkpoint(&url).await;
+    assert_eq!(result["status"], "done", "{}", result);
+
+    assert_eq!(checkpoint(&router), content(150_000, 1));
+    assert_eq!(files.hits(PATH), 2);
+}
+
+#[tokio::test]
+async fn downloads_in_parallel_chunks() {
+    let (router, _node) = start(|config| {
+        config.download.parallel_chunks = 3;
+        config.download.chunk_size = 50_000;
+    })
+    .await;
+    let files = FileServer::start().await;
+    let url = files.add(PATH, &content(220_000, 0));
+    // the first chunk is interrupted and resumed alone
+    files.interrupt(PATH, 10_000, 1);
+
+    let result = router.run_with_checkpoint(&url).await;
+    assert_eq!(result["status"], "done", "{}", result);
+    assert_eq!(checkpoint(&router), content(220_000, 0));
+
+    let ranges = files.ranges(PATH);
+    assert_eq!(ranges[0], range("bytes=0-49999"));
+    for expected in [
+        "bytes=10000-49999",
+        "bytes=50000-99999",
+        "bytes=100000-149999",
+        "bytes=150000-199999",
+        "bytes=200000-219999",
+    ] {
+        assert!(ranges.contains(&range(expected)), "{:?}", ranges);
+    }
+}
+
+#[tokio::test]
+async fn downloads_in_order_when_ranges_are_ignored() {
+    let (router, _node) = start(|config| {
+        config.download.parallel_chunks = 3;
+        config.download.chunk_size = 50_000;
+    })
+    .await;
+    let files = FileServer::start().await;
+    let url = files.add(PATH, &content(120_000, 0));
+    files.ignore_ranges(PATH);
+
+    let result = router.run_with_checkpoint(&url).await;
+    assert_eq!(result["status"], "done", "{}", result);
+
+    assert_eq!(checkpoint(&router), content(120_000, 0));
+    assert_eq!(files.hits(PATH), 1);
+}
+
+#[tokio::test]
+async fn reports_download_progress() {
+    let (router, _node) = start(|_| {}).await;
+    let files = FileServer::start().await;
+    let url = files.add(PATH, &content(100_000, 0));
+    files.delay(PATH, 40_000, Duration::from_secs(1));
+
+    let id = router.submit_with_checkpoint(&url).await;
+
+    // the task shows the download it waits for
+    let status = wait_until(|| async {
+        let status = router.status(&id).await;
+        (status["status"] == "downloading" && status["data"][0]["downloaded"] == 40_000)
+            .then_some(status)
+    })
+    .await;
+    let download = &status["data"][0];
+    assert_eq!(download["status"], "pending");
+    assert_eq!(download["url"], url.as_str());
+    assert_eq!(download["total"], 100_000);
+    assert_eq!(download["target_dirs"], json!(["models/checkpoints"]));
+    assert!(download["speed"].as_u64().unwrap() > 0);
+    assert!(download["eta"].is_u64());
+    let file_id = download["file_id"].as_str().unwrap().to_string();
+
+    let pending = router.get_json("/downloads?status=pending").await;
+    assert_eq!(pending.as_array().unwrap().len(), 1);
+    assert_eq!(pending[0]["file_id"], file_id.as_str());
+    assert_eq!(pending[0]["downloaded"], 40_000);
+
+    let result = router.wait_for(&id).await;
+    assert_eq!(result["status"], "done", "{}", result);
+
+    let download = router.get_json(&format!("/downloads/{}", file_id)).await;
+    assert_eq!(download["status"], "completed");
+    assert_eq!(download["downloaded"], 100_000);
+    assert_eq!(download["total"], 100_000);
+    assert_eq!(download["speed"], Value::Null);
+
+    assert_eq!(
+        router.get_json("/downloads?status=pending").await,
+        json!([])
+    );
+    let completed = router.get_json("/downloads?status=completed").await;
+    assert_eq!(completed.as_array().unwrap().len(), 1);
+
+    let resp = router.get("/downloads/missing").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    let (key, _) = router.create_key(&["workflow:run", "workflow:read"]).await;
+    let resp = router.get_as(&key, "/downloads").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+}
+
+fn sha256(content: &[u8]) -> String {
+    hex::encode(Sha256::digest(content))
+}
+
+async fn submit_model(router: &TestRouter, model: Value) -> reqwest::Response {
+    let mut payload = sd15_payload();
+    payload["params"]["checkpoint"] = model;
+    router
+        .post("/workflow")
+        .json(&payload)
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn run_model(router: &TestRouter, model: Value) -> Value {
+    let resp = submit_model(router, model).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: Value = resp.json().await.unwrap();
+    router.wait_for(body["id"].as_str().unwrap()).await
+}
+
+#[tokio::test]
+async fn verifies_downloaded_files() {
+    let (router, _node) = start(|_| {}).await;
+    let files = FileServer::start().await;
+    let url = files.add(PATH, &content(100_000, 0));
+    let hash = sha256(&content(100_000, 0));
+
+    let result = run_model(
+        &router,
+        json!({ "type": "custom", "name": url, "sha256": hash.to_uppercase(), "size": 100_000 }),
+    )
+    .await;
+    assert_eq!(result["status"], "done", "{}", result);
+    let completed = router.get_json("/downloads?status=completed").await;
+    assert_eq!(completed[0]["sha256"], hash.as_str());
+
+    // a file that is not the expected one is not cached
+    let other = files.add("/models/other.safetensors", &content(100_000, 1));
+    let result = run_model(
+        &router,
+        json!({ "type": "custom", "name": other, "sha256": sha256(&content(100_000, 2)) }),
+    )
+    .await;
+    assert_eq!(result["status"], "error", "{}", result);
+    let failed = router.get_json("/downloads?status=failed").await;
+    assert_eq!(failed[0]["downloaded"], 0);
+    let result = run_model(
+        &router,
+        json!({ "type": "custom", "name": other, "size": 99_999 }),
+    )
+    .await;
+    assert_eq!(result["status"], "error", "{}", result);
+
+    let resp = submit_model(
+        &router,
+        json!({ "type": "custom", "name": url, "sha256": "not-a-hash" }),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn verifies_cached_files_before_reuse() {
+    let (router, _node) = start(|_| {}).await;
+    let files = FileServer::start().await;
+    let url = files.add(PATH, &content(100_000, 0));
+    let hash = sha256(&content(100_000, 0));
+    let model = json!({ "type": "custom", "name": url, "sha256": hash });
+
+    let result = run_model(&router, model.clone()).await;
+    assert_eq!(result["status"], "done", "{}", result);
+
+    // corrupted in place, the recorded hash is trusted unless asked to verify
+    std::fs::write(router.path("cache").join(&hash), content(100_000, 1)).unwrap();
+    let result = run_model(&router, model.clone()).await;
+    assert_eq!(result["status"], "done", "{}", result);
+    assert_eq!(files.hits(PATH), 1);
+
+    let mut verified = model.clone();
+    verified["verify"] = json!(true);
+    let result = run_model(&router, verified.clone()).await;
+    assert_eq!(result["status"], "done", "{}", result);
+    assert_eq!(files.hits(PATH), 2);
+    assert_eq!(checkpoint(&router), content(100_000, 0));
+
+    // an intact file is reused after verifying it
+    let result = run_model(&router, verified).await;
+    assert_eq!(result["status"], "done", "{}", result);
+    assert_eq!(files.hits(PATH), 2);
+}
+
+#[tokio::test]
+async fn downloads_corrupted_files_in_use_again_without_unlinking() {
+    let slow = MockBehavior {
+        step_delay: Duration::from_millis(500),
+        ..Default::default()
+    };
+    let (router, node) = start(|_| {}).await;
+    node.set_behavior(slow.clone());
+    let other = MockComfyUI::start_with(slow).await;
+    router.add_node(other.url()).await;
+    let files = FileServer::start().await;
+    let url = files.add(PATH, &content(100_000, 0));
+    let hash = sha256(&content(100_000, 0));
+    let model = json!({ "type": "custom", "name": url, "sha256": hash });
+
+    let running = submit_model(&router, model.clone()).await;
+    let running: Value = running.json().await.unwrap();
+    let running = running["id"].as_str().unwrap().to_string();
+    wait_until(|| async { (router.status(&running).await["status"] == "running").then_some(()) })
+        .await;
+
+    std::fs::write(router.path("cache").join(&hash), content(100_000, 1)).unwrap();
+    let mut verified = model;
+    verified["verify"] = json!(true);
+    let result = run_model(&router, verified).await;
+    assert_eq!(result["status"], "done", "{}", result);
+    assert_eq!(files.hits(PATH), 2);
+
+    // downloaded again under the same file id, the link of the running workflow is kept
+    let result = router.wait_for(&running).await;
+    assert_eq!(result["status"], "done", "{}", result);
+    let prompts: Vec<_> = node.prompts().into_iter().chain(other.prompts()).collect();
+    assert_eq!(prompts.len(), 2);
+    assert_eq!(
+        prompts[0]["3"]["inputs"]["ckpt_name"],
+        prompts[1]["3"]["inputs"]["ckpt_name"]
+    );
+    assert_eq!(checkpoint(&router), content(100_000, 0));
+}
+
+#[tokio::test]
+async fn keeps_files_in_use_when_another_hash_is_expected() {
+    let slow = MockBehavior {
+        step_delay: Duration::from_millis(500),
+        ..Default::default()
+    };
+    let (router, node) = start(|_| {}).await;
+    node.set_behavior(slow.clone());
+    let other = MockComfyUI::start_with(slow).await;
+    router.add_node(other.url()).await;
+    let files = FileServer::start().await;
+    let url = files.add(PATH, &content(100_000, 0));
+    let hash = sha256(&content(100_000, 0));
+
+    let running = router.submit_with_checkpoint(&url).await;
+    wait_until(|| async { (router.status(&running).await["status"] == "running").then_some(()) })
+        .await;
+
+    // the request with a wrong hash fails, the workflow keeps its file
+    let result = run_model(
+        &router,
+        json!({ "type": "custom", "name": url, "sha256": sha256(&content(100_000, 1)) }),
+    )
+    .await;
+    assert_eq!(result["status"], "error", "{}", result);
+    assert!(
+        result["data"].as_str().unwrap().contains("in use"),
+        "{}",
+        result
+    );
+    let resp = router
+        .post("/downloads")
+        .json(&json!({ "url": url, "target_dirs": ["models/checkpoints"], "size": 1 }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+    assert_eq!(router.status(&running).await["status"], "running");
+    assert_eq!(checkpoint(&router), content(100_000, 0));
+    let result = router.wait_for(&running).await;
+    assert_eq!(result["status"], "done", "{}", result);
+    assert_eq!(cached_files(&router), [hash]);
+    assert_eq!(files.hits(PATH), 1);
+}
+
+#[tokio::test]
+async fn stores_identical_content_once() {
+    let (router, _node) = start(|_| {}).await;
+    let files = FileServer::start().await;
+    let mirror = FileServer::start().await;
+    let hash = sha256(&content(100_000, 0));
+
+    for url in [
+        files.add(PATH, &content(100_000, 0)),
+        mirror.add(PATH, &content(100_000, 0)),
+    ] {
+        let result = router.run_with_checkpoint(&url).await;
+        assert_eq!(result["status"], "done", "{}", result);
+    }
+    assert_eq!(files.hits(PATH), 1);
+    assert_eq!(mirror.hits(PATH), 1);
+
+    // with its hash known, the content is not downloaded from another URL
+    let signed = mirror.add("/models/signed.safetensors", &content(100_000, 0));
+    let result = run_model(
+        &router,
+        json!({ "type": "custom", "name": signed, "sha256": hash }),
+    )
+    .await;
+    assert_eq!(result["status"], "done", "{}", result);
+    assert_eq!(mirror.hits("/models/signed.safetensors"), 0);
+
+    // every URL is linked to the one file named by its hash
+    assert_eq!(cached_files(&router), [hash.as_str()]);
+    let downloads = router.get_json("/downloads").await;
+    assert_eq!(downloads.as_array().unwrap().len(), 3);
+    for link in std::fs::read_dir(router.root_dir().join("models/checkpoints")).unwrap() {
+        let link = link.unwrap().path();
+        assert_eq!(
+            std::fs::read_link(&link).unwrap(),
+            router.path("cache").join(&hash)
+        );
+    }
+}
+
+#[tokio::test]
+async fn identifies_files_by_kept_query_params() {
+    let (router, _node) = start(|config| {
+        config
+            .download
+            .keep_query
+            .insert("127.0.0.1".into(), vec!["file".into()]);
+    })
+    .await;
+    let files = FileServer::start().await;
+    let url = files.add(PATH, &content(100_000, 0));
+    let with_query = |query: &str| {
+        let mut url = url.clone();
+        url.set_query(Some(query));
+        url
+    };
+
+    for (query, hits) in [
+        ("file=a&token=1", 1),
+        ("file=b&token=2", 2),
+        ("token=3&file=a", 2),
+    ] {
+        let result = router.run_with_checkpoint(&with_query(query)).await;
+        assert_eq!(result["status"], "done", "{}", result);
+        assert_eq!(files.hits(PATH), hits, "{}", query);
+    }
+    let downloads = router.get_json("/downloads").await;
+    let mut urls: Vec<_> = downloads
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| v["url"].as_str().unwrap().to_string())
+        .collect();
+    urls.sort();
+    assert_eq!(
+        urls,
+        [
+            with_query("file=a").to_string(),
+            with_query("file=b").to_string()
+        ]
+    );
+
+    // without a rule for the host, the query does not identify the file
+    let (router, _node) = start(|_| {}).await;
+    for query in ["file=a&token=1", "file=b&token=2"] {
+        let result = router.run_with_checkpoint(&with_query(query)).await;
+        assert_eq!(result["status"], "done", "{}", result);
+    }
+    assert_eq!(files.hits(PATH), 3);
+}
+
+#[tokio::test]
+async fn evicts_least_recently_used_files() {
+    let (router, _node) = start(|config| config.download.max_cache_bytes = 25).await;
+    let files = FileServer::start().await;
+    let urls: Vec<_> = (0..3)
+        .map(|i| files.add(&format!("/models/{}.safetensors", i), &content(10, i)))
+        .collect();
+
+    for url in [&urls[0], &urls[1], &urls[0], &urls[2]] {
+        let result = router.run_with_checkpoint(url).await;
+        assert_eq!(result["status"], "done", "{}", result);
+    }
+
+    // the first file was used again after the second one was downloaded
+    let cached = wait_until(|| async {
+        let cached = cached_files(&router);
+        (cached.len() == 2).then_some(cached)
+    })
+    .await;
+    let mut expected = vec![sha256(&content(10, 0)), sha256(&content(10, 2))];
+    expected.sort();
+    assert_eq!(cached, expected);
+    assert_eq!(files.hits("/models/0.safetensors"), 1);
+}
+
+#[tokio::test]
+async fn keeps_files_in_use_by_running_workflows() {
+    let slow = MockBehavior {
+        step_delay: Duration::from_millis(500),
+        ..Default::default()
+    };
+    let (router, node) = start(|config| config.download.max_cache_bytes = 15).await;
+    node.set_behavior(slow.clone());
+    let other = MockComfyUI::start_with(slow).await;
+    router.add_node(other.url()).await;
+    let files = FileServer::start().await;
+    let first = files.add("/models/first.safetensors", &content(10, 0));
+    let second = files.add("/models/second.safetensors", &content(10, 1));
+
+    let is_running = |id: String| {
+        let router = &router;
+        async move { (router.status(&id).await["status"] == "running").then_some(()) }
+    };
+
+    // both files are used at once, together above the cache limit
+    let first_id = router.submit_with_checkpoint(&first).await;
+    wait_until(|| is_running(first_id.clone())).await;
+    let second_id = router.submit_with_checkpoint(&second).await;
+    wait_until(|| is_running(second_id.clone())).await;
+    assert_eq!(router.status(&first_id).await["status"], "running");
+    assert_eq!(checkpoints(&router), [content(10, 0), content(10, 1)]);
+
+    for id in [&first_id, &second_id] {
+        let result = router.wait_for(id).await;
+        assert_eq!(result["status"], "done", "{}", result);
+    }
+    assert_eq!(cached_files(&router).len(), 2);
+
+    // once released, they are evicted by the next download
+    let third = files.add("/models/third.safetensors", &content(10, 2));
+    let result = router.run_with_checkpoint(&third).await;
+    assert_eq!(result["status"], "done", "{}", result);
+    wait_until(|| async { (cached_files(&router) == [sha256(&content(10, 2))]).then_some(()) })
+        .await;
+}
+
+#[tokio::test]
+async fn keeps_running_downloads_out_of_eviction() {
+    let (router, _node) = start(|config| {
+        config.download.max_cache_bytes = 25;
+        config.download.eviction_policy = EvictionPolicy::Lfu;
+    })
+    .await;
+    // the workflow waiting for the slow download keeps a node busy
+    let other = MockComfyUI::start().await;
+    router.add_node(other.url()).await;
+    let files = FileServer::start().await;
+    let cached = files.add("/models/cached.safetensors", &content(10, 0));
+    let slow = files.add("/models/slow.safetensors", &content(20, 1));
+    let fast = files.add("/models/fast.safetensors", &content(10, 2));
+    files.delay("/models/slow.safetensors", 10, Duration::from_secs(3));
+
+    let result = router.run_with_checkpoint(&cached).await;
+    assert_eq!(result["status"], "done", "{}", result);
+
+    // the slow download is half written when the fast one completes and evicts
+    let slow_id = router.submit_with_checkpoint(&slow).await;
+    wait_until(|| async {
+        cached_files(&router)
+            .iter()
+            .any(|v| v.ends_with(".download"))
+            .then_some(())
+    })
+    .await;
+    let result = router.run_with_checkpoint(&fast).await;
+    assert_eq!(result["status"], "done", "{}", result);
+
+    // the partial file is neither evicted nor counted, so both cached files fit
+    let metrics = wait_until(|| async {
+        let metrics = router.get("/metrics").send().await.unwrap();
+        let metrics = metrics.text().await.unwrap();
+        metrics
+            .lines()
+            .any(|v| v == "comfy_router_cache_size_bytes 20")
+            .then_some(metrics)
+    })
+    .await;
+    assert!(metrics.contains("comfy_router_cache_evictions_total 0"));
+    let mut expected = vec![sha256(&content(10, 0)), sha256(&content(10, 2))];
+    expected.sort();
+    let blobs: Vec<_> = cached_files(&router)
+        .into_iter()
+        .filter(|v| !v.contains('.'))
+        .collect();
+    assert_eq!(blobs, expected);
+
+    let result = router.wait_for(&slow_id).await;
+    assert_eq!(result["status"], "done", "{}", result);
+    assert_eq!(files.hits("/models/slow.safetensors"), 1);
+}
+
+/// The cached files after downloading files of `sizes` and using them in order by index,
+/// once one of them is evicted.
+async fn cached_after(policy: EvictionPolicy, sizes: &[usize], uses: &[usize]) -> Vec<String> {
+    let (router, _node) = start(|config| {
+        config.download.max_cache_bytes = 25;
+        config.download.eviction_policy = policy;
+    })
+    .await;
+    let files = FileServer::start().await;
+    let urls: Vec<_> = sizes
+        .iter()
+        .enumerate()
+        .map(|(i, size)| {
+            files.add(
+                &format!("/models/{}.safetensors", i),
+                &content(*size, i as u8),
+            )
+        })
+        .collect();
+
+    for i in uses {
+        let result = router.run_with_checkpoint(&urls[*i]).await;
+        assert_eq!(result["status"], "done", "{}", result);
+    }
+
+    wait_until(|| async {
+        let cached = cached_files(&router);
+        (cached.len() < sizes.len()).then_some(cached)
+    })
+    .await
+}
+
+#[tokio::test]
+async fn evicts_by_policy() {
+    // least frequently used, although the first file was used before the second
+    let cached = cached_after(EvictionPolicy::Lfu, &[10, 10, 10], &[0, 0, 1, 2]).await;
+    assert!(!cached.contains(&sha256(&content(10, 1))), "{:?}", cached);
+    assert_eq!(cached.len(), 2);
+
+    // largest, although the first file was used before the second
+    let cached = cached_after(EvictionPolicy::Size, &[10, 14, 5], &[0, 1, 2]).await;
+    assert!(!cached.contains(&sha256(&content(14, 1))), "{:?}", cached);
+    assert_eq!(cached.len(), 2);
+}
+
+#[tokio::test]
+async fn applies_folder_quotas() {
+    let (router, _node) = start(|config| {
+        config
+            .download
+            .quotas
+            .insert("models/checkpoints".into(), 15);
+    })
+    .await;
+    let files = FileServer::start().await;
+    let vae = files.add("/models/vae.safetensors", &content(10, 9));
+
+    for i in 0..2 {
+        let mut payload = sd15_payload();
+        payload["params"]["checkpoint"] = json!({
+            "type": "custom",
+            "name": files.add(&format!("/models/{}.safetensors", i), &content(10, i)),
+        });
+        payload["params"]["vae"] = json!({ "type": "custom", "name": vae });
+        let id = router.submit(&payload).await;
+        let result = router.wait_for(&id).await;
+        assert_eq!(result["status"], "done", "{}", result);
+    }
+
+    // the first checkpoint is evicted, the VAE in another folder is not counted
+    let mut expected = vec![sha256(&content(10, 1)), sha256(&content(10, 9))];
+    expected.sort();
+    wait_until(|| async { (cached_files(&router) == expected).then_some(()) }).await;
+}
+
+#[tokio::test]
+async fn never_evicts_listed_urls() {
+    let files = FileServer::start().await;
+    let kept = files.add("/models/kept/model.safetensors", &content(10, 0));
+    let prefix = kept.as_str().replace("model.safetensors", "*");
+    let (router, _node) = start(|config| {
+        config.download.max_cache_bytes = 15;
+        config.download.never_evict = vec![prefix];
+    })
+    .await;
+
+    for url in [
+        kept,
+        files.add("/models/1.safetensors", &content(10, 1)),
+        files.add("/models/2.safetensors", &content(10, 2)),
+    ] {
+        let result = router.run_with_checkpoint(&url).await;
+        assert_eq!(result["status"], "done", "{}", result);
+    }
+
+    let mut expected = vec![sha256(&content(10, 0)), sha256(&content(10, 2))];
+    expected.sort();
+    wait_until(|| async { (cached_files(&router) == expected).then_some(()) }).await;
+}
+
+#[tokio::test]
+async fn expires_input_images() {
+    let (router, _node) = start(|config| {
+        config.download.ttl.insert("input".into(), 1);
+    })
+    .await;
+    let files = FileServer::start().await;
+
+    let mut payload = sd15_payload();
+    payload["params"]["checkpoint"] = json!({
+        "type": "custom",
+        "name": files.add(PATH, &content(10, 0)),
+    });
+    payload["params"]["input_image"] = json!({
+        "type": "url",
+        "content": files.add("/images/input.png", b"image"),
+    });
+    payload["params"]["denoise"] = json!(0.5);
+    let id = router.submit(&payload).await;
+    let result = router.wait_for(&id).await;
+    assert_eq!(result["status"], "done", "{}", result);
+    assert_eq!(cached_files(&router).len(), 2);
+
+    // the image is evicted after its TTL, the checkpoint is kept
+    wait_until(|| async { (cached_files(&router) == [sha256(&content(10, 0))]).then_some(()) })
+        .await;
+    assert_eq!(
+        std::fs::read_dir(router.root_dir().join("input"))
+            .unwrap()
+            .count(),
+        0
+    );
+}
+
+#[tokio::test]
+async fn prewarms_files_into_target_dirs() {
+    let (router, _node) = start(|_| {}).await;
+    let files = FileServer::start().await;
+    let url = files.add(PATH, &content(10, 0));
+
+    for target_dirs in [&[][..], &["../models"], &["/models"], &["models/../.."]] {
+        let resp = router
+            .post("/downloads")
+            .json(&json!({ "url": url, "target_dirs": target_dirs }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{:?}", target_dirs);
+    }
+
+    let file_id = router
+        .prewarmed(&url, &["models/checkpoints", "models/loras"])
+        .await;
+    for target_dir in ["models/checkpoints", "models/loras"] {
+        let link = router.root_dir().join(target_dir).join(&file_id);
+        assert_eq!(std::fs::read(link).unwrap(), content(10, 0));
+    }
+
+    // the workflow uses the cached file
+    let result = router.run_with_checkpoint(&url).await;
+    assert_eq!(result["status"], "done", "{}", result);
+    assert_eq!(files.hits(PATH), 1);
+
+    let info = router.get_json(&format!("/downloads/{}", file_id)).await;
+    assert_eq!(
+        info["target_dirs"],
+        json!(["models/checkpoints", "models/loras"])
+    );
+    assert_eq!(info["total"], 10);
+    assert_eq!(info["use_count"], 2);
+    assert!(info["last_used_at"].is_u64());
+    assert_eq!(info["pinned"], false);
+}
+
+#[tokio::test]
+async fn deletes_and_purges_cached_files() {
+    let (router, _node) = start(|_| {}).await;
+    let files = FileServer::start().await;
+    let first = router
+        .prewarmed(
+            &files.add("/models/first.safetensors", &content(10, 0)),
+            &["models/checkpoints"],
+        )
+        .await;
+    let second = router
+        .prewarmed(
+            &files.add("/models/second.safetensors", &content(10, 1)),
+            &["models/checkpoints"],
+        )
+        .await;
+    let image = router
+        .prewarmed(&files.add("/images/input.png", b"image"), &["input"])
+        .await;
+
+    let resp = router
+        .post(&format!("/downloads/{}/delete", first))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(!router
+        .root_dir()
+        .join("models/checkpoints")
+        .join(&first)
+        .exists());
+    assert!(!router.path("cache").join(sha256(&content(10, 0))).exists());
+    let resp = router
+        .get(&format!("/downloads/{}", first))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let resp = router
+        .post(&format!("/downloads/{}/delete", first))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    let purge = |body: Value| {
+        let router = &router;
+        async move {
+            router
+                .post("/downloads/purge")
+                .json(&body)
+                .send()
+                .await
+                .unwrap()
+        }
+    };
+    assert_eq!(purge(json!({})).await.status(), StatusCode::BAD_REQUEST);
+
+    let resp = purge(json!({ "pattern": "http://*/images/*" })).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let result: Value = resp.json().await.unwrap();
+    assert_eq!(result, json!({ "purged": [image], "skipped": [] }));
+    assert!(!router.root_dir().join("input").join(&image).exists());
+
+    // the remaining file was used just now
+    let result: Value = purge(json!({ "older_than": 3600 }))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(result, json!({ "purged": [], "skipped": [] }));
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    let result: Value = purge(json!({ "older_than": 0 }))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(result, json!({ "purged": [second], "skipped": [] }));
+
+    assert_eq!(router.get_json("/downloads").await, json!([]));
+    assert!(cached_files(&router).is_empty());
+}
+
+#[tokio::test]
+async fn keeps_files_in_use_when_deleting() {
+    let (router, node) = start(|_| {}).await;
+    node.set_behavior(MockBehavior {
+        step_delay: Duration::from_millis(500),
+        ..Default::default()
+    });
+    let files = FileServer::start().await;
+    let url = files.add(PATH, &content(10, 0));
+
+    let id = router.submit_with_checkpoint(&url).await;
+    wait_until(|| async { (router.status(&id).await["status"] == "running").then_some(()) }).await;
+    let downloads = router.get_json("/downloads").await;
+    let file_id = downloads[0]["file_id"].as_str().unwrap().to_string();
+    assert_eq!(downloads[0]["pinned"], true);
+
+    let resp = router
+        .post(&format!("/downloads/{}/delete", file_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+    let resp = router
+        .post("/downloads/purge")
+        .json(&json!({ "pattern": "*" }))
+        .send()
+        .await
+        .unwrap();
+    let result: Value = resp.json().await.unwrap();
+    assert_eq!(result, json!({ "purged": [], "skipped": [file_id] }));
+
+    let result = router.wait_for(&id).await;
+    assert_eq!(result["status"], "done", "{}", result);
+    let resp = router
+        .post(&format!("/downloads/{}/delete", file_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(cached_files(&router).is_empty());
+}
+
+async fn prefetch(router: &TestRouter, body: Value) -> reqwest::Response {
+    router
+        .post("/downloads/prefetch")
+        .json(&body)
+        .send()
+        .await
+        .unwrap()
+}
+
+/// Poll the pre-fetch until no node is waiting or warming up.
+async fn warmed_up(router: &TestRouter, id: &str) -> Value {
+    wait_until(|| async {
+        let info = router
+            .get_json(&format!("/downloads/prefetch/{}", id))
+            .await;
+        let warm_up = info["warm_up"].as_array().unwrap();
+        warm_up
+            .iter()
+            .all(|v| v["status"] == "done" || v["status"] == "error")
+            .then_some(info)
+    })
+    .await
+}
+
+#[tokio::test]
+async fn prefetches_models_and_warms_up_nodes() {
+    let (router, node) = start(|_| {}).await;
+    let other = MockComfyUI::start().await;
+    router.add_node(other.url()).await;
+    let files = FileServer::start().await;
+    let checkpoint = files.add(PATH, &content(10, 0));
+    let lora = files.add("/models/lora.safetensors", &content(10, 1));
+    let vae = files.add("/models/vae.safetensors", &content(10, 2));
+
+    let resp = prefetch(&router, json!({ "models": [] })).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let resp = prefetch(
+        &router,
+        json!({ "models": [{ "url": lora, "kind": "lora" }], "warm_up": true }),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let resp = router
+        .get("/downloads/prefetch/unknown")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    let resp = prefetch(
+        &router,
+        json!({
+            "models": [
+                { "url": checkpoint, "kind": "checkpoint", "sha256": sha256(&content(10, 0)) },
+                { "url": lora, "kind": "lora" },
+                { "url": vae, "kind": "vae" },
+            ],
+            "warm_up": true,
+        }),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let info: Value = resp.json().await.unwrap();
+    let id = info["id"].as_str().unwrap();
+    assert_eq!(info["models"].as_array().unwrap().len(), 3);
+
+    let info = warmed_up(&router, id).await;
+    for warm_up in info["warm_up"].as_array().unwrap() {
+        assert_eq!(warm_up["status"], "done", "{}", info);
+    }
+    let mut file_ids = vec![];
+    for (model, folder) in
+        info["models"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .zip(["checkpoints", "loras", "vae"])
+    {
+        assert_eq!(model["download"]["status"], "completed", "{}", info);
+        let file_id = model["download"]["file_id"].as_str().unwrap().to_string();
+        let link = router.root_dir().join("models").join(folder).join(&file_id);
+        assert!(link.exists(), "{}", link.display());
+        file_ids.push(file_id);
+    }
+
+    // each node loaded the checkpoint with the LoRA
+    for node in [&node, &other] {
+        let prompts = node.prompts();
+        assert_eq!(prompts.len(), 1);
+        let nodes: Vec<_> = prompts[0].as_object().unwrap().values().collect();
+        assert!(nodes
+            .iter()
+            .any(|v| v["class_type"] == "CheckpointLoaderSimple"
+                && v["inputs"]["ckpt_name"] == file_ids[0]));
+        assert!(nodes
+            .iter()
+            .any(|v| v["class_type"] == "LoraLoader" && v["inputs"]["lora_name"] == file_ids[1]));
+    }
+
+    // the nodes are available again, and the workflow does not download anything
+    let result = router.run_with_checkpoint(&checkpoint).await;
+    assert_eq!(result["status"], "done", "{}", result);
+    assert_eq!(files.hits(PATH), 1);
+}
+
+#[tokio::test]
+async fn reports_failed_prefetch_downloads() {
+    let (router, node) = start(|config| config.download.retries = 0).await;
+    let files = FileServer::start().await;
+    let checkpoint = files.add(PATH, &content(10, 0));
+
+    let resp = prefetch(
+        &router,
+        json!({
+            "models": [{ "url": checkpoint, "kind": "checkpoint", "sha256": sha256(b"other") }],
+            "warm_up": true,
+        }),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let info: Value = resp.json().await.unwrap();
+
+    let info = warmed_up(&router, info["id"].as_str().unwrap()).await;
+    assert_eq!(
+        info["models"][0]["download"]["status"], "failed",
+        "{}",
+        info
+    );
+    assert_eq!(info["warm_up"][0]["status"], "error", "{}", info);
+    assert!(node.prompts().is_empty());
+}
+
+#[tokio::test]
+async fn gives_up_warming_up_busy_nodes() {
+    let (router, node) = start(|config| config.cluster.warm_up_timeout = 1).await;
+    node.set_behavior(MockBehavior {
+        step_delay: Duration::from_millis(1000),
+        ..Default::default()
+    });
+    let files = FileServer::start().await;
+    let checkpoint = files.add(PATH, &content(10, 0));
+
+    // the node stays busy with a workflow longer than the warm-up waits
+    let id = router.submit(&sd15_payload()).await;
+    wait_until(|| async { (!node.prompts().is_empty()).then_some(()) }).await;
+
+    let resp = prefetch(
+        &router,
+        json!({
+            "models": [{ "url": checkpoint, "kind": "checkpoint" }],
+            "warm_up": true,
+        }),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let info: Value = resp.json().await.unwrap();
+    let info = warmed_up(&router, info["id"].as_str().unwrap()).await;
+    assert_eq!(info["warm_up"][0]["status"], "error", "{}", info);
+    assert!(
+        info["warm_up"][0]["error"]
+            .as_str()
+            .unwrap()
+            .contains("busy"),
+        "{}",
+        info
+    );
+
+    // the warm-up leaves the node to the workflow
+    let result = router.wait_for(&id).await;
+    assert_eq!(result["status"], "done", "{}", result);
+    assert_eq!(node.prompts().len(), 1);
+    node.set_behavior(MockBehavior::default());
+    let result = router.run_with_checkpoint(&checkpoint).await;
+    assert_eq!(result["status"], "done", "{}", result);
+}
@@ -0,0 +1,53 @@
+mod common;
+
+use common::{file_server::FileServer, mock_comfy::MockComfyUI, TestRouter};
+use url::Url;
+
+#[tokio::test]
+async fn downloads_through_proxy_with_user_agent() {
+    let proxy = FileServer::start().await;
+    proxy.add("/models/model.safetensors", b"checkpoint");
+
+    let router = TestRouter::start_with(|config| {
+        config.http.proxy = Some(proxy.add("/", b""));
+        config.http.user_agent = "router-test/1.0".into();
+    })
+    .await;
+    let node = MockComfyUI::start().await;
+    router.add_node(node.url()).await;
+
+    // the host does not resolve, only the proxy can serve it
+    let url = Url::parse("http://models.invalid/models/model.safetensors").unwrap();
+    let result = router.run_with_checkpoint(&url).await;
+    assert_eq!(result["status"], "done", "{}", result);
+
+    assert_eq!(proxy.hits("/models/model.safetensors"), 1);
+    assert_eq!(
+        proxy.user_agent("/models/model.safetensors").as_deref(),
+        Some("router-test/1.0")
+    );
+}
+
+#[tokio::test]
+async fn retries_unavailable_downloads() {
+    let files = FileServer::start().await;
+    let router = TestRouter::start_with(|config| {
+        config.http.retries = 2;
+        config.http.retry_backoff_ms = 10;
+    })
+    .await;
+    let node = MockComfyUI::start().await;
+    router.add_node(node.url()).await;
+
+    let flaky = files.add("/models/flaky.safetensors", b"checkpoint");
+    files.fail("/models/flaky.safetensors", 2);
+    let result = router.run_with_checkpoint(&flaky).await;
+    assert_eq!(result["status"], "done", "{}", result);
+    assert_eq!(files.hits("/models/flaky.safetensors"), 3);
+
+    let down = files.add("/models/down.safetensors", b"checkpoint");
+    files.fail("/models/down.safetensors", 3);
+    let result = router.run_with_checkpoint(&down).await;
+    assert_eq!(result["status"], "error", "{}", result);
+    assert_eq!(files.hits("/models/down.safetensors"), 3);
+}
@@ -0,0 +1,174 @@
+mod common;
+
+use common::{mock_comfy::MockComfyUI, sd15_payload, TestRouter};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+
+#[tokio::test]
+async fn limits_request_rate_per_key() {
+    let router = TestRouter::start().await;
+    let (key, _) = router
+        .create_key_with_limits(
+            &["workflow:run"],
+            json!({ "requests_per_minute": 1, "burst": 2 }),
+        )
+        .await;
+    let (other_key, _) = router
+        .create_key_with_limits(&["workflow:run"], json!({ "requests_per_minute": 1 }))
+        .await;
+
+    let resp = router.submit_as(&key, &sd15_payload()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["ratelimit-limit"], "2");
+    assert_eq!(resp.headers()["ratelimit-remaining"], "1");
+
+    let resp = router.submit_as(&key, &sd15_payload()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["ratelimit-remaining"], "0");
+
+    let resp = router.submit_as(&key, &sd15_payload()).await;
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(resp.headers()["ratelimit-remaining"], "0");
+    let retry_after: u64 = resp.headers()["retry-after"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after > 0 && retry_after <= 60);
+    let body: Value = resp.json().await.unwrap();
+    assert!(body["message"].as_str().unwrap().contains("rate limit"));
+
+    // limits are tracked per key
+    let resp = router.submit_as(&other_key, &sd15_payload()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // admin is not limited
+    router.submit(&sd15_payload()).await;
+}
+
+#[tokio::test]
+async fn applies_default_limits_from_config() {
+    let router = TestRouter::start_with(|config| {
+        config.default_limits.requests_per_minute = Some(1);
+    })
+    .await;
+    let (key, _) = router.create_key(&["workflow:run"]).await;
+    let (unlimited_key, _) = router
+        .create_key_with_limits(&["workflow:run"], json!({ "requests_per_minute": 600 }))
+        .await;
+
+    assert_eq!(
+        router.submit_as(&key, &sd15_payload()).await.status(),
+        StatusCode::OK
+    );
+    assert_eq!(
+        router.submit_as(&key, &sd15_payload()).await.status(),
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    for _ in 0..3 {
+        assert_eq!(
+            router
+                .submit_as(&unlimited_key, &sd15_payload())
+                .await
+                .status(),
+            StatusCode::OK
+        );
+    }
+}
+
+#[tokio::test]
+async fn limits_concurrent_jobs() {
+    let router = TestRouter::start().await;
+    let (key, _) = router
+        .create_key_with_limits(
+            &["workflow:run", "workflow:read"],
+            json!({ "max_concurrent_jobs": 1 }),
+        )
+        .await;
+
+    // no node yet, the first job stays pending
+    let resp = router.submit_as(&key, &sd15_payload()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let id = resp.json::<Value>().await.unwrap()["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    let resp = router.submit_as(&key, &sd15_payload()).await;
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    let body: Value = resp.json().await.unwrap();
+    assert!(body["message"].as_str().unwrap().contains("concurrent"));
+
+    let node = MockComfyUI::start().await;
+    router.add_node(node.url()).await;
+    router.wait_for(&id).await;
+
+    let resp = router.submit_as(&key, &sd15_payload()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn enforces_daily_image_and_step_quotas() {
+    let router = TestRouter::start().await;
+    let (image_key, _) = router
+        .create_key_with_limits(&["workflow:run"], json!({ "daily_images": 3 }))
+        .await;
+    let (step_key, _) = router
+        .create_key_with_limits(&["workflow:run"], json!({ "daily_steps": 10 }))
+        .await;
+
+    let mut payload = sd15_payload();
+    payload["params"]["batch_size"] = json!(2);
+    payload["params"]["steps"] = json!(4);
+
+    // 2 images
+    assert_eq!(
+        router.submit_as(&image_key, &payload).await.status(),
+        StatusCode::OK
+    );
+    // 4 images exceeds the quota
+    let resp = router.submit_as(&image_key, &payload).await;
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(resp.headers().contains_key("retry-after"));
+    let body: Value = resp.json().await.unwrap();
+    assert!(body["message"].as_str().unwrap().contains("image quota"));
+
+    // 8 steps
+    assert_eq!(
+        router.submit_as(&step_key, &payload).await.status(),
+        StatusCode::OK
+    );
+    // 16 steps exceeds the quota
+    let resp = router.submit_as(&step_key, &payload).await;
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    let body: Value = resp.json().await.unwrap();
+    assert!(body["message"].as_str().unwrap().contains("step quota"));
+}
+
+#[tokio::test]
+async fn rejected_jobs_do_not_consume_quota() {
+    let router = TestRouter::start_with(|config| config.cluster.pending_limit = 1).await;
+    let (key, _) = router
+        .create_key_with_limits(&["workflow:run"], json!({ "daily_images": 1 }))
+        .await;
+
+    // fill the pending queue
+    router.submit(&sd15_payload()).await;
+
+    let resp = router.submit_as(&key, &sd15_payload()).await;
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    let body: Value = resp.json().await.unwrap();
+    assert!(body["message"]
+        .as_str()
+        .unwrap()
+        .contains("pending queue is full"));
+
+    let node = MockComfyUI::start().await;
+    router.add_node(node.url()).await;
+    common::wait_until(|| async {
+        let resp = router.submit_as(&key, &sd15_payload()).await;
+        (resp.status() == StatusCode::OK).then_some(())
+    })
+    .await;
+}
@@ -0,0 +1,120 @@
+mod common;
+
+use comfy_router::{config::LogFormat, telemetry};
+use common::{mock_comfy::MockComfyUI, sd15_payload, wait_until, TestRouter};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use std::path::Path;
+
+/// All JSON lines written to the log files so far.
+fn read_logs(dir: &Path) -> Vec<Value> {
+    std::fs::read_dir(dir)
+        .unwrap()
+        .flat_map(|entry| {
+            let content = std::fs::read_to_string(entry.unwrap().path()).unwrap();
+            content
+                .lines()
+                .map(|line| serde_json::from_str::<Value>(line).expect("log line is JSON"))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+fn has_message(logs: &[Value], message: &str) -> bool {
+    logs.iter().any(|v| v["fields"]["message"] == message)
+}
+
+// the subscriber is global, so everything runs in a single test
+#[tokio::test]
+async fn writes_json_logs_and_changes_filter_at_runtime() {
+    let log_dir = tempfile::tempdir().unwrap();
+    let log_path = log_dir.path().to_path_buf();
+    let router = TestRouter::start_with_state(
+        |config| {
+            config.log.format = LogFormat::Json;
+            config.log.filter = "info,comfy_router::workflow=debug".into();
+            config.log.dir = Some(log_path);
+        },
+        |config, state| {
+            let telemetry = telemetry::init(config).unwrap();
+            state.with_log_filter(telemetry.log_filter())
+        },
+    )
+    .await;
+    let node = MockComfyUI::start().await;
+    router.add_node(node.url()).await;
+
+    let resp = router.get("/logging/filter").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(body["filter"], "comfy_router::workflow=debug,info");
+
+    let id = router.submit(&sd15_payload()).await;
+    assert_eq!(router.wait_for(&id).await["status"], "done");
+
+    // task logs carry the task id and node of the span they happen in
+    let logs = wait_until(|| async {
+        let logs = read_logs(log_dir.path());
+        has_message(&logs, "workflow finished").then_some(logs)
+    })
+    .await;
+    let submitted = logs
+        .iter()
+        .find(|v| v["fields"]["message"] == "workflow submitted")
+        .unwrap();
+    assert_eq!(submitted["fields"]["task_id"], id.as_str());
+    assert_eq!(submitted["level"], "INFO");
+    let finished = logs
+        .iter()
+        .find(|v| v["fields"]["message"] == "workflow finished")
+        .unwrap();
+    assert_eq!(finished["span"]["task_id"], id.as_str());
+    assert_eq!(finished["span"]["node"], node.url().as_str());
+
+    // lower the level, info logs of later tasks are dropped
+    let resp = router
+        .post("/logging/filter")
+        .json(&json!({ "filter": "warn" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(body["filter"], "warn");
+
+    let id = router.submit(&sd15_payload()).await;
+    assert_eq!(router.wait_for(&id).await["status"], "done");
+    let logs = read_logs(log_dir.path());
+    assert!(!logs
+        .iter()
+        .any(|v| v["fields"]["task_id"] == id.as_str() || v["span"]["task_id"] == id.as_str()));
+
+    let resp = router
+        .post("/logging/filter")
+        .json(&json!({ "filter": "info,[" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let (key, _) = router.create_key(&["workflow:run"]).await;
+    let resp = router
+        .post_as(&key, "/logging/filter")
+        .json(&json!({ "filter": "debug" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    let resp = router.get("/logging/filter").send().await.unwrap();
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(body["filter"], "warn");
+
+    let resp = router.get("/audit?action=logging").send().await.unwrap();
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(body["total"], 1);
+    assert_eq!(
+        body["entries"][0]["details"]["previous"],
+        "comfy_router::workflow=debug,info"
+    );
+}
@@ -0,0 +1,142 @@
+mod common;
+
+use common::{
+    file_server::FileServer,
+    mock_comfy::{MockBehavior, MockComfyUI},
+    sd15_payload, wait_until, TestRouter,
+};
+use reqwest::StatusCode;
+use serde_json::json;
+
+async fn scrape(router: &TestRouter) -> String {
+    let resp = router.get("/metrics").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(resp.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain"));
+    resp.text().await.unwrap()
+}
+
+/// Wait until the metrics contain all the lines, metrics are updated
+/// slightly after the results are visible.
+async fn wait_for_metrics(router: &TestRouter, lines: &[&str]) -> String {
+    wait_until(|| async {
+        let body = scrape(router).await;
+        lines
+            .iter()
+            .all(|line| body.lines().any(|v| v == *line))
+            .then_some(body)
+    })
+    .await
+}
+
+#[tokio::test]
+async fn exposes_workflow_and_node_metrics() {
+    let router = TestRouter::start().await;
+    let node = MockComfyUI::start().await;
+    router.add_node(node.url()).await;
+
+    let id = router.submit(&sd15_payload()).await;
+    assert_eq!(router.wait_for(&id).await["status"], "done");
+
+    node.set_behavior(MockBehavior {
+        execution_error: Some("CUDA out of memory".into()),
+        ..Default::default()
+    });
+    let id = router.submit(&sd15_payload()).await;
+    assert_eq!(router.wait_for(&id).await["status"], "error");
+
+    let body = wait_for_metrics(
+        &router,
+        &[
+            r#"comfy_router_workflows_total{status="success",type="SD15"} 1"#,
+            r#"comfy_router_workflows_total{status="error",type="SD15"} 1"#,
+            r#"comfy_router_workflow_errors_total{kind="execution",type="SD15"} 1"#,
+            "comfy_router_queue_depth 0",
+            "comfy_router_queue_wait_seconds_count 2",
+        ],
+    )
+    .await;
+
+    assert!(body.contains(&format!(
+        r#"comfy_router_workflow_duration_seconds_count{{node="{}",type="SD15"}} 2"#,
+        node.url()
+    )));
+    assert!(body.contains(&format!(
+        r#"comfy_router_node_status{{node="{}",status="idle"}} 1"#,
+        node.url()
+    )));
+    assert!(body.contains(&format!(
+        r#"comfy_router_node_status{{node="{}",status="busy"}} 0"#,
+        node.url()
+    )));
+
+    // requests are labelled by route, not by path
+    assert!(body.contains(
+        r#"comfy_router_http_requests_total{method="GET",path="/workflow/:id",status="200"}"#
+    ));
+    assert!(body.contains(
+        r#"comfy_router_http_requests_total{method="POST",path="/workflow",status="200"} 2"#
+    ));
+}
+
+#[tokio::test]
+async fn exposes_download_and_cache_metrics() {
+    let router = TestRouter::start_with(|config| {
+        config.download.max_cache_bytes = 12;
+    })
+    .await;
+    let files = FileServer::start().await;
+    let node = MockComfyUI::start().await;
+    router.add_node(node.url()).await;
+
+    let first = files.add("/first.safetensors", b"checkpoint");
+    let second = files.add("/second.safetensors", b"CHECKPOINT");
+    for url in [&first, &first, &second] {
+        let mut payload = sd15_payload();
+        payload["params"]["checkpoint"] = json!({ "type": "custom", "name": url });
+        let id = router.submit(&payload).await;
+        assert_eq!(router.wait_for(&id).await["status"], "done");
+    }
+
+    let mut payload = sd15_payload();
+    payload["params"]["checkpoint"] = json!({
+        "type": "custom",
+        "name": files.add("/missing.safetensors", b"").join("/not-found.safetensors").unwrap()
+    });
+    let id = router.submit(&payload).await;
+    assert_eq!(router.wait_for(&id).await["status"], "error");
+
+    let body = wait_for_metrics(
+        &router,
+        &[
+            "comfy_router_cache_hits_total 1",
+            "comfy_router_cache_misses_total 3",
+            r#"comfy_router_downloads_total{status="completed"} 2"#,
+            r#"comfy_router_downloads_total{status="failed"} 1"#,
+            "comfy_router_download_bytes_total 20",
+            "comfy_router_download_duration_seconds_count 3",
+            "comfy_router_cache_evictions_total 1",
+            "comfy_router_cache_evicted_bytes_total 10",
+            r#"comfy_router_workflow_errors_total{kind="prompt",type="SD15"} 1"#,
+        ],
+    )
+    .await;
+
+    assert!(body.contains("comfy_router_cache_size_bytes 10"));
+    assert!(body.contains("comfy_router_cache_max_bytes 12"));
+}
+
+#[tokio::test]
+async fn requires_cluster_admin() {
+    let router = TestRouter::start().await;
+    let (key, _) = router.create_key(&["workflow:run"]).await;
+    let (admin_key, _) = router.create_key(&["cluster:admin"]).await;
+
+    let resp = router.get_as(&key, "/metrics").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    let resp = router.get_as(&admin_key, "/metrics").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+}
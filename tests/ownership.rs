@@ -0,0 +1,111 @@
+mod common;
+
+use common::{sd15_payload, TestRouter};
+use reqwest::StatusCode;
+use serde_json::Value;
+
+#[tokio::test]
+async fn restricts_results_to_owner_and_admins() {
+    let router = TestRouter::start().await;
+    let (owner, _) = router.create_key(&["workflow:run", "workflow:read"]).await;
+    let (other, _) = router.create_key(&["workflow:run", "workflow:read"]).await;
+    let (admin, _) = router.create_key(&["workflow:read", "cluster:admin"]).await;
+
+    let resp = router.submit_as(&owner, &sd15_payload()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let submitted: Value = resp.json().await.unwrap();
+    let path = format!("/workflow/{}", submitted["id"].as_str().unwrap());
+
+    let resp = router.get_as(&owner, &path).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // others can not tell whether the task exists
+    let resp = router.get_as(&other, &path).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    let resp = router.get_as(&admin, &path).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let resp = router.get(&path).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn requires_valid_preview_token() {
+    let router = TestRouter::start().await;
+    let first = router.submit_full(&sd15_payload()).await;
+    let second = router.submit_full(&sd15_payload()).await;
+    let id = first["id"].as_str().unwrap();
+    let token = first["preview_token"].as_str().unwrap();
+    assert!(first["preview_expires_at"].as_u64().unwrap() > 0);
+
+    let resp = router
+        .anonymous()
+        .get(router.url(&format!("/preview/{}", id)))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    let resp = router.preview(id, "123.abcd").await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    // tokens are bound to their task
+    let resp = router
+        .preview(id, second["preview_token"].as_str().unwrap())
+        .await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    // tampering with the expiry invalidates the signature
+    let (_, signature) = token.split_once('.').unwrap();
+    let resp = router
+        .preview(id, &format!("{}.{}", u64::MAX, signature))
+        .await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    let resp = router.preview(id, token).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(body["status"], "pending");
+}
+
+#[tokio::test]
+async fn rejects_expired_preview_token() {
+    let router = TestRouter::start_with(|config| config.auth.preview_token_ttl = 0).await;
+    let submitted = router.submit_full(&sd15_payload()).await;
+
+    let resp = router
+        .preview(
+            submitted["id"].as_str().unwrap(),
+            submitted["preview_token"].as_str().unwrap(),
+        )
+        .await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    let body: Value = resp.json().await.unwrap();
+    assert!(body["message"].as_str().unwrap().contains("expired"));
+}
+
+#[tokio::test]
+async fn renews_preview_token_for_owner_only() {
+    let router = TestRouter::start().await;
+    let (owner, _) = router.create_key(&["workflow:run", "workflow:read"]).await;
+    let (other, _) = router.create_key(&["workflow:read"]).await;
+
+    let resp = router.submit_as(&owner, &sd15_payload()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let submitted: Value = resp.json().await.unwrap();
+    let id = submitted["id"].as_str().unwrap();
+    let path = format!("/workflow/{}/preview_token", id);
+
+    let resp = router.post_as(&other, &path).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    let resp = router.post_as(&owner, &path).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: Value = resp.json().await.unwrap();
+
+    let resp = router
+        .preview(id, body["preview_token"].as_str().unwrap())
+        .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+}
@@ -0,0 +1,193 @@
+mod common;
+
+use common::{content, file_server::FileServer, wait_until, TestRouter};
+use serde_json::Value;
+use std::{path::Path, time::Duration};
+use url::Url;
+
+/// Wait until the download of `file_id` is saved in the record with `status`.
+async fn saved(router: &TestRouter, file_id: &str, status: &str) {
+    wait_until(|| async {
+        let record = std::fs::read_to_string(router.path("record.json")).ok()?;
+        let record: Value = serde_json::from_str(&record).ok()?;
+        (record["downloads"][file_id]["status"] == status).then_some(())
+    })
+    .await;
+}
+
+/// Pre-warm `url` into `target_dir`, and wait until the completed download is saved
+/// in the record. Return its file id.
+async fn prewarmed_and_saved(router: &TestRouter, url: &Url, target_dir: &str) -> String {
+    let file_id = router.prewarm(url, &[target_dir]).await;
+    saved(router, &file_id, "completed").await;
+    file_id
+}
+
+/// Start another router on the record, cache dir and root folder of `router`,
+/// as after a restart.
+async fn restart(router: &TestRouter) -> TestRouter {
+    TestRouter::start_with(|config| {
+        config.storage.record_path = router.path("record.json");
+        config.download.cache_dir = router.path("cache");
+        config.download.root_dir = router.root_dir();
+    })
+    .await
+}
+
+/// File ids of the downloads, sorted.
+async fn downloads(router: &TestRouter) -> Vec<String> {
+    let downloads = router.get_json("/downloads").await;
+    let mut file_ids: Vec<_> = downloads
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| v["file_id"].as_str().unwrap().to_string())
+        .collect();
+    file_ids.sort();
+    file_ids
+}
+
+fn is_link(path: &Path) -> bool {
+    path.symlink_metadata()
+        .is_ok_and(|v| v.file_type().is_symlink())
+}
+
+#[tokio::test]
+async fn reconciles_record_and_cache_on_startup() {
+    let router = TestRouter::start().await;
+    let files = FileServer::start().await;
+    let kept = prewarmed_and_saved(
+        &router,
+        &files.add("/kept.safetensors", &content(100, 0)),
+        "models/checkpoints",
+    )
+    .await;
+    let lost = prewarmed_and_saved(
+        &router,
+        &files.add("/lost.safetensors", &content(100, 1)),
+        "models/loras",
+    )
+    .await;
+
+    let kept_link = router.root_dir().join("models/checkpoints").join(&kept);
+    let lost_link = router.root_dir().join("models/loras").join(&lost);
+    std::fs::remove_file(&kept_link).unwrap();
+    std::fs::remove_file(std::fs::read_link(&lost_link).unwrap()).unwrap();
+    let stale = router.path("cache").join("leftover.download");
+    std::fs::write(&stale, b"partial").unwrap();
+    let dangling = router.root_dir().join("models/vae/gone.safetensors");
+    std::fs::create_dir_all(dangling.parent().unwrap()).unwrap();
+    std::os::unix::fs::symlink(router.path("cache").join("gone"), &dangling).unwrap();
+
+    let restarted = restart(&router).await;
+
+    assert_eq!(downloads(&restarted).await, vec![kept.clone()]);
+    assert_eq!(std::fs::read(&kept_link).unwrap(), content(100, 0));
+    assert!(!is_link(&lost_link));
+    assert!(!is_link(&dangling));
+    assert!(!stale.exists());
+}
+
+#[tokio::test]
+async fn skips_unreadable_folders_on_startup() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let router = TestRouter::start().await;
+    let files = FileServer::start().await;
+    let file_id = prewarmed_and_saved(
+        &router,
+        &files.add("/model.safetensors", &content(100, 0)),
+        "models/checkpoints",
+    )
+    .await;
+    let private = router.root_dir().join("models/private");
+    std::fs::create_dir_all(&private).unwrap();
+    std::fs::set_permissions(&private, std::fs::Permissions::from_mode(0o000)).unwrap();
+
+    // permissions are not enforced as root, the folder is then read as usual
+    let restarted = restart(&router).await;
+    std::fs::set_permissions(&private, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    assert_eq!(downloads(&restarted).await, vec![file_id.clone()]);
+    let link = router.root_dir().join("models/checkpoints").join(&file_id);
+    assert_eq!(std::fs::read(link).unwrap(), content(100, 0));
+}
+
+#[tokio::test]
+async fn rebuilds_corrupted_record_from_links() {
+    let router = TestRouter::start().await;
+    let files = FileServer::start().await;
+    let file_id = prewarmed_and_saved(
+        &router,
+        &files.add("/model.safetensors", &content(100, 0)),
+        "models/checkpoints",
+    )
+    .await;
+    let info = router.get_json(&format!("/downloads/{}", file_id)).await;
+
+    std::fs::write(router.path("record.json"), b"{\"downloads\": {").unwrap();
+    let restarted = restart(&router).await;
+
+    let restored = restarted.get_json(&format!("/downloads/{}", file_id)).await;
+    assert_eq!(restored["status"], "completed");
+    assert_eq!(restored["sha256"], info["sha256"]);
+    assert_eq!(restored["total"], 100);
+    assert_eq!(
+        std::fs::read(router.root_dir().join("models/checkpoints").join(&file_id)).unwrap(),
+        content(100, 0)
+    );
+    assert!(router.path("record.json.corrupted").exists());
+
+    // the rebuilt record is saved
+    wait_until(|| async {
+        let record = std::fs::read_to_string(router.path("record.json")).ok()?;
+        let record: Value = serde_json::from_str(&record).ok()?;
+        record["downloads"][&file_id].is_object().then_some(())
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn resumes_interrupted_download_after_restart() {
+    let router = TestRouter::start().await;
+    let files = FileServer::start().await;
+    let url = files.add("/model.safetensors", &content(100_000, 0));
+    // the download hangs halfway until the router is restarted
+    files.delay("/model.safetensors", 40_000, Duration::from_secs(60));
+
+    let file_id = router.prewarm(&url, &["models/checkpoints"]).await;
+    saved(&router, &file_id, "pending").await;
+    let download_path = router
+        .path("cache")
+        .join(&file_id)
+        .with_extension("download");
+    wait_until(|| async {
+        let size = std::fs::metadata(&download_path).ok()?.len();
+        (size == 40_000).then_some(())
+    })
+    .await;
+    files.delay("/model.safetensors", usize::MAX, Duration::ZERO);
+
+    let restarted = restart(&router).await;
+    let info = restarted.get_json(&format!("/downloads/{}", file_id)).await;
+    assert_eq!(info["status"], "failed");
+
+    // requested again, the download goes on from its partial file
+    assert_eq!(
+        restarted.prewarm(&url, &["models/checkpoints"]).await,
+        file_id
+    );
+    wait_until(|| async {
+        let info = restarted.get_json(&format!("/downloads/{}", file_id)).await;
+        (info["status"] == "completed").then_some(())
+    })
+    .await;
+    assert_eq!(
+        std::fs::read(router.root_dir().join("models/checkpoints").join(&file_id)).unwrap(),
+        content(100_000, 0)
+    );
+    assert_eq!(
+        files.ranges("/model.safetensors"),
+        vec![None, Some("bytes=40000-".to_string())]
+    );
+}
@@ -0,0 +1,198 @@
+mod common;
+
+use comfy_router::config::ConfigSource;
+use common::{
+    file_server::FileServer, mock_comfy::MockComfyUI, sd15_payload, wait_until, TestRouter,
+    PASSWORD,
+};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+/// A router started from a config file in a temporary directory,
+/// the admin password is set by a flag so that the helpers keep working after reloads.
+async fn start_from_file(content: &str) -> (TestRouter, TempDir, PathBuf) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.toml");
+    std::fs::write(&path, content).unwrap();
+
+    let file = path.clone();
+    let router = TestRouter::start_with_state(
+        |_| {},
+        |_, state| {
+            state.with_config_source(ConfigSource {
+                file: Some(file),
+                overrides: vec![("auth.password".into(), PASSWORD.into())],
+            })
+        },
+    )
+    .await;
+
+    (router, dir, path)
+}
+
+async fn reload(router: &TestRouter, path: &Path, content: &str) -> reqwest::Response {
+    std::fs::write(path, content).unwrap();
+    router.post("/config/reload").send().await.unwrap()
+}
+
+fn cached_files(router: &TestRouter) -> usize {
+    std::fs::read_dir(router.path("cache")).unwrap().count()
+}
+
+#[tokio::test]
+async fn applies_reloadable_settings() {
+    let (router, _dir, path) = start_from_file("").await;
+    let files = FileServer::start().await;
+    let node = MockComfyUI::start().await;
+    router.add_node(node.url()).await;
+
+    let mut ids = vec![];
+    for url in [
+        files.add("/first.safetensors", b"checkpoint"),
+        files.add("/second.safetensors", b"CHECKPOINT"),
+    ] {
+        let mut payload = sd15_payload();
+        payload["params"]["checkpoint"] = json!({ "type": "custom", "name": url });
+        let id = router.submit(&payload).await;
+        assert_eq!(router.wait_for(&id).await["status"], "done");
+        ids.push(id);
+    }
+    let id = router.submit(&sd15_payload()).await;
+    assert_eq!(router.wait_for(&id).await["status"], "done");
+    ids.push(id);
+    assert_eq!(cached_files(&router), 2);
+
+    let resp = reload(
+        &router,
+        &path,
+        r#"
+port = 1
+
+[auth]
+username = "root"
+password = "overridden"
+
+[cluster]
+history_limit = 2
+
+[download]
+max_cache_bytes = 12
+"#,
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let changes: Value = resp.json().await.unwrap();
+    assert_eq!(
+        changes["applied"],
+        json!([
+            "auth.username",
+            "cluster.history_limit",
+            "download.max_cache_bytes"
+        ])
+    );
+    let restart_required = changes["restart_required"].as_array().unwrap();
+    assert!(restart_required.contains(&json!("port")));
+    assert!(!restart_required.contains(&json!("auth.username")));
+
+    // credentials changed
+    let resp = router.get("/config").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let get = |path: &str| {
+        router
+            .anonymous()
+            .get(router.url(path))
+            .basic_auth("root", Some(PASSWORD))
+    };
+
+    // only reloadable settings are in effect
+    let config: Value = get("/config").send().await.unwrap().json().await.unwrap();
+    assert_eq!(config["port"], 0);
+    assert_eq!(config["auth"]["password"], "********");
+    assert_eq!(config["cluster"]["history_limit"], 2);
+    assert_eq!(config["download"]["max_cache_bytes"], 12);
+
+    // history is trimmed to the new limit
+    let resp = get(&format!("/workflow/{}", ids[0])).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let resp = get(&format!("/workflow/{}", ids[2])).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // the cache shrinks without waiting for the next download
+    wait_until(|| async { (cached_files(&router) == 1).then_some(()) }).await;
+
+    let audit: Value = get("/audit?action=config.reload")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(audit["total"], 1);
+    assert_eq!(
+        audit["entries"][0]["details"]["applied"],
+        changes["applied"]
+    );
+}
+
+#[tokio::test]
+async fn applies_queue_limits() {
+    // without nodes, submitted workflows stay in the queue
+    let (router, _dir, path) = start_from_file("[cluster]\npending_limit = 5\n").await;
+
+    router.submit(&sd15_payload()).await;
+    router.submit(&sd15_payload()).await;
+
+    let resp = reload(&router, &path, "[cluster]\npending_limit = 1\n").await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // queued workflows are kept, new ones are rejected
+    let resp = router
+        .post("/workflow")
+        .json(&sd15_payload())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    let resp = reload(&router, &path, "[cluster]\npending_limit = 3\n").await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    router.submit(&sd15_payload()).await;
+}
+
+#[tokio::test]
+async fn rejects_invalid_config() {
+    let (router, _dir, path) = start_from_file("").await;
+
+    let resp = reload(&router, &path, "[download]\nmax_cache_bytes = \"64GB\"\n").await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    assert!(resp
+        .text()
+        .await
+        .unwrap()
+        .contains("download.max_cache_bytes"));
+
+    let resp = reload(&router, &path, "[cluster]\nhistory_limt = 1\n").await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    // nothing changed
+    let config: Value = router
+        .get("/config")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(config["download"]["max_cache_bytes"], 1024 * 1024 * 1024);
+
+    let (key, _) = router.create_key(&["workflow:run"]).await;
+    let resp = router.post_as(&key, "/config/reload").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    // a router that was not started from a config has nothing to reload
+    let router = TestRouter::start().await;
+    let resp = router.post("/config/reload").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
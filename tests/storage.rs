@@ -0,0 +1,192 @@
+mod common;
+
+use common::{
+    agent::TestAgent, content, file_server::FileServer, mock_comfy::MockComfyUI, sd15_payload,
+    TestRouter,
+};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use url::Url;
+
+async fn add_remote_node(router: &TestRouter, node: &MockComfyUI, agent: Option<Url>) {
+    let resp = router
+        .post("/cluster/nodes")
+        .json(&json!({
+            "url": node.url(),
+            "storage": { "type": "remote", "agent": agent },
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+async fn held_files(router: &TestRouter) -> Vec<String> {
+    let nodes: Value = router
+        .get("/cluster/nodes")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let mut held: Vec<_> = nodes["nodes"][0]["status"]["files"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| v.as_str().unwrap().to_string())
+        .collect();
+    held.sort();
+    held
+}
+
+#[tokio::test]
+async fn pushes_models_and_inputs_to_remote_nodes_once() {
+    let router = TestRouter::start().await;
+    let files = FileServer::start().await;
+    let checkpoint = files.add("/model.safetensors", &content(10_000, 0));
+    let image = files.add("/image.png", &content(1_000, 1));
+    let node = MockComfyUI::start().await;
+    let agent = TestAgent::start().await;
+    add_remote_node(&router, &node, Some(agent.url())).await;
+
+    let mut payload = sd15_payload();
+    payload["params"]["checkpoint"] = json!({ "type": "custom", "name": checkpoint });
+    payload["params"]["input_image"] = json!({ "type": "url", "content": image });
+    payload["params"]["denoise"] = json!(0.5);
+
+    let id = router.submit(&payload).await;
+    let result = router.wait_for(&id).await;
+    assert_eq!(result["status"], "done", "{}", result);
+
+    let models = agent.files();
+    assert_eq!(models.len(), 1);
+    assert!(models[0].starts_with("models/checkpoints/"));
+    assert_eq!(agent.read(&models[0]), content(10_000, 0));
+    let modified = agent.modified(&models[0]);
+
+    let inputs = node.pushed();
+    assert_eq!(inputs.len(), 1);
+    assert!(inputs[0].0.starts_with("input/"));
+    assert_eq!(inputs[0].1, content(1_000, 1));
+
+    assert_eq!(
+        held_files(&router).await,
+        vec![inputs[0].0.clone(), models[0].clone()]
+    );
+
+    // the second workflow finds the files on the node
+    let id = router.submit(&payload).await;
+    let result = router.wait_for(&id).await;
+    assert_eq!(result["status"], "done", "{}", result);
+    assert_eq!(agent.modified(&models[0]), modified);
+    assert_eq!(node.pushed().len(), 1);
+}
+
+#[tokio::test]
+async fn pushes_files_missing_from_the_node_again() {
+    let router = TestRouter::start().await;
+    let files = FileServer::start().await;
+    let checkpoint = files.add("/model.safetensors", &content(10_000, 0));
+    let node = MockComfyUI::start().await;
+    let agent = TestAgent::start().await;
+    add_remote_node(&router, &node, Some(agent.url())).await;
+
+    let result = router.run_with_checkpoint(&checkpoint).await;
+    assert_eq!(result["status"], "done", "{}", result);
+    let models = agent.files();
+    assert_eq!(models.len(), 1);
+
+    // e.g. a wiped disk, the node is still listed as holding the model
+    std::fs::remove_file(agent.path(&models[0])).unwrap();
+    assert_eq!(held_files(&router).await, models);
+
+    let result = router.run_with_checkpoint(&checkpoint).await;
+    assert_eq!(result["status"], "done", "{}", result);
+    assert_eq!(agent.files(), models);
+    assert_eq!(agent.read(&models[0]), content(10_000, 0));
+}
+
+#[tokio::test]
+async fn prefers_nodes_which_have_the_files() {
+    let router = TestRouter::start().await;
+    let files = FileServer::start().await;
+    let checkpoint = files.add("/model.safetensors", &content(10_000, 0));
+    let first = MockComfyUI::start().await;
+    let second = MockComfyUI::start().await;
+    let first_agent = TestAgent::start().await;
+    let second_agent = TestAgent::start().await;
+    add_remote_node(&router, &first, Some(first_agent.url())).await;
+    add_remote_node(&router, &second, Some(second_agent.url())).await;
+
+    for _ in 0..3 {
+        let result = router.run_with_checkpoint(&checkpoint).await;
+        assert_eq!(result["status"], "done", "{}", result);
+    }
+
+    // every workflow runs on the node the checkpoint was pushed to first
+    let (holder, other) = match first.prompts().len() {
+        0 => ((&second, &second_agent), &first_agent),
+        _ => ((&first, &first_agent), &second_agent),
+    };
+    assert_eq!(holder.0.prompts().len(), 3);
+    assert_eq!(holder.1.files().len(), 1);
+    assert!(other.files().is_empty());
+}
+
+#[tokio::test]
+async fn fails_to_push_models_without_agent() {
+    let router = TestRouter::start().await;
+    let files = FileServer::start().await;
+    let checkpoint = files.add("/model.safetensors", &content(10_000, 0));
+    let node = MockComfyUI::start().await;
+    add_remote_node(&router, &node, None).await;
+
+    let result = router.run_with_checkpoint(&checkpoint).await;
+    assert_eq!(result["status"], "error");
+    assert!(
+        result["data"].as_str().unwrap().contains("no agent"),
+        "{}",
+        result
+    );
+    assert!(node.prompts().is_empty());
+
+    // build-in models are expected on the node
+    let id = router.submit(&sd15_payload()).await;
+    assert_eq!(router.wait_for(&id).await["status"], "done");
+}
+
+#[tokio::test]
+async fn sends_the_agent_token_instead_of_the_node_credentials() {
+    let router = TestRouter::start().await;
+    let files = FileServer::start().await;
+    let checkpoint = files.add("/model.safetensors", &content(10_000, 0));
+    let node = MockComfyUI::start().await;
+    let agent = TestAgent::start_with_token(Some("agent-secret")).await;
+    let resp = router
+        .post("/cluster/nodes")
+        .json(&json!({
+            "url": node.url(),
+            "auth": { "type": "basic", "username": "user", "password": "node-secret" },
+            "storage": { "type": "remote", "agent": agent.url(), "token": "agent-secret" },
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let result = router.run_with_checkpoint(&checkpoint).await;
+    assert_eq!(result["status"], "done", "{}", result);
+    assert_eq!(agent.files().len(), 1);
+
+    let nodes: Value = router
+        .get("/cluster/nodes")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let storage = &nodes["nodes"][0]["options"]["storage"];
+    assert_eq!(storage["token"], "********");
+}
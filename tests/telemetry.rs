@@ -0,0 +1,135 @@
+mod common;
+
+use comfy_router::{
+    config::{AppConfig, OtelProtocol},
+    telemetry,
+};
+use common::{
+    collector::{Collector, ExportedSpan},
+    file_server::FileServer,
+    mock_comfy::MockComfyUI,
+    sd15_payload, wait_until, TestRouter,
+};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+
+const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+const PARENT_SPAN_ID: &str = "00f067aa0ba902b7";
+
+fn find<'a>(spans: &'a [ExportedSpan], name: &str) -> &'a ExportedSpan {
+    spans
+        .iter()
+        .find(|v| v.name == name)
+        .unwrap_or_else(|| panic!("span {} not found in {:#?}", name, spans))
+}
+
+fn trace(collector: &Collector, trace_id: &str) -> Vec<ExportedSpan> {
+    collector
+        .spans()
+        .into_iter()
+        .filter(|v| v.trace_id == trace_id)
+        .collect()
+}
+
+// The subscriber is global, so everything is checked in a single test.
+#[tokio::test]
+async fn exports_one_trace_per_task() {
+    // export quickly instead of waiting for shutdown
+    std::env::set_var("OTEL_BSP_SCHEDULE_DELAY", "50");
+
+    let collector = Collector::start().await;
+    let mut config = AppConfig::default();
+    config.otel.endpoint = Some(collector.url().clone());
+    config.otel.protocol = OtelProtocol::HttpJson;
+    let telemetry = telemetry::init(&config).unwrap();
+
+    let router = TestRouter::start().await;
+    let files = FileServer::start().await;
+    let node = MockComfyUI::start().await;
+    router.add_node(node.url()).await;
+
+    let mut payload = sd15_payload();
+    payload["params"]["checkpoint"] = json!({
+        "type": "custom",
+        "name": files.add("/model.safetensors", b"checkpoint"),
+    });
+
+    // continue the trace of the caller
+    let resp = router
+        .post("/workflow")
+        .header(
+            "traceparent",
+            format!("00-{}-{}-01", TRACE_ID, PARENT_SPAN_ID),
+        )
+        .json(&payload)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let id = resp.json::<Value>().await.unwrap()["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert_eq!(router.wait_for(&id).await["status"], "done");
+
+    let spans = wait_until(|| async {
+        let spans = trace(&collector, TRACE_ID);
+        spans
+            .iter()
+            .any(|v| v.name == "workflow_task")
+            .then_some(spans)
+    })
+    .await;
+
+    let request = find(&spans, "POST /workflow");
+    assert_eq!(request.parent_span_id, PARENT_SPAN_ID);
+
+    let queue_wait = find(&spans, "queue_wait");
+    assert_eq!(queue_wait.parent_span_id, request.span_id);
+
+    let workflow_task = find(&spans, "workflow_task");
+    assert_eq!(workflow_task.parent_span_id, request.span_id);
+
+    let generate_prompt = find(&spans, "generate_prompt");
+    assert_eq!(generate_prompt.parent_span_id, workflow_task.span_id);
+
+    let execute = find(&spans, "execute");
+    assert_eq!(execute.parent_span_id, workflow_task.span_id);
+
+    // the download is nested in prompt generation
+    let download = find(&spans, "download");
+    let mut parent = download.parent_span_id.clone();
+    while parent != generate_prompt.span_id {
+        parent = spans
+            .iter()
+            .find(|v| v.span_id == parent)
+            .unwrap_or_else(|| panic!("download is not nested in generate_prompt"))
+            .parent_span_id
+            .clone();
+    }
+
+    // without traceparent, a new trace starts at the request
+    let id = router.submit(&sd15_payload()).await;
+    assert_eq!(router.wait_for(&id).await["status"], "done");
+
+    let (request, spans) = wait_until(|| async {
+        let spans = collector.spans();
+        let request = spans
+            .iter()
+            .find(|v| v.name == "POST /workflow" && v.trace_id != TRACE_ID)?
+            .clone();
+        let spans = trace(&collector, &request.trace_id);
+        spans
+            .iter()
+            .any(|v| v.name == "workflow_task")
+            .then_some((request, spans))
+    })
+    .await;
+    assert_eq!(request.parent_span_id, "");
+    assert_eq!(
+        find(&spans, "workflow_task").parent_span_id,
+        request.span_id
+    );
+
+    telemetry.shutdown().await;
+}
@@ -0,0 +1,102 @@
+mod common;
+
+use common::{
+    images,
+    mock_comfy::{MockBehavior, MockComfyUI, MockServer},
+    sd15_payload,
+    tls::TestCa,
+    wait_until, TestRouter,
+};
+use reqwest::{Certificate, Client, StatusCode};
+use std::time::Duration;
+
+/// A new client for each request, pooled connections keep the certificate they started with.
+async fn health_check(router: &TestRouter, ca: &TestCa) -> reqwest::Result<StatusCode> {
+    let client = Client::builder()
+        .tls_built_in_root_certs(false)
+        .add_root_certificate(Certificate::from_pem(ca.pem().as_bytes()).unwrap())
+        .build()
+        .unwrap();
+    let resp = client.get(router.url("/health_check")).send().await?;
+    Ok(resp.status())
+}
+
+#[tokio::test]
+async fn serves_https_and_reloads_certificate() {
+    let dir = tempfile::tempdir().unwrap();
+    let old_ca = TestCa::new("old");
+    let new_ca = TestCa::new("new");
+    old_ca.server_cert().write(dir.path());
+
+    let router = TestRouter::start_with(|config| {
+        config.tls.cert_path = Some(dir.path().join("cert.pem"));
+        config.tls.key_path = Some(dir.path().join("key.pem"));
+        config.tls.reload_interval = 1;
+    })
+    .await;
+    assert_eq!(router.url("/").scheme(), "https");
+
+    assert_eq!(
+        health_check(&router, &old_ca).await.unwrap(),
+        StatusCode::OK
+    );
+    assert!(health_check(&router, &new_ca).await.is_err());
+
+    // an invalid certificate is not loaded, the current one is kept
+    std::fs::write(dir.path().join("cert.pem"), "not a certificate").unwrap();
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert_eq!(
+        health_check(&router, &old_ca).await.unwrap(),
+        StatusCode::OK
+    );
+
+    new_ca.server_cert().write(dir.path());
+    wait_until(|| async { health_check(&router, &new_ca).await.ok() }).await;
+    assert!(health_check(&router, &old_ca).await.is_err());
+}
+
+#[tokio::test]
+async fn runs_workflow_on_https_node_with_client_certificate() {
+    let dir = tempfile::tempdir().unwrap();
+    let ca = TestCa::new("nodes");
+    std::fs::write(dir.path().join("ca.pem"), ca.pem()).unwrap();
+    ca.client_cert().write(dir.path());
+
+    let node = MockComfyUI::start_with_server(
+        MockBehavior {
+            outputs: vec![b"secure".to_vec()],
+            ..Default::default()
+        },
+        MockServer {
+            tls: Some(ca.mtls_server_config()),
+            ..Default::default()
+        },
+    )
+    .await;
+    assert_eq!(node.url().scheme(), "https");
+
+    let router = TestRouter::start_with(|config| {
+        config.node_tls.ca_path = Some(dir.path().join("ca.pem"));
+        config.node_tls.cert_path = Some(dir.path().join("cert.pem"));
+        config.node_tls.key_path = Some(dir.path().join("key.pem"));
+    })
+    .await;
+    router.add_node(node.url()).await;
+
+    let id = router.submit(&sd15_payload()).await;
+    let result = router.wait_for(&id).await;
+    assert_eq!(result["status"], "done", "{}", result);
+    assert_eq!(images(&result), vec![b"secure".to_vec()]);
+
+    // the node rejects a router without client certificate
+    let router = TestRouter::start_with(|config| {
+        config.node_tls.ca_path = Some(dir.path().join("ca.pem"));
+    })
+    .await;
+    router.add_node(node.url()).await;
+
+    let id = router.submit(&sd15_payload()).await;
+    let result = router.wait_for(&id).await;
+    assert_eq!(result["status"], "error", "{}", result);
+    assert_eq!(node.prompts().len(), 1);
+}
@@ -0,0 +1,316 @@
+mod common;
+
+use common::{
+    file_server::FileServer,
+    images,
+    mock_comfy::{MockBehavior, MockComfyUI},
+    sd15_payload, wait_until, TestRouter,
+};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+#[tokio::test]
+async fn runs_workflow_on_node() {
+    let router = TestRouter::start().await;
+    let node = MockComfyUI::start_with(MockBehavior {
+        outputs: vec![b"first".to_vec(), b"second".to_vec()],
+        ..Default::default()
+    })
+    .await;
+    router.add_node(node.url()).await;
+
+    let id = router.submit(&sd15_payload()).await;
+    let result = router.wait_for(&id).await;
+
+    assert_eq!(result["status"], "done");
+    assert_eq!(images(&result), vec![b"first".to_vec(), b"second".to_vec()]);
+
+    let prompts = node.prompts();
+    assert_eq!(prompts.len(), 1);
+    let class_types: Vec<&str> = prompts[0]
+        .as_object()
+        .unwrap()
+        .values()
+        .map(|node| node["class_type"].as_str().unwrap())
+        .collect();
+    assert!(class_types.contains(&"CheckpointLoaderSimple"));
+    assert!(class_types.contains(&"KSampler"));
+    assert!(class_types.contains(&"SaveImageWebsocket"));
+}
+
+#[tokio::test]
+async fn distributes_jobs_across_nodes() {
+    let router = TestRouter::start().await;
+    let behavior = MockBehavior {
+        step_delay: Duration::from_millis(50),
+        ..Default::default()
+    };
+    let nodes = vec![
+        MockComfyUI::start_with(behavior.clone()).await,
+        MockComfyUI::start_with(behavior.clone()).await,
+        MockComfyUI::start_with(behavior).await,
+    ];
+    for node in &nodes {
+        router.add_node(node.url()).await;
+    }
+
+    let mut ids = vec![];
+    for _ in 0..6 {
+        ids.push(router.submit(&sd15_payload()).await);
+    }
+
+    for id in &ids {
+        let result = router.wait_for(id).await;
+        assert_eq!(result["status"], "done", "{}", result);
+    }
+
+    let counts: Vec<usize> = nodes.iter().map(|node| node.prompts().len()).collect();
+    assert_eq!(counts.iter().sum::<usize>(), 6);
+    assert!(counts.iter().filter(|count| **count > 0).count() > 1);
+
+    // all nodes are released after the jobs
+    let cluster: Value = router
+        .get("/cluster/nodes")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(cluster["nodes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .all(|node| node["status"]["status"] == "idle"));
+}
+
+#[tokio::test]
+async fn runs_pending_job_when_node_joins() {
+    let router = TestRouter::start().await;
+    let id = router.submit(&sd15_payload()).await;
+
+    let result = router.status(&id).await;
+    assert_eq!(result["status"], "pending");
+
+    let node = MockComfyUI::start().await;
+    router.add_node(node.url()).await;
+
+    let result = router.wait_for(&id).await;
+    assert_eq!(result["status"], "done");
+}
+
+#[tokio::test]
+async fn reports_execution_error() {
+    let router = TestRouter::start().await;
+    let node = MockComfyUI::start_with(MockBehavior {
+        execution_error: Some("CUDA out of memory".into()),
+        ..Default::default()
+    })
+    .await;
+    router.add_node(node.url()).await;
+
+    let id = router.submit(&sd15_payload()).await;
+    let result = router.wait_for(&id).await;
+
+    assert_eq!(result["status"], "error");
+    assert_eq!(result["data"], "CUDA out of memory");
+}
+
+#[tokio::test]
+async fn reports_rejected_prompt() {
+    let router = TestRouter::start().await;
+    let node = MockComfyUI::start_with(MockBehavior {
+        prompt_error: Some("Prompt outputs failed validation".into()),
+        ..Default::default()
+    })
+    .await;
+    router.add_node(node.url()).await;
+
+    let id = router.submit(&sd15_payload()).await;
+    let result = router.wait_for(&id).await;
+
+    assert_eq!(result["status"], "error");
+}
+
+#[tokio::test]
+async fn reports_node_disconnect() {
+    let router = TestRouter::start().await;
+    let node = MockComfyUI::start_with(MockBehavior {
+        disconnect_after: Some(1),
+        ..Default::default()
+    })
+    .await;
+    router.add_node(node.url()).await;
+
+    let id = router.submit(&sd15_payload()).await;
+    let result = router.wait_for(&id).await;
+
+    assert_eq!(result["status"], "error");
+    assert!(result["data"].as_str().unwrap().contains("disconnected"));
+
+    // the node can be used again
+    node.set_behavior(MockBehavior::default());
+    let id = router.submit(&sd15_payload()).await;
+    let result = router.wait_for(&id).await;
+    assert_eq!(result["status"], "done");
+}
+
+#[tokio::test]
+async fn reports_interrupt_on_node() {
+    let router = TestRouter::start().await;
+    let node = MockComfyUI::start_with(MockBehavior {
+        steps: 100,
+        step_delay: Duration::from_millis(20),
+        ..Default::default()
+    })
+    .await;
+    router.add_node(node.url()).await;
+
+    let id = router.submit(&sd15_payload()).await;
+    wait_until(|| async {
+        let result = router.status(&id).await;
+        (result["status"] == "running").then_some(())
+    })
+    .await;
+
+    // interrupted from the ComfyUI side, e.g. through its own web UI
+    let resp = router
+        .anonymous()
+        .post(node.url().join("/interrupt").unwrap())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(node.interrupt_count(), 1);
+
+    let result = router.wait_for(&id).await;
+    assert_eq!(result["status"], "error");
+    assert!(result["data"].as_str().unwrap().contains("interrupted"));
+}
+
+#[tokio::test]
+async fn rejects_jobs_when_pending_queue_is_full() {
+    let router = TestRouter::start_with(|config| config.cluster.pending_limit = 2).await;
+
+    router.submit(&sd15_payload()).await;
+    router.submit(&sd15_payload()).await;
+
+    let resp = router
+        .post("/workflow")
+        .json(&sd15_payload())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn rejects_invalid_payload() {
+    let router = TestRouter::start().await;
+
+    let resp = router
+        .post("/workflow")
+        .json(&json!({ "type": "SD15", "params": {} }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn previews_running_workflow() {
+    let router = TestRouter::start().await;
+    let node = MockComfyUI::start_with(MockBehavior {
+        steps: 20,
+        step_delay: Duration::from_millis(30),
+        ..Default::default()
+    })
+    .await;
+    router.add_node(node.url()).await;
+
+    let submitted = router.submit_full(&sd15_payload()).await;
+    let id = submitted["id"].as_str().unwrap();
+    let token = submitted["preview_token"].as_str().unwrap();
+
+    // preview does not require authentication, only the preview token
+    let preview = wait_until(|| async {
+        let preview: Value = router.preview(id, token).await.json().await.unwrap();
+        let has_preview = preview["status"] == "running"
+            && !preview["data"]["previews"].as_array().unwrap().is_empty();
+        has_preview.then_some(preview)
+    })
+    .await;
+    assert!(preview["data"]["progress"].as_f64().unwrap() > 0.0);
+
+    router.wait_for(id).await;
+
+    // final images are not exposed through preview
+    let preview: Value = router.preview(id, token).await.json().await.unwrap();
+    assert_eq!(preview, json!({ "status": "done", "data": [] }));
+}
+
+#[tokio::test]
+async fn returns_not_found_for_unknown_task() {
+    let router = TestRouter::start().await;
+
+    let resp = router.get("/workflow/unknown").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn downloads_custom_models_once() {
+    let router = TestRouter::start().await;
+    let files = FileServer::start().await;
+    let node = MockComfyUI::start().await;
+    router.add_node(node.url()).await;
+
+    let checkpoint = files.add("/models/model.safetensors", b"checkpoint");
+    let mut payload = sd15_payload();
+    payload["params"]["checkpoint"] = json!({ "type": "custom", "name": checkpoint });
+
+    for _ in 0..2 {
+        let id = router.submit(&payload).await;
+        let result = router.wait_for(&id).await;
+        assert_eq!(result["status"], "done", "{}", result);
+    }
+
+    assert_eq!(files.hits("/models/model.safetensors"), 1);
+
+    for prompt in node.prompts() {
+        let ckpt_name = prompt
+            .as_object()
+            .unwrap()
+            .values()
+            .find(|node| node["class_type"] == "CheckpointLoaderSimple")
+            .map(|node| node["inputs"]["ckpt_name"].as_str().unwrap().to_string())
+            .unwrap();
+        assert!(ckpt_name.ends_with(".safetensors"));
+
+        let linked = router
+            .root_dir()
+            .join("models/checkpoints")
+            .join(&ckpt_name);
+        assert_eq!(std::fs::read(linked).unwrap(), b"checkpoint");
+    }
+}
+
+#[tokio::test]
+async fn fails_workflow_when_download_fails() {
+    let router = TestRouter::start().await;
+    let files = FileServer::start().await;
+    let node = MockComfyUI::start().await;
+    router.add_node(node.url()).await;
+
+    let mut payload = sd15_payload();
+    payload["params"]["checkpoint"] = json!({
+        "type": "custom",
+        "name": files.add("/missing.safetensors", b"").join("/not-found.safetensors").unwrap()
+    });
+
+    let id = router.submit(&payload).await;
+    let result = router.wait_for(&id).await;
+
+    assert_eq!(result["status"], "error");
+    assert!(node.prompts().is_empty());
+}
@@ -0,0 +1,113 @@
+import type { DownloadInfo } from "@/components/DownloadCache";
+import { Badge } from "@/components/ui/badge";
+import { Button } from "@/components/ui/button";
+import {
+  Dialog,
+  DialogContent,
+  DialogDescription,
+  DialogFooter,
+  DialogHeader,
+  DialogTitle,
+  DialogTrigger,
+} from "@/components/ui/dialog";
+import api from "@/lib/api";
+import { useMutation } from "@tanstack/react-query";
+import { useState } from "react";
+
+function formatBytes(bytes: number) {
+  const units = ["B", "KB", "MB", "GB", "TB"];
+  let value = bytes;
+  let unit = 0;
+  while (value >= 1024 && unit < units.length - 1) {
+    value /= 1024;
+    unit += 1;
+  }
+  return `${value.toFixed(unit === 0 ? 0 : 1)} ${units[unit]}`;
+}
+
+function CacheEntry({
+  file_id,
+  url,
+  status,
+  target_dirs,
+  downloaded,
+  total,
+  last_used_at,
+  pinned,
+  refetch,
+}: DownloadInfo & { refetch: () => Promise<void> }) {
+  const { mutateAsync: deleteDownload, isPending } = useMutation({
+    mutationKey: ["delete", "download"],
+    mutationFn: async (file_id: string) => {
+      await api.post(`/downloads/${file_id}/delete`);
+    },
+  });
+
+  const [open, setOpen] = useState(false);
+
+  return (
+    <div className="py-4 px-4 w-full border rounded-md border-gray-300 flex justify-between">
+      <div className="flex flex-col min-w-0">
+        <div className="truncate">{url}</div>
+        <div className="text-sm text-gray-500 mt-1">
+          {total !== null
+            ? `${formatBytes(downloaded)} / ${formatBytes(total)}`
+            : formatBytes(downloaded)}
+          {" · "}
+          {target_dirs.join(", ")}
+          {" · "}
+          {last_used_at !== null
+            ? `last used ${new Date(last_used_at).toLocaleString()}`
+            : "never used"}
+        </div>
+        <div className="flex items-center justify-start mt-4 space-x-2">
+          <Badge
+            className={
+              status === "completed"
+                ? "bg-green-600"
+                : status === "pending"
+                ? "bg-yellow-600"
+                : "bg-red-600"
+            }
+          >
+            {status}
+          </Badge>
+          {pinned && <Badge variant="secondary">pinned</Badge>}
+        </div>
+      </div>
+
+      <div className="flex items-center justify-end">
+        <Dialog open={open} onOpenChange={setOpen}>
+          <DialogTrigger asChild>
+            <Button
+              variant="destructive"
+              disabled={pinned || status === "pending"}
+            >
+              Delete
+            </Button>
+          </DialogTrigger>
+          <DialogContent className="sm:max-w-[425px]">
+            <DialogHeader>
+              <DialogTitle>Delete cached file</DialogTitle>
+              <DialogDescription>{`Are you sure to delete ${url} and its links`}</DialogDescription>
+            </DialogHeader>
+            <DialogFooter>
+              <Button
+                disabled={isPending}
+                onClick={async () => {
+                  await deleteDownload(file_id);
+                  await refetch();
+                  setOpen(false);
+                }}
+              >
+                {isPending ? "Deleting..." : "Delete"}
+              </Button>
+            </DialogFooter>
+          </DialogContent>
+        </Dialog>
+      </div>
+    </div>
+  );
+}
+
+export default CacheEntry;
@@ -19,6 +19,7 @@ import { Input } from "./ui/input";
 function ClusterNode({
   url,
   status,
+  options,
   refetch,
 }: NodeStatus & { refetch: () => Promise<void> }) {
   const { mutateAsync: removeNode } = useMutation({
@@ -58,6 +59,11 @@ function ClusterNode({
           >
             {status.status}
           </Badge>
+          {options.storage.type === "remote" && (
+            <Badge variant="outline" className="ml-2">
+              {`remote · ${status.files.length} files`}
+            </Badge>
+          )}
         </div>
       </div>
 
@@ -0,0 +1,265 @@
+import CacheEntry from "@/components/CacheEntry";
+import { Button } from "@/components/ui/button";
+import {
+  Dialog,
+  DialogContent,
+  DialogDescription,
+  DialogFooter,
+  DialogHeader,
+  DialogTitle,
+  DialogTrigger,
+} from "@/components/ui/dialog";
+import { Input } from "@/components/ui/input";
+import api from "@/lib/api";
+import { useForm } from "@tanstack/react-form";
+import { useMutation, useQuery } from "@tanstack/react-query";
+import { useState } from "react";
+
+export type DownloadInfo = {
+  file_id: string;
+  url: string;
+  status: "pending" | "completed" | "failed";
+  target_dirs: string[];
+  sha256: string | null;
+  downloaded: number;
+  total: number | null;
+  speed: number | null;
+  eta: number | null;
+  last_used_at: number | null;
+  use_count: number;
+  pinned: boolean;
+};
+
+export default function DownloadCache() {
+  const { data, refetch } = useQuery({
+    queryKey: ["downloads"],
+    queryFn: async () => {
+      const response = await api.get<DownloadInfo[]>("/downloads");
+      return response.data;
+    },
+    refetchInterval: 1000,
+  });
+
+  const { mutateAsync: prewarm } = useMutation({
+    mutationKey: ["prewarm", "download"],
+    mutationFn: async ({
+      url,
+      target_dirs,
+    }: {
+      url: string;
+      target_dirs: string;
+    }) => {
+      await api.post("/downloads", {
+        url,
+        target_dirs: target_dirs
+          .split(",")
+          .map((v) => v.trim())
+          .filter((v) => v),
+      });
+    },
+  });
+
+  const { mutateAsync: purge } = useMutation({
+    mutationKey: ["purge", "downloads"],
+    mutationFn: async ({
+      pattern,
+      older_than_days,
+    }: {
+      pattern: string;
+      older_than_days: string;
+    }) => {
+      await api.post("/downloads/purge", {
+        pattern: pattern || null,
+        older_than: older_than_days
+          ? Math.round(Number(older_than_days) * 24 * 3600)
+          : null,
+      });
+    },
+  });
+
+  const prewarmForm = useForm({
+    defaultValues: {
+      url: "",
+      target_dirs: "models/checkpoints",
+    },
+    onSubmit: async ({ value }) => {
+      await prewarm(value);
+      await refetch();
+      setPrewarmOpen(false);
+      prewarmForm.reset();
+    },
+  });
+
+  const purgeForm = useForm({
+    defaultValues: {
+      pattern: "",
+      older_than_days: "",
+    },
+    onSubmit: async ({ value }) => {
+      await purge(value);
+      await refetch();
+      setPurgeOpen(false);
+      purgeForm.reset();
+    },
+  });
+
+  const [prewarmOpen, setPrewarmOpen] = useState(false);
+  const [purgeOpen, setPurgeOpen] = useState(false);
+
+  return (
+    <>
+      <div className="flex justify-between items-center pt-24">
+        <div className="font-bold text-xl">Download Cache</div>
+
+        <div className="flex space-x-2">
+          <Dialog open={prewarmOpen} onOpenChange={setPrewarmOpen}>
+            <DialogTrigger asChild>
+              <Button variant="outline">Pre-warm</Button>
+            </DialogTrigger>
+            <DialogContent className="sm:max-w-[425px]">
+              <form
+                onSubmit={(e) => {
+                  e.preventDefault();
+                  e.stopPropagation();
+                  prewarmForm.handleSubmit();
+                }}
+              >
+                <DialogHeader>
+                  <DialogTitle>Pre-warm</DialogTitle>
+                  <DialogDescription>
+                    Download a file and link it into folders under the ComfyUI
+                    root.
+                  </DialogDescription>
+                </DialogHeader>
+                <div className="grid gap-4 py-4">
+                  <div className="grid grid-cols-4 items-center gap-4">
+                    <prewarmForm.Field name="url">
+                      {(field) => (
+                        <Input
+                          id={field.name}
+                          name={field.name}
+                          value={field.state.value}
+                          onBlur={field.handleBlur}
+                          onChange={(e) => field.handleChange(e.target.value)}
+                          className="col-span-4"
+                          placeholder="https://example.com/model.safetensors"
+                        />
+                      )}
+                    </prewarmForm.Field>
+                    <prewarmForm.Field name="target_dirs">
+                      {(field) => (
+                        <Input
+                          id={field.name}
+                          name={field.name}
+                          value={field.state.value}
+                          onBlur={field.handleBlur}
+                          onChange={(e) => field.handleChange(e.target.value)}
+                          className="col-span-4"
+                          placeholder="models/checkpoints, models/loras"
+                        />
+                      )}
+                    </prewarmForm.Field>
+                  </div>
+                </div>
+                <DialogFooter>
+                  <prewarmForm.Subscribe
+                    selector={(state) => [state.canSubmit, state.isSubmitting]}
+                  >
+                    {([canSubmit, isSubmitting]) => (
+                      <Button type="submit" disabled={!canSubmit}>
+                        {isSubmitting ? "Submitting..." : "Submit"}
+                      </Button>
+                    )}
+                  </prewarmForm.Subscribe>
+                </DialogFooter>
+              </form>
+            </DialogContent>
+          </Dialog>
+
+          <Dialog open={purgeOpen} onOpenChange={setPurgeOpen}>
+            <DialogTrigger asChild>
+              <Button variant="destructive">Purge</Button>
+            </DialogTrigger>
+            <DialogContent className="sm:max-w-[425px]">
+              <form
+                onSubmit={(e) => {
+                  e.preventDefault();
+                  e.stopPropagation();
+                  purgeForm.handleSubmit();
+                }}
+              >
+                <DialogHeader>
+                  <DialogTitle>Purge</DialogTitle>
+                  <DialogDescription>
+                    Delete the cached files matching a URL pattern and/or not
+                    used for a while, except those in use.
+                  </DialogDescription>
+                </DialogHeader>
+                <div className="grid gap-4 py-4">
+                  <div className="grid grid-cols-4 items-center gap-4">
+                    <purgeForm.Field name="pattern">
+                      {(field) => (
+                        <Input
+                          id={field.name}
+                          name={field.name}
+                          value={field.state.value}
+                          onBlur={field.handleBlur}
+                          onChange={(e) => field.handleChange(e.target.value)}
+                          className="col-span-4"
+                          placeholder="https://civitai.com/*"
+                        />
+                      )}
+                    </purgeForm.Field>
+                    <purgeForm.Field name="older_than_days">
+                      {(field) => (
+                        <Input
+                          id={field.name}
+                          name={field.name}
+                          type="number"
+                          min={0}
+                          value={field.state.value}
+                          onBlur={field.handleBlur}
+                          onChange={(e) => field.handleChange(e.target.value)}
+                          className="col-span-4"
+                          placeholder="Not used for days"
+                        />
+                      )}
+                    </purgeForm.Field>
+                  </div>
+                </div>
+                <DialogFooter>
+                  <purgeForm.Subscribe
+                    selector={(state) => [
+                      state.canSubmit,
+                      state.isSubmitting,
+                      state.values.pattern || state.values.older_than_days,
+                    ]}
+                  >
+                    {([canSubmit, isSubmitting, hasCriteria]) => (
+                      <Button type="submit" disabled={!canSubmit || !hasCriteria}>
+                        {isSubmitting ? "Purging..." : "Purge"}
+                      </Button>
+                    )}
+                  </purgeForm.Subscribe>
+                </DialogFooter>
+              </form>
+            </DialogContent>
+          </Dialog>
+        </div>
+      </div>
+
+      {/* list content */}
+      <div className="pt-8 pb-24 flex flex-col space-y-8">
+        {data?.map((download) => (
+          <CacheEntry
+            key={download.file_id}
+            {...download}
+            refetch={async () => {
+              await refetch();
+            }}
+          />
+        ))}
+      </div>
+    </>
+  );
+}
@@ -1,4 +1,5 @@
 import ClusterNode from "@/components/ClusterNode";
+import DownloadCache from "@/components/DownloadCache";
 import { Button } from "@/components/ui/button";
 import {
   Dialog,
@@ -19,7 +20,17 @@ export type NodeStatus = {
   url: string;
   status: {
     status: "busy" | "idle" | "offline";
-    cache: object;
+    files: string[];
+  };
+  options: {
+    headers: Record<string, string>;
+    auth:
+      | { type: "basic"; username: string; password: string }
+      | { type: "bearer"; token: string }
+      | null;
+    storage:
+      | { type: "shared" }
+      | { type: "remote"; agent: string | null; token: string | null };
   };
 };
 
@@ -35,17 +46,21 @@ export default function Index() {
 
   const { mutateAsync: addNode } = useMutation({
     mutationKey: ["add", "node"],
-    mutationFn: async (url: string) => {
-      await api.post("/cluster/nodes", { url });
+    mutationFn: async ({ url, token }: { url: string; token: string }) => {
+      await api.post("/cluster/nodes", {
+        url,
+        auth: token ? { type: "bearer", token } : null,
+      });
     },
   });
 
   const form = useForm({
     defaultValues: {
       url: "",
+      token: "",
     },
     onSubmit: async ({ value }) => {
-      await addNode(value.url);
+      await addNode(value);
       await refetch();
       setOpen(false);
       form.reset();
@@ -86,6 +101,21 @@ export default function Index() {
                         onBlur={field.handleBlur}
                         onChange={(e) => field.handleChange(e.target.value)}
                         className="col-span-4"
+                        placeholder="http://10.0.0.2:8188"
+                      />
+                    )}
+                  </form.Field>
+                  <form.Field name="token">
+                    {(field) => (
+                      <Input
+                        id={field.name}
+                        name={field.name}
+                        type="password"
+                        value={field.state.value}
+                        onBlur={field.handleBlur}
+                        onChange={(e) => field.handleChange(e.target.value)}
+                        className="col-span-4"
+                        placeholder="Bearer token (optional)"
                       />
                     )}
                   </form.Field>
@@ -119,6 +149,8 @@ export default function Index() {
           />
         ))}
       </div>
+
+      <DownloadCache />
     </div>
   );
 }